@@ -31,7 +31,7 @@ impl AnalysisLoader for Loader {
         None
     }
     fn search_directories(&self) -> Vec<SearchDirectory> {
-        vec![SearchDirectory { path: self.deps_dir.clone(), prefix_rewrite: None }]
+        vec![SearchDirectory::new(self.deps_dir.clone(), None)]
     }
 }
 
@@ -4,15 +4,24 @@ use std::iter;
 use std::path::{Path, PathBuf};
 use std::time::SystemTime;
 
+#[cfg(feature = "derive")]
+use crate::lowering::build_index;
 use crate::raw::{CrateId, DefKind};
+use crate::util::blank_comments_and_literals;
+#[cfg(feature = "derive")]
+use crate::util::map_as_seq;
 use crate::{Id, Span, SymbolQuery};
+#[cfg(feature = "derive")]
+use serde::{Deserialize, Serialize};
 use span::{Column, Row, ZeroIndexed};
 
 /// This is the main database that contains all the collected symbol information,
 /// such as definitions, their mapping between spans, hierarchy and so on,
 /// organized in a per-crate fashion.
+#[cfg_attr(feature = "derive", derive(Serialize, Deserialize))]
 pub(crate) struct Analysis {
     /// Contains lowered data with global inter-crate `Id`s per each crate.
+    #[cfg_attr(feature = "derive", serde(with = "map_as_seq"))]
     pub per_crate: HashMap<CrateId, PerCrateAnalysis>,
 
     // This is a bit of a hack and should be considered temporary. A def has an
@@ -31,9 +40,11 @@ pub(crate) struct Analysis {
     pub src_url_base: String,
 }
 
+#[cfg_attr(feature = "derive", derive(Serialize, Deserialize))]
 pub struct PerCrateAnalysis {
     // Map span to id of def (either because it is the span of the def, or of
     // the def for the ref).
+    #[cfg_attr(feature = "derive", serde(with = "map_as_seq"))]
     pub def_id_for_span: HashMap<Span, Ref>,
     pub defs: HashMap<Id, Def>,
     pub defs_per_file: HashMap<PathBuf, Vec<Id>>,
@@ -41,14 +52,37 @@ pub struct PerCrateAnalysis {
     pub def_names: HashMap<String, Vec<Id>>,
 
     // Index of all symbols that powers the search.
-    // See `SymbolQuery`.
+    // See `SymbolQuery`. Not serialized, see `rebuild_def_index`.
+    #[cfg_attr(feature = "derive", serde(skip, default = "empty_fst"))]
     pub def_fst: fst::Map<Vec<u8>>,
+    #[cfg_attr(feature = "derive", serde(skip))]
     pub def_fst_values: Vec<Vec<Id>>,
 
     pub ref_spans: HashMap<Id, Vec<Span>>,
+    #[cfg_attr(feature = "derive", serde(with = "map_as_seq"))]
     pub globs: HashMap<Span, Glob>,
     pub impls: HashMap<Id, Vec<Span>>,
+    // Impls of each trait.
+    pub trait_impls: HashMap<Id, Vec<Impl>>,
+    // The defs implementing each declaration, e.g., the methods implementing
+    // a trait method in trait impls.
+    pub implementations: HashMap<Id, Vec<Id>>,
+    // Non-glob `use` imports in each file.
+    pub imports: HashMap<PathBuf, Vec<Import>>,
+    // The `pub use`s re-exporting each def, as the module and the name under
+    // which it is re-exported. Only recorded for crates whose data covers just
+    // their public items (e.g., the standard library), where every `use` is
+    // a `pub use`.
+    pub reexports: HashMap<Id, Vec<(Id, String)>>,
+    // Direct supertraits and subtraits of each trait.
+    pub super_traits: HashMap<Id, HashSet<Id>>,
+    pub sub_traits: HashMap<Id, HashSet<Id>>,
     pub idents: HashMap<PathBuf, IdentsByLine>,
+    // Macro invocations in each file.
+    pub macro_refs: HashMap<PathBuf, Vec<MacroRef>>,
+    // The invocations of each macro whose definition is known, as pairs of the
+    // span of the definition and of the invocations, by the definition's file.
+    pub macro_expansions: HashMap<PathBuf, Vec<(Span, Vec<Span>)>>,
 
     pub root_id: Option<Id>,
     pub timestamp: SystemTime,
@@ -60,16 +94,16 @@ pub struct PerCrateAnalysis {
 }
 
 #[derive(Debug, Clone)]
+#[cfg_attr(feature = "derive", derive(Serialize, Deserialize))]
 pub enum Ref {
     // The common case - a reference to a single definition.
     Id(Id),
     // Two defs contribute to a single reference, occurs in the field name
     // shorthand, maybe other places.
     Double(Id, Id),
-    // Multiple ids, we record only the first def we found, plus a count of defs.
-    // Should only happen due to generated code which has not been well-filtered
-    // by the compiler.
-    Multi(Id, usize),
+    // More than two defs, e.g., due to generated code which has not been
+    // well-filtered by the compiler.
+    Multi(Vec<Id>),
 }
 
 impl Ref {
@@ -77,20 +111,37 @@ impl Ref {
         match *self {
             Ref::Id(id) => id,
             Ref::Double(id, _) => id,
-            Ref::Multi(id, _) => id,
+            Ref::Multi(ref ids) => ids[0],
         }
     }
 
+    // All the ids of this reference, in the order they were recorded.
+    pub fn ids(&self) -> Vec<Id> {
+        match *self {
+            Ref::Id(id) => vec![id],
+            Ref::Double(id1, id2) => vec![id1, id2],
+            Ref::Multi(ref ids) => ids.clone(),
+        }
+    }
+
+    // The same def is often recorded more than once for a span, e.g., for
+    // each derived impl, so ids are only added once.
     pub fn add_id(&self, def_id: Id) -> Ref {
+        if self.ids().contains(&def_id) {
+            return self.clone();
+        }
         match *self {
             Ref::Id(id) => Ref::Double(id, def_id),
-            Ref::Double(id, _) => Ref::Multi(id, 3),
-            Ref::Multi(id, n) => Ref::Multi(id, n + 1),
+            Ref::Double(id1, id2) => Ref::Multi(vec![id1, id2, def_id]),
+            Ref::Multi(ref ids) => {
+                Ref::Multi(ids.iter().cloned().chain(iter::once(def_id)).collect())
+            }
         }
     }
 }
 
 #[derive(Debug, Clone)]
+#[cfg_attr(feature = "derive", derive(Serialize, Deserialize))]
 pub struct Def {
     pub kind: DefKind,
     pub span: Span,
@@ -98,9 +149,14 @@ pub struct Def {
     pub qualname: String,
     pub distro_crate: bool,
     pub parent: Option<Id>,
+    /// The declaration this def implements, e.g., the trait method for a
+    /// method in a trait impl.
+    pub decl_id: Option<Id>,
     pub value: String,
     pub docs: String,
-    // pub sig: Option<Signature>,
+    /// Only recorded if the data was generated with signatures enabled in
+    /// the save-analysis config.
+    pub sig: Option<Signature>,
 }
 
 pub type IdentsByLine = BTreeMap<Row<ZeroIndexed>, IdentsByColumn>;
@@ -113,6 +169,7 @@ pub type IdentsByColumn = BTreeMap<Column<ZeroIndexed>, IdentBound>;
 /// we have to build the whole index for every file (which is a lot for a large
 /// project), whereas we only get idents a few at a time and not very often.
 #[derive(new, Clone, Debug)]
+#[cfg_attr(feature = "derive", derive(Serialize, Deserialize))]
 pub struct IdentBound {
     pub column_end: Column<ZeroIndexed>,
     pub id: Id,
@@ -120,6 +177,7 @@ pub struct IdentBound {
 }
 
 #[derive(Copy, Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "derive", derive(Serialize, Deserialize))]
 pub enum IdentKind {
     Def,
     Ref,
@@ -136,17 +194,18 @@ pub struct Ident {
     pub kind: IdentKind,
 }
 
+/// The text of a def's signature, e.g., `fn foo(: Bar) -> Baz {}`, with the
+/// defs and refs in it given by byte offsets into the text.
 #[derive(Debug, Clone)]
+#[cfg_attr(feature = "derive", derive(Serialize, Deserialize))]
 pub struct Signature {
-    pub span: Span,
     pub text: String,
-    pub ident_start: u32,
-    pub ident_end: u32,
     pub defs: Vec<SigElement>,
     pub refs: Vec<SigElement>,
 }
 
 #[derive(Debug, Clone)]
+#[cfg_attr(feature = "derive", derive(Serialize, Deserialize))]
 pub struct SigElement {
     pub id: Id,
     pub start: usize,
@@ -154,25 +213,69 @@ pub struct SigElement {
 }
 
 #[derive(Debug)]
+#[cfg_attr(feature = "derive", derive(Serialize, Deserialize))]
 pub struct Glob {
     pub value: String,
 }
 
+#[derive(Debug)]
+#[cfg_attr(feature = "derive", derive(Serialize, Deserialize))]
+pub struct Import {
+    pub span: Span,
+    pub alias_span: Option<Span>,
+    pub def_id: Id,
+}
+
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "derive", derive(Serialize, Deserialize))]
+pub struct MacroRef {
+    // The span of the whole invocation, e.g., `foo!(bar)`.
+    pub span: Span,
+    pub qualname: String,
+    // The span of the macro's definition, `None` for built-in macros.
+    pub callee_span: Option<Span>,
+}
+
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "derive", derive(Serialize, Deserialize))]
+pub struct Impl {
+    pub self_id: Id,
+    pub span: Span,
+    // True for impls of the form `impl<T> Trait for T`.
+    pub blanket: bool,
+}
+
+/// Returns `true` if `inner` is within `outer`.
+pub(crate) fn span_contains(outer: &Span, inner: &Span) -> bool {
+    let (outer_range, inner_range) = (&outer.range, &inner.range);
+    outer.file == inner.file
+        && (outer_range.row_start, outer_range.col_start)
+            <= (inner_range.row_start, inner_range.col_start)
+        && (inner_range.row_end, inner_range.col_end) <= (outer_range.row_end, outer_range.col_end)
+}
+
 impl PerCrateAnalysis {
     pub fn new(timestamp: SystemTime, path: Option<PathBuf>) -> PerCrateAnalysis {
-        let empty_fst = fst::Map::from_iter(iter::empty::<(String, u64)>()).unwrap();
         PerCrateAnalysis {
             def_id_for_span: HashMap::new(),
             defs: HashMap::new(),
             defs_per_file: HashMap::new(),
             children: HashMap::new(),
             def_names: HashMap::new(),
-            def_fst: empty_fst,
+            def_fst: empty_fst(),
             def_fst_values: Vec::new(),
             ref_spans: HashMap::new(),
             globs: HashMap::new(),
             impls: HashMap::new(),
+            trait_impls: HashMap::new(),
+            implementations: HashMap::new(),
+            imports: HashMap::new(),
+            reexports: HashMap::new(),
+            super_traits: HashMap::new(),
+            sub_traits: HashMap::new(),
             idents: HashMap::new(),
+            macro_refs: HashMap::new(),
+            macro_expansions: HashMap::new(),
             root_id: None,
             timestamp,
             path,
@@ -180,6 +283,21 @@ impl PerCrateAnalysis {
         }
     }
 
+    // Rebuilds the symbol search index from `def_names`, e.g., after the data
+    // has been deserialized.
+    #[cfg(feature = "derive")]
+    pub(crate) fn rebuild_def_index(&mut self) {
+        let defs_to_index = self
+            .def_names
+            .iter()
+            .filter(|(name, _)| !name.is_empty())
+            .flat_map(|(name, ids)| ids.iter().map(move |id| (name.to_lowercase(), *id)))
+            .collect();
+        let (def_fst, def_fst_values) = build_index(defs_to_index);
+        self.def_fst = def_fst;
+        self.def_fst_values = def_fst_values;
+    }
+
     // Returns true if there is a def in this crate with the same crate-local id
     // and span as `def`.
     pub(crate) fn has_congruent_def(&self, local_id: u32, span: &Span) -> bool {
@@ -190,6 +308,54 @@ impl PerCrateAnalysis {
         }
     }
 
+    // Returns the innermost item in `span.file` containing the start of `span`,
+    // walking up its parents until one of `kinds` is found.
+    //
+    // Save-analysis only records the span of an item's name (except for file
+    // modules, which span the whole file), so an item is considered to extend
+    // from its name up to the line of its next sibling, or to the end of its
+    // parent. Positions before an item's name (e.g., whitespace or keywords)
+    // therefore belong to the enclosing item. Methods of inherent impls have
+    // no parent, so they are taken to extend up to the next such method (or
+    // the end of the file), with the later items in between taking precedence.
+    // Given `text`, the source of `span.file`, items with a block or ending in
+    // `;` end there instead, so that, e.g., blank lines after a function
+    // belong to the enclosing module.
+    fn enclosing_def(
+        &self,
+        span: &Span,
+        kinds: &[DefKind],
+        text: Option<&str>,
+    ) -> Option<(Id, Def)> {
+        let (id, def) = self.item_extents(&span.file, text).enclosing_def(span, kinds)?;
+        Some((id, def.clone()))
+    }
+
+    // The extents of the items in `file`, to find the items enclosing several
+    // spans without computing them again (see `enclosing_def`).
+    fn item_extents(&self, file: &Path, text: Option<&str>) -> ItemExtents<'_> {
+        let items: Vec<(Id, &Def)> = self
+            .defs_per_file
+            .get(file)
+            .into_iter()
+            .flatten()
+            .filter_map(|id| self.defs.get(id).map(|def| (*id, def)))
+            .filter(|&(_, def)| {
+                def.parent.is_some() || def.kind == DefKind::Mod || def.kind == DefKind::Method
+            })
+            .collect();
+
+        let text = text.map(blank_comments_and_literals);
+        let extents = items
+            .iter()
+            .map(|&(id, def)| {
+                let text_end = text.as_ref().and_then(|text| item_text_end(def, text));
+                (id, item_start(def), item_end(def, &items), text_end)
+            })
+            .collect();
+        ItemExtents { analysis: self, extents }
+    }
+
     // Returns all identifiers which overlap with `span`. There is no guarantee about
     // the ordering of identifiers in the result, but they will probably be roughly
     // in order of appearance.
@@ -233,6 +399,148 @@ impl PerCrateAnalysis {
     }
 }
 
+fn empty_fst() -> fst::Map<Vec<u8>> {
+    fst::Map::from_iter(iter::empty::<(String, u64)>()).unwrap()
+}
+
+type Position = (Row<ZeroIndexed>, Column<ZeroIndexed>);
+
+// The items of a file, each with its start, its end (exclusive, `None` for the
+// end of the file) and, if known, the position of the `;` or `}` ending it.
+struct ItemExtents<'a> {
+    analysis: &'a PerCrateAnalysis,
+    extents: Vec<(Id, Position, Option<Position>, Option<Position>)>,
+}
+
+impl<'a> ItemExtents<'a> {
+    fn enclosing_def(&self, span: &Span, kinds: &[DefKind]) -> Option<(Id, &'a Def)> {
+        let pos = (span.range.row_start, span.range.col_start);
+        let (mut id, _, _, _) = self
+            .extents
+            .iter()
+            .filter(|&&(_, start, end, text_end)| {
+                start <= pos
+                    && end.is_none_or(|end| pos < end)
+                    && text_end.is_none_or(|end| pos <= end)
+            })
+            .max_by_key(|&&(_, start, _, _)| start)?;
+
+        let mut def = self.analysis.defs.get(&id)?;
+        while !kinds.contains(&def.kind) {
+            id = def.parent?;
+            def = self.analysis.defs.get(&id)?;
+        }
+        Some((id, def))
+    }
+}
+
+const CALLABLE_KINDS: &[DefKind] = &[DefKind::Function, DefKind::Method];
+
+// The kinds of items which end with their block or with a `;`.
+const BLOCK_KINDS: &[DefKind] = &[
+    DefKind::Mod,
+    DefKind::Struct,
+    DefKind::Tuple,
+    DefKind::Enum,
+    DefKind::Union,
+    DefKind::Trait,
+    DefKind::Function,
+    DefKind::Method,
+];
+
+// The kinds of defs which can be dead code on their own.
+const ITEM_KINDS: &[DefKind] = &[
+    DefKind::Enum,
+    DefKind::Tuple,
+    DefKind::Struct,
+    DefKind::Union,
+    DefKind::Trait,
+    DefKind::Function,
+    DefKind::Method,
+    DefKind::Type,
+    DefKind::Static,
+    DefKind::Const,
+];
+
+// Items of trait impls are used through the trait. Not all of them have a
+// `decl_id`, e.g., associated consts, but their qualnames name the trait.
+fn is_trait_impl_item(def: &Def) -> bool {
+    def.decl_id.is_some() || def.qualname.contains(" as ")
+}
+
+// Sorts calls by position and removes duplicates (references can be recorded
+// more than once, e.g., in macro arguments).
+fn sort_calls(calls: &mut Vec<(Id, Span)>) {
+    calls.sort_by_key(|(id, span)| {
+        (span.file.clone(), span.range.row_start, span.range.col_start, *id)
+    });
+    calls.dedup();
+}
+
+fn item_start(def: &Def) -> Position {
+    (def.span.range.row_start, def.span.range.col_start)
+}
+
+fn is_file_mod(def: &Def) -> bool {
+    let range = &def.span.range;
+    def.kind == DefKind::Mod
+        && (range.row_start != range.row_end
+            || (range.col_end.0 - range.col_start.0) as usize != def.name.len())
+}
+
+// The end of an item, as approximated by `PerCrateAnalysis::enclosing_def`.
+// `None` means the item extends to the end of the file.
+fn item_end(def: &Def, items: &[(Id, &Def)]) -> Option<Position> {
+    let range = &def.span.range;
+    if is_file_mod(def) {
+        return Some((range.row_end, range.col_end));
+    }
+
+    let next_sibling = items
+        .iter()
+        .filter(|&&(_, d)| d.parent == def.parent && item_start(d) > item_start(def))
+        .map(|&(_, d)| (d.span.range.row_start, Column::new_zero_indexed(0)))
+        .min();
+    next_sibling.or_else(|| {
+        let &(_, parent) = items.iter().find(|&&(id, _)| Some(id) == def.parent)?;
+        item_end(parent, items)
+    })
+}
+
+// The position of the `;` or of the `}` closing the block which ends `def` in
+// `text`, the source of its file with comments and literals blanked out. `None`
+// for kinds of items without such an end, or if it isn't found.
+fn item_text_end(def: &Def, text: &str) -> Option<Position> {
+    if !BLOCK_KINDS.contains(&def.kind) || is_file_mod(def) {
+        return None;
+    }
+    let start = item_start(def);
+    let chars = text.split('\n').enumerate().skip(start.0 .0 as usize).flat_map(|(row, line)| {
+        let row = Row::new_zero_indexed(row as u32);
+        line.chars()
+            .enumerate()
+            .map(move |(col, c)| ((row, Column::new_zero_indexed(col as u32)), c))
+    });
+
+    let (mut brackets, mut braces) = (0, 0);
+    for (pos, c) in chars.filter(|&(pos, _)| pos >= start) {
+        match c {
+            '(' | '[' => brackets += 1,
+            ')' | ']' => brackets -= 1,
+            ';' if brackets == 0 && braces == 0 => return Some(pos),
+            '{' => braces += 1,
+            '}' => {
+                braces -= 1;
+                if braces == 0 {
+                    return Some(pos);
+                }
+            }
+            _ => {}
+        }
+    }
+    None
+}
+
 impl Analysis {
     pub fn new() -> Analysis {
         Analysis {
@@ -257,6 +565,21 @@ impl Analysis {
         self.per_crate.insert(crate_id, per_crate);
     }
 
+    /// Removes references from other crates to definitions of `crate_id`
+    /// which no longer exist, e.g., after the crate has been re-lowered.
+    pub fn remove_dangling_refs(&mut self, crate_id: &CrateId) {
+        let (crate_num, defs) = match self.per_crate.get(crate_id) {
+            Some(c) => (c.global_crate_num, c.defs.keys().cloned().collect::<HashSet<_>>()),
+            None => return,
+        };
+        let is_live = |id: &Id| id.crate_num() != crate_num || defs.contains(id);
+
+        for (_, c) in self.per_crate.iter_mut().filter(|&(id, _)| id != crate_id) {
+            c.ref_spans.retain(|id, _| is_live(id));
+            c.def_id_for_span.retain(|_, r| is_live(&r.some_id()));
+        }
+    }
+
     pub fn has_def(&self, id: Id) -> bool {
         self.per_crate.values().any(|c| c.defs.contains_key(&id))
     }
@@ -301,6 +624,42 @@ impl Analysis {
         self.ref_for_span(span).map(|r| r.some_id())
     }
 
+    /// Returns the macro invocation whose span contains `span`, if any.
+    pub fn macro_ref_for_span(&self, span: &Span) -> Option<MacroRef> {
+        self.for_each_crate(|c| {
+            c.macro_refs.get(&span.file)?.iter().find(|m| span_contains(&m.span, span)).cloned()
+        })
+    }
+
+    /// Returns the spans of the invocations of the macros whose definition
+    /// contains `span`, i.e., of the expansions `span` is part of.
+    pub fn expansion_sites(&self, span: &Span) -> Vec<Span> {
+        self.for_all_crates(|c| {
+            Some(
+                c.macro_expansions
+                    .get(&span.file)?
+                    .iter()
+                    .filter(|(definition, _)| span_contains(definition, span))
+                    .flat_map(|(_, invocations)| invocations.iter().cloned())
+                    .collect(),
+            )
+        })
+    }
+
+    /// Returns the macro invocations in `file` whose definition is known.
+    pub fn macro_refs_in_file(&self, file: &Path) -> Vec<MacroRef> {
+        self.for_all_crates(|c| {
+            Some(
+                c.macro_refs
+                    .get(file)?
+                    .iter()
+                    .filter(|m| m.callee_span.is_some())
+                    .cloned()
+                    .collect(),
+            )
+        })
+    }
+
     pub fn ref_for_span(&self, span: &Span) -> Option<Ref> {
         self.for_each_crate(|c| c.def_id_for_span.get(span).cloned())
     }
@@ -405,6 +764,188 @@ impl Analysis {
         })
     }
 
+    // Returns the spans of imports in `file` whose imported def is not
+    // referenced elsewhere in that file. An import is only reported if it is
+    // unused in every crate it is part of.
+    pub fn unused_imports(&self, file: &Path) -> Vec<Span> {
+        let mut used = HashSet::new();
+        let mut unused = HashSet::new();
+        for c in self.per_crate.values() {
+            let imports = match c.imports.get(file) {
+                Some(imports) => imports,
+                None => continue,
+            };
+            let import_spans: HashSet<&Span> = imports
+                .iter()
+                .flat_map(|i| iter::once(&i.span).chain(i.alias_span.as_ref()))
+                .collect();
+            for i in imports {
+                let is_used = c.ref_spans.get(&i.def_id).is_some_and(|spans| {
+                    spans.iter().any(|s| s.file == file && !import_spans.contains(s))
+                });
+                if is_used {
+                    used.insert(i.span.clone());
+                } else {
+                    unused.insert(i.span.clone());
+                }
+            }
+        }
+
+        let mut result: Vec<_> = unused.difference(&used).cloned().collect();
+        result.sort();
+        result
+    }
+
+    // Returns the items in files under `dir` which are not referenced by any
+    // crate, ordered by position. A def recorded by several crates (e.g., the
+    // lib and test crates of a package) is only reported if none of its ids is
+    // referenced.
+    pub fn unreferenced_defs(&self, dir: &Path) -> Vec<(Id, Def)> {
+        let referenced: HashSet<Id> = self
+            .per_crate
+            .values()
+            .flat_map(|c| c.ref_spans.iter().filter(|(_, spans)| !spans.is_empty()))
+            .map(|(id, _)| *id)
+            .collect();
+        let mut live_spans = HashSet::new();
+        let mut candidates = vec![];
+        for c in self.per_crate.values() {
+            for (id, def) in &c.defs {
+                if def.distro_crate || !def.span.file.starts_with(dir) {
+                    continue;
+                }
+                if referenced.contains(id) {
+                    live_spans.insert(def.span.clone());
+                } else if ITEM_KINDS.contains(&def.kind) && !is_trait_impl_item(def) {
+                    candidates.push((*id, def.clone()));
+                }
+            }
+        }
+
+        let mut result: Vec<_> =
+            candidates.into_iter().filter(|(_, def)| !live_spans.contains(&def.span)).collect();
+        result.sort_by(|(_, a), (_, b)| a.span.cmp(&b.span));
+        result.dedup_by(|(_, a), (_, b)| a.span == b.span);
+        result
+    }
+
+    /// Returns the innermost def of one of `kinds` (and its id) which contains
+    /// the start of `span`. If given, `text` is the source of `span.file`.
+    pub fn enclosing_def(
+        &self,
+        span: &Span,
+        kinds: &[DefKind],
+        text: Option<&str>,
+    ) -> Option<(Id, Def)> {
+        self.for_each_crate(|c| c.enclosing_def(span, kinds, text))
+    }
+
+    /// Returns the spans of the later bindings with the same name as the local
+    /// `id` in the same function, ordered by position.
+    pub fn shadows_of(&self, id: Id) -> Vec<Span> {
+        let def = match self.with_defs(id, Clone::clone) {
+            Some(def) if def.kind == DefKind::Local => def,
+            _ => return vec![],
+        };
+        let scope = |span: &Span| self.enclosing_def(span, CALLABLE_KINDS, None).map(|(id, _)| id);
+        let def_scope = scope(&def.span);
+
+        let ids = self.with_defs_per_file(&def.span.file, Clone::clone).unwrap_or_default();
+        let mut shadows: Vec<Span> = ids
+            .into_iter()
+            .filter_map(|id| self.with_defs(id, Clone::clone))
+            .filter(|other| {
+                other.kind == DefKind::Local
+                    && other.name == def.name
+                    && other.span.range.start() > def.span.range.start()
+            })
+            .map(|other| other.span)
+            .filter(|span| scope(span) == def_scope)
+            .collect();
+        shadows.sort();
+        shadows
+    }
+
+    /// Returns the functions and methods calling the one with `id`, with the
+    /// span of each call, ordered by position. Any reference to the function
+    /// from the body of another one counts as a call, e.g., also passing it
+    /// as a function pointer.
+    pub fn callers_of(&self, id: Id) -> Vec<(Id, Span)> {
+        let def_span = self.with_defs(id, |def| def.span.clone());
+        let refs = self.with_ref_spans(id, |refs| Some(refs.clone())).unwrap_or_default();
+        let mut callers: Vec<_> = refs
+            .into_iter()
+            .filter(|span| Some(span) != def_span.as_ref())
+            .filter_map(|span| {
+                let (caller, _) = self.enclosing_def(&span, CALLABLE_KINDS, None)?;
+                Some((caller, span))
+            })
+            .collect();
+        sort_calls(&mut callers);
+        callers
+    }
+
+    /// Returns the functions and methods called from the body of the one with
+    /// `id`, with the span of each call, ordered by position. Counterpart of
+    /// `callers_of`.
+    pub fn callees_of(&self, id: Id) -> Vec<(Id, Span)> {
+        let file = match self.with_defs(id, |def| def.span.file.clone()) {
+            Some(file) => file,
+            None => return vec![],
+        };
+        let refs = self.for_all_crates(|c| {
+            Some(
+                c.def_id_for_span
+                    .iter()
+                    .filter(|(span, _)| span.file == file)
+                    .map(|(span, r)| (span.clone(), r.ids()))
+                    .collect(),
+            )
+        });
+
+        // The refs in the function's body, found with the extents of the items
+        // of the crate defining it.
+        let refs: Vec<(Span, Vec<Id>)> = self
+            .for_each_crate(|c| {
+                c.defs.get(&id)?;
+                let extents = c.item_extents(&file, None);
+                let enclosed_by = |span: &Span| {
+                    extents.enclosing_def(span, CALLABLE_KINDS).map(|(caller, _)| caller)
+                };
+                Some(
+                    refs.iter()
+                        .filter(|(span, _)| enclosed_by(span) == Some(id))
+                        .cloned()
+                        .collect(),
+                )
+            })
+            .unwrap_or_default();
+
+        let mut callees: Vec<_> = refs
+            .into_iter()
+            .flat_map(|(span, ids)| ids.into_iter().map(move |callee| (callee, span.clone())))
+            .filter(|(callee, span)| {
+                // Leave out the definitions themselves, e.g., of nested functions.
+                self.with_defs(*callee, |def| {
+                    CALLABLE_KINDS.contains(&def.kind) && def.span != *span
+                })
+                .unwrap_or(false)
+            })
+            .collect();
+        sort_calls(&mut callees);
+        callees
+    }
+
+    /// Returns the trait implemented by the impl whose self type is within
+    /// `span`.
+    pub fn impl_trait(&self, span: &Span) -> Option<Id> {
+        self.for_each_crate(|c| {
+            c.trait_impls.iter().find_map(|(trait_id, impls)| {
+                Some(*trait_id).filter(|_| impls.iter().any(|imp| span_contains(span, &imp.span)))
+            })
+        })
+    }
+
     pub fn with_def_names<F, T>(&self, name: &str, f: F) -> Vec<T>
     where
         F: Fn(&Vec<Id>) -> Vec<T>,
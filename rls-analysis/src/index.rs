@@ -0,0 +1,91 @@
+//! Saving the lowered analysis data to disk and restoring it, so that the
+//! save-analysis files don't have to be read and lowered again when nothing
+//! has changed.
+
+use crate::analysis::Analysis;
+use crate::raw::CrateId;
+
+use serde::{Deserialize, Serialize};
+
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::hash::{Hash, Hasher};
+use std::io::{self, BufReader, BufWriter};
+use std::path::Path;
+
+// Must be bumped whenever the format of the lowered data changes.
+const INDEX_VERSION: u32 = 6;
+
+#[derive(Serialize, Deserialize)]
+struct Index<A> {
+    version: u32,
+    // Hash of the paths and modification times of the save-analysis files the
+    // data was lowered from.
+    inputs_hash: u64,
+    master_crate_map: Vec<(CrateId, u32)>,
+    analysis: A,
+}
+
+pub(crate) fn save(
+    path: &Path,
+    analysis: &Analysis,
+    master_crate_map: &HashMap<CrateId, u32>,
+) -> io::Result<()> {
+    let index = Index {
+        version: INDEX_VERSION,
+        inputs_hash: inputs_hash(analysis)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "missing analysis input"))?,
+        master_crate_map: master_crate_map.iter().map(|(k, v)| (k.clone(), *v)).collect(),
+        analysis,
+    };
+
+    // Write to a temporary file first, so that a concurrent or interrupted
+    // save never leaves a truncated index behind.
+    let tmp_path = path.with_extension("tmp");
+    let writer = BufWriter::new(File::create(&tmp_path)?);
+    serde_json::to_writer(writer, &index)?;
+    fs::rename(tmp_path, path)
+}
+
+// Returns `None` if the index can't be read, was written by a different
+// version, or any of its inputs have changed since it was written.
+pub(crate) fn load(path: &Path) -> Option<(Analysis, HashMap<CrateId, u32>)> {
+    let reader = BufReader::new(File::open(path).ok()?);
+    let index: Index<Analysis> = match serde_json::from_reader(reader) {
+        Ok(index) => index,
+        Err(e) => {
+            info!("could not read analysis index {}: {}", path.display(), e);
+            return None;
+        }
+    };
+
+    if index.version != INDEX_VERSION {
+        info!("analysis index {} has version {}, ignoring", path.display(), index.version);
+        return None;
+    }
+    if inputs_hash(&index.analysis) != Some(index.inputs_hash) {
+        info!("analysis index {} is stale, ignoring", path.display());
+        return None;
+    }
+
+    let mut analysis = index.analysis;
+    for per_crate in analysis.per_crate.values_mut() {
+        per_crate.rebuild_def_index();
+    }
+    Some((analysis, index.master_crate_map.into_iter().collect()))
+}
+
+// Crates without a path were passed in directly rather than read from disk,
+// so there is nothing to check them against.
+fn inputs_hash(analysis: &Analysis) -> Option<u64> {
+    let mut paths: Vec<_> = analysis.per_crate.values().filter_map(|c| c.path.as_ref()).collect();
+    paths.sort();
+
+    let mut hasher = DefaultHasher::new();
+    for path in paths {
+        path.hash(&mut hasher);
+        fs::metadata(path).and_then(|m| m.modified()).ok()?.hash(&mut hasher);
+    }
+    Some(hasher.finish())
+}
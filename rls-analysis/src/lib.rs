@@ -9,6 +9,8 @@ extern crate rls_data as data;
 extern crate rls_span as span;
 
 mod analysis;
+#[cfg(feature = "derive")]
+mod index;
 mod listings;
 mod loader;
 mod lowering;
@@ -18,19 +20,27 @@ mod symbol_query;
 mod test;
 mod util;
 
-use analysis::Analysis;
-pub use analysis::{Def, Ident, IdentKind, Ref};
+use analysis::{span_contains, Analysis};
+pub use analysis::{Def, Ident, IdentKind, Ref, SigElement, Signature};
 pub use loader::{AnalysisLoader, CargoAnalysisLoader, SearchDirectory, Target};
 pub use raw::{
     deserialize_crate_data, name_space_for_def_kind, read_analysis_from_files, read_crate_data,
     Crate, CrateId, DefKind,
 };
 pub use symbol_query::SymbolQuery;
+use util::blank_comments_and_literals;
 
-use std::collections::HashMap;
+#[cfg(feature = "derive")]
+use serde::{Deserialize, Serialize};
+
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::fmt::Debug;
+#[cfg(feature = "derive")]
+use std::fs;
 use std::path::{Path, PathBuf};
 use std::sync::Mutex;
+#[cfg(feature = "derive")]
+use std::sync::MutexGuard;
 use std::time::{Instant, SystemTime};
 use std::u64;
 
@@ -38,8 +48,24 @@ pub struct AnalysisHost<L: AnalysisLoader = CargoAnalysisLoader> {
     analysis: Mutex<Option<Analysis>>,
     master_crate_map: Mutex<HashMap<CrateId, u32>>,
     loader: Mutex<L>,
+    crate_exclusion: Mutex<Option<CrateExclusion>>,
+    #[cfg(feature = "derive")]
+    unloaded: Mutex<Option<Unloaded>>,
+}
+
+/// Where `AnalysisHost::unload` wrote the analysis data, and how to reload it
+/// if the index can't be used.
+#[cfg(feature = "derive")]
+struct Unloaded {
+    index: PathBuf,
+    path_prefix: PathBuf,
+    base_dir: PathBuf,
 }
 
+/// Decides from the source root of a crate whether to skip its data, see
+/// `AnalysisHost::set_crate_exclusion`.
+type CrateExclusion = Box<dyn Fn(&Path) -> bool + Send + Sync>;
+
 pub type AResult<T> = Result<T, AError>;
 
 #[derive(Debug, Copy, Clone, PartialEq, Eq)]
@@ -71,10 +97,61 @@ impl SymbolResult {
 
 pub type Span = span::Span<span::ZeroIndexed>;
 
+/// An impl of a trait, as returned by `AnalysisHost::trait_impls`.
+#[derive(Debug, Clone)]
+pub struct TraitImpl {
+    /// The implementing type.
+    pub self_id: Id,
+    pub self_name: String,
+    /// The span of the trait name in the impl header.
+    pub span: Span,
+    /// True for impls of the form `impl<T> Trait for T`.
+    pub blanket: bool,
+}
+
+/// A part of a def's signature, as returned by `AnalysisHost::signature_parts`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SigPart {
+    Text(String),
+    /// A reference to another def, e.g., the type of a parameter.
+    Link(String, Id),
+}
+
+/// An item of a file's outline, as returned by `AnalysisHost::outline`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct OutlineItem {
+    pub id: Id,
+    pub kind: DefKind,
+    pub name: String,
+    /// The span of the item's name.
+    pub span: Span,
+    /// The number of items of the outline which the item is nested in.
+    pub depth: u32,
+}
+
+/// A variant of an enum, as returned by `AnalysisHost::enum_variants`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct EnumVariant {
+    pub id: Id,
+    pub name: String,
+    pub kind: VariantKind,
+    /// The names of the fields in order of declaration (numbers for tuple
+    /// variants).
+    pub fields: Vec<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VariantKind {
+    Unit,
+    Tuple,
+    Struct,
+}
+
 /// A common identifier for definitions, references etc. This is effectively a
 /// `DefId` with globally unique crate number (instead of a compiler generated
 /// crate-local number).
-#[derive(Copy, Clone, Eq, PartialEq, Debug, Hash, new)]
+#[derive(Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Debug, Hash, new)]
+#[cfg_attr(feature = "derive", derive(Serialize, Deserialize))]
 pub struct Id(u64);
 
 impl Id {
@@ -83,6 +160,10 @@ impl Id {
         // then index for least significant bits.
         Id((u64::from(crate_id) << 32) | u64::from(local_id))
     }
+
+    fn crate_num(self) -> u32 {
+        (self.0 >> 32) as u32
+    }
 }
 
 /// Used to indicate a missing index in the Id.
@@ -100,13 +181,120 @@ macro_rules! def_span {
     };
 }
 
+/// Sorts defs by the spans of their definitions, removing duplicates (e.g., a
+/// type with several impls of a generic trait).
+fn sorted_by_span(mut defs: Vec<(Id, Span)>) -> Vec<(Id, Span)> {
+    defs.sort_by(|(_, a), (_, b)| a.cmp(b));
+    defs.dedup_by_key(|(id, _)| *id);
+    defs
+}
+
+/// Returns the span of the definition referred to at `span` or, if there is
+/// none and `span` is in a macro invocation, the span of the macro definition.
+fn def_or_macro_span(analysis: &Analysis, span: &Span) -> Option<Span> {
+    analysis
+        .def_id_for_span(span)
+        .and_then(|id| def_span!(analysis, id))
+        .or_else(|| analysis.macro_ref_for_span(span)?.callee_span)
+}
+
+/// What `find_all_refs` does with references inside macro definitions, i.e.,
+/// references made by each expansion of the macro.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExpansionRefs {
+    /// Returns them as they are, e.g., to rename them.
+    Keep,
+    /// Replaces them with the spans of the macro's invocations.
+    Remap,
+    /// Leaves them out.
+    Exclude,
+}
+
+/// Options for `AnalysisHost::find_all_refs`. The default finds the references
+/// in the order they happen to be stored in, without the declaration.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FindRefsOptions {
+    /// Includes the declaration as the first result.
+    pub include_decl: bool,
+    /// Returns no spans at all (not even the declaration) if any reference is
+    /// a reference to multiple defs. Note that for large numbers of refs, this
+    /// might take significantly longer to execute.
+    pub force_unique_spans: bool,
+    /// Orders the references by file and position (and removes duplicates).
+    pub sorted: bool,
+    /// What happens to references in macro definitions.
+    pub expansion_refs: ExpansionRefs,
+}
+
+impl Default for FindRefsOptions {
+    fn default() -> FindRefsOptions {
+        FindRefsOptions {
+            include_decl: false,
+            force_unique_spans: false,
+            sorted: false,
+            expansion_refs: ExpansionRefs::Keep,
+        }
+    }
+}
+
+/// Applies `expansion_refs` to the references in macro definitions in `refs`.
+/// References written in the arguments of an invocation are kept as they are.
+fn map_expansion_refs(
+    analysis: &Analysis,
+    refs: Vec<Span>,
+    expansion_refs: ExpansionRefs,
+) -> Vec<Span> {
+    if expansion_refs == ExpansionRefs::Keep {
+        return refs;
+    }
+
+    let mut seen = HashSet::new();
+    refs.into_iter()
+        .flat_map(|r| {
+            let sites = analysis.expansion_sites(&r);
+            match expansion_refs {
+                _ if sites.is_empty() => vec![r],
+                ExpansionRefs::Remap => sites,
+                _ => vec![],
+            }
+        })
+        // Several references in a definition map to the same invocations.
+        .filter(|r| seen.insert(r.clone()))
+        .collect()
+}
+
+/// Sorts `spans` by file, then start and end positions, and removes
+/// duplicates (e.g., references generated by the same macro invocation).
+fn sort_spans(spans: &mut Vec<Span>) {
+    let key = |s: &Span| {
+        (s.file.clone(), s.range.row_start, s.range.col_start, s.range.row_end, s.range.col_end)
+    };
+    spans.sort_by_key(key);
+    spans.dedup();
+}
+
 impl AnalysisHost<CargoAnalysisLoader> {
     pub fn new(target: Target) -> AnalysisHost {
         AnalysisHost {
             analysis: Mutex::new(None),
             master_crate_map: Mutex::new(HashMap::new()),
             loader: Mutex::new(CargoAnalysisLoader::new(target)),
+            crate_exclusion: Mutex::new(None),
+            #[cfg(feature = "derive")]
+            unloaded: Mutex::new(None),
+        }
+    }
+
+    /// Sets the profile whose save-analysis data is loaded from disk. If it
+    /// changes, the next reload is a hard one, since the data loaded so far
+    /// is for the previous profile.
+    pub fn set_target(&self, target: Target) -> AResult<()> {
+        let mut loader = self.loader.lock()?;
+        if loader.target != target {
+            loader.target = target;
+            loader.path_prefix = None;
         }
+        Ok(())
     }
 }
 
@@ -116,12 +304,54 @@ impl<L: AnalysisLoader> AnalysisHost<L> {
             analysis: Mutex::new(None),
             master_crate_map: Mutex::new(HashMap::new()),
             loader: Mutex::new(loader),
+            crate_exclusion: Mutex::new(None),
+            #[cfg(feature = "derive")]
+            unloaded: Mutex::new(None),
         }
     }
 
+    /// Skips the data of crates whose source root (the directory of the crate
+    /// root file) `exclude` returns `true` for, when it's next read or passed
+    /// in. Crates which are already loaded are kept until the next hard reload.
+    pub fn set_crate_exclusion(
+        &self,
+        exclude: impl Fn(&Path) -> bool + Send + Sync + 'static,
+    ) -> AResult<()> {
+        *self.crate_exclusion.lock()? = Some(Box::new(exclude));
+        Ok(())
+    }
+
+    /// Removes the crates excluded by `set_crate_exclusion` from `crates`.
+    fn without_excluded(
+        &self,
+        crates: Vec<raw::Crate>,
+        base_dir: &Path,
+    ) -> AResult<Vec<raw::Crate>> {
+        let exclusion = self.crate_exclusion.lock()?;
+        let exclude = match *exclusion {
+            Some(ref exclude) => exclude,
+            None => return Ok(crates),
+        };
+
+        Ok(crates
+            .into_iter()
+            .filter(|c| {
+                let root = match c.analysis.prelude {
+                    Some(ref prelude) => base_dir.join(&prelude.crate_root),
+                    None => return true,
+                };
+                let excluded = exclude(&root);
+                if excluded {
+                    info!("Excluding crate {} at {}", c.id.name, root.display());
+                }
+                !excluded
+            })
+            .collect())
+    }
+
     /// Reloads given data passed in `analysis`. This will first check and read
     /// on-disk data (just like `reload`). It then imports the data we're
-    /// passing in directly.
+    /// passing in directly, except for that of crates in `blacklist`.
     pub fn reload_from_analysis(
         &self,
         analysis: Vec<data::Analysis>,
@@ -131,10 +361,18 @@ impl<L: AnalysisLoader> AnalysisHost<L> {
     ) -> AResult<()> {
         self.reload_with_blacklist(path_prefix, base_dir, blacklist)?;
 
+        let is_blacklisted = |analysis: &data::Analysis| {
+            analysis
+                .prelude
+                .as_ref()
+                .is_some_and(|p| blacklist.iter().any(|name| name.as_ref() == p.crate_id.name))
+        };
         let crates: Vec<_> = analysis
             .into_iter()
+            .filter(|analysis| !is_blacklisted(analysis))
             .map(|analysis| raw::Crate::new(analysis, SystemTime::now(), None, None))
             .collect();
+        let crates = self.without_excluded(crates, base_dir)?;
 
         lowering::lower(crates, base_dir, self, |host, per_crate, id| {
             let mut a = host.analysis.lock()?;
@@ -143,6 +381,36 @@ impl<L: AnalysisLoader> AnalysisHost<L> {
         })
     }
 
+    /// Lowers new analysis data for a single, already loaded crate and swaps
+    /// it in place of the old data. Other crates are not re-read or re-lowered;
+    /// since the master crate map is kept, their references into the updated
+    /// crate stay valid, except for those to definitions which no longer exist,
+    /// which are dropped.
+    pub fn update_crate(
+        &self,
+        crate_id: &CrateId,
+        analysis: data::Analysis,
+        base_dir: &Path,
+    ) -> AResult<()> {
+        trace!("update_crate {:?} {:?}", crate_id, base_dir);
+        if analysis.prelude.as_ref().map(|p| &p.crate_id) != Some(crate_id) {
+            return Err(AError::Unclassified);
+        }
+
+        let path = self.with_analysis(|a| a.per_crate.get(crate_id).map(|c| c.path.clone()))?;
+        let krate = raw::Crate::new(analysis, SystemTime::now(), path, None);
+
+        lowering::lower(vec![krate], base_dir, self, |host, per_crate, id| {
+            let mut a = host.analysis.lock()?;
+            a.as_mut().unwrap().update(id, per_crate);
+            Ok(())
+        })?;
+
+        let mut a = self.analysis.lock()?;
+        a.as_mut().unwrap().remove_dangling_refs(crate_id);
+        Ok(())
+    }
+
     pub fn reload(&self, path_prefix: &Path, base_dir: &Path) -> AResult<()> {
         self.reload_with_blacklist(path_prefix, base_dir, &[] as &[&str])
     }
@@ -154,6 +422,8 @@ impl<L: AnalysisLoader> AnalysisHost<L> {
         blacklist: &[impl AsRef<str> + Debug],
     ) -> AResult<()> {
         trace!("reload_with_blacklist {:?} {:?} {:?}", path_prefix, base_dir, blacklist);
+        #[cfg(feature = "derive")]
+        drop(self.ensure_loaded()?);
         let empty = self.analysis.lock()?.is_none();
         if empty || self.loader.lock()?.needs_hard_reload(path_prefix) {
             return self.hard_reload_with_blacklist(path_prefix, base_dir, blacklist);
@@ -164,6 +434,7 @@ impl<L: AnalysisLoader> AnalysisHost<L> {
             let loader = self.loader.lock()?;
             read_analysis_from_files(&*loader, timestamps, blacklist)
         };
+        let raw_analysis = self.without_excluded(raw_analysis, base_dir)?;
 
         lowering::lower(raw_analysis, base_dir, self, |host, per_crate, id| {
             let mut a = host.analysis.lock()?;
@@ -188,12 +459,15 @@ impl<L: AnalysisLoader> AnalysisHost<L> {
         // then once we're done, we'll swap its data into self.
         let mut fresh_host = self.loader.lock()?.fresh_host();
         fresh_host.analysis = Mutex::new(Some(Analysis::new()));
+        // Keep the numbers of the known crates, so that ids don't change.
+        fresh_host.master_crate_map = Mutex::new(self.master_crate_map.lock()?.clone());
 
         {
             let mut fresh_loader = fresh_host.loader.lock().unwrap();
             fresh_loader.set_path_prefix(path_prefix); // TODO: Needed?
 
             let raw_analysis = read_analysis_from_files(&*fresh_loader, HashMap::new(), blacklist);
+            let raw_analysis = self.without_excluded(raw_analysis, base_dir)?;
             lowering::lower(raw_analysis, base_dir, &fresh_host, |host, per_crate, id| {
                 let mut a = host.analysis.lock()?;
                 a.as_mut().unwrap().update(id, per_crate);
@@ -216,26 +490,185 @@ impl<L: AnalysisLoader> AnalysisHost<L> {
             };
         }
 
-        swap_mutex_fields!(analysis, master_crate_map, loader);
+        {
+            swap_mutex_fields!(analysis, master_crate_map, loader);
+        }
+        // Any data dropped by `unload` is superseded by what we just loaded.
+        #[cfg(feature = "derive")]
+        drop(self.ensure_loaded()?);
 
         Ok(())
     }
 
+    /// Writes the currently loaded analysis data to `path`, so that it can be
+    /// restored later with `load_index`.
+    #[cfg(feature = "derive")]
+    pub fn save_index(&self, path: &Path) -> AResult<()> {
+        trace!("save_index {:?}", path);
+        let _unloaded = self.ensure_loaded()?;
+        let analysis = self.analysis.lock()?;
+        let analysis = analysis.as_ref().ok_or(AError::Unclassified)?;
+        let master_crate_map = self.master_crate_map.lock()?;
+        index::save(path, analysis, &master_crate_map).map_err(|e| {
+            info!("could not save analysis index {}: {}", path.display(), e);
+            AError::Unclassified
+        })
+    }
+
+    /// Restores analysis data written by `save_index`, skipping reading and
+    /// lowering the save-analysis files. If the index can't be read, was
+    /// written by a different version of this crate, or any of the files it was
+    /// built from have changed since, it is ignored and the data is reloaded as
+    /// by `hard_reload`.
+    ///
+    /// Returns true if the index was used. Crates which are not in the index at
+    /// all are not loaded; a subsequent `reload` will pick them up.
+    #[cfg(feature = "derive")]
+    pub fn load_index(&self, path: &Path, path_prefix: &Path, base_dir: &Path) -> AResult<bool> {
+        trace!("load_index {:?} {:?} {:?}", path, path_prefix, base_dir);
+        match index::load(path) {
+            Some((analysis, master_crate_map)) => {
+                let mut a = self.analysis.lock()?;
+                let mut m = self.master_crate_map.lock()?;
+                let mut loader = self.loader.lock()?;
+                loader.set_path_prefix(path_prefix);
+                *a = Some(analysis);
+                *m = master_crate_map;
+                Ok(true)
+            }
+            None => {
+                self.hard_reload(path_prefix, base_dir)?;
+                Ok(false)
+            }
+        }
+    }
+
+    /// Drops the loaded analysis data to reclaim memory, after writing it to
+    /// `index`. It is restored from there (or reloaded as by `hard_reload` if
+    /// that fails) as soon as it's needed again, blocking any callers until
+    /// then. Does nothing if there is no data loaded.
+    #[cfg(feature = "derive")]
+    pub fn unload(&self, index: &Path, path_prefix: &Path, base_dir: &Path) -> AResult<()> {
+        trace!("unload {:?}", index);
+        let mut unloaded = self.unloaded.lock()?;
+        let mut analysis = self.analysis.lock()?;
+        if let Some(ref a) = *analysis {
+            let master_crate_map = self.master_crate_map.lock()?;
+            index::save(index, a, &master_crate_map).map_err(|e| {
+                info!("could not save analysis index {}: {}", index.display(), e);
+                AError::Unclassified
+            })?;
+        } else {
+            return Ok(());
+        }
+
+        *analysis = None;
+        *unloaded = Some(Unloaded {
+            index: index.to_owned(),
+            path_prefix: path_prefix.to_owned(),
+            base_dir: base_dir.to_owned(),
+        });
+        Ok(())
+    }
+
     /// Note that `self.has_def()` =/> `self.goto_def().is_ok()`, since if the
     /// Def is in an api crate, there is no reasonable Span to jump to.
     pub fn has_def(&self, id: Id) -> bool {
-        match self.analysis.lock() {
-            Ok(a) => a.as_ref().unwrap().has_def(id),
-            _ => false,
-        }
+        self.with_analysis(|a| Some(a.has_def(id))).unwrap_or(false)
     }
 
     pub fn get_def(&self, id: Id) -> AResult<Def> {
         self.with_analysis(|a| a.with_defs(id, Clone::clone))
     }
 
+    /// Returns the span of the definition referred to at `span`. For the name
+    /// of a macro invocation, this is the span of the macro definition, except
+    /// for built-in macros, which have none.
     pub fn goto_def(&self, span: &Span) -> AResult<Span> {
-        self.with_analysis(|a| a.def_id_for_span(span).and_then(|id| def_span!(a, id)))
+        self.with_analysis(|a| def_or_macro_span(a, span))
+    }
+
+    /// Like `goto_def`, but resolves several spans at once. The result has an
+    /// entry for each span, which is `None` if there is no definition for it.
+    pub fn goto_defs(&self, spans: &[Span]) -> AResult<Vec<Option<Span>>> {
+        self.with_analysis(|a| Some(spans.iter().map(|span| def_or_macro_span(a, span)).collect()))
+    }
+
+    /// Like `goto_def`, but returns the spans of all definitions if the
+    /// reference at `span` refers to more than one, e.g., the field and the
+    /// local variable in a field init shorthand (`Foo { x }`).
+    pub fn goto_def_all(&self, span: &Span) -> AResult<Vec<Span>> {
+        self.with_analysis(|a| match a.ref_for_span(span) {
+            Some(r) => Some(r.ids().into_iter().filter_map(|id| def_span!(a, id)).collect()),
+            None => a.macro_ref_for_span(span)?.callee_span.map(|span| vec![span]),
+        })
+    }
+
+    /// Like `goto_def`, but if the definition implements a declaration (e.g.,
+    /// a method in a trait impl), returns the span of that declaration instead.
+    pub fn goto_decl(&self, span: &Span) -> AResult<Span> {
+        self.with_analysis(|a| {
+            let id = a.def_id_for_span(span)?;
+            let decl_id = a.with_defs_and_then(id, |def| def.decl_id);
+            decl_id.and_then(|decl_id| def_span!(a, decl_id)).or_else(|| def_span!(a, id))
+        })
+    }
+
+    /// Returns the spans of the defs implementing the declaration `id`, e.g.,
+    /// the methods implementing a trait method, in order of their position.
+    pub fn method_implementations(&self, id: Id) -> AResult<Vec<Span>> {
+        self.with_analysis(|a| {
+            let mut spans = a.for_all_crates(|c| {
+                c.implementations
+                    .get(&id)
+                    .map(|ids| ids.iter().filter_map(|id| def_span!(a, *id)).collect())
+            });
+            spans.sort();
+            Some(spans)
+        })
+    }
+
+    /// Returns the spans of the bindings which shadow the local variable
+    /// `id`: the later `let`s (and other bindings) of the same name in the same
+    /// function, ordered by position. Bindings in nested blocks count, since
+    /// they shadow it within the block. The data has no block scopes though,
+    /// so neither can a binding in a block which ended before be told apart.
+    /// Bindings in other functions, including nested ones, never count.
+    pub fn shadows_of(&self, id: Id) -> AResult<Vec<Span>> {
+        self.with_analysis(|a| Some(a.shadows_of(id)))
+    }
+
+    /// Returns the spans of the reads of the local with `id`, ordered by
+    /// position, i.e., its references other than the targets of assignments.
+    /// Save-analysis doesn't tell reads from writes, so we look at the source:
+    /// a reference followed by `=` is a write, while one followed by a compound
+    /// assignment (e.g., `x += 1`) is both and counts as a read, as do writes
+    /// through a field or an index (e.g., `x.y = 1`). Defs other than locals
+    /// have no reads. `load_text` returns the current source of a file, e.g.,
+    /// from the VFS.
+    pub fn reads_of(
+        &self,
+        id: Id,
+        load_text: impl Fn(&Path) -> Option<String>,
+    ) -> AResult<Vec<Span>> {
+        let refs = self.with_analysis(|a| {
+            if a.with_defs(id, |def| def.kind == DefKind::Local) != Some(true) {
+                return Some(vec![]);
+            }
+            let mut refs = a.with_ref_spans(id, |refs| Some(refs.clone())).unwrap_or_default();
+            refs.sort();
+            refs.dedup();
+            Some(refs)
+        })?;
+
+        let mut texts = HashMap::new();
+        Ok(refs
+            .into_iter()
+            .filter(|span| {
+                let text = texts.entry(span.file.clone()).or_insert_with(|| load_text(&span.file));
+                text.as_ref().is_none_or(|text| !AnalysisHost::<L>::is_assigned(text, span))
+            })
+            .collect())
     }
 
     pub fn for_each_child_def<F, T>(&self, id: Id, f: F) -> AResult<Vec<T>>
@@ -245,6 +678,27 @@ impl<L: AnalysisLoader> AnalysisHost<L> {
         self.with_analysis(|a| a.for_each_child(id, f))
     }
 
+    /// Returns the fields of the struct, union or enum variant `type_id` in
+    /// declaration order, as their names, types and ids. The fields of tuple
+    /// structs and variants are named by their position, i.e., `0`, `1`, etc.
+    pub fn fields_of(&self, type_id: Id) -> AResult<Vec<(String, String, Id)>> {
+        let mut fields: Vec<_> = self
+            .with_analysis(|a| a.for_each_child(type_id, |id, def| (id, def.clone())))?
+            .into_iter()
+            .filter(|(_, def)| def.kind == DefKind::Field)
+            .collect();
+        fields.sort_by(|(_, a), (_, b)| a.span.cmp(&b.span));
+        Ok(fields
+            .into_iter()
+            .enumerate()
+            .map(|(i, (id, def))| {
+                // Positional fields may be recorded without a name.
+                let name = if def.name.is_empty() { i.to_string() } else { def.name };
+                (name, def.value, id)
+            })
+            .collect())
+    }
+
     pub fn def_parents(&self, id: Id) -> AResult<Vec<(Id, String)>> {
         self.with_analysis(|a| {
             let mut result = vec![];
@@ -265,6 +719,112 @@ impl<L: AnalysisLoader> AnalysisHost<L> {
         })
     }
 
+    /// Returns the paths by which the def `id` can be imported into the file
+    /// `from`, shortest first: its qualified name, and the paths of its
+    /// re-exports (e.g., `std::collections::HashMap` for
+    /// `std::collections::hash::map::HashMap`). The paths start with `crate`
+    /// if the def is defined in a crate which `from` is part of, and with the
+    /// name of the def's crate otherwise. Returns nothing for defs which can't
+    /// be imported, such as methods.
+    pub fn import_paths(&self, id: Id, from: &Path) -> AResult<Vec<String>> {
+        self.with_analysis(|a| {
+            // The path of a def, if its qualified name is one and it can be
+            // imported. Qualified names are the name of the crate followed by
+            // the local qualified name, e.g., `::foo::Bar`.
+            let path_of = |id: Id| {
+                let (crate_id, c) = a.per_crate.iter().find(|(_, c)| c.defs.contains_key(&id))?;
+                let def = &c.defs[&id];
+                if let DefKind::Method | DefKind::Field | DefKind::Local = def.kind {
+                    return None;
+                }
+                let local = def.qualname.strip_prefix(crate_id.name.as_str())?;
+                let root =
+                    if c.defs_per_file.contains_key(from) { "crate" } else { &crate_id.name };
+                let path = format!("{}{}", root, local.trim_end_matches(':'));
+                let is_ident =
+                    |s: &str| !s.is_empty() && s.chars().all(|c| c.is_alphanumeric() || c == '_');
+                if path.split("::").all(is_ident) {
+                    Some(path)
+                } else {
+                    None
+                }
+            };
+
+            let mut paths: Vec<String> = a
+                .for_all_crates(|c| c.reexports.get(&id).cloned())
+                .into_iter()
+                .filter_map(|(module, name)| Some(format!("{}::{}", path_of(module)?, name)))
+                .chain(path_of(id))
+                .collect();
+            paths.sort_by_key(|path| (path.matches("::").count(), path.len(), path.clone()));
+            paths.dedup();
+            Some(paths)
+        })
+    }
+
+    /// Returns the path of the item at `span`, e.g., `["crate", "foo", "Bar"]`.
+    ///
+    /// The first segment is `crate` if the item is defined in the same crate
+    /// as `span`, and the name of the item's crate otherwise. Items in
+    /// function bodies get the function in their path, like in rustc's paths.
+    /// If the enclosing item isn't known (e.g., for a local variable in a
+    /// closure or block), it's represented by an `{anonymous}` segment.
+    pub fn qualified_path(&self, span: &Span) -> AResult<Vec<String>> {
+        self.with_analysis(|a| {
+            let id = a.def_id_for_span(span)?;
+            let crate_name = a
+                .per_crate
+                .iter()
+                .find(|(_, c)| c.defs.contains_key(&id))
+                .map(|(crate_id, _)| crate_id.name.clone())?;
+            let root = if a.local_def_id_for_span(span).is_some() {
+                "crate".to_owned()
+            } else {
+                crate_name.clone()
+            };
+
+            let mut result = vec![];
+            let mut next = id;
+            loop {
+                if a.per_crate.values().any(|c| c.root_id == Some(next)) {
+                    result.push(root);
+                    break;
+                }
+
+                let (name, qualname, parent) =
+                    a.with_defs(next, |def| (def.name.clone(), def.qualname.clone(), def.parent))?;
+                result.push(name);
+                match parent {
+                    Some(p) => next = p,
+                    None => {
+                        // The def isn't a child of a module, fall back to the
+                        // scope recorded in its qualname (`krate::foo::x`, or
+                        // `kratex$12` for locals). Newer compilers record a
+                        // `HirId` after the `$`, which may itself contain `::`.
+                        let qualname = qualname.strip_prefix(&crate_name).unwrap_or(&qualname);
+                        let qualname = qualname.split('$').next().unwrap_or(qualname);
+                        let mut scope: Vec<&str> = qualname.split("::").collect();
+                        scope.pop();
+                        match scope.first() {
+                            Some(&"") => {
+                                result.extend(scope[1..].iter().rev().map(|s| s.to_string()));
+                                result.push(root);
+                            }
+                            _ => {
+                                result.extend(scope.iter().rev().map(|s| s.to_string()));
+                                result.push("{anonymous}".to_owned());
+                            }
+                        }
+                        break;
+                    }
+                }
+            }
+
+            result.reverse();
+            Some(result)
+        })
+    }
+
     /// Returns the name of each crate in the program and the id of the root
     /// module of that crate.
     pub fn def_roots(&self) -> AResult<Vec<(Id, String)>> {
@@ -289,18 +849,10 @@ impl<L: AnalysisLoader> AnalysisHost<L> {
         self.with_analysis(|a| a.local_def_id_for_span(span))
     }
 
-    // `include_decl` means the declaration will be included as the first result.
-    // `force_unique_spans` means that if any reference is a reference to multiple
-    // defs, then we return an empty vector (in which case, even if include_decl
-    // is true, the result will be empty).
-    // Note that for large numbers of refs, if `force_unique_spans` is true, then
-    // this function might take significantly longer to execute.
-    pub fn find_all_refs(
-        &self,
-        span: &Span,
-        include_decl: bool,
-        force_unique_spans: bool,
-    ) -> AResult<Vec<Span>> {
+    /// Returns the spans of all references to the def at `span`, as selected
+    /// and ordered according to `options`.
+    pub fn find_all_refs(&self, span: &Span, options: FindRefsOptions) -> AResult<Vec<Span>> {
+        let FindRefsOptions { include_decl, force_unique_spans, sorted, expansion_refs } = options;
         let t_start = Instant::now();
         let result = self.with_analysis(|a| {
             a.def_id_for_span(span).map(|id| {
@@ -319,8 +871,18 @@ impl<L: AnalysisLoader> AnalysisHost<L> {
                     }
                     Some(refs.clone())
                 });
-                refs.map(|refs| decl.into_iter().chain(refs.into_iter()).collect::<Vec<_>>())
-                    .unwrap_or_else(|| vec![])
+                refs.map(|refs| {
+                    let mut refs = map_expansion_refs(a, refs, expansion_refs);
+                    if sorted {
+                        sort_spans(&mut refs);
+                        // Keep the decl first rather than in its sorted place.
+                        if let Some(ref decl) = decl {
+                            refs.retain(|r| r != decl);
+                        }
+                    }
+                    decl.into_iter().chain(refs.into_iter()).collect::<Vec<_>>()
+                })
+                .unwrap_or_else(|| vec![])
             })
         });
 
@@ -332,6 +894,65 @@ impl<L: AnalysisLoader> AnalysisHost<L> {
         result
     }
 
+    /// Like `find_all_refs` (sorted, with the declaration first and references
+    /// made by macro expansions remapped), but only returns the spans in
+    /// `file`. The item may be defined in another file. Unlike filtering the
+    /// result of `find_all_refs`, this only has to remap the references which
+    /// are in `file` or in the definitions of macros invoked in `file`.
+    pub fn find_refs_in_file(&self, span: &Span, file: &Path) -> AResult<Vec<Span>> {
+        self.with_analysis(|a| {
+            let id = a.def_id_for_span(span)?;
+            let decl = def_span!(a, id).filter(|decl| decl.file == file);
+            let invocations = a.macro_refs_in_file(file);
+            let remap = |r: &Span| {
+                let sites: Vec<_> = invocations
+                    .iter()
+                    .filter(|m| m.callee_span.iter().any(|c| span_contains(c, r)))
+                    .map(|m| m.span.clone())
+                    .collect();
+                if !sites.is_empty() {
+                    sites
+                } else if r.file == file && a.expansion_sites(r).is_empty() {
+                    vec![r.clone()]
+                } else {
+                    // In a macro which is only invoked elsewhere.
+                    vec![]
+                }
+            };
+            let mut refs = a
+                .with_ref_spans(id, |refs| Some(refs.iter().flat_map(&remap).collect::<Vec<_>>()))
+                .unwrap_or_default();
+            sort_spans(&mut refs);
+            if let Some(ref decl) = decl {
+                refs.retain(|r| r != decl);
+            }
+            Some(decl.into_iter().chain(refs).collect())
+        })
+    }
+
+    /// Like `find_all_refs` (sorted, with references made by macro expansions
+    /// remapped), but groups the references by file. The files are sorted by
+    /// path and, if `include_decl` is set, the declaration comes first in its
+    /// file.
+    pub fn refs_grouped(
+        &self,
+        span: &Span,
+        include_decl: bool,
+    ) -> AResult<Vec<(PathBuf, Vec<Span>)>> {
+        let options = FindRefsOptions {
+            include_decl,
+            sorted: true,
+            expansion_refs: ExpansionRefs::Remap,
+            ..FindRefsOptions::default()
+        };
+        let refs = self.find_all_refs(span, options)?;
+        let mut grouped: BTreeMap<PathBuf, Vec<Span>> = BTreeMap::new();
+        for r in refs {
+            grouped.entry(r.file.clone()).or_default().push(r);
+        }
+        Ok(grouped.into_iter().collect())
+    }
+
     pub fn show_type(&self, span: &Span) -> AResult<String> {
         self.with_analysis(|a| {
             a.def_id_for_span(span)
@@ -424,6 +1045,246 @@ impl<L: AnalysisLoader> AnalysisHost<L> {
         self.with_analysis(|a| Some(a.for_all_crates(|c| c.impls.get(&id).cloned())))
     }
 
+    /// Returns each impl of the trait `trait_id` together with its implementing type.
+    pub fn trait_impls(&self, trait_id: Id) -> AResult<Vec<TraitImpl>> {
+        self.with_analysis(|a| {
+            Some(a.for_all_crates(|c| {
+                c.trait_impls.get(&trait_id).map(|impls| {
+                    impls
+                        .iter()
+                        .map(|i| TraitImpl {
+                            self_id: i.self_id,
+                            self_name: a
+                                .with_defs(i.self_id, clone_field!(name))
+                                .unwrap_or_default(),
+                            span: i.span.clone(),
+                            blanket: i.blanket,
+                        })
+                        .collect()
+                })
+            }))
+        })
+    }
+
+    /// Returns the variants of the enum `enum_id` in order of declaration, or
+    /// an empty list if it isn't an enum.
+    pub fn enum_variants(&self, enum_id: Id) -> AResult<Vec<EnumVariant>> {
+        self.with_analysis(|a| {
+            let mut variants =
+                a.for_each_child(enum_id, |id, def| (id, def.clone())).unwrap_or_default();
+            variants.retain(|(_, def)| {
+                def.kind == DefKind::TupleVariant || def.kind == DefKind::StructVariant
+            });
+            variants.sort_by(|(_, a), (_, b)| a.span.cmp(&b.span));
+
+            Some(
+                variants
+                    .into_iter()
+                    .map(|(id, def)| {
+                        let mut fields = a
+                            .for_each_child(id, |_, field| (field.span.clone(), field.name.clone()))
+                            .unwrap_or_default();
+                        fields.sort();
+                        let fields: Vec<_> = fields.into_iter().map(|(_, name)| name).collect();
+
+                        // Save-analysis records unit variants as tuple variants.
+                        let kind = match def.kind {
+                            DefKind::StructVariant => VariantKind::Struct,
+                            _ if fields.is_empty() && !def.value.ends_with(')') => {
+                                VariantKind::Unit
+                            }
+                            _ => VariantKind::Tuple,
+                        };
+                        EnumVariant { id, name: def.name, kind, fields }
+                    })
+                    .collect(),
+            )
+        })
+    }
+
+    /// Returns the direct supertraits of the trait `trait_id`, together with
+    /// the spans of their definitions.
+    pub fn super_traits(&self, trait_id: Id) -> AResult<Vec<(Id, Span)>> {
+        self.with_analysis(|a| {
+            Some(a.for_all_crates(|c| {
+                c.super_traits.get(&trait_id).map(|ids| {
+                    ids.iter().filter_map(|id| def_span!(a, *id).map(|span| (*id, span))).collect()
+                })
+            }))
+        })
+    }
+
+    /// Returns the traits which have `trait_id` as a direct supertrait,
+    /// together with the spans of their definitions.
+    pub fn sub_traits(&self, trait_id: Id) -> AResult<Vec<(Id, Span)>> {
+        self.with_analysis(|a| {
+            Some(a.for_all_crates(|c| {
+                c.sub_traits.get(&trait_id).map(|ids| {
+                    ids.iter().filter_map(|id| def_span!(a, *id).map(|span| (*id, span))).collect()
+                })
+            }))
+        })
+    }
+
+    /// Returns the traits which the type `self_id` implements, each with the
+    /// span of the impl. Blanket impls (e.g., `impl<T> Trait for T`) are left
+    /// out.
+    pub fn implemented_traits(&self, self_id: Id) -> AResult<Vec<(Id, Span)>> {
+        self.with_analysis(|a| {
+            Some(a.for_all_crates(|c| {
+                let traits = c.trait_impls.iter().flat_map(|(trait_id, impls)| {
+                    impls
+                        .iter()
+                        .filter(|i| i.self_id == self_id && !i.blanket)
+                        .map(move |i| (*trait_id, i.span.clone()))
+                });
+                Some(traits.collect())
+            }))
+        })
+    }
+
+    /// Returns the direct supertypes of the type or trait `id` in a type
+    /// hierarchy, i.e., the supertraits of a trait or the traits implemented
+    /// by a type, together with the spans of their definitions.
+    pub fn supertypes(&self, id: Id) -> AResult<Vec<(Id, Span)>> {
+        if self.get_def(id)?.kind == DefKind::Trait {
+            return self.super_traits(id).map(sorted_by_span);
+        }
+        let traits = self.implemented_traits(id)?;
+        self.with_analysis(|a| {
+            let supertypes = traits
+                .into_iter()
+                .filter_map(|(trait_id, _)| def_span!(a, trait_id).map(|span| (trait_id, span)));
+            Some(sorted_by_span(supertypes.collect()))
+        })
+    }
+
+    /// Returns the direct subtypes of the trait `id` in a type hierarchy, i.e.,
+    /// its subtraits and the types implementing it, together with the spans of
+    /// their definitions. Types other than traits have no subtypes.
+    pub fn subtypes(&self, id: Id) -> AResult<Vec<(Id, Span)>> {
+        if self.get_def(id)?.kind != DefKind::Trait {
+            return Ok(vec![]);
+        }
+        let mut subtypes = self.sub_traits(id)?;
+        let impls = self.trait_impls(id)?;
+        self.with_analysis(|a| {
+            subtypes.extend(
+                impls
+                    .into_iter()
+                    .filter(|i| !i.blanket)
+                    .filter_map(|i| def_span!(a, i.self_id).map(|span| (i.self_id, span))),
+            );
+            Some(sorted_by_span(subtypes))
+        })
+    }
+
+    /// Returns the target type of the `Deref` impl whose self type is at
+    /// `impl_span` (see `implemented_traits`).
+    ///
+    /// Save-analysis doesn't record the associated types of impls, but it does
+    /// record the refs in them. So the target is the first type referred to
+    /// between the impl's header and its `deref` method, i.e., in
+    /// `type Target = ...;`, or failing that the type returned by `deref`.
+    pub fn deref_target(&self, impl_span: &Span) -> AResult<Id> {
+        self.with_analysis(|a| {
+            let file = &impl_span.file;
+            let header_row = impl_span.range.row_start;
+            let deref = a
+                .for_all_crates(|c| {
+                    let methods = c.defs_per_file.get(file)?.iter().filter_map(|id| {
+                        let def = c.defs.get(id)?;
+                        let is_deref = def.kind == DefKind::Method
+                            && def.name == "deref"
+                            && def.decl_id.is_some()
+                            && def.span.range.row_start > header_row;
+                        Some(def.span.clone()).filter(|_| is_deref)
+                    });
+                    Some(methods.collect())
+                })
+                .into_iter()
+                .min_by_key(|span| span.range.row_start)?;
+            // The method must belong to this impl rather than a later one.
+            let impl_rows = a.for_all_crates(|c| {
+                let impls = c.impls.values().flatten().filter(|span| span.file == *file);
+                Some(impls.map(|span| span.range.row_start).collect())
+            });
+            if impl_rows.into_iter().any(|row| row > header_row && row <= deref.range.row_start) {
+                return None;
+            }
+
+            // Refs to types (or to defs which aren't loaded) in the impl.
+            let mut refs = a.for_all_crates(|c| {
+                let refs = c.def_id_for_span.iter().filter(|(span, r)| {
+                    span.file == *file
+                        && a.with_defs(r.some_id(), |def| {
+                            matches!(
+                                def.kind,
+                                DefKind::Struct
+                                    | DefKind::Enum
+                                    | DefKind::Union
+                                    | DefKind::Type
+                                    | DefKind::Trait
+                            )
+                        })
+                        .unwrap_or(true)
+                });
+                Some(refs.map(|(span, r)| (span.range, r.some_id())).collect())
+            });
+            refs.sort_by_key(|(range, _)| (range.row_start, range.col_start));
+
+            let in_target = |range: &span::Range<span::ZeroIndexed>| {
+                range.row_start > header_row && range.row_start < deref.range.row_start
+            };
+            let in_return_type = |range: &span::Range<span::ZeroIndexed>| {
+                range.row_start == deref.range.row_start && range.col_start >= deref.range.col_end
+            };
+            refs.iter()
+                .find(|(range, _)| in_target(range))
+                .or_else(|| refs.iter().find(|(range, _)| in_return_type(range)))
+                .map(|(_, id)| *id)
+        })
+    }
+
+    /// Returns the signature of the def `id` split into plain text and links
+    /// to the defs it refers to, e.g., for rendering navigable signatures.
+    /// Refs to defs which aren't loaded are left as plain text, as are refs
+    /// overlapping an earlier one or not lying within the text. Returns an
+    /// empty list if the def has no signature.
+    pub fn signature_parts(&self, id: Id) -> AResult<Vec<SigPart>> {
+        self.with_analysis(|a| {
+            let sig = a.with_defs(id, |def| def.sig.clone())?;
+            let sig = match sig {
+                Some(sig) => sig,
+                None => return Some(vec![]),
+            };
+            let text = &sig.text;
+
+            let mut refs = sig.refs;
+            refs.sort_by_key(|r| (r.start, r.end));
+            let mut parts = vec![];
+            let mut offset = 0;
+            for r in refs {
+                let valid = offset <= r.start
+                    && r.start < r.end
+                    && text.get(r.start..r.end).is_some()
+                    && a.with_defs(r.id, |_| ()).is_some();
+                if !valid {
+                    continue;
+                }
+                if offset < r.start {
+                    parts.push(SigPart::Text(text[offset..r.start].to_owned()));
+                }
+                parts.push(SigPart::Link(text[r.start..r.end].to_owned(), r.id));
+                offset = r.end;
+            }
+            if offset < text.len() {
+                parts.push(SigPart::Text(text[offset..].to_owned()));
+            }
+            Some(parts)
+        })
+    }
+
     /// Search for a symbol name, returning a list of def_ids for that name.
     pub fn search_for_id(&self, name: &str) -> AResult<Vec<Id>> {
         self.with_analysis(|a| Some(a.with_def_names(name, Clone::clone)))
@@ -435,6 +1296,22 @@ impl<L: AnalysisLoader> AnalysisHost<L> {
         self.with_analysis(|a| Some(a.idents(span)))
     }
 
+    /// Calls `f` with each def of the crates named `crate_name` (there can be
+    /// more than one, e.g., several versions of a dependency), while holding
+    /// the analysis lock. Does nothing if there is no such crate.
+    pub fn for_each_def_in_crate<F>(&self, crate_name: &str, mut f: F) -> AResult<()>
+    where
+        F: FnMut(&Def),
+    {
+        self.with_analysis(|a| {
+            let crates = a.per_crate.iter().filter(|(id, _)| id.name == crate_name);
+            for (_, per_crate) in crates {
+                per_crate.defs.values().for_each(&mut f);
+            }
+            Some(())
+        })
+    }
+
     pub fn symbols(&self, file_name: &Path) -> AResult<Vec<SymbolResult>> {
         self.with_analysis(|a| {
             a.with_defs_per_file(file_name, |ids| {
@@ -445,12 +1322,210 @@ impl<L: AnalysisLoader> AnalysisHost<L> {
         })
     }
 
+    /// Returns the items defined in a file (leaving out fields and local
+    /// variables) in order of position, each with its nesting depth. Items at
+    /// the same position are ordered by depth, then by name.
+    pub fn outline(&self, file_name: &Path) -> AResult<Vec<OutlineItem>> {
+        self.with_analysis(|a| {
+            let ids = a.with_defs_per_file(file_name, Clone::clone)?;
+            let defs: HashMap<Id, Def> = ids
+                .into_iter()
+                .filter_map(|id| Some((id, a.with_defs(id, Clone::clone)?)))
+                .filter(|(_, def)| {
+                    !def.name.is_empty() && def.kind != DefKind::Field && def.kind != DefKind::Local
+                })
+                .collect();
+
+            // Not every def records its parent, but the parent records its
+            // children.
+            let mut parents: HashMap<Id, Id> = HashMap::new();
+            for (id, def) in &defs {
+                if let Some(parent) = def.parent {
+                    parents.insert(*id, parent);
+                }
+                for child in a.for_each_child(*id, |child, _| child).unwrap_or_default() {
+                    parents.entry(child).or_insert(*id);
+                }
+            }
+
+            let mut items: Vec<OutlineItem> = defs
+                .iter()
+                .map(|(id, def)| {
+                    let ancestors = std::iter::successors(parents.get(id), |id| parents.get(id));
+                    // Bounded in case the parents form a cycle.
+                    let depth = ancestors
+                        .take(defs.len())
+                        .filter(|ancestor| defs.contains_key(ancestor))
+                        .count();
+                    OutlineItem {
+                        id: *id,
+                        kind: def.kind,
+                        name: def.name.clone(),
+                        span: def.span.clone(),
+                        depth: depth as u32,
+                    }
+                })
+                .collect();
+            items.sort_by(|a, b| {
+                (&a.span, a.depth, &a.name, a.id).cmp(&(&b.span, b.depth, &b.name, b.id))
+            });
+            Some(items)
+        })
+    }
+
+    /// Returns the innermost definition of one of `kinds` which contains the
+    /// start of `span`, e.g., the function or module enclosing a cursor position.
+    /// `text` is the current source of `span.file`, which tells where items end.
+    pub fn enclosing_def(
+        &self,
+        span: &Span,
+        kinds: &[DefKind],
+        text: &str,
+    ) -> AResult<Option<Def>> {
+        self.with_analysis(|a| Some(a.enclosing_def(span, kinds, Some(text)).map(|(_, def)| def)))
+    }
+
+    /// Returns the functions and methods calling the one with `id`, each with
+    /// the span of the call. A caller with several calls has an entry for each.
+    pub fn callers_of(&self, id: Id) -> AResult<Vec<(Id, Span)>> {
+        self.with_analysis(|a| Some(a.callers_of(id)))
+    }
+
+    /// Returns the functions and methods called from the body of the one with
+    /// `id`, each with the span of the call. Both method calls and calls
+    /// through a path (UFCS) are included; calls of trait methods refer to the
+    /// method's declaration in the trait.
+    pub fn callees_of(&self, id: Id) -> AResult<Vec<(Id, Span)>> {
+        self.with_analysis(|a| Some(a.callees_of(id)))
+    }
+
+    /// Returns the trait implemented by the impl whose self type is within
+    /// `span`, e.g., the span of the impl's header.
+    pub fn impl_trait(&self, span: &Span) -> AResult<Id> {
+        self.with_analysis(|a| a.impl_trait(span))
+    }
+
+    /// Returns the items (methods, associated types and associated consts)
+    /// declared in the trait `trait_id`, in order of declaration.
+    pub fn trait_items(&self, trait_id: Id) -> AResult<Vec<Def>> {
+        self.with_analysis(|a| {
+            let trait_qualname = a.with_defs(trait_id, clone_field!(qualname))?;
+            // The methods of impls of the trait are its children too.
+            let mut items = a
+                .for_each_child(trait_id, |_, def| def.clone())
+                .unwrap_or_default()
+                .into_iter()
+                .filter(|def| def.qualname == format!("{}::{}", trait_qualname, def.name))
+                .collect::<Vec<_>>();
+            items.sort_by(|a, b| a.span.cmp(&b.span));
+            Some(items)
+        })
+    }
+
+    /// Returns the spans of the `use` imports in `file_name` which are not used
+    /// in that file. Glob imports and re-exports (`pub use`) are never reported.
+    /// `text` is the current source of the file, e.g., from the VFS.
+    pub fn unused_imports(&self, file_name: &Path, text: &str) -> AResult<Vec<Span>> {
+        let unused = self.with_analysis(|a| Some(a.unused_imports(file_name)))?;
+        if unused.is_empty() {
+            return Ok(unused);
+        }
+
+        // Save-analysis does not record the visibility of imports, so we look
+        // for re-exports in the source.
+        let text = blank_comments_and_literals(text);
+        Ok(unused.into_iter().filter(|span| !AnalysisHost::<L>::is_reexport(&text, span)).collect())
+    }
+
+    /// Returns the private and `pub(...)` items in files under `dir` which are
+    /// not referenced anywhere in the loaded analysis data, ordered by position.
+    /// `main` functions and trait items are never reported, nor are items whose
+    /// source can't be read. `load_text` returns the current source of a file,
+    /// e.g., from the VFS.
+    pub fn unreferenced_defs(
+        &self,
+        dir: &Path,
+        load_text: impl Fn(&Path) -> Option<String>,
+    ) -> AResult<Vec<Def>> {
+        let defs = self.with_analysis(|a| {
+            let in_trait = |def: &Def| {
+                def.parent.and_then(|p| a.with_defs(p, |p| p.kind == DefKind::Trait)) == Some(true)
+            };
+            Some(
+                a.unreferenced_defs(dir)
+                    .into_iter()
+                    .map(|(_, def)| def)
+                    .filter(|def| !(def.kind == DefKind::Function && def.name == "main"))
+                    .filter(|def| !in_trait(def))
+                    .collect::<Vec<_>>(),
+            )
+        })?;
+
+        // Save-analysis does not record the visibility of defs either.
+        let mut texts = HashMap::new();
+        Ok(defs
+            .into_iter()
+            .filter(|def| {
+                let text =
+                    texts.entry(def.span.file.clone()).or_insert_with(|| load_text(&def.span.file));
+                text.as_ref().is_some_and(|text| !AnalysisHost::<L>::is_public(text, &def.span))
+            })
+            .collect())
+    }
+
+    /// Returns the span of each glob import in `file_name`, together with the
+    /// names that glob brings into scope and which are used in the crate.
+    /// Names are resolved by the compiler, so re-exports are already followed.
+    pub fn glob_imports(&self, file_name: &Path) -> AResult<Vec<(Span, Vec<String>)>> {
+        self.with_analysis(|a| {
+            let mut result = a.for_all_crates(|c| {
+                Some(
+                    c.globs
+                        .iter()
+                        .filter(|&(span, _)| span.file == file_name)
+                        .map(|(span, glob)| {
+                            let names = glob
+                                .value
+                                .split(',')
+                                .map(|name| name.trim().to_owned())
+                                .filter(|name| !name.is_empty())
+                                .collect();
+                            (span.clone(), names)
+                        })
+                        .collect(),
+                )
+            });
+            result.sort_by(|a, b| a.0.cmp(&b.0));
+            result.dedup_by(|a, b| a.0 == b.0);
+            Some(result)
+        })
+    }
+
     pub fn doc_url(&self, span: &Span) -> AResult<String> {
         // e.g., https://doc.rust-lang.org/nightly/std/string/String.t.html
         self.with_analysis(|a| {
-            a.def_id_for_span(span).and_then(|id| {
-                a.with_defs_and_then(id, |def| AnalysisHost::<L>::mk_doc_url(def, a))
-            })
+            a.def_id_for_span(span)
+                .and_then(|id| {
+                    a.with_defs_and_then(id, |def| AnalysisHost::<L>::mk_doc_url(def, a))
+                })
+                .or_else(|| {
+                    // Built-in macros are documented in `std`.
+                    let macro_ref = a.macro_ref_for_span(span)?;
+                    if macro_ref.callee_span.is_some() {
+                        return None;
+                    }
+                    let name = macro_ref.qualname.rsplit("::").next()?;
+                    Some(format!("{}/std/macro.{}.html", a.doc_url_base, name))
+                })
+        })
+    }
+
+    /// Returns the URL of the documentation of the def with `id`, e.g., one
+    /// found by an earlier query. Only defs of distribution crates (such as
+    /// the standard library) have one.
+    pub fn doc_url_for_id(&self, id: Id) -> AResult<String> {
+        self.with_analysis(|a| {
+            a.with_defs_and_then(id, |def| AnalysisHost::<L>::mk_doc_url(def, a))
         })
     }
 
@@ -472,6 +1547,10 @@ impl<L: AnalysisLoader> AnalysisHost<L> {
     where
         F: FnOnce(&Analysis) -> Option<T>,
     {
+        // Holding on to `unloaded` keeps the data from being unloaded again
+        // before we're done with it.
+        #[cfg(feature = "derive")]
+        let _unloaded = self.ensure_loaded()?;
         let a = self.analysis.lock()?;
         if let Some(ref a) = *a {
             f(a).ok_or(AError::Unclassified)
@@ -480,6 +1559,21 @@ impl<L: AnalysisLoader> AnalysisHost<L> {
         }
     }
 
+    // Restores the data dropped by `unload`, unless it has been reloaded since.
+    // Returns the lock on `unloaded`, which must be taken before `analysis`.
+    #[cfg(feature = "derive")]
+    fn ensure_loaded(&self) -> AResult<MutexGuard<'_, Option<Unloaded>>> {
+        let mut unloaded = self.unloaded.lock()?;
+        if let Some(u) = unloaded.take() {
+            if self.analysis.lock()?.is_none() {
+                trace!("restoring unloaded analysis from {:?}", u.index);
+                self.load_index(&u.index, &u.path_prefix, &u.base_dir)?;
+            }
+            let _ = fs::remove_file(&u.index);
+        }
+        Ok(unloaded)
+    }
+
     fn mk_doc_url(def: &Def, analysis: &Analysis) -> Option<String> {
         if !def.distro_crate {
             return None;
@@ -529,6 +1623,57 @@ impl<L: AnalysisLoader> AnalysisHost<L> {
         }
     }
 
+    // Checks whether the `use` item containing `span` (which must be within
+    // `text`, with comments and literals blanked out) has a visibility modifier.
+    fn is_reexport(text: &str, span: &Span) -> bool {
+        let row = span.range.row_start.0 as usize;
+        let col = span.range.col_start.0 as usize;
+        let line_start: usize = text.split('\n').take(row).map(|l| l.len() + 1).sum();
+        let col_offset = text[line_start..].char_indices().nth(col).map_or(0, |(i, _)| i);
+        let prefix = &text[..(line_start + col_offset).min(text.len())];
+
+        let is_ident_char = |c: char| c.is_alphanumeric() || c == '_';
+        let use_start = prefix.rmatch_indices("use").map(|(i, _)| i).find(|&i| {
+            !prefix[..i].ends_with(is_ident_char)
+                && prefix[i + 3..].starts_with(|c: char| c.is_whitespace() || c == '{')
+        });
+        let before_use = match use_start {
+            Some(i) => prefix[..i].trim_end(),
+            None => return false,
+        };
+        // `pub(crate) use` etc.
+        let before_use = match before_use.strip_suffix(')') {
+            Some(s) => s.rsplit_once('(').map(|(a, _)| a).unwrap_or("").trim_end(),
+            None => before_use,
+        };
+        before_use.ends_with("pub") && !before_use[..before_use.len() - 3].ends_with(is_ident_char)
+    }
+
+    // Checks whether the reference at `span` (which must be within `text`) is
+    // the target of a plain assignment, i.e., is followed by `=` but not by
+    // `==` or `=>`.
+    fn is_assigned(text: &str, span: &Span) -> bool {
+        let line = text.split('\n').nth(span.range.row_end.0 as usize).unwrap_or("");
+        let col = span.range.col_end.0 as usize;
+        let rest = line.char_indices().nth(col).map_or("", |(i, _)| &line[i..]).trim_start();
+        rest.starts_with('=') && !rest[1..].starts_with(&['=', '>'][..])
+    }
+
+    // Checks whether the item whose name is at `span` (which must be within
+    // `text`) is declared `pub`, without a restriction such as `pub(crate)`.
+    fn is_public(text: &str, span: &Span) -> bool {
+        let line = text.split('\n').nth(span.range.row_start.0 as usize).unwrap_or("");
+        let col = span.range.col_start.0 as usize;
+        let prefix = line.char_indices().nth(col).map_or(line, |(i, _)| &line[..i]);
+
+        let is_ident_char = |c: char| c.is_alphanumeric() || c == '_';
+        prefix.match_indices("pub").any(|(i, _)| {
+            !prefix[..i].ends_with(is_ident_char)
+                && !prefix[i + 3..].starts_with(is_ident_char)
+                && !prefix[i + 3..].trim_start().starts_with('(')
+        })
+    }
+
     fn mk_src_url(def: &Def, path_prefix: Option<&PathBuf>, analysis: &Analysis) -> Option<String> {
         if !def.distro_crate {
             return None;
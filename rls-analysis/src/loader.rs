@@ -15,9 +15,12 @@ use crate::AnalysisHost;
 pub struct CargoAnalysisLoader {
     pub path_prefix: Option<PathBuf>,
     pub target: Target,
+    /// Directories searched in addition to the sysroot and Cargo's target
+    /// directory, e.g., where another build system writes analysis data.
+    pub extra_directories: Vec<SearchDirectory>,
 }
 
-#[derive(Debug, new)]
+#[derive(Debug, Clone, new)]
 pub struct SearchDirectory {
     pub path: PathBuf,
     // The directory searched must have spans re-written to be based on a new
@@ -25,33 +28,64 @@ pub struct SearchDirectory {
     // on the Rust CI, but live in the user's sysroot directory, this adjustment
     // (which happens in `lower_span`) means we have the new source location.
     pub prefix_rewrite: Option<PathBuf>,
+    /// If the same crate is found in several directories, the data from the
+    /// directory with the highest priority is used, e.g., the workspace's
+    /// target directory rather than the sysroot.
+    #[new(default)]
+    pub priority: u32,
+}
+
+impl SearchDirectory {
+    pub fn with_priority(self, priority: u32) -> SearchDirectory {
+        SearchDirectory { priority, ..self }
+    }
 }
 
 impl CargoAnalysisLoader {
     pub fn new(target: Target) -> CargoAnalysisLoader {
-        CargoAnalysisLoader { path_prefix: None, target }
+        CargoAnalysisLoader { path_prefix: None, target, extra_directories: vec![] }
+    }
+
+    /// Also searches `dir`, after the directories of the Cargo layout.
+    pub fn with_search_directory(mut self, dir: SearchDirectory) -> CargoAnalysisLoader {
+        self.extra_directories.push(dir);
+        self
     }
 }
 
 /// Allows to specify from where and which analysis files will be considered
 /// when reloading data to lower.
+///
+/// `CargoAnalysisLoader` finds the data written by Cargo (and the sysroot's
+/// data for the standard library). Other build systems can implement this
+/// trait to point an `AnalysisHost` at wherever they write the data, using
+/// `AnalysisHost::new_with_loader`.
 pub trait AnalysisLoader: Sized {
+    /// Returns true if the data needs to be reloaded from scratch when the
+    /// project is at `path_prefix`, e.g., because it moved.
     fn needs_hard_reload(&self, path_prefix: &Path) -> bool;
+    /// Returns an empty host with a copy of this loader, which is filled by a
+    /// hard reload.
     fn fresh_host(&self) -> AnalysisHost<Self>;
+    /// Sets the root of the project, which `search_directories` may be
+    /// relative to.
     fn set_path_prefix(&mut self, path_prefix: &Path);
     fn abs_path_prefix(&self) -> Option<PathBuf>;
     /// Returns every directory in which analysis files are to be considered.
+    /// If the same crate is in several, the one with the highest priority is
+    /// used (see `SearchDirectory::priority`).
     fn search_directories(&self) -> Vec<SearchDirectory>;
 }
 
 impl AnalysisLoader for CargoAnalysisLoader {
     fn needs_hard_reload(&self, path_prefix: &Path) -> bool {
-        self.path_prefix.as_ref().map_or(true, |p| p != path_prefix)
+        self.path_prefix.as_deref() != Some(path_prefix)
     }
 
     fn fresh_host(&self) -> AnalysisHost<Self> {
         AnalysisHost::new_with_loader(CargoAnalysisLoader {
             path_prefix: self.path_prefix.clone(),
+            extra_directories: self.extra_directories.clone(),
             ..CargoAnalysisLoader::new(self.target)
         })
     }
@@ -79,7 +113,13 @@ impl AnalysisLoader for CargoAnalysisLoader {
 
         let src_path = sys_root_path.join("lib").join("rustlib").join("src").join("rust");
 
-        vec![SearchDirectory::new(libs_path, Some(src_path)), SearchDirectory::new(deps_path, None)]
+        let mut dirs = vec![
+            SearchDirectory::new(libs_path, Some(src_path)),
+            // Crates rebuilt in the workspace shadow those in the sysroot.
+            SearchDirectory::new(deps_path, None).with_priority(1),
+        ];
+        dirs.extend(self.extra_directories.iter().cloned());
+        dirs
     }
 }
 
@@ -174,4 +214,32 @@ mod tests {
         let target_path = sys_root_path.join("lib").join("rustlib").join(&target_triple);
         assert!(target_path.is_dir(), "{:?} is not a directory!", target_path);
     }
+
+    #[test]
+    fn release_target_search_directory() {
+        let host = AnalysisHost::new(Target::Release);
+        let project = Path::new("/home/user/project");
+        let mut loader = host.loader.lock().unwrap();
+        loader.set_path_prefix(project);
+
+        let deps_dir = project.join("target").join("rls").join("release").join("deps");
+        let dirs = loader.search_directories();
+        assert!(dirs.iter().any(|dir| dir.path == deps_dir.join("save-analysis")), "{:?}", dirs);
+        assert!(!loader.needs_hard_reload(project));
+    }
+
+    #[test]
+    fn changing_target_needs_hard_reload() {
+        let host = AnalysisHost::new(Target::Debug);
+        let project = Path::new("/home/user/project");
+        host.loader.lock().unwrap().set_path_prefix(project);
+
+        host.set_target(Target::Debug).unwrap();
+        assert!(!host.loader.lock().unwrap().needs_hard_reload(project));
+
+        host.set_target(Target::Release).unwrap();
+        let loader = host.loader.lock().unwrap();
+        assert_eq!(loader.target, Target::Release);
+        assert!(loader.needs_hard_reload(project));
+    }
 }
@@ -1,7 +1,9 @@
 //! For processing the raw save-analysis data from rustc into the rls
 //! in-memory representation.
 
-use crate::analysis::{Def, Glob, PerCrateAnalysis, Ref};
+use crate::analysis::{
+    Def, Glob, Impl, Import, MacroRef, PerCrateAnalysis, Ref, SigElement, Signature,
+};
 #[cfg(feature = "idents")]
 use crate::analysis::{IdentBound, IdentKind, IdentsByColumn, IdentsByLine};
 use crate::loader::AnalysisLoader;
@@ -11,7 +13,7 @@ use crate::{AResult, AnalysisHost, Id, Span, NULL};
 
 use std::collections::hash_map::Entry;
 use std::collections::{HashMap, HashSet};
-use std::iter::Extend;
+use std::iter::{self, Extend};
 use std::path::{Path, PathBuf};
 use std::time::Instant;
 use std::u32;
@@ -33,6 +35,11 @@ where
     let rss = util::get_resident().unwrap_or(0);
     let t_start = Instant::now();
 
+    // References are only recorded to defs we already know about, so the
+    // crates they refer to have to be lowered first.
+    let raw_analysis = dependency_order(raw_analysis);
+    number_new_crates(&mut *analysis.master_crate_map.lock()?, &raw_analysis);
+
     // Keep a queue of crates that we are yet to overwrite as part of the lowering
     // process (to know which already-existing defs we can overwrite and lower)
     let mut invalidated_crates: Vec<_> = raw_analysis.iter().map(|c| c.id.clone()).collect();
@@ -67,6 +74,54 @@ where
     Ok(())
 }
 
+/// Orders `crates` so that each one comes after those of the others which it
+/// depends on, keeping the given order otherwise.
+fn dependency_order(mut crates: Vec<raw::Crate>) -> Vec<raw::Crate> {
+    let mut ordered = Vec::with_capacity(crates.len());
+    while !crates.is_empty() {
+        let pending: HashSet<CrateId> = crates.iter().map(|c| c.id.clone()).collect();
+        let depends_on_pending = |c: &raw::Crate| {
+            c.analysis
+                .prelude
+                .iter()
+                .flat_map(|prelude| &prelude.external_crates)
+                .any(|ext| ext.id != c.id && pending.contains(&ext.id))
+        };
+        let (ready, rest): (Vec<_>, Vec<_>) =
+            crates.into_iter().partition(|c| !depends_on_pending(c));
+        if ready.is_empty() {
+            // A dependency cycle, which rustc doesn't allow.
+            ordered.extend(rest);
+            break;
+        }
+        ordered.extend(ready);
+        crates = rest;
+    }
+    ordered
+}
+
+/// Numbers the crates of `crates`, and those they depend on, which aren't in
+/// `master_crate_map` yet. They are numbered after the known crates, by name
+/// and disambiguator rather than in the order they are read in, so that the
+/// same crates get the same numbers, and their defs the same ids, across runs.
+fn number_new_crates(master_crate_map: &mut HashMap<CrateId, u32>, crates: &[raw::Crate]) {
+    let mut new_crates: Vec<&CrateId> = crates
+        .iter()
+        .flat_map(|c| {
+            let external_crates = c.analysis.prelude.iter().flat_map(|p| &p.external_crates);
+            iter::once(&c.id).chain(external_crates.map(|ext| &ext.id))
+        })
+        .filter(|id| !master_crate_map.contains_key(id))
+        .collect();
+    new_crates.sort_by(|a, b| (&a.name, a.disambiguator).cmp(&(&b.name, b.disambiguator)));
+    new_crates.dedup();
+
+    for id in new_crates {
+        let next = master_crate_map.len() as u32;
+        master_crate_map.insert(id.clone(), next);
+    }
+}
+
 fn lower_span(raw_span: &raw::SpanData, base_dir: &Path, path_rewrite: &Option<PathBuf>) -> Span {
     let file_name = &raw_span.file_name;
 
@@ -176,9 +231,16 @@ impl<'a> CrateReader<'a> {
 
         let is_distro_crate = krate.analysis.config.distro_crate;
         reader.read_defs(krate.analysis.defs, &mut per_crate, is_distro_crate, project_analysis);
-        reader.read_imports(krate.analysis.imports, &mut per_crate, project_analysis);
+        let pub_only = krate.analysis.config.pub_only;
+        reader.read_imports(krate.analysis.imports, &mut per_crate, pub_only, project_analysis);
         reader.read_refs(krate.analysis.refs, &mut per_crate, project_analysis);
-        reader.read_impls(krate.analysis.relations, &mut per_crate, project_analysis);
+        reader.read_macro_refs(krate.analysis.macro_refs, &mut per_crate);
+        reader.read_relations(
+            krate.analysis.relations,
+            krate.analysis.impls,
+            &mut per_crate,
+            project_analysis,
+        );
         per_crate.global_crate_num = reader.crate_map[0];
 
         {
@@ -199,6 +261,7 @@ impl<'a> CrateReader<'a> {
         &self,
         imports: Vec<raw::Import>,
         analysis: &mut PerCrateAnalysis,
+        pub_only: bool,
         project_analysis: &AnalysisHost<L>,
     ) {
         for i in imports {
@@ -213,14 +276,53 @@ impl<'a> CrateReader<'a> {
             } else if let Some(ref ref_id) = i.ref_id {
                 // Import where we know the referred def.
                 let def_id = self.id_from_compiler_id(*ref_id);
-                self.record_ref(def_id, span, analysis, project_analysis);
-                if let Some(alias_span) = i.alias_span {
-                    let alias_span = lower_span(&alias_span, &self.base_dir, &self.path_rewrite);
-                    self.record_ref(def_id, alias_span, analysis, project_analysis);
+                self.record_ref(def_id, span.clone(), analysis, project_analysis);
+                let alias_span =
+                    i.alias_span.map(|s| lower_span(&s, &self.base_dir, &self.path_rewrite));
+                if let Some(ref alias_span) = alias_span {
+                    self.record_ref(def_id, alias_span.clone(), analysis, project_analysis);
                     let mut analysis = project_analysis.analysis.lock().unwrap();
                     analysis.as_mut().unwrap().aliased_imports.insert(def_id);
                 }
+                if pub_only && i.kind == raw::ImportKind::Use {
+                    if let Some(parent) = i.parent {
+                        let parent = self.id_from_compiler_id(parent);
+                        let reexport = (parent, i.name.clone());
+                        analysis.reexports.entry(def_id).or_default().push(reexport);
+                    }
+                }
+                // Only track imports of defs we know about, otherwise we can't
+                // tell whether they are used.
+                if i.kind == raw::ImportKind::Use && analysis.def_id_for_span.contains_key(&span) {
+                    let import = Import { span, alias_span, def_id };
+                    analysis.imports.entry(import.span.file.clone()).or_default().push(import);
+                }
+            }
+        }
+    }
+
+    fn read_macro_refs(&self, macro_refs: Vec<raw::MacroRef>, analysis: &mut PerCrateAnalysis) {
+        for m in macro_refs {
+            let span = lower_span(&m.span, &self.base_dir, &self.path_rewrite);
+            // Built-in macros have no source; their callee span has no file or
+            // a placeholder one, like `<println macros>`.
+            let callee_file = m.callee_span.file_name.to_string_lossy();
+            let callee_span = if callee_file.is_empty() || callee_file.starts_with('<') {
+                None
+            } else {
+                Some(lower_span(&m.callee_span, &self.base_dir, &self.path_rewrite))
+            };
+            trace!("record macro ref {:?} {} {:?}", span, m.qualname, callee_span);
+            if let Some(ref callee_span) = callee_span {
+                let expansions =
+                    analysis.macro_expansions.entry(callee_span.file.clone()).or_default();
+                match expansions.iter_mut().find(|(definition, _)| definition == callee_span) {
+                    Some((_, invocations)) => invocations.push(span.clone()),
+                    None => expansions.push((callee_span.clone(), vec![span.clone()])),
+                }
             }
+            let macro_ref = MacroRef { span, qualname: m.qualname, callee_span };
+            analysis.macro_refs.entry(macro_ref.span.file.clone()).or_default().push(macro_ref);
         }
     }
 
@@ -337,14 +439,15 @@ impl<'a> CrateReader<'a> {
             if id != NULL && !analysis.defs.contains_key(&id) {
                 let file_name = span.file.clone();
                 analysis.defs_per_file.entry(file_name).or_insert_with(Vec::new).push(id);
-                let decl_id = match d.decl_id {
-                    Some(ref decl_id) => {
-                        let def_id = self.id_from_compiler_id(*decl_id);
+                let decl_id = d.decl_id.map(|decl_id| self.id_from_compiler_id(decl_id));
+                let def_ref = match decl_id {
+                    Some(def_id) => {
                         analysis
                             .ref_spans
                             .entry(def_id)
                             .or_insert_with(Vec::new)
                             .push(span.clone());
+                        analysis.implementations.entry(def_id).or_default().push(id);
                         Ref::Id(def_id)
                     }
                     None => Ref::Id(id),
@@ -354,7 +457,7 @@ impl<'a> CrateReader<'a> {
                         debug!("def already exists at span: {:?} {:?}", span, d);
                     }
                     Entry::Vacant(ve) => {
-                        ve.insert(decl_id);
+                        ve.insert(def_ref);
                     }
                 }
 
@@ -390,8 +493,9 @@ impl<'a> CrateReader<'a> {
                     qualname: format!("{}{}", self.crate_name, d.qualname),
                     distro_crate,
                     parent,
+                    decl_id,
                     docs: d.docs,
-                    // sig: d.sig.map(|ref s| self.lower_sig(s, &self.base_dir)),
+                    sig: d.sig.map(|s| self.lower_sig(s)),
                 };
                 trace!(
                     "record def: {:?}/{:?} ({}): {:?}",
@@ -441,53 +545,88 @@ impl<'a> CrateReader<'a> {
         }
     }
 
-    fn read_impls<L: AnalysisLoader>(
+    fn read_relations<L: AnalysisLoader>(
         &self,
         relations: Vec<raw::Relation>,
+        impls: Vec<raw::Impl>,
         analysis: &mut PerCrateAnalysis,
         project_analysis: &AnalysisHost<L>,
     ) {
+        let impl_kinds: HashMap<_, _> = impls.into_iter().map(|i| (i.id, i.kind)).collect();
         for r in relations {
-            match r.kind {
-                RelationKind::Impl { .. } => {}
-                _ => continue,
-            }
+            let impl_id = match r.kind {
+                RelationKind::Impl { id } => id,
+                RelationKind::SuperTrait => {
+                    self.read_super_trait(r, analysis, project_analysis);
+                    continue;
+                }
+            };
             let self_id = self.id_from_compiler_id(r.from);
             let trait_id = self.id_from_compiler_id(r.to);
             let span = lower_span(&r.span, &self.base_dir, &self.path_rewrite);
-            if self_id != NULL {
-                if let Some(self_id) = abs_ref_id(self_id, analysis, project_analysis) {
-                    trace!("record impl for self type {:?} {}", span, self_id);
-                    analysis.impls.entry(self_id).or_insert_with(Vec::new).push(span.clone());
-                }
+            let self_id = if self_id != NULL {
+                abs_ref_id(self_id, analysis, project_analysis)
+            } else {
+                None
+            };
+            if let Some(self_id) = self_id {
+                trace!("record impl for self type {:?} {}", span, self_id);
+                analysis.impls.entry(self_id).or_default().push(span.clone());
             }
             if trait_id != NULL {
                 if let Some(trait_id) = abs_ref_id(trait_id, analysis, project_analysis) {
                     trace!("record impl for trait {:?} {}", span, trait_id);
-                    analysis.impls.entry(trait_id).or_insert_with(Vec::new).push(span);
+                    analysis.impls.entry(trait_id).or_default().push(span.clone());
+
+                    if let Some(self_id) = self_id {
+                        // Save-analysis does not currently emit `ImplKind::Blanket`,
+                        // so also check whether the self type is a generic parameter
+                        // (their qualnames are of the form `T$id`).
+                        let blanket = impl_kinds.get(&impl_id) == Some(&raw::ImplKind::Blanket)
+                            || analysis.defs.get(&self_id).is_some_and(|def| {
+                                def.kind == DefKind::Type && def.qualname.contains('$')
+                            });
+                        let imp = Impl { self_id, span, blanket };
+                        analysis.trait_impls.entry(trait_id).or_default().push(imp);
+                    }
                 }
             }
         }
     }
 
-    // fn lower_sig(&self, raw_sig: &raw::Signature, base_dir: &Path) -> Signature {
-    //     Signature {
-    //         span: lower_span(&raw_sig.span, base_dir, &self.path_rewrite),
-    //         text: raw_sig.text.clone(),
-    //         ident_start: raw_sig.ident_start as u32,
-    //         ident_end: raw_sig.ident_end as u32,
-    //         defs: raw_sig.defs.iter().map(|se| self.lower_sig_element(se)).collect(),
-    //         refs: raw_sig.refs.iter().map(|se| self.lower_sig_element(se)).collect(),
-    //     }
-    // }
-
-    // fn lower_sig_element(&self, raw_se: &raw::SigElement) -> SigElement {
-    //     SigElement {
-    //         id: self.id_from_compiler_id(raw_se.id),
-    //         start: raw_se.start,
-    //         end: raw_se.end,
-    //     }
-    // }
+    fn read_super_trait<L: AnalysisLoader>(
+        &self,
+        relation: raw::Relation,
+        analysis: &mut PerCrateAnalysis,
+        project_analysis: &AnalysisHost<L>,
+    ) {
+        // The relation is from the supertrait to the subtrait.
+        let super_id = self.id_from_compiler_id(relation.from);
+        let sub_id = self.id_from_compiler_id(relation.to);
+        if super_id == NULL || sub_id == NULL {
+            return;
+        }
+        let super_id = abs_ref_id(super_id, analysis, project_analysis);
+        let sub_id = abs_ref_id(sub_id, analysis, project_analysis);
+        if let (Some(super_id), Some(sub_id)) = (super_id, sub_id) {
+            trace!("record supertrait {} of {}", super_id, sub_id);
+            analysis.super_traits.entry(sub_id).or_default().insert(super_id);
+            analysis.sub_traits.entry(super_id).or_default().insert(sub_id);
+        }
+    }
+
+    fn lower_sig(&self, raw_sig: raw::Signature) -> Signature {
+        let lower_element = |se: raw::SigElement| SigElement {
+            id: self.id_from_compiler_id(se.id),
+            start: se.start,
+            end: se.end,
+        };
+        Signature {
+            text: raw_sig.text,
+            defs: raw_sig.defs.into_iter().map(lower_element).collect(),
+            refs: raw_sig.refs.into_iter().map(lower_element).collect(),
+        }
+    }
 
     /// Recreates resulting crate-local (`u32`, `u32`) id from compiler
     /// to a global `u64` `Id`, mapping from a local to global crate id.
@@ -514,7 +653,7 @@ fn abs_ref_id<L: AnalysisLoader>(
     None
 }
 
-fn build_index(mut defs: Vec<(String, Id)>) -> (fst::Map<Vec<u8>>, Vec<Vec<Id>>) {
+pub(crate) fn build_index(mut defs: Vec<(String, Id)>) -> (fst::Map<Vec<u8>>, Vec<Vec<Id>>) {
     defs.sort_by(|(n1, _), (n2, _)| n1.cmp(n2));
     let by_name = defs.into_iter().group_by(|(n, _)| n.clone());
 
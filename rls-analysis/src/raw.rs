@@ -1,10 +1,11 @@
 use data::config::Config;
 use data::Analysis;
 pub use data::{
-    CratePreludeData, Def, DefKind, GlobalCrateId as CrateId, Import, Ref, Relation, RelationKind,
-    SigElement, Signature, SpanData,
+    CratePreludeData, Def, DefKind, GlobalCrateId as CrateId, Impl, ImplKind, Import, ImportKind,
+    MacroRef, Ref, Relation, RelationKind, SigElement, Signature, SpanData,
 };
 
+use std::cmp::Reverse;
 use std::collections::HashMap;
 use std::fmt::Debug;
 use std::fs::File;
@@ -42,7 +43,9 @@ impl Crate {
 }
 
 /// Reads raw analysis data for non-blacklisted crates from files in directories
-/// pointed by `loader`.
+/// pointed by `loader`. If a crate is found in several directories, only the
+/// data from the one with the highest priority is returned (see
+/// `prefer_prioritized`).
 pub fn read_analysis_from_files<L: AnalysisLoader>(
     loader: &L,
     crate_timestamps: HashMap<PathBuf, SystemTime>,
@@ -66,15 +69,12 @@ pub fn read_analysis_from_files<L: AnalysisLoader>(
                     }
 
                     let path = dir.path.join(&l.name);
-                    let is_fresh = crate_timestamps.get(&path).map_or(true, |t| time > t);
+                    let is_fresh = crate_timestamps.get(&path).is_none_or(|t| time > t);
                     if is_fresh {
                         if let Some(analysis) = read_crate_data(&path) {
-                            result.push(Crate::new(
-                                analysis,
-                                *time,
-                                Some(path),
-                                dir.prefix_rewrite.clone(),
-                            ));
+                            let krate =
+                                Crate::new(analysis, *time, Some(path), dir.prefix_rewrite.clone());
+                            result.push((dir.priority, krate));
                         };
                     }
                 }
@@ -90,7 +90,34 @@ pub fn read_analysis_from_files<L: AnalysisLoader>(
             );
         });
 
-    result
+    prefer_prioritized(result)
+}
+
+/// Keeps one of the crates with the same id: the one from the directory with
+/// the highest priority, then the newest one. Any remaining tie is broken by
+/// path, so that the choice doesn't depend on the order of the directories.
+fn prefer_prioritized(crates: Vec<(u32, Crate)>) -> Vec<Crate> {
+    let rank = |(priority, krate): &(u32, Crate)| {
+        (*priority, krate.timestamp, Reverse(krate.path.clone()))
+    };
+
+    let mut kept: Vec<(u32, Crate)> = Vec::with_capacity(crates.len());
+    let mut indices: HashMap<CrateId, usize> = HashMap::new();
+    for candidate in crates {
+        match indices.get(&candidate.1.id) {
+            Some(&i) => {
+                if rank(&candidate) > rank(&kept[i]) {
+                    trace!("{:?} shadows {:?}", candidate.1.path, kept[i].1.path);
+                    kept[i] = candidate;
+                }
+            }
+            None => {
+                indices.insert(candidate.1.id.clone(), kept.len());
+                kept.push(candidate);
+            }
+        }
+    }
+    kept.into_iter().map(|(_, krate)| krate).collect()
 }
 
 fn ignore_data(file_name: &str, crate_blacklist: &[impl AsRef<str>]) -> bool {
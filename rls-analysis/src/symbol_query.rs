@@ -1,9 +1,10 @@
 use fst::{self, Streamer};
+use regex::{Regex, RegexBuilder};
 
 /// `SymbolQuery` specifies the preficate for filtering symbols by name.
 ///
-/// All matching is case-insensitive. Filtering by prefix or by subsequence
-/// is supported, subsequence being a good default choice.
+/// All matching is case-insensitive. Filtering by prefix, by subsequence or by
+/// a regular expression is supported, subsequence being a good default choice.
 ///
 /// As the number of results might be huge, consider the `limit` hint,
 /// which serves as *approximate* limit on the number of results returned.
@@ -14,6 +15,7 @@ use fst::{self, Streamer};
 pub struct SymbolQuery {
     query_string: String,
     mode: Mode,
+    regex: Option<Regex>,
     limit: usize,
     greater_than: String,
 }
@@ -22,11 +24,23 @@ pub struct SymbolQuery {
 enum Mode {
     Prefix,
     Subsequence,
+    Regex,
 }
 
+/// The most memory the compiled program of a regex query may use, in bytes.
+/// Larger ones, e.g., with deeply nested repetitions, are rejected rather than
+/// slowing down the search.
+const REGEX_SIZE_LIMIT: usize = 1 << 20;
+
 impl SymbolQuery {
     fn new(query_string: String, mode: Mode) -> SymbolQuery {
-        SymbolQuery { query_string, mode, limit: usize::max_value(), greater_than: String::new() }
+        SymbolQuery {
+            query_string,
+            mode,
+            regex: None,
+            limit: usize::max_value(),
+            greater_than: String::new(),
+        }
     }
 
     pub fn subsequence(query_string: &str) -> SymbolQuery {
@@ -37,6 +51,18 @@ impl SymbolQuery {
         SymbolQuery::new(query_string.to_lowercase(), Mode::Prefix)
     }
 
+    /// Matches the names which contain a match of `pattern` (anchor it with
+    /// `^` and `$` to match whole names). Returns an error if the pattern is
+    /// invalid or too complex.
+    pub fn regex(pattern: &str) -> Result<SymbolQuery, regex::Error> {
+        let regex = RegexBuilder::new(pattern)
+            .case_insensitive(true)
+            .size_limit(REGEX_SIZE_LIMIT)
+            .dfa_size_limit(REGEX_SIZE_LIMIT)
+            .build()?;
+        Ok(SymbolQuery { regex: Some(regex), ..SymbolQuery::new(String::new(), Mode::Regex) })
+    }
+
     pub fn limit(self, limit: usize) -> SymbolQuery {
         SymbolQuery { limit, ..self }
     }
@@ -62,7 +88,13 @@ impl SymbolQuery {
         F: Fn(&mut Vec<T>, &fst::map::IndexedValue),
     {
         let mut res = Vec::new();
-        while let Some((_, entries)) = stream.next() {
+        while let Some((key, entries)) = stream.next() {
+            // The automaton matches every name in regex mode.
+            if let Some(ref regex) = self.regex {
+                if !std::str::from_utf8(key).is_ok_and(|key| regex.is_match(key)) {
+                    continue;
+                }
+            }
             for e in entries {
                 f(&mut res, e);
             }
@@ -114,7 +146,7 @@ impl<'a> fst::Automaton for QueryAutomaton<'a> {
         }
         match self.mode {
             Mode::Prefix => NO_MATCH,
-            Mode::Subsequence => state,
+            Mode::Subsequence | Mode::Regex => state,
         }
     }
 
@@ -160,5 +192,10 @@ mod tests {
             &["antares", "canopus"],
         );
         check(SymbolQuery::subsequence("an").limit(2).greater_than("canopus"), &["lalandry"]);
+
+        check(SymbolQuery::regex("^A.*R$").unwrap(), &["agreetor", "anektor"]);
+        check(SymbolQuery::regex("us$").unwrap().limit(1), &["arcturus"]);
+        assert!(SymbolQuery::regex("(an").is_err());
+        assert!(SymbolQuery::regex("((a{100}){100}){100}").is_err());
     }
 }
@@ -1,9 +1,19 @@
 use crate::loader::SearchDirectory;
-use crate::raw::DefKind;
-use crate::{AnalysisHost, AnalysisLoader};
+use crate::raw::{self, DefKind};
+use crate::{
+    AnalysisHost, AnalysisLoader, CargoAnalysisLoader, ExpansionRefs, FindRefsOptions, Id, SigPart,
+    Span, SymbolQuery, Target, VariantKind,
+};
+use span::{Column, Row};
 
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
+use std::fs;
 use std::path::{Path, PathBuf};
+#[cfg(feature = "derive")]
+use std::sync::Arc;
+#[cfg(feature = "derive")]
+use std::thread;
+use std::time::{Duration, SystemTime};
 
 #[derive(Clone, new)]
 struct TestAnalysisLoader {
@@ -30,6 +40,37 @@ impl AnalysisLoader for TestAnalysisLoader {
     }
 }
 
+/// Reads analysis data from several directories, with the given priorities.
+#[derive(Clone)]
+struct MultiDirLoader {
+    dirs: Vec<(PathBuf, u32)>,
+}
+
+impl AnalysisLoader for MultiDirLoader {
+    fn needs_hard_reload(&self, _path_prefix: &Path) -> bool {
+        true
+    }
+
+    fn fresh_host(&self) -> AnalysisHost<Self> {
+        AnalysisHost::new_with_loader(self.clone())
+    }
+
+    fn set_path_prefix(&mut self, _path_prefix: &Path) {}
+
+    fn abs_path_prefix(&self) -> Option<PathBuf> {
+        panic!();
+    }
+
+    fn search_directories(&self) -> Vec<SearchDirectory> {
+        self.dirs
+            .iter()
+            .map(|(path, priority)| {
+                SearchDirectory::new(path.clone(), None).with_priority(*priority)
+            })
+            .collect()
+    }
+}
+
 #[test]
 fn doc_urls_resolve_correctly() {
     let host = AnalysisHost::new_with_loader(TestAnalysisLoader::new(
@@ -132,6 +173,75 @@ fn doc_urls_resolve_correctly() {
     assert_url_for_type(&host, "fs", "std::fs", "https://doc.rust-lang.org/nightly/std/fs/");
 }
 
+#[test]
+fn doc_url_for_id() {
+    let host = AnalysisHost::new_with_loader(TestAnalysisLoader::new(
+        Path::new("test_data/rust-analysis").to_owned(),
+    ));
+    host.reload(Path::new("test_data/rust-analysis"), Path::new("test_data/rust-analysis"))
+        .unwrap();
+
+    let ids = host.search_for_id("Ipv4Addr").unwrap();
+    assert_eq!(ids.len(), 1);
+    assert_eq!(
+        host.doc_url_for_id(ids[0]),
+        Ok("https://doc.rust-lang.org/nightly/std/net/ip/Ipv4Addr.t.html".to_owned())
+    );
+
+    // Defs of the workspace aren't documented online.
+    let host = AnalysisHost::new_with_loader(TestAnalysisLoader::new(
+        Path::new("test_data/hello/save-analysis").to_owned(),
+    ));
+    host.reload(Path::new("test_data/hello"), Path::new("test_data/hello")).unwrap();
+    let ids = host.search_for_id("main").unwrap();
+    assert!(!ids.is_empty());
+    assert!(host.doc_url_for_id(ids[0]).is_err());
+}
+
+#[test]
+fn test_import_paths() {
+    let host = AnalysisHost::new_with_loader(TestAnalysisLoader::new(
+        Path::new("test_data/rust-analysis").to_owned(),
+    ));
+    host.reload(Path::new("test_data/rust-analysis"), Path::new("test_data/rust-analysis"))
+        .unwrap();
+
+    let hash_map = host
+        .search_for_id("HashMap")
+        .unwrap()
+        .into_iter()
+        .find(|&id| host.get_def(id).unwrap().kind == DefKind::Struct)
+        .unwrap();
+    let main = Path::new("src/main.rs");
+    let paths = host.import_paths(hash_map, main).unwrap();
+    assert_eq!(paths[0], "std::collections::HashMap");
+    assert!(paths.contains(&"std::collections::hash::map::HashMap".to_owned()));
+
+    // Methods can't be imported.
+    let is_ascii = host
+        .search_for_id("is_ascii")
+        .unwrap()
+        .into_iter()
+        .find(|&id| host.get_def(id).unwrap().qualname == "std::ascii::AsciiExt::is_ascii")
+        .unwrap();
+    assert_eq!(host.import_paths(is_ascii, main).unwrap(), Vec::<String>::new());
+}
+
+#[test]
+fn test_import_paths_local() {
+    let host = AnalysisHost::new_with_loader(TestAnalysisLoader::new(
+        Path::new("test_data/types/save-analysis").to_owned(),
+    ));
+    host.reload(Path::new("test_data/types"), Path::new("test_data/types")).unwrap();
+
+    // Defs are imported with `crate` into files of their own crate.
+    let id = host.search_for_id("TestType").unwrap()[0];
+    let main = Path::new("test_data/types/src/main.rs");
+    assert_eq!(host.import_paths(id, main).unwrap(), vec!["crate::test_module::TestType"]);
+    let other = Path::new("test_data/hello/src/main.rs");
+    assert_eq!(host.import_paths(id, other).unwrap(), vec!["types::test_module::TestType"]);
+}
+
 #[test]
 fn smoke() {
     // Read in test data and lower it, check we don't crash.
@@ -278,7 +388,7 @@ fn test_types() {
     ));
     host.reload(Path::new("test_data/types"), Path::new("test_data/types")).unwrap();
 
-    assert_type(&host, "Foo", DefKind::Struct, &[1, 6, 7, 10, 10]);
+    assert_type(&host, "Foo", DefKind::Struct, &[1, 6, 7, 10, 10, 36, 44]);
     assert_type(&host, "f", DefKind::Field, &[2, 6]);
     assert_type(&host, "main", DefKind::Function, &[5]);
     assert_type(&host, "test_binding", DefKind::Local, &[11]);
@@ -287,8 +397,9 @@ fn test_types() {
     assert_type(&host, "test_module", DefKind::Mod, &[17]);
     assert_type(&host, "TestType", DefKind::Type, &[18]);
     assert_type(&host, "TestUnion", DefKind::Union, &[21]);
-    assert_type(&host, "TestTrait", DefKind::Trait, &[25]);
-    assert_type(&host, "test_method", DefKind::Method, &[26]);
+    assert_type(&host, "TestTrait", DefKind::Trait, &[25, 36, 40]);
+    // The trait method and the methods implementing it.
+    assert_eq!(host.search_for_id("test_method").unwrap().len(), 3);
     assert_type(&host, "FooEnum", DefKind::Enum, &[29]);
     assert_type(&host, "TupleVariant", DefKind::TupleVariant, &[30]);
     assert_type(&host, "StructVariant", DefKind::StructVariant, &[31]);
@@ -318,6 +429,174 @@ fn test_types() {
     assert_eq!(lower_matches[0].name, "FooEnum");
 }
 
+#[test]
+fn test_qualified_path() {
+    let host = AnalysisHost::new_with_loader(TestAnalysisLoader::new(
+        Path::new("test_data/types/save-analysis").to_owned(),
+    ));
+    host.reload(Path::new("test_data/types"), Path::new("test_data/types")).unwrap();
+
+    let path_at = |name: &str| {
+        let id = host.search_for_id(name).unwrap()[0];
+        let span = host.get_def(id).unwrap().span;
+        host.qualified_path(&span).unwrap()
+    };
+
+    assert_eq!(path_at("TestType"), ["crate", "test_module", "TestType"]);
+    assert_eq!(path_at("StructVariant"), ["crate", "FooEnum", "StructVariant"]);
+    assert_eq!(path_at("TEST_CONST"), ["crate", "foo", "TEST_CONST"]);
+    assert_eq!(path_at("test_binding"), ["{anonymous}", "test_binding"]);
+}
+
+#[test]
+fn test_fields_of() {
+    let host = AnalysisHost::new_with_loader(TestAnalysisLoader::new(
+        Path::new("test_data/types/save-analysis").to_owned(),
+    ));
+    host.reload(Path::new("test_data/types"), Path::new("test_data/types")).unwrap();
+
+    let fields_of = |name: &str| {
+        let id = host.search_for_id(name).unwrap()[0];
+        host.fields_of(id).unwrap()
+    };
+
+    let f = host.search_for_id("f").unwrap()[0];
+    assert_eq!(fields_of("Foo"), vec![("f".to_owned(), "u32".to_owned(), f)]);
+    let x = host.search_for_id("x").unwrap();
+    let fields = fields_of("StructVariant");
+    assert_eq!(fields.len(), 1);
+    assert_eq!((&*fields[0].0, &*fields[0].1), ("x", "u8"));
+    assert!(x.contains(&fields[0].2));
+    assert!(fields_of("TupleVariant").is_empty());
+    // Tuple structs have positional fields.
+    let fields: Vec<_> = fields_of("Pair").into_iter().map(|(name, ty, _)| (name, ty)).collect();
+    assert_eq!(
+        fields,
+        vec![("0".to_owned(), "u8".to_owned()), ("1".to_owned(), "std::string::String".to_owned())]
+    );
+    // Only fields, not methods.
+    assert!(fields_of("TestTrait").is_empty());
+}
+
+#[test]
+fn test_crate_exclusion() {
+    let host = AnalysisHost::new_with_loader(TestAnalysisLoader::new(
+        Path::new("test_data/types/save-analysis").to_owned(),
+    ));
+    let roots = std::sync::Arc::new(std::sync::Mutex::new(vec![]));
+    let seen = roots.clone();
+    host.set_crate_exclusion(move |root| {
+        seen.lock().unwrap().push(root.to_owned());
+        root == Path::new("test_data/types/src")
+    })
+    .unwrap();
+    host.reload(Path::new("test_data/types"), Path::new("test_data/types")).unwrap();
+
+    // The crate's source root is the directory of `src/main.rs`.
+    assert_eq!(*roots.lock().unwrap(), vec![PathBuf::from("test_data/types/src")]);
+    assert!(host.search_for_id("TestType").unwrap().is_empty());
+    assert!(host.search_for_id("foo").unwrap().is_empty());
+
+    host.set_crate_exclusion(|root| root == Path::new("test_data/types/generated")).unwrap();
+    host.hard_reload(Path::new("test_data/types"), Path::new("test_data/types")).unwrap();
+    assert_eq!(host.search_for_id("TestType").unwrap().len(), 1);
+}
+
+#[test]
+fn test_for_each_def_in_crate() {
+    let host = AnalysisHost::new_with_loader(TestAnalysisLoader::new(
+        Path::new("test_data/types/save-analysis").to_owned(),
+    ));
+    host.reload(Path::new("test_data/types"), Path::new("test_data/types")).unwrap();
+
+    let fixture = raw::read_crate_data(Path::new("test_data/types/save-analysis/types.json"));
+    let mut names = vec![];
+    host.for_each_def_in_crate("types", |def| names.push(def.name.clone())).unwrap();
+    assert_eq!(names.len(), fixture.unwrap().defs.len());
+    assert!(names.iter().any(|name| name == "TestType"));
+
+    let mut count = 0;
+    host.for_each_def_in_crate("no_such_crate", |_| count += 1).unwrap();
+    assert_eq!(count, 0);
+}
+
+#[test]
+fn test_enum_variants() {
+    let host = AnalysisHost::new_with_loader(TestAnalysisLoader::new(
+        Path::new("test_data/types/save-analysis").to_owned(),
+    ));
+    host.reload(Path::new("test_data/types"), Path::new("test_data/types")).unwrap();
+
+    let id = host.search_for_id("FooEnum").unwrap()[0];
+    let variants: Vec<_> =
+        host.enum_variants(id).unwrap().into_iter().map(|v| (v.name, v.kind, v.fields)).collect();
+    assert_eq!(
+        variants,
+        vec![
+            ("TupleVariant".to_owned(), VariantKind::Unit, vec![]),
+            ("StructVariant".to_owned(), VariantKind::Struct, vec!["x".to_owned()]),
+        ]
+    );
+
+    let id = host.search_for_id("Foo").unwrap()[0];
+    assert!(host.enum_variants(id).unwrap().is_empty());
+}
+
+#[test]
+fn test_impl_trait() {
+    let host = AnalysisHost::new_with_loader(TestAnalysisLoader::new(
+        Path::new("test_data/trait_items/save-analysis").to_owned(),
+    ));
+    host.reload(Path::new("test_data/trait_items"), Path::new("test_data/trait_items")).unwrap();
+
+    // The header of `impl TestTrait for Foo`.
+    let header = Span::new(
+        Row::new_zero_indexed(13),
+        Row::new_zero_indexed(13),
+        Column::new_zero_indexed(0),
+        Column::new_zero_indexed(24),
+        Path::new("test_data/trait_items/src/main.rs"),
+    );
+    let trait_id = host.impl_trait(&header).unwrap();
+    assert_eq!(trait_id, host.search_for_id("TestTrait").unwrap()[0]);
+
+    // Associated types and consts are items of the trait too, but the methods
+    // of the impl are not.
+    let items: Vec<_> = host
+        .trait_items(trait_id)
+        .unwrap()
+        .into_iter()
+        .map(|def| (def.span.range.row_start.0, def.kind, def.name))
+        .collect();
+    assert_eq!(
+        items,
+        vec![
+            (1, DefKind::Type, "Output".to_owned()),
+            (2, DefKind::Const, "LIMIT".to_owned()),
+            (4, DefKind::Method, "test_method".to_owned()),
+            (5, DefKind::Method, "provided".to_owned()),
+            (8, DefKind::Method, "other".to_owned()),
+        ]
+    );
+
+    // Stubs for the missing items are generated from their signatures.
+    let items = host.trait_items(trait_id).unwrap();
+    assert_eq!(items[2].sig.as_ref().unwrap().text, "fn test_method(: & Self,) {}");
+    assert_eq!(
+        items[4].sig.as_ref().unwrap().text,
+        "fn other(: & Self,: u32,) -> Option<Self::Output> {}"
+    );
+
+    let main = Span::new(
+        Row::new_zero_indexed(22),
+        Row::new_zero_indexed(22),
+        Column::new_zero_indexed(0),
+        Column::new_zero_indexed(11),
+        Path::new("test_data/trait_items/src/main.rs"),
+    );
+    assert!(host.impl_trait(&main).is_err());
+}
+
 #[test]
 fn test_child_count() {
     let host = AnalysisHost::new_with_loader(TestAnalysisLoader::new(
@@ -365,11 +644,1030 @@ fn test_all_ref_unique() {
 
     let spans = host.search("bar").unwrap();
     assert_eq!(spans.len(), 4);
-    let refs = host.find_all_refs(&spans[3], true, true);
+    let refs = host.find_all_refs(
+        &spans[3],
+        FindRefsOptions {
+            include_decl: true,
+            force_unique_spans: true,
+            ..FindRefsOptions::default()
+        },
+    );
     assert_eq!(refs.unwrap().len(), 0);
 
     let spans = host.search("qux").unwrap();
     assert_eq!(spans.len(), 3);
-    let refs = host.find_all_refs(&spans[2], true, true);
+    let refs = host.find_all_refs(
+        &spans[2],
+        FindRefsOptions {
+            include_decl: true,
+            force_unique_spans: true,
+            ..FindRefsOptions::default()
+        },
+    );
     assert_eq!(refs.unwrap().len(), 3);
 }
+
+#[test]
+fn test_update_crate() {
+    let host = AnalysisHost::new_with_loader(TestAnalysisLoader::new(
+        Path::new("test_data/multi_crate/save-analysis").to_owned(),
+    ));
+    host.reload(Path::new("test_data/multi_crate"), Path::new("test_data/multi_crate")).unwrap();
+
+    let ids = host.search_for_id("greet").unwrap();
+    assert_eq!(ids.len(), 1);
+    let id = ids[0];
+    let refs = host.find_all_refs_by_id(id).unwrap();
+    assert_eq!(refs.len(), 2);
+
+    let analysis = crate::read_crate_data(Path::new(
+        "test_data/multi_crate/save-analysis/libmulti_crate.json",
+    ))
+    .unwrap();
+    let crate_id = analysis.prelude.as_ref().unwrap().crate_id.clone();
+    host.update_crate(&crate_id, analysis, Path::new("test_data/multi_crate")).unwrap();
+
+    // The id of `greet` is unchanged and the reference from the binary crate
+    // still resolves to the re-lowered definition.
+    assert_eq!(host.search_for_id("greet").unwrap(), vec![id]);
+    let refs = host.find_all_refs_by_id(id).unwrap();
+    assert_eq!(refs.len(), 2);
+    let main_ref = refs.iter().find(|s| s.file.ends_with("main.rs")).unwrap();
+    assert_eq!(host.goto_def(main_ref).unwrap(), host.get_def(id).unwrap().span);
+}
+
+#[test]
+fn test_stable_crate_numbers() {
+    let load = || {
+        let host = AnalysisHost::new_with_loader(TestAnalysisLoader::new(
+            Path::new("test_data/multi_crate/save-analysis").to_owned(),
+        ));
+        host.reload(Path::new("test_data/multi_crate"), Path::new("test_data/multi_crate"))
+            .unwrap();
+        host
+    };
+    let host = load();
+    let id = host.search_for_id("greet").unwrap()[0];
+
+    // The same data gets the same ids, whether reloaded or loaded afresh.
+    host.hard_reload(Path::new("test_data/multi_crate"), Path::new("test_data/multi_crate"))
+        .unwrap();
+    assert_eq!(host.search_for_id("greet").unwrap(), vec![id]);
+    assert_eq!(load().search_for_id("greet").unwrap(), vec![id]);
+
+    // A new crate is numbered after the known ones, which keep their numbers.
+    let known = host.master_crate_map.lock().unwrap().clone();
+    let analysis =
+        crate::read_crate_data(Path::new("test_data/types/save-analysis/types.json")).unwrap();
+    let crate_id = analysis.prelude.as_ref().unwrap().crate_id.clone();
+    let multi_crate = Path::new("test_data/multi_crate");
+    host.reload_from_analysis(vec![analysis], multi_crate, multi_crate, &[] as &[&str]).unwrap();
+    let numbers = host.master_crate_map.lock().unwrap().clone();
+    assert!(known.iter().all(|(id, num)| numbers[id] == *num));
+    assert!(numbers[&crate_id] >= known.len() as u32);
+    assert_eq!(host.search_for_id("greet").unwrap(), vec![id]);
+}
+
+#[test]
+fn test_reload_from_analysis_blacklist() {
+    let host = AnalysisHost::new_with_loader(TestAnalysisLoader::new(
+        Path::new("test_data/multi_crate/save-analysis").to_owned(),
+    ));
+    let analysis =
+        crate::read_crate_data(Path::new("test_data/types/save-analysis/types.json")).unwrap();
+    let multi_crate = Path::new("test_data/multi_crate");
+
+    // Blacklisted crates are skipped, whether read from disk or passed in.
+    host.reload_from_analysis(vec![analysis], multi_crate, multi_crate, &["types"]).unwrap();
+    assert!(!host.search_for_id("greet").unwrap().is_empty());
+    assert!(host.search_for_id("TestTrait").unwrap().is_empty());
+}
+
+#[test]
+fn test_cross_crate_goto_def() {
+    let host = AnalysisHost::new_with_loader(TestAnalysisLoader::new(
+        Path::new("test_data/workspace/save-analysis").to_owned(),
+    ));
+    host.reload(Path::new("test_data/workspace"), Path::new("test_data/workspace")).unwrap();
+
+    let main_rs = Path::new("test_data/workspace/app/src/main.rs");
+    let span = |row, col_start, col_end| {
+        Span::new(
+            Row::new_zero_indexed(row),
+            Row::new_zero_indexed(row),
+            Column::new_zero_indexed(col_start),
+            Column::new_zero_indexed(col_end),
+            main_rs,
+        )
+    };
+
+    // References in the `app` crate to definitions in the `util` crate.
+    let def = host.goto_def(&span(5, 35, 41)).unwrap();
+    assert_eq!(def.file, Path::new("test_data/workspace/util/src/lib.rs"));
+    assert_eq!((def.range.row_start.0, def.range.col_start.0), (4, 7));
+    let def = host.goto_def(&span(3, 17, 23)).unwrap();
+    assert_eq!(def.file, Path::new("test_data/workspace/util/src/lib.rs"));
+    assert_eq!((def.range.row_start.0, def.range.col_start.0), (0, 11));
+
+    // There is no analysis data for `std`, so `String` has no definition.
+    assert!(host.goto_def(&span(4, 15, 21)).is_err());
+    assert!(host.goto_def_all(&span(4, 15, 21)).is_err());
+}
+
+#[test]
+fn test_refs_grouped() {
+    let host = AnalysisHost::new_with_loader(TestAnalysisLoader::new(
+        Path::new("test_data/multi_crate/save-analysis").to_owned(),
+    ));
+    host.reload(Path::new("test_data/multi_crate"), Path::new("test_data/multi_crate")).unwrap();
+
+    let id = host.search_for_id("greet").unwrap()[0];
+    let decl = host.get_def(id).unwrap().span;
+    let grouped = host.refs_grouped(&decl, true).unwrap();
+    let files: Vec<_> = grouped.iter().map(|(file, refs)| (file.clone(), refs.len())).collect();
+    assert_eq!(
+        files,
+        vec![
+            (PathBuf::from("test_data/multi_crate/src/lib.rs"), 1),
+            (PathBuf::from("test_data/multi_crate/src/main.rs"), 1),
+        ]
+    );
+    assert_eq!(grouped[0].1[0], decl);
+
+    let grouped = host.refs_grouped(&decl, false).unwrap();
+    assert_eq!(grouped.len(), 1);
+    assert!(grouped[0].0.ends_with("main.rs"));
+}
+
+#[test]
+fn test_find_refs_in_file() {
+    let host = AnalysisHost::new_with_loader(TestAnalysisLoader::new(
+        Path::new("test_data/multi_crate/save-analysis").to_owned(),
+    ));
+    host.reload(Path::new("test_data/multi_crate"), Path::new("test_data/multi_crate")).unwrap();
+
+    let lib = Path::new("test_data/multi_crate/src/lib.rs");
+    let main = Path::new("test_data/multi_crate/src/main.rs");
+    let id = host.search_for_id("greet").unwrap()[0];
+    let decl = host.get_def(id).unwrap().span;
+
+    // `greet` is defined in lib.rs and only used in main.rs.
+    assert_eq!(host.find_refs_in_file(&decl, lib).unwrap(), vec![decl.clone()]);
+    let refs = host.find_refs_in_file(&decl, main).unwrap();
+    assert_eq!(refs.len(), 1);
+    assert_eq!(refs[0].file, main);
+    assert_eq!(host.find_refs_in_file(&refs[0], main).unwrap(), refs);
+    assert_eq!(host.find_refs_in_file(&refs[0], lib).unwrap(), vec![decl]);
+}
+
+#[test]
+fn test_glob_imports() {
+    let host = AnalysisHost::new_with_loader(TestAnalysisLoader::new(
+        Path::new("test_data/deglob/save-analysis").to_owned(),
+    ));
+    host.reload(Path::new("test_data/deglob"), Path::new("test_data/deglob")).unwrap();
+
+    let globs = host.glob_imports(Path::new("test_data/deglob/src/main.rs")).unwrap();
+    let globs: Vec<_> = globs
+        .into_iter()
+        .map(|(span, names)| (span.range.row_start.0, span.range.col_start.0, names))
+        .collect();
+    assert_eq!(
+        globs,
+        vec![
+            (2, 13, vec!["Stdin".to_owned(), "Stdout".to_owned()]),
+            (5, 14, vec!["size_of".to_owned()]),
+            (5, 31, vec!["max".to_owned()]),
+        ]
+    );
+
+    assert!(host.glob_imports(Path::new("test_data/deglob/src/lib.rs")).unwrap().is_empty());
+}
+
+#[test]
+fn test_enclosing_def() {
+    let host = AnalysisHost::new_with_loader(TestAnalysisLoader::new(
+        Path::new("test_data/types/save-analysis").to_owned(),
+    ));
+    host.reload(Path::new("test_data/types"), Path::new("test_data/types")).unwrap();
+    let text = fs::read_to_string("test_data/types/src/main.rs").unwrap();
+
+    let enclosing = |row: u32, col: u32, kinds: &[DefKind]| {
+        let span = Span::new(
+            Row::new_zero_indexed(row),
+            Row::new_zero_indexed(row),
+            Column::new_zero_indexed(col),
+            Column::new_zero_indexed(col),
+            Path::new("test_data/types/src/main.rs"),
+        );
+        host.enclosing_def(&span, kinds, &text).unwrap().map(|def| (def.kind, def.name))
+    };
+    let fn_or_mod = &[DefKind::Function, DefKind::Mod];
+
+    // Inside the body of `foo`.
+    assert_eq!(enclosing(10, 8, fn_or_mod), Some((DefKind::Function, "foo".to_owned())));
+    assert_eq!(enclosing(13, 4, fn_or_mod), Some((DefKind::Function, "foo".to_owned())));
+    // On the name of `foo`.
+    assert_eq!(enclosing(9, 4, fn_or_mod), Some((DefKind::Function, "foo".to_owned())));
+    // Before the name of `foo`.
+    assert_eq!(enclosing(9, 0, fn_or_mod), Some((DefKind::Mod, "".to_owned())));
+    // On the closing brace of `foo`, and on the blank line after it.
+    assert_eq!(enclosing(14, 0, fn_or_mod), Some((DefKind::Function, "foo".to_owned())));
+    assert_eq!(enclosing(15, 0, fn_or_mod), Some((DefKind::Mod, "".to_owned())));
+    // After the `;` of a trait method declaration.
+    assert_eq!(
+        enclosing(25, 26, &[DefKind::Method, DefKind::Trait]),
+        Some((DefKind::Trait, "TestTrait".to_owned()))
+    );
+    // Inside `test_module`, only looking for modules.
+    assert_eq!(enclosing(17, 8, &[DefKind::Mod]), Some((DefKind::Mod, "test_module".to_owned())));
+    // Inside a trait method declaration, looking for the trait.
+    assert_eq!(
+        enclosing(25, 12, &[DefKind::Trait]),
+        Some((DefKind::Trait, "TestTrait".to_owned()))
+    );
+    assert_eq!(enclosing(25, 12, &[DefKind::Function]), None);
+}
+
+#[test]
+fn test_goto_decl() {
+    let host = AnalysisHost::new_with_loader(TestAnalysisLoader::new(
+        Path::new("test_data/decl/save-analysis").to_owned(),
+    ));
+    host.reload(Path::new("test_data/decl"), Path::new("test_data/decl")).unwrap();
+
+    let span = |row: u32, col_start: u32, col_end: u32| {
+        Span::new(
+            Row::new_zero_indexed(row),
+            Row::new_zero_indexed(row),
+            Column::new_zero_indexed(col_start),
+            Column::new_zero_indexed(col_end),
+            Path::new("test_data/decl/src/main.rs"),
+        )
+    };
+    let trait_method = span(1, 7, 12);
+    let impl_method = span(7, 7, 12);
+
+    // The method in the impl records the trait method as its declaration.
+    let ids = host.search_for_id("greet").unwrap();
+    assert_eq!(ids.len(), 2);
+    let defs: Vec<_> = ids.into_iter().map(|id| host.get_def(id).unwrap()).collect();
+    let impl_def = defs.iter().find(|def| def.span == impl_method).unwrap();
+    assert_eq!(host.get_def(impl_def.decl_id.unwrap()).unwrap().span, trait_method);
+
+    assert_eq!(host.goto_decl(&impl_method).unwrap(), trait_method);
+    assert_eq!(host.goto_decl(&span(11, 8, 13)).unwrap(), trait_method);
+    // Without a declaration, this is the same as the definition.
+    assert_eq!(host.goto_decl(&span(11, 4, 7)).unwrap(), span(4, 7, 10));
+}
+
+#[test]
+fn test_method_implementations() {
+    let host = AnalysisHost::new_with_loader(TestAnalysisLoader::new(
+        Path::new("test_data/types/save-analysis").to_owned(),
+    ));
+    host.reload(Path::new("test_data/types"), Path::new("test_data/types")).unwrap();
+
+    let span = |row: u32, col_start: u32, col_end: u32| {
+        Span::new(
+            Row::new_zero_indexed(row),
+            Row::new_zero_indexed(row),
+            Column::new_zero_indexed(col_start),
+            Column::new_zero_indexed(col_end),
+            Path::new("test_data/types/src/main.rs"),
+        )
+    };
+    let trait_method = span(25, 7, 18);
+
+    // The name of the trait method and of its implementations all refer to
+    // the trait method.
+    let id = host.id(&trait_method).unwrap();
+    assert_eq!(host.id(&span(36, 7, 18)).unwrap(), id);
+    assert_eq!(host.method_implementations(id).unwrap(), vec![span(36, 7, 18), span(40, 7, 18)]);
+
+    // Methods of inherent impls don't implement anything, nor are they
+    // implemented.
+    let inherent = host.id(&span(44, 7, 15)).unwrap();
+    assert_eq!(host.get_def(inherent).unwrap().decl_id, None);
+    assert!(host.method_implementations(inherent).unwrap().is_empty());
+}
+
+#[test]
+fn test_shadows_of() {
+    let host = AnalysisHost::new_with_loader(TestAnalysisLoader::new(
+        Path::new("test_data/shadowing/save-analysis").to_owned(),
+    ));
+    host.reload(Path::new("test_data/shadowing"), Path::new("test_data/shadowing")).unwrap();
+
+    let span = |row: u32, col: u32| {
+        Span::new(
+            Row::new_zero_indexed(row),
+            Row::new_zero_indexed(row),
+            Column::new_zero_indexed(col),
+            Column::new_zero_indexed(col + 1),
+            Path::new("test_data/shadowing/src/main.rs"),
+        )
+    };
+    let (first, second, nested) = (span(1, 8), span(2, 8), span(4, 12));
+
+    // `let x = 1; let x = x + 2;`: the second shadows the first, and so does
+    // the one in the nested block, but not the one in another function.
+    let id = host.id(&first).unwrap();
+    assert_eq!(host.shadows_of(id).unwrap(), vec![second.clone(), nested.clone()]);
+    let id = host.id(&second).unwrap();
+    assert_eq!(host.shadows_of(id).unwrap(), vec![nested.clone()]);
+    let id = host.id(&nested).unwrap();
+    assert!(host.shadows_of(id).unwrap().is_empty());
+
+    // Bindings of other names, and things other than bindings, aren't shadowed.
+    let y = host.id(&span(7, 8)).unwrap();
+    assert!(host.shadows_of(y).unwrap().is_empty());
+    let main = host.id(&Span::new(
+        Row::new_zero_indexed(0),
+        Row::new_zero_indexed(0),
+        Column::new_zero_indexed(3),
+        Column::new_zero_indexed(7),
+        Path::new("test_data/shadowing/src/main.rs"),
+    ));
+    assert!(host.shadows_of(main.unwrap()).unwrap().is_empty());
+}
+
+#[test]
+fn test_reads_of() {
+    let host = AnalysisHost::new_with_loader(TestAnalysisLoader::new(
+        Path::new("test_data/shadowing/save-analysis").to_owned(),
+    ));
+    host.reload(Path::new("test_data/shadowing"), Path::new("test_data/shadowing")).unwrap();
+    let read_text = |path: &Path| std::fs::read_to_string(path).ok();
+
+    let span = |row: u32, col: u32| {
+        Span::new(
+            Row::new_zero_indexed(row),
+            Row::new_zero_indexed(row),
+            Column::new_zero_indexed(col),
+            Column::new_zero_indexed(col + 1),
+            Path::new("test_data/shadowing/src/main.rs"),
+        )
+    };
+
+    // `let x = 1; let x = x + 2;`: the first `x` is read once, and its `let`
+    // isn't a read.
+    let id = host.id(&span(1, 8)).unwrap();
+    assert_eq!(host.reads_of(id, read_text).unwrap(), vec![span(2, 12)]);
+    // The one in the nested block is read by `println!`.
+    let id = host.id(&span(4, 12)).unwrap();
+    let reads = host.reads_of(id, read_text).unwrap();
+    assert!(!reads.is_empty());
+    assert!(reads.iter().all(|read| read.range.row_start.0 == 5));
+
+    // Functions aren't locals.
+    let main = host.id(&Span::new(
+        Row::new_zero_indexed(0),
+        Row::new_zero_indexed(0),
+        Column::new_zero_indexed(3),
+        Column::new_zero_indexed(7),
+        Path::new("test_data/shadowing/src/main.rs"),
+    ));
+    assert!(host.reads_of(main.unwrap(), read_text).unwrap().is_empty());
+
+    // Plain assignments are writes, compound ones are both.
+    let text = "x = 1;\nx += 1;\nx == 1;\n    x => 1,\nx.y = 1;\n";
+    let assigned: Vec<_> = (0..5)
+        .map(|row| {
+            let col = if row == 3 { 4 } else { 0 };
+            AnalysisHost::<TestAnalysisLoader>::is_assigned(text, &span(row, col))
+        })
+        .collect();
+    assert_eq!(assigned, vec![true, false, false, false, false]);
+}
+
+#[test]
+fn test_trait_impls() {
+    let host = AnalysisHost::new_with_loader(TestAnalysisLoader::new(
+        Path::new("test_data/find_impls/save-analysis").to_owned(),
+    ));
+    host.reload(Path::new("test_data/find_impls"), Path::new("test_data/find_impls")).unwrap();
+
+    let trait_impls = |name: &str| {
+        let ids = host.search_for_id(name).unwrap();
+        assert_eq!(ids.len(), 1);
+        let mut impls: Vec<_> = host
+            .trait_impls(ids[0])
+            .unwrap()
+            .into_iter()
+            .map(|i| (i.self_name, i.span.range.row_start.0, i.blanket))
+            .collect();
+        impls.sort();
+        impls
+    };
+
+    assert_eq!(
+        trait_impls("Super"),
+        vec![("Bar".to_owned(), 9, false), ("Foo".to_owned(), 13, false)]
+    );
+    assert_eq!(trait_impls("Sub"), vec![("Foo".to_owned(), 12, false)]);
+    assert_eq!(trait_impls("Blanket"), vec![("T".to_owned(), 16, true)]);
+}
+
+#[test]
+fn test_super_and_sub_traits() {
+    let host = AnalysisHost::new_with_loader(TestAnalysisLoader::new(
+        Path::new("test_data/find_impls/save-analysis").to_owned(),
+    ));
+    host.reload(Path::new("test_data/find_impls"), Path::new("test_data/find_impls")).unwrap();
+
+    let id = |name: &str| {
+        let ids = host.search_for_id(name).unwrap();
+        assert_eq!(ids.len(), 1);
+        ids[0]
+    };
+    let names = |traits: Vec<(Id, Span)>| {
+        let mut names: Vec<_> =
+            traits.into_iter().map(|(id, _)| host.get_def(id).unwrap().name).collect();
+        names.sort();
+        names
+    };
+
+    assert_eq!(names(host.super_traits(id("Sub")).unwrap()), vec!["Super"]);
+    assert_eq!(names(host.sub_traits(id("Super")).unwrap()), vec!["Multi", "Sub"]);
+    // A trait with multiple supertraits.
+    assert_eq!(names(host.super_traits(id("Multi")).unwrap()), vec!["Blanket", "Super"]);
+    assert_eq!(names(host.sub_traits(id("Blanket")).unwrap()), vec!["Multi"]);
+    assert!(host.super_traits(id("Super")).unwrap().is_empty());
+    assert!(host.sub_traits(id("Sub")).unwrap().is_empty());
+
+    let (_, span) = host.super_traits(id("Sub")).unwrap().remove(0);
+    assert_eq!(span, host.get_def(id("Super")).unwrap().span);
+}
+
+#[test]
+fn test_type_hierarchy() {
+    let host = AnalysisHost::new_with_loader(TestAnalysisLoader::new(
+        Path::new("test_data/find_impls/save-analysis").to_owned(),
+    ));
+    host.reload(Path::new("test_data/find_impls"), Path::new("test_data/find_impls")).unwrap();
+
+    let id = |name: &str| {
+        let ids = host.search_for_id(name).unwrap();
+        assert_eq!(ids.len(), 1);
+        ids[0]
+    };
+    let names = |types: Vec<(Id, Span)>| {
+        types.into_iter().map(|(id, _)| host.get_def(id).unwrap().name).collect::<Vec<_>>()
+    };
+
+    // Types have the traits they implement as supertypes, in order of
+    // definition.
+    assert_eq!(names(host.supertypes(id("Foo")).unwrap()), vec!["Super", "Sub"]);
+    assert_eq!(names(host.supertypes(id("Sub")).unwrap()), vec!["Super"]);
+    assert_eq!(names(host.supertypes(id("Multi")).unwrap()), vec!["Super", "Blanket"]);
+
+    // Traits have the types implementing them and their subtraits as
+    // subtypes, but not the types of blanket impls.
+    assert_eq!(names(host.subtypes(id("Super")).unwrap()), vec!["Bar", "Foo", "Sub", "Multi"]);
+    assert!(host
+        .subtypes(id("Blanket"))
+        .unwrap()
+        .iter()
+        .all(|(id, _)| { host.get_def(*id).unwrap().kind == DefKind::Trait }));
+    assert!(host.subtypes(id("Foo")).unwrap().is_empty());
+
+    let (_, span) = host.supertypes(id("Foo")).unwrap().remove(0);
+    assert_eq!(span, host.get_def(id("Super")).unwrap().span);
+
+    // The target of a `Deref` impl is found from the refs in its body.
+    let impl_span = host.find_impls(id("Wrapper")).unwrap().remove(0);
+    assert_eq!(host.deref_target(&impl_span).unwrap(), id("Foo"));
+    let bar_impl = host.find_impls(id("Bar")).unwrap().remove(0);
+    assert!(host.deref_target(&bar_impl).is_err());
+}
+
+#[test]
+fn test_signature_parts() {
+    let host = AnalysisHost::new_with_loader(TestAnalysisLoader::new(
+        Path::new("test_data/signatures/save-analysis").to_owned(),
+    ));
+    host.reload(Path::new("test_data/signatures"), Path::new("test_data/signatures")).unwrap();
+
+    let id = |name: &str| {
+        let ids = host.search_for_id(name).unwrap();
+        assert_eq!(ids.len(), 1);
+        ids[0]
+    };
+
+    // `Option` is defined in `core`, whose data isn't loaded, so it's only
+    // text.
+    assert_eq!(
+        host.signature_parts(id("convert")).unwrap(),
+        vec![
+            SigPart::Text("fn convert(: ".to_owned()),
+            SigPart::Link("Bar".to_owned(), id("Bar")),
+            SigPart::Text(",: u32,) -> Option<Foo> {}".to_owned()),
+        ]
+    );
+    assert_eq!(
+        host.signature_parts(id("main")).unwrap(),
+        vec![SigPart::Text("fn main() {}".to_owned())]
+    );
+    // Locals have no signature.
+    assert!(host.signature_parts(id("count")).unwrap().is_empty());
+}
+
+#[test]
+fn test_outline() {
+    let host = AnalysisHost::new_with_loader(TestAnalysisLoader::new(
+        Path::new("test_data/types/save-analysis").to_owned(),
+    ));
+    host.reload(Path::new("test_data/types"), Path::new("test_data/types")).unwrap();
+
+    let outline = host.outline(Path::new("test_data/types/src/main.rs")).unwrap();
+    let items: Vec<_> = outline
+        .iter()
+        .map(|item| (item.kind, item.name.as_str(), item.span.range.row_start.0, item.depth))
+        .collect();
+    assert_eq!(
+        items,
+        vec![
+            (DefKind::Struct, "Foo", 0, 0),
+            (DefKind::Function, "main", 4, 0),
+            (DefKind::Function, "foo", 9, 0),
+            (DefKind::Const, "TEST_CONST", 11, 0),
+            (DefKind::Static, "TEST_STATIC", 12, 0),
+            (DefKind::Mod, "test_module", 16, 0),
+            (DefKind::Type, "TestType", 17, 1),
+            (DefKind::Union, "TestUnion", 20, 0),
+            (DefKind::Trait, "TestTrait", 24, 0),
+            (DefKind::Method, "test_method", 25, 1),
+            (DefKind::Enum, "FooEnum", 28, 0),
+            (DefKind::TupleVariant, "TupleVariant", 29, 1),
+            (DefKind::StructVariant, "StructVariant", 30, 1),
+            (DefKind::Struct, "Bar", 33, 0),
+            (DefKind::Method, "test_method", 36, 1),
+            (DefKind::Method, "test_method", 40, 1),
+            (DefKind::Method, "inherent", 44, 0),
+            (DefKind::Struct, "Pair", 47, 0),
+        ]
+    );
+}
+
+#[test]
+fn test_goto_def_all() {
+    let host = AnalysisHost::new_with_loader(TestAnalysisLoader::new(
+        Path::new("test_data/shorthand/save-analysis").to_owned(),
+    ));
+    host.reload(Path::new("test_data/shorthand"), Path::new("test_data/shorthand")).unwrap();
+
+    let span = |row: u32, col_start: u32, col_end: u32| {
+        Span::new(
+            Row::new_zero_indexed(row),
+            Row::new_zero_indexed(row),
+            Column::new_zero_indexed(col_start),
+            Column::new_zero_indexed(col_end),
+            Path::new("test_data/shorthand/src/main.rs"),
+        )
+    };
+    let field = span(1, 4, 5);
+    let local = span(5, 8, 9);
+
+    // The field init shorthand refers to both the field and the local.
+    let mut defs = host.goto_def_all(&span(6, 20, 21)).unwrap();
+    defs.sort();
+    assert_eq!(defs, vec![field.clone(), local]);
+
+    // A plain field access has a single definition.
+    assert_eq!(host.goto_def_all(&span(7, 14, 15)).unwrap(), vec![field]);
+    assert!(host.goto_def_all(&span(7, 0, 1)).is_err());
+
+    // The name glob imported into `b` is both a module and a function.
+    let module = span(11, 12, 13);
+    let function = span(15, 11, 12);
+    let import = span(23, 7, 8);
+    let mut defs = host.goto_def_all(&import).unwrap();
+    defs.sort();
+    assert_eq!(defs, vec![module, function]);
+    // Without asking for all of them, only one is returned.
+    assert!(defs.contains(&host.goto_def(&import).unwrap()));
+}
+
+#[test]
+fn test_goto_defs() {
+    let host = AnalysisHost::new_with_loader(TestAnalysisLoader::new(
+        Path::new("test_data/shorthand/save-analysis").to_owned(),
+    ));
+    host.reload(Path::new("test_data/shorthand"), Path::new("test_data/shorthand")).unwrap();
+
+    let span = |row: u32, col_start: u32, col_end: u32| {
+        Span::new(
+            Row::new_zero_indexed(row),
+            Row::new_zero_indexed(row),
+            Column::new_zero_indexed(col_start),
+            Column::new_zero_indexed(col_end),
+            Path::new("test_data/shorthand/src/main.rs"),
+        )
+    };
+
+    let defs = host.goto_defs(&[span(7, 14, 15), span(7, 0, 1), span(7, 12, 13)]).unwrap();
+    assert_eq!(defs, vec![Some(span(1, 4, 5)), None, Some(span(6, 8, 9))]);
+}
+
+#[test]
+fn test_unused_imports() {
+    let host = AnalysisHost::new_with_loader(TestAnalysisLoader::new(
+        Path::new("test_data/unused_imports/save-analysis").to_owned(),
+    ));
+    host.reload(Path::new("test_data/unused_imports"), Path::new("test_data/unused_imports"))
+        .unwrap();
+
+    // `used` is used, `reexported` is re-exported and `*` is a glob. The
+    // comment before `unused_too` does not make it a re-export.
+    let file_name = Path::new("test_data/unused_imports/src/main.rs");
+    let text = fs::read_to_string(file_name).unwrap();
+    let unused = host.unused_imports(file_name, &text).unwrap();
+    let unused: Vec<_> = unused
+        .into_iter()
+        .map(|span| (span.range.row_start.0, span.range.col_start.0, span.range.col_end.0))
+        .collect();
+    assert_eq!(unused, vec![(9, 11, 17), (13, 11, 21)]);
+}
+
+#[test]
+fn test_query_defs_regex() {
+    let host = AnalysisHost::new_with_loader(TestAnalysisLoader::new(
+        Path::new("test_data/types/save-analysis").to_owned(),
+    ));
+    host.reload(Path::new("test_data/types"), Path::new("test_data/types")).unwrap();
+
+    // Matching is case-insensitive, like for the other queries.
+    let query = SymbolQuery::regex("^Foo.*").unwrap();
+    let mut names: Vec<_> = host.query_defs(query).unwrap().into_iter().map(|d| d.name).collect();
+    names.sort();
+    assert_eq!(names, vec!["Foo", "FooEnum", "foo"]);
+}
+
+#[test]
+fn test_unreferenced_defs() {
+    let load = |name: &str| {
+        let dir = Path::new("test_data").join(name);
+        let host =
+            AnalysisHost::new_with_loader(TestAnalysisLoader::new(dir.join("save-analysis")));
+        host.reload(&dir, &dir).unwrap();
+        host.unreferenced_defs(&dir, |path| std::fs::read_to_string(path).ok()).unwrap()
+    };
+
+    // `other` is never called, `main` is the entry point.
+    let defs = load("shadowing");
+    let names: Vec<_> =
+        defs.iter().map(|def| (def.name.as_str(), def.span.range.row_start.0)).collect();
+    assert_eq!(names, vec![("other", 11)]);
+
+    // Every function and method is called, including the trait method
+    // implementations, and trait items are never reported.
+    assert!(load("calls").is_empty());
+    assert!(load("trait_items").is_empty());
+}
+
+#[test]
+fn test_search_directory_priority() {
+    // The same crate data in two directories, the low-priority copy being newer.
+    let root = std::env::temp_dir().join(format!("rls-analysis-priority-{}", std::process::id()));
+    let copy_to = |dir: &str, modified: SystemTime| {
+        let dir = root.join(dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("hello.json");
+        std::fs::copy("test_data/hello/save-analysis/hello.json", &path).unwrap();
+        std::fs::File::options().write(true).open(&path).unwrap().set_modified(modified).unwrap();
+        dir
+    };
+    let now = SystemTime::now();
+    let workspace = copy_to("workspace", now - Duration::from_secs(60));
+    let distro = copy_to("distro", now);
+    let read = |dirs: Vec<(PathBuf, u32)>| {
+        raw::read_analysis_from_files(&MultiDirLoader { dirs }, HashMap::new(), &[] as &[&str])
+    };
+
+    let crates = read(vec![(distro.clone(), 0), (workspace.clone(), 1)]);
+    assert_eq!(crates.len(), 1);
+    assert_eq!(crates[0].path, Some(workspace.join("hello.json")));
+    let crates = read(vec![(workspace.clone(), 1), (distro.clone(), 0)]);
+    assert_eq!(crates[0].path, Some(workspace.join("hello.json")));
+
+    // With equal priorities, the newer data wins.
+    let crates = read(vec![(distro.clone(), 0), (workspace.clone(), 0)]);
+    assert_eq!(crates.len(), 1);
+    assert_eq!(crates[0].path, Some(distro.join("hello.json")));
+
+    // With equal timestamps too, the choice doesn't depend on the order.
+    copy_to("workspace", now);
+    for dirs in [
+        vec![(distro.clone(), 0), (workspace.clone(), 0)],
+        vec![(workspace, 0), (distro.clone(), 0)],
+    ] {
+        assert_eq!(read(dirs)[0].path, Some(distro.join("hello.json")));
+    }
+
+    std::fs::remove_dir_all(&root).unwrap();
+}
+
+#[test]
+fn test_custom_search_directory() {
+    // Analysis data outside of the Cargo layout, as from another build system.
+    let dir = Path::new("test_data/hello/save-analysis");
+    let base_dir = Path::new("test_data/hello");
+    let mut loader = CargoAnalysisLoader::new(Target::Debug)
+        .with_search_directory(SearchDirectory::new(dir.to_owned(), None));
+    loader.set_path_prefix(base_dir);
+    let dirs = loader.search_directories();
+    assert_eq!(dirs.len(), 3);
+    assert!(dirs[1].path.ends_with("target/rls/debug/deps/save-analysis"));
+    assert_eq!(dirs[2].path, dir);
+
+    let host = AnalysisHost::new_with_loader(loader);
+    host.reload(base_dir, base_dir).unwrap();
+    assert_eq!(host.search_for_id("print_hello").unwrap().len(), 1);
+}
+
+#[cfg(feature = "derive")]
+#[test]
+fn test_save_and_load_index() {
+    let loader =
+        TestAnalysisLoader::new(Path::new("test_data/multi_crate/save-analysis").to_owned());
+    let base_dir = Path::new("test_data/multi_crate");
+    let index_path =
+        std::env::temp_dir().join(format!("rls-analysis-index-{}.json", std::process::id()));
+
+    let host = AnalysisHost::new_with_loader(loader.clone());
+    host.reload(base_dir, base_dir).unwrap();
+    host.save_index(&index_path).unwrap();
+
+    let loaded = AnalysisHost::new_with_loader(loader.clone());
+    assert!(loaded.load_index(&index_path, base_dir, base_dir).unwrap());
+
+    let id = host.search_for_id("greet").unwrap()[0];
+    assert_eq!(loaded.search_for_id("greet").unwrap(), vec![id]);
+    assert_eq!(loaded.get_def(id).unwrap().qualname, host.get_def(id).unwrap().qualname);
+    let mut refs = loaded.find_all_refs_by_id(id).unwrap();
+    refs.sort();
+    let mut expected_refs = host.find_all_refs_by_id(id).unwrap();
+    expected_refs.sort();
+    assert_eq!(refs, expected_refs);
+    let main_ref = refs.iter().find(|s| s.file.ends_with("main.rs")).unwrap();
+    assert_eq!(loaded.goto_def(main_ref).unwrap(), host.goto_def(main_ref).unwrap());
+    let names = |host: &AnalysisHost<TestAnalysisLoader>| {
+        host.matching_defs("gr").unwrap().into_iter().map(|d| d.qualname).collect::<HashSet<_>>()
+    };
+    assert_eq!(names(&loaded), names(&host));
+
+    // An index written by a different version falls back to reading the
+    // save-analysis data.
+    let mut index: serde_json::Value =
+        serde_json::from_str(&std::fs::read_to_string(&index_path).unwrap()).unwrap();
+    let version = index["version"].as_u64().unwrap();
+    index["version"] = (version + 1).into();
+    std::fs::write(&index_path, index.to_string()).unwrap();
+    let reloaded = AnalysisHost::new_with_loader(loader.clone());
+    assert!(!reloaded.load_index(&index_path, base_dir, base_dir).unwrap());
+    assert_eq!(reloaded.search_for_id("greet").unwrap(), vec![id]);
+
+    // So does an unreadable index.
+    std::fs::write(&index_path, "not an index").unwrap();
+    let reloaded = AnalysisHost::new_with_loader(loader);
+    assert!(!reloaded.load_index(&index_path, base_dir, base_dir).unwrap());
+    assert_eq!(reloaded.search_for_id("greet").unwrap(), vec![id]);
+
+    std::fs::remove_file(&index_path).unwrap();
+}
+
+#[cfg(feature = "derive")]
+#[test]
+fn test_unload_and_restore() {
+    let loader =
+        TestAnalysisLoader::new(Path::new("test_data/multi_crate/save-analysis").to_owned());
+    let base_dir = Path::new("test_data/multi_crate");
+    let index_path =
+        std::env::temp_dir().join(format!("rls-analysis-unload-{}.json", std::process::id()));
+
+    let host = Arc::new(AnalysisHost::new_with_loader(loader));
+    host.reload(base_dir, base_dir).unwrap();
+    let id = host.search_for_id("greet").unwrap()[0];
+    let expected_refs = host.find_all_refs_by_id(id).unwrap();
+
+    host.unload(&index_path, base_dir, base_dir).unwrap();
+    assert!(host.analysis.lock().unwrap().is_none());
+    assert!(index_path.exists());
+
+    // Requests made while the data is unloaded wait for it to be restored.
+    let threads: Vec<_> = (0..2)
+        .map(|_| {
+            let host = Arc::clone(&host);
+            thread::spawn(move || host.find_all_refs_by_id(id).unwrap())
+        })
+        .collect();
+    for thread in threads {
+        assert_eq!(thread.join().unwrap(), expected_refs);
+    }
+    assert!(host.has_def(id));
+    assert!(!index_path.exists());
+
+    // A reload replaces the unloaded data rather than being overwritten by it.
+    host.unload(&index_path, base_dir, base_dir).unwrap();
+    host.hard_reload(base_dir, base_dir).unwrap();
+    assert!(!index_path.exists());
+    assert_eq!(host.search_for_id("greet").unwrap(), vec![id]);
+}
+
+#[test]
+fn test_find_all_refs_sorted() {
+    let host = AnalysisHost::new_with_loader(TestAnalysisLoader::new(
+        Path::new("test_data/types/save-analysis").to_owned(),
+    ));
+    host.reload(Path::new("test_data/types"), Path::new("test_data/types")).unwrap();
+
+    let id = host.search_for_id("Foo").unwrap()[0];
+    let decl = host.get_def(id).unwrap().span;
+
+    let refs = host
+        .find_all_refs(
+            &decl,
+            FindRefsOptions { include_decl: true, sorted: true, ..FindRefsOptions::default() },
+        )
+        .unwrap();
+    assert!(refs.len() > 2);
+    assert_eq!(refs[0], decl);
+    let key = |s: &Span| {
+        (s.file.clone(), s.range.row_start, s.range.col_start, s.range.row_end, s.range.col_end)
+    };
+    for pair in refs[1..].windows(2) {
+        assert!(key(&pair[0]) < key(&pair[1]), "{:?} is not before {:?}", pair[0], pair[1]);
+    }
+    assert!(!refs[1..].contains(&decl));
+
+    // The same refs as unsorted.
+    let mut unsorted = host.find_all_refs(&decl, FindRefsOptions::default()).unwrap();
+    unsorted.sort_by_key(key);
+    unsorted.dedup();
+    assert_eq!(unsorted, refs[1..].to_vec());
+}
+
+// Rustc no longer records macro invocations or the references made by their
+// expansions, so the `macro_refs` of the `macros` fixture and the references to
+// `LIMIT` in `clamp!` were added to its save-analysis data by hand, in the
+// format older compilers wrote.
+#[test]
+fn test_goto_def_macro() {
+    let host = AnalysisHost::new_with_loader(TestAnalysisLoader::new(
+        Path::new("test_data/macros/save-analysis").to_owned(),
+    ));
+    host.reload(Path::new("test_data/macros"), Path::new("test_data/macros")).unwrap();
+
+    let file = Path::new("test_data/macros/src/main.rs");
+    let invocation_name = Span::new(
+        Row::new_zero_indexed(7),
+        Row::new_zero_indexed(7),
+        Column::new_zero_indexed(12),
+        Column::new_zero_indexed(18),
+        file,
+    );
+    let macro_name = Span::new(
+        Row::new_zero_indexed(0),
+        Row::new_zero_indexed(0),
+        Column::new_zero_indexed(13),
+        Column::new_zero_indexed(19),
+        file,
+    );
+    assert_eq!(host.goto_def(&invocation_name), Ok(macro_name.clone()));
+    assert_eq!(host.goto_def_all(&invocation_name), Ok(vec![macro_name]));
+
+    // Refs in the invocation still resolve to their own definitions.
+    let x_ref = Span::new(
+        Row::new_zero_indexed(8),
+        Row::new_zero_indexed(8),
+        Column::new_zero_indexed(19),
+        Column::new_zero_indexed(20),
+        file,
+    );
+    let x_def = host.goto_def(&x_ref).unwrap();
+    assert_eq!(x_def.range.row_start, Row::new_zero_indexed(7));
+
+    // Built-in macros have no definition, but are documented.
+    let println_name = Span::new(
+        Row::new_zero_indexed(8),
+        Row::new_zero_indexed(8),
+        Column::new_zero_indexed(4),
+        Column::new_zero_indexed(11),
+        file,
+    );
+    assert!(host.goto_def(&println_name).is_err());
+    assert_eq!(
+        host.doc_url(&println_name),
+        Ok("https://doc.rust-lang.org/nightly/std/macro.println.html".to_owned())
+    );
+}
+
+#[test]
+fn test_find_all_refs_in_expansions() {
+    let host = AnalysisHost::new_with_loader(TestAnalysisLoader::new(
+        Path::new("test_data/macros/save-analysis").to_owned(),
+    ));
+    host.reload(Path::new("test_data/macros"), Path::new("test_data/macros")).unwrap();
+
+    let file = Path::new("test_data/macros/src/main.rs");
+    let span = |line, col_start, col_end| {
+        Span::new(
+            Row::new_zero_indexed(line),
+            Row::new_zero_indexed(line),
+            Column::new_zero_indexed(col_start),
+            Column::new_zero_indexed(col_end),
+            file,
+        )
+    };
+    let decl = span(11, 7, 12);
+    let find_refs = |span: &Span, include_decl, expansion_refs| {
+        let options =
+            FindRefsOptions { include_decl, sorted: true, expansion_refs, ..Default::default() };
+        host.find_all_refs(span, options).unwrap()
+    };
+
+    // Both references in the definition map to the one invocation.
+    let remapped = find_refs(&decl, true, ExpansionRefs::Remap);
+    assert_eq!(remapped, vec![decl.clone(), span(24, 4, 13)]);
+
+    let excluded = find_refs(&decl, true, ExpansionRefs::Exclude);
+    assert_eq!(excluded, vec![decl.clone()]);
+
+    let kept = find_refs(&decl, true, ExpansionRefs::Keep);
+    assert_eq!(kept, vec![decl, span(15, 16, 21), span(16, 12, 17)]);
+
+    // References written in the arguments of the invocation are kept.
+    let y = span(23, 11, 12);
+    let refs = find_refs(&y, false, ExpansionRefs::Remap);
+    assert_eq!(refs, vec![span(24, 11, 12)]);
+}
+
+#[test]
+fn test_callers_and_callees() {
+    let host = AnalysisHost::new_with_loader(TestAnalysisLoader::new(
+        Path::new("test_data/calls/save-analysis").to_owned(),
+    ));
+    host.reload(Path::new("test_data/calls"), Path::new("test_data/calls")).unwrap();
+
+    let id = |name: &str| host.search_for_id(name).unwrap()[0];
+    // (name, zero-indexed row of the call) pairs.
+    let names = |calls: Vec<(Id, Span)>| {
+        calls
+            .into_iter()
+            .map(|(id, span)| (host.get_def(id).unwrap().name, span.range.row_start.0))
+            .collect::<Vec<_>>()
+    };
+
+    assert_eq!(
+        names(host.callers_of(id("bar")).unwrap()),
+        vec![("foo".to_owned(), 16), ("main".to_owned(), 30)]
+    );
+    // Method calls and calls through a path both count.
+    assert_eq!(
+        names(host.callers_of(id("bump")).unwrap()),
+        vec![("foo".to_owned(), 15), ("main".to_owned(), 31)]
+    );
+    assert_eq!(
+        names(host.callers_of(id("countdown")).unwrap()),
+        vec![("countdown".to_owned(), 23), ("main".to_owned(), 32)]
+    );
+    assert!(host.callers_of(id("main")).unwrap().is_empty());
+
+    assert_eq!(names(host.callees_of(id("bump")).unwrap()), vec![("add_one".to_owned(), 6)]);
+    assert_eq!(
+        names(host.callees_of(id("countdown")).unwrap()),
+        vec![("countdown".to_owned(), 23)]
+    );
+    assert!(host.callees_of(id("bar")).unwrap().is_empty());
+}
+
+#[test]
+fn test_callees_of() {
+    let host = AnalysisHost::new_with_loader(TestAnalysisLoader::new(
+        Path::new("test_data/calls/save-analysis").to_owned(),
+    ));
+    host.reload(Path::new("test_data/calls"), Path::new("test_data/calls")).unwrap();
+
+    let main = host.search_for_id("main").unwrap()[0];
+    let callees: Vec<_> = host
+        .callees_of(main)
+        .unwrap()
+        .into_iter()
+        .map(|(id, span)| (host.get_def(id).unwrap().name, span.range.row_start.0))
+        .collect();
+
+    // Method syntax and UFCS calls are both captured, each with its call site,
+    // including calls of a trait method.
+    assert_eq!(
+        callees,
+        vec![
+            ("foo".to_owned(), 29),
+            ("bar".to_owned(), 30),
+            ("bump".to_owned(), 31),
+            ("countdown".to_owned(), 32),
+            ("reset".to_owned(), 33),
+            ("reset".to_owned(), 34),
+        ]
+    );
+}
@@ -16,3 +16,102 @@ pub fn get_resident() -> Option<usize> {
 pub fn get_resident() -> Option<usize> {
     None
 }
+
+/// (De)serializes a `HashMap` as a sequence of key-value pairs, for maps whose
+/// keys can't be used as keys of a JSON object (e.g., spans).
+#[cfg(feature = "derive")]
+pub mod map_as_seq {
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+    use std::collections::HashMap;
+    use std::hash::Hash;
+
+    pub fn serialize<K, V, S>(map: &HashMap<K, V>, s: S) -> Result<S::Ok, S::Error>
+    where
+        K: Serialize,
+        V: Serialize,
+        S: Serializer,
+    {
+        s.collect_seq(map)
+    }
+
+    pub fn deserialize<'de, K, V, D>(d: D) -> Result<HashMap<K, V>, D::Error>
+    where
+        K: Deserialize<'de> + Eq + Hash,
+        V: Deserialize<'de>,
+        D: Deserializer<'de>,
+    {
+        Vec::<(K, V)>::deserialize(d).map(|pairs| pairs.into_iter().collect())
+    }
+}
+
+/// Returns `text` with comments and the contents of string and character
+/// literals replaced by spaces. Line breaks are kept, so positions in rows and
+/// (char) columns are the same as in `text`.
+pub fn blank_comments_and_literals(text: &str) -> String {
+    let chars: Vec<char> = text.chars().collect();
+    let at = |i: usize| chars.get(i).cloned();
+    let is_ident_char = |c: char| c.is_alphanumeric() || c == '_';
+    let find = |from: usize, pat: &[char]| {
+        (from..chars.len()).find(|&j| chars[j..].starts_with(pat)).map_or(chars.len(), |j| j)
+    };
+
+    let mut result = String::with_capacity(text.len());
+    let mut i = 0;
+    while let Some(c) = at(i) {
+        let prev_is_ident = i > 0 && is_ident_char(chars[i - 1]);
+        // Where the comment or literal starting at `i` ends, if any.
+        let end = match (c, at(i + 1)) {
+            ('/', Some('/')) => find(i, &['\n']),
+            ('/', Some('*')) => {
+                let mut depth = 0;
+                let mut j = i;
+                while j < chars.len() {
+                    if chars[j..].starts_with(&['/', '*']) {
+                        depth += 1;
+                        j += 2;
+                    } else if chars[j..].starts_with(&['*', '/']) {
+                        depth -= 1;
+                        j += 2;
+                        if depth == 0 {
+                            break;
+                        }
+                    } else {
+                        j += 1;
+                    }
+                }
+                j
+            }
+            ('"', _) => {
+                let mut j = i + 1;
+                while at(j).is_some_and(|c| c != '"') {
+                    j += if at(j) == Some('\\') { 2 } else { 1 };
+                }
+                j + 1
+            }
+            ('r', Some('"')) | ('r', Some('#')) if !prev_is_ident => {
+                let hashes = chars[i + 1..].iter().take_while(|&&c| c == '#').count();
+                if at(i + 1 + hashes) == Some('"') {
+                    let mut closing = vec!['"'];
+                    closing.extend(std::iter::repeat_n('#', hashes));
+                    find(i + 2 + hashes, &closing) + closing.len()
+                } else {
+                    i
+                }
+            }
+            // A character rather than a lifetime.
+            ('\'', Some('\\')) => find(i + 3, &['\'']) + 1,
+            ('\'', Some(_)) if at(i + 2) == Some('\'') => i + 3,
+            _ => i,
+        };
+
+        if end == i {
+            result.push(c);
+            i += 1;
+        } else {
+            let end = end.min(chars.len());
+            result.extend(chars[i..end].iter().map(|&c| if c == '\n' { '\n' } else { ' ' }));
+            i = end;
+        }
+    }
+    result
+}
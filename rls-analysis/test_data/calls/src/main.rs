@@ -0,0 +1,46 @@
+struct Counter {
+    count: u32,
+}
+
+impl Counter {
+    fn bump(&mut self) {
+        self.count = add_one(self.count);
+    }
+}
+
+fn add_one(x: u32) -> u32 {
+    x + 1
+}
+
+fn foo(c: &mut Counter) {
+    c.bump();
+    bar();
+}
+
+fn bar() {}
+
+fn countdown(n: u32) {
+    if n > 0 {
+        countdown(n - 1);
+    }
+}
+
+fn main() {
+    let mut c = Counter { count: 0 };
+    foo(&mut c);
+    bar();
+    Counter::bump(&mut c);
+    countdown(3);
+    c.reset();
+    Reset::reset(&mut c);
+}
+
+trait Reset {
+    fn reset(&mut self);
+}
+
+impl Reset for Counter {
+    fn reset(&mut self) {
+        self.count = 0;
+    }
+}
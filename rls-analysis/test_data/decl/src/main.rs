@@ -0,0 +1,13 @@
+trait Greet {
+    fn greet(&self);
+}
+
+struct Foo;
+
+impl Greet for Foo {
+    fn greet(&self) {}
+}
+
+fn main() {
+    Foo.greet();
+}
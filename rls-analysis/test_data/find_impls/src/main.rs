@@ -0,0 +1,31 @@
+#![allow(dead_code)]
+
+#[derive(PartialEq)]
+struct Bar;
+struct Foo;
+
+trait Super{}
+trait Sub: Super {}
+
+impl Super for Bar {}
+impl Eq for Bar {}
+
+impl Sub for Foo {}
+impl Super for Foo {}
+
+trait Blanket {}
+impl<T> Blanket for T {}
+
+fn main() {}
+
+trait Multi: Super + Blanket {}
+
+struct Wrapper(Foo);
+
+impl std::ops::Deref for Wrapper {
+    type Target = Foo;
+
+    fn deref(&self) -> &Foo {
+        &self.0
+    }
+}
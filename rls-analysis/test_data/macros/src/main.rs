@@ -0,0 +1,26 @@
+macro_rules! double {
+    ($e:expr) => {
+        $e * 2
+    };
+}
+
+fn main() {
+    let x = double!(21);
+    println!("{}", x);
+}
+
+static LIMIT: i32 = 100;
+
+macro_rules! clamp {
+    ($e:expr) => {
+        if $e > LIMIT {
+            LIMIT
+        } else {
+            $e
+        }
+    };
+}
+
+fn clamped(y: i32) -> i32 {
+    clamp!(y)
+}
@@ -36,3 +36,42 @@ build exprs exprs/save-analysis
 
 # all_ref_unique
 build rename rename/save-analysis
+
+# update_crate
+build multi_crate multi_crate/save-analysis
+
+# glob_imports
+build deglob deglob/save-analysis
+
+# goto_decl
+build decl decl/save-analysis
+
+# trait_impls
+build find_impls find_impls/save-analysis
+
+# goto_def_all
+build shorthand shorthand/save-analysis
+
+# unused_imports
+build unused_imports unused_imports/save-analysis
+
+# goto_def on macro invocations
+build macros macros/save-analysis
+
+# callers_of and callees_of
+build calls calls/save-analysis
+
+# impl_trait (doesn't compile, since an impl is missing items), with
+# signatures enabled in the save-analysis config
+RUST_SAVE_ANALYSIS_CONFIG='{"output_file":null,"full_docs":false,"pub_only":false,"reachable_only":false,"distro_crate":false,"signatures":true,"borrow_data":false}' \
+    build trait_items trait_items/save-analysis
+
+# shadows_of
+build shadowing shadowing/save-analysis
+
+# Cross-crate references in a workspace
+build workspace workspace/save-analysis
+
+# signature_parts (with signatures enabled in the save-analysis config)
+RUST_SAVE_ANALYSIS_CONFIG='{"output_file":null,"full_docs":false,"pub_only":false,"reachable_only":false,"distro_crate":false,"signatures":true,"borrow_data":false}' \
+    build signatures signatures/save-analysis
@@ -0,0 +1,6 @@
+extern crate multi_crate;
+
+fn main() {
+    let greeting = multi_crate::greet("world");
+    println!("{}", greeting);
+}
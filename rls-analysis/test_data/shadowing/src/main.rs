@@ -0,0 +1,15 @@
+fn main() {
+    let x = 1;
+    let x = x + 2;
+    {
+        let x = x * 2;
+        println!("{}", x);
+    }
+    let y = x;
+    println!("{}", y);
+}
+
+fn other() -> u32 {
+    let x = 4;
+    x
+}
@@ -0,0 +1,29 @@
+struct Point {
+    x: u32,
+}
+
+fn main() {
+    let x = 1;
+    let p = Point { x };
+    let _ = p.x;
+}
+
+mod a {
+    pub mod m {
+        pub fn f() {}
+    }
+
+    pub fn m() {}
+}
+
+mod b {
+    pub use super::a::*;
+}
+
+// `m` is glob imported into `b` as both a module and a function.
+use b::m;
+
+fn both() {
+    m();
+    m::f();
+}
@@ -0,0 +1,10 @@
+#![allow(dead_code, unused_variables)]
+
+struct Foo;
+struct Bar;
+
+fn convert(bar: Bar, count: u32) -> Option<Foo> {
+    None
+}
+
+fn main() {}
@@ -0,0 +1,25 @@
+trait TestTrait {
+    type Output;
+    const LIMIT: u32;
+
+    fn test_method(&self);
+    fn provided(&self) -> u32 {
+        0
+    }
+    fn other(&self, x: u32) -> Option<Self::Output>;
+}
+
+struct Foo;
+
+impl TestTrait for Foo {
+    type Output = u32;
+    const LIMIT: u32 = 1;
+
+    fn other(&self, x: u32) -> Option<u32> {
+        Some(x)
+    }
+}
+
+fn main() {
+    Foo.test_method();
+}
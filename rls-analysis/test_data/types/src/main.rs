@@ -30,3 +30,19 @@ enum FooEnum {
     TupleVariant,
     StructVariant { x: u8 },
 }
+
+struct Bar;
+
+impl TestTrait for Foo {
+    fn test_method(&self) {}
+}
+
+impl TestTrait for Bar {
+    fn test_method(&self) {}
+}
+
+impl Foo {
+    fn inherent(&self) {}
+}
+
+struct Pair(u8, String);
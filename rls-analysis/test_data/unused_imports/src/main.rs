@@ -0,0 +1,19 @@
+mod inner {
+    pub fn used() {}
+    pub fn unused() {}
+    pub fn reexported() {}
+    pub fn via_glob() {}
+    pub fn unused_too() {}
+}
+
+pub use inner::reexported;
+use inner::unused;
+use inner::used;
+use inner::*;
+// This used to be pub
+use inner::unused_too;
+
+fn main() {
+    used();
+    via_glob();
+}
@@ -0,0 +1,7 @@
+use util::Config;
+
+fn main() {
+    let config = Config { verbose: true };
+    let name = String::from("app");
+    println!("{}: {}", name, util::helper(&config));
+}
@@ -0,0 +1,7 @@
+pub struct Config {
+    pub verbose: bool,
+}
+
+pub fn helper(config: &Config) -> u32 {
+    if config.verbose { 1 } else { 0 }
+}
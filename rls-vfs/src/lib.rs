@@ -4,15 +4,23 @@ extern crate rls_span as span;
 #[macro_use]
 extern crate log;
 
-use std::collections::HashMap;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{HashMap, HashSet};
+use std::ffi::OsStr;
 use std::fmt;
 use std::fs;
+use std::hash::Hasher;
 use std::io::Read;
 use std::marker::PhantomData;
 use std::mem;
+use std::ops::{Deref, DerefMut};
+use std::panic::{self, AssertUnwindSafe};
 use std::path::{Path, PathBuf};
-use std::sync::Mutex;
+use std::sync::{Mutex, MutexGuard, RwLock};
 use std::thread::{self, Thread};
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
 
 #[cfg(test)]
 mod test;
@@ -28,6 +36,26 @@ macro_rules! try_opt_loc {
 
 pub struct Vfs<U = ()>(VfsInternal<RealFileLoader, U>);
 
+/// Extensions of files which are always treated as text, see
+/// `Vfs::with_text_extensions`.
+pub const DEFAULT_TEXT_EXTENSIONS: &[&str] = &["rs", "toml"];
+
+/// The languages of files with these extensions, unless registered otherwise
+/// with `Vfs::register_extension`.
+pub const DEFAULT_LANGUAGES: &[(&str, Language)] =
+    &[("rs", Language::Rust), ("toml", Language::Toml)];
+
+/// What a file is written in, see `Vfs::language`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Language {
+    Rust,
+    Toml,
+}
+
+/// How long to wait for another thread to load a file before giving up on it,
+/// see `Vfs::set_load_timeout`.
+pub const DEFAULT_LOAD_TIMEOUT: Duration = Duration::from_secs(60);
+
 /// Span of the text to be replaced defined in col/row terms.
 #[derive(Debug, Hash, PartialEq, Eq, PartialOrd, Ord)]
 pub struct SpanData {
@@ -183,6 +211,80 @@ impl<U> Vfs<U> {
         Vfs(VfsInternal::<RealFileLoader, U>::new())
     }
 
+    /// Creates a new, empty VFS which always treats files with one of the
+    /// given extensions (e.g., `"rs"`) as text. Such files which are not valid
+    /// UTF-8 are reported as `Error::Io` rather than loaded as binary files.
+    ///
+    /// `Vfs::new` uses `DEFAULT_TEXT_EXTENSIONS`.
+    pub fn with_text_extensions<I, S>(extensions: I) -> Vfs<U>
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<str>,
+    {
+        Vfs(VfsInternal::<RealFileLoader, U>::with_text_extensions(extensions))
+    }
+
+    /// Sets how long to wait for another thread which is loading the same file
+    /// (e.g., in `load_file`). If the file is still not loaded by then, the
+    /// other thread is assumed to have died, and the waiting call returns an
+    /// `Error::Io`, so the file is loaded afresh by the next call.
+    ///
+    /// Defaults to `DEFAULT_LOAD_TIMEOUT`, which is generous so that slow
+    /// reads don't time out.
+    pub fn set_load_timeout(&mut self, timeout: Duration) {
+        self.0.load_timeout = timeout;
+    }
+
+    /// Sets whether to normalize the CRLF line endings of files to LF ones when
+    /// they are loaded from disk, so that their lines and columns are the same
+    /// as those of the text without the `\r`s. Such files are written back
+    /// with CRLF line endings. Files with mixed line endings are left as they
+    /// are, since their line endings couldn't be restored.
+    ///
+    /// Off by default. Only affects files loaded afterwards.
+    pub fn set_normalize_line_endings(&mut self, normalize: bool) {
+        self.0.normalize_line_endings = normalize;
+    }
+
+    /// Keeps the contents of text files as of when `file_saved` was last
+    /// called for them, to be returned by `saved_content` (e.g., to compare
+    /// unsaved edits with what was last built).
+    ///
+    /// Off by default, since it takes a copy of every saved file.
+    pub fn set_keep_saved_content(&mut self, keep: bool) {
+        self.0.keep_saved_content = keep;
+    }
+
+    /// Treats files whose names end with the given extension (e.g., `"rs.in"`
+    /// for `foo.rs.in`, with or without the leading dot) as written in
+    /// `language`, and always as text. Where registered extensions overlap,
+    /// the longest one which matches a file decides its language.
+    ///
+    /// Files which are already loaded are only read as text once reloaded.
+    /// This only affects how the VFS and its users treat such files: it
+    /// doesn't make a crate include them.
+    pub fn register_extension(&self, extension: &str, language: Language) {
+        self.0.register_extension(extension, language)
+    }
+
+    /// Undoes `register_extension` for the given extension, so that its files
+    /// are treated as they were before (e.g., `.rs` files as Rust again).
+    pub fn unregister_extension(&self, extension: &str) {
+        self.0.unregister_extension(extension)
+    }
+
+    /// Returns the extensions (without the leading dot) registered with
+    /// `register_extension`, and their languages.
+    pub fn registered_extensions(&self) -> Vec<(String, Language)> {
+        self.0.registered_extensions()
+    }
+
+    /// Returns the language of the file at `path` by its extension, if the
+    /// extension is known (see `DEFAULT_LANGUAGES` and `register_extension`).
+    pub fn language(&self, path: &Path) -> Option<Language> {
+        self.0.language(path)
+    }
+
     /// Indicate that the current file as known to the VFS has been written to
     /// disk.
     pub fn file_saved(&self, path: &Path) -> Result<(), Error> {
@@ -195,10 +297,23 @@ impl<U> Vfs<U> {
         self.0.flush_file(path)
     }
 
+    /// Removes all files which are synced with the disk from the VFS, keeping
+    /// only unsaved changes.
+    pub fn flush_all(&self) {
+        self.0.flush_all()
+    }
+
     pub fn file_is_synced(&self, path: &Path) -> Result<bool, Error> {
         self.0.file_is_synced(path)
     }
 
+    /// Returns the contents of the text file at `path` as of when it was last
+    /// saved, or `None` if it wasn't saved since it was loaded. Only recorded
+    /// if `set_keep_saved_content` is on.
+    pub fn saved_content(&self, path: &Path) -> Result<Option<String>, Error> {
+        self.0.saved_content(path)
+    }
+
     /// Record a set of changes to the VFS.
     pub fn on_changes(&self, changes: &[Change]) -> Result<(), Error> {
         self.0.on_changes(changes)
@@ -213,19 +328,53 @@ impl<U> Vfs<U> {
         self.0.get_changes()
     }
 
+    /// Returns the paths of all files in the VFS (changed or not), without
+    /// copying their contents.
+    pub fn cached_paths(&self) -> Vec<PathBuf> {
+        self.0.cached_paths()
+    }
+
     /// Returns true if the VFS contains any changed files.
     pub fn has_changes(&self) -> bool {
         self.0.has_changes()
     }
 
+    /// Returns all files which have been changed in memory (including being
+    /// set with `set_file`) at or after `since`, whether or not the changes
+    /// have been saved since.
+    pub fn changed_since(&self, since: Instant) -> Vec<PathBuf> {
+        self.0.changed_since(since)
+    }
+
     pub fn set_file(&self, path: &Path, text: &str) {
         self.0.set_file(path, text)
     }
 
+    /// Like `set_file` for each of `files`, but takes the locks once rather
+    /// than once per file, e.g., to restore many open documents. If a path
+    /// appears more than once, its last text is kept.
+    pub fn set_files(&self, files: &[(PathBuf, String)]) {
+        self.0.set_files(files)
+    }
+
     pub fn load_file(&self, path: &Path) -> Result<FileContents, Error> {
         self.0.load_file(path)
     }
 
+    /// Returns the contents of the file as bytes, whether it is a text or a
+    /// binary file.
+    pub fn load_bytes(&self, path: &Path) -> Result<Vec<u8>, Error> {
+        self.0.load_bytes(path)
+    }
+
+    /// Returns the contents of the file at `path` with the `changes` to it
+    /// applied, without storing them. The changes are all relative to the
+    /// current contents, as the edits of an LSP `TextDocumentEdit` are, and
+    /// overlapping changes are an error. Changes to other files are ignored.
+    pub fn preview_edits(&self, path: &Path, changes: &[Change]) -> Result<String, Error> {
+        self.0.preview_edits(path, changes)
+    }
+
     pub fn load_line(
         &self,
         path: &Path,
@@ -243,6 +392,61 @@ impl<U> Vfs<U> {
         self.0.load_lines(path, line_start, line_end)
     }
 
+    /// Returns up to `2 * radius + 1` lines centered on `center_line`, fewer
+    /// near the start or end of the file, for showing the context of a
+    /// diagnostic. Each line is paired with its one-based line number, and
+    /// doesn't include its line terminator.
+    pub fn load_context(
+        &self,
+        path: &Path,
+        center_line: span::Row<span::ZeroIndexed>,
+        radius: usize,
+    ) -> Result<Vec<(usize, String)>, Error> {
+        self.0.load_context(path, center_line, radius)
+    }
+
+    /// Like `load_line`, but returns the line as UTF-16 code units, as used
+    /// for LSP positions. The UTF-16 text of all lines of a file is computed
+    /// on first use and cached until the file is changed.
+    pub fn load_line_utf16(
+        &self,
+        path: &Path,
+        line: span::Row<span::ZeroIndexed>,
+    ) -> Result<Vec<u16>, Error> {
+        self.0.load_line_utf16(path, line)
+    }
+
+    /// Returns the length of a line in UTF-16 code units, excluding the line
+    /// terminator. Uses the same cache as `load_line_utf16`.
+    pub fn line_len_utf16(
+        &self,
+        path: &Path,
+        line: span::Row<span::ZeroIndexed>,
+    ) -> Result<u32, Error> {
+        self.0.line_len_utf16(path, line)
+    }
+
+    /// Returns a hash of the contents of the file, the bytes of a binary file,
+    /// e.g., to key caches on. The hash of a text file is computed on first use
+    /// and cached until the file is changed. It is only meaningful within a
+    /// run, so it shouldn't be persisted.
+    pub fn content_hash(&self, path: &Path) -> Result<u64, Error> {
+        self.0.content_hash(path)
+    }
+
+    /// Returns the number of lines of a text file. As in an editor, an empty
+    /// file has one line, and so does the (empty) text after a trailing
+    /// newline.
+    pub fn line_count(&self, path: &Path) -> Result<usize, Error> {
+        self.0.line_count(path)
+    }
+
+    /// Returns the number of characters (Unicode scalar values) of a text
+    /// file, including line terminators.
+    pub fn char_count(&self, path: &Path) -> Result<usize, Error> {
+        self.0.char_count(path)
+    }
+
     pub fn load_span(&self, span: span::Span<span::ZeroIndexed>) -> Result<String, Error> {
         self.0.load_span(span)
     }
@@ -258,6 +462,29 @@ impl<U> Vfs<U> {
         self.0.write_file(path)
     }
 
+    /// Returns the changes which turn the file at `path` as it is on disk into
+    /// its in-memory contents. Each change replaces whole lines and its span is
+    /// in terms of the on-disk text; the changes are ordered from the end of
+    /// the file to the start, so they can be applied in order. Returns no
+    /// changes if the file is not cached or has the same contents as on disk.
+    pub fn disk_diff(&self, path: &Path) -> Result<Vec<Change>, Error> {
+        self.0.disk_diff(path)
+    }
+
+    /// Returns the text files in the VFS, e.g., to persist unsaved changes so
+    /// they can be restored with `restore` if the process restarts.
+    pub fn snapshot(&self) -> VfsSnapshot {
+        self.0.snapshot()
+    }
+
+    /// Adds the files of `snapshot` to the VFS, replacing any cached ones.
+    /// Files which are different on disk from the snapshot (e.g., because
+    /// they were changed on disk since) keep the text from the snapshot, but
+    /// are marked as changed.
+    pub fn restore(&self, snapshot: VfsSnapshot) {
+        self.0.restore(snapshot)
+    }
+
     pub fn set_user_data(&self, path: &Path, data: Option<U>) -> Result<(), Error> {
         self.0.set_user_data(path, data)
     }
@@ -284,26 +511,190 @@ impl<U> Vfs<U> {
 }
 
 // Important invariants! If you are going to lock both files and pending_files,
-// you must lock pending_files first.
+// you must lock pending_files first. This is checked in debug builds, see
+// `OrderedGuard`.
 // You must have both locks to insert or remove files.
 struct VfsInternal<T, U> {
     files: Mutex<HashMap<PathBuf, File<U>>>,
     pending_files: Mutex<HashMap<PathBuf, Vec<Thread>>>,
     loader: PhantomData<T>,
+    // Extensions (without the leading dot) of files which are always text.
+    text_extensions: RwLock<HashSet<String>>,
+    // The text extensions the VFS was created with.
+    default_text_extensions: HashSet<String>,
+    // The languages of files by extension (without the leading dot).
+    languages: RwLock<HashMap<String, Language>>,
+    // The extensions registered with `register_extension`.
+    registered_extensions: RwLock<HashMap<String, Language>>,
+    // How long to wait for another thread to load a file.
+    load_timeout: Duration,
+    // Whether to normalize CRLF line endings to LF ones on load.
+    normalize_line_endings: bool,
+    // Whether to keep the text of files as of when they were last saved.
+    keep_saved_content: bool,
+}
+
+/// The locks of `VfsInternal`, in the order in which they must be taken.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum LockKind {
+    PendingFiles,
+    Files,
+}
+
+#[cfg(debug_assertions)]
+thread_local! {
+    /// The `VfsInternal` locks held by the current thread.
+    static HELD_LOCKS: std::cell::RefCell<Vec<LockKind>> =
+        const { std::cell::RefCell::new(Vec::new()) };
+}
+
+/// A guard for one of the locks of `VfsInternal`. In debug builds, taking a
+/// lock while holding one which must be taken after it (e.g., `pending_files`
+/// while holding `files`) panics, rather than risking a deadlock.
+struct OrderedGuard<'a, T> {
+    guard: MutexGuard<'a, T>,
+    #[cfg_attr(not(debug_assertions), allow(dead_code))]
+    kind: LockKind,
+}
+
+impl<'a, T> OrderedGuard<'a, T> {
+    fn new(mutex: &'a Mutex<T>, kind: LockKind) -> OrderedGuard<'a, T> {
+        #[cfg(debug_assertions)]
+        HELD_LOCKS.with(|held| {
+            if let Some(later) = held.borrow().iter().find(|&&held| held >= kind) {
+                panic!(
+                    "VFS lock order violated: locking {:?} while holding {:?} \
+                     (pending_files must be locked before files)",
+                    kind, later
+                );
+            }
+        });
+        let guard = mutex.lock().unwrap();
+        #[cfg(debug_assertions)]
+        HELD_LOCKS.with(|held| held.borrow_mut().push(kind));
+        OrderedGuard { guard, kind }
+    }
+}
+
+impl<T> Drop for OrderedGuard<'_, T> {
+    fn drop(&mut self) {
+        #[cfg(debug_assertions)]
+        HELD_LOCKS.with(|held| held.borrow_mut().retain(|&held| held != self.kind));
+    }
+}
+
+impl<T> Deref for OrderedGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.guard
+    }
+}
+
+impl<T> DerefMut for OrderedGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        &mut self.guard
+    }
 }
 
 impl<T: FileLoader, U> VfsInternal<T, U> {
     fn new() -> VfsInternal<T, U> {
+        Self::with_text_extensions(DEFAULT_TEXT_EXTENSIONS)
+    }
+
+    fn with_text_extensions<I, S>(extensions: I) -> VfsInternal<T, U>
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<str>,
+    {
+        let text_extensions: HashSet<String> = extensions
+            .into_iter()
+            .map(|ext| ext.as_ref().trim_start_matches('.').to_owned())
+            .collect();
         VfsInternal {
             files: Mutex::new(HashMap::new()),
             pending_files: Mutex::new(HashMap::new()),
             loader: PhantomData,
+            text_extensions: RwLock::new(text_extensions.clone()),
+            default_text_extensions: text_extensions,
+            languages: RwLock::new(
+                DEFAULT_LANGUAGES
+                    .iter()
+                    .map(|&(ext, language)| (ext.to_owned(), language))
+                    .collect(),
+            ),
+            registered_extensions: RwLock::new(HashMap::new()),
+            load_timeout: DEFAULT_LOAD_TIMEOUT,
+            normalize_line_endings: false,
+            keep_saved_content: false,
+        }
+    }
+
+    fn register_extension(&self, extension: &str, language: Language) {
+        let extension = extension.trim_start_matches('.');
+        self.registered_extensions.write().unwrap().insert(extension.to_owned(), language);
+        self.text_extensions.write().unwrap().insert(extension.to_owned());
+        self.languages.write().unwrap().insert(extension.to_owned(), language);
+    }
+
+    fn unregister_extension(&self, extension: &str) {
+        let extension = extension.trim_start_matches('.');
+        if self.registered_extensions.write().unwrap().remove(extension).is_none() {
+            return;
+        }
+
+        if !self.default_text_extensions.contains(extension) {
+            self.text_extensions.write().unwrap().remove(extension);
+        }
+        let mut languages = self.languages.write().unwrap();
+        match DEFAULT_LANGUAGES.iter().find(|&&(ext, _)| ext == extension) {
+            Some(&(_, language)) => languages.insert(extension.to_owned(), language),
+            None => languages.remove(extension),
+        };
+    }
+
+    fn registered_extensions(&self) -> Vec<(String, Language)> {
+        let registered = self.registered_extensions.read().unwrap();
+        registered.iter().map(|(ext, &language)| (ext.clone(), language)).collect()
+    }
+
+    fn language(&self, path: &Path) -> Option<Language> {
+        let languages = self.languages.read().unwrap();
+        let extension = longest_extension(path, languages.keys())?;
+        languages.get(extension).copied()
+    }
+
+    /// Reads the file at `path` from disk, normalizing its line endings if
+    /// enabled.
+    fn read_file(&self, path: &Path) -> Result<File<U>, Error> {
+        let mut file = T::read(path, &self.text_extensions.read().unwrap())?;
+        if self.normalize_line_endings {
+            if let FileKind::Text(ref mut f) = file.kind {
+                f.normalize_line_endings();
+            }
         }
+        Ok(file)
+    }
+
+    fn lock_pending_files(&self) -> OrderedGuard<'_, HashMap<PathBuf, Vec<Thread>>> {
+        OrderedGuard::new(&self.pending_files, LockKind::PendingFiles)
+    }
+
+    fn lock_files(&self) -> OrderedGuard<'_, HashMap<PathBuf, File<U>>> {
+        OrderedGuard::new(&self.files, LockKind::Files)
+    }
+
+    /// Locks `files` before `pending_files`, which should panic in debug
+    /// builds.
+    #[cfg(all(test, debug_assertions))]
+    fn lock_out_of_order(&self) {
+        let _files = self.lock_files();
+        let _pending_files = self.lock_pending_files();
     }
 
     fn clear(&self) {
-        let mut pending_files = self.pending_files.lock().unwrap();
-        let mut files = self.files.lock().unwrap();
+        let mut pending_files = self.lock_pending_files();
+        let mut files = self.lock_files();
         *files = HashMap::new();
         let mut new_pending_files = HashMap::new();
         mem::swap(&mut *pending_files, &mut new_pending_files);
@@ -315,10 +706,15 @@ impl<T: FileLoader, U> VfsInternal<T, U> {
     }
 
     fn file_saved(&self, path: &Path) -> Result<(), Error> {
-        let mut files = self.files.lock().unwrap();
+        let mut files = self.lock_files();
         if let Some(ref mut f) = files.get_mut(path) {
             match f.kind {
-                FileKind::Text(ref mut f) => f.changed = false,
+                FileKind::Text(ref mut f) => {
+                    f.changed = false;
+                    if self.keep_saved_content {
+                        f.saved_text = Some(f.text.clone());
+                    }
+                }
                 FileKind::Binary(_) => return Err(Error::BadFileKind),
             }
         }
@@ -327,8 +723,8 @@ impl<T: FileLoader, U> VfsInternal<T, U> {
 
     fn flush_file(&self, path: &Path) -> Result<(), Error> {
         loop {
-            let mut pending_files = self.pending_files.lock().unwrap();
-            let mut files = self.files.lock().unwrap();
+            let mut pending_files = self.lock_pending_files();
+            let mut files = self.lock_files();
             if !pending_files.contains_key(path) {
                 files.remove(path);
                 return Ok(());
@@ -339,20 +735,35 @@ impl<T: FileLoader, U> VfsInternal<T, U> {
         }
     }
 
+    fn flush_all(&self) {
+        let _pending_files = self.lock_pending_files();
+        let mut files = self.lock_files();
+        files.retain(|_, f| f.changed());
+    }
+
     fn file_is_synced(&self, path: &Path) -> Result<bool, Error> {
-        let files = self.files.lock().unwrap();
+        let files = self.lock_files();
         match files.get(path) {
             Some(f) => Ok(!f.changed()),
             None => Err(Error::FileNotCached),
         }
     }
 
+    fn saved_content(&self, path: &Path) -> Result<Option<String>, Error> {
+        let files = self.lock_files();
+        match files.get(path).map(|f| &f.kind) {
+            Some(FileKind::Text(f)) => Ok(f.saved_text.clone()),
+            Some(FileKind::Binary(_)) => Err(Error::BadFileKind),
+            None => Err(Error::FileNotCached),
+        }
+    }
+
     fn on_changes(&self, changes: &[Change]) -> Result<(), Error> {
         trace!("on_changes: {:?}", changes);
         for (file_name, changes) in coalesce_changes(changes) {
             let path = Path::new(file_name);
             {
-                let mut files = self.files.lock().unwrap();
+                let mut files = self.lock_files();
                 if let Some(file) = files.get_mut(Path::new(path)) {
                     file.make_change(&changes)?;
                     continue;
@@ -365,10 +776,10 @@ impl<T: FileLoader, U> VfsInternal<T, U> {
             // edits are intended to be applied to the version of the file
             // we read from disk. That is, the on disk contents might have
             // changed after the edit request.
-            let mut file = T::read(Path::new(path))?;
+            let mut file = self.read_file(Path::new(path))?;
             file.make_change(&changes)?;
 
-            let mut files = self.files.lock().unwrap();
+            let mut files = self.lock_files();
             files.insert(path.to_path_buf(), file);
         }
 
@@ -376,18 +787,45 @@ impl<T: FileLoader, U> VfsInternal<T, U> {
     }
 
     fn set_file(&self, path: &Path, text: &str) {
-        let file = File {
-            kind: FileKind::Text(TextFile {
-                text: text.to_owned(),
-                line_indices: make_line_indices(text),
-                changed: true,
-            }),
-            user_data: None,
-        };
+        self.insert_text_file(path, text.to_owned(), true);
+    }
+
+    fn set_files(&self, new_files: &[(PathBuf, String)]) {
+        // Make the files before taking the locks. Later ones replace earlier
+        // ones for the same path when inserted.
+        let new_files: Vec<(&Path, File<U>)> = new_files
+            .iter()
+            .map(|(path, text)| (path.as_path(), File::new_text(text.clone(), true)))
+            .collect();
+
+        loop {
+            {
+                let mut pending_files = self.lock_pending_files();
+                let mut files = self.lock_files();
+                // A file which is being loaded would replace the new text
+                // once loaded, so wait for it first.
+                let pending = new_files
+                    .iter()
+                    .map(|&(path, _)| path)
+                    .find(|path| pending_files.contains_key(*path));
+                match pending {
+                    Some(path) => pending_files.get_mut(path).unwrap().push(thread::current()),
+                    None => {
+                        files.extend(new_files.into_iter().map(|(path, f)| (path.to_owned(), f)));
+                        return;
+                    }
+                }
+            }
+            thread::park();
+        }
+    }
+
+    fn insert_text_file(&self, path: &Path, text: String, changed: bool) {
+        let file = File::new_text(text, changed);
 
         loop {
-            let mut pending_files = self.pending_files.lock().unwrap();
-            let mut files = self.files.lock().unwrap();
+            let mut pending_files = self.lock_pending_files();
+            let mut files = self.lock_files();
             if !pending_files.contains_key(path) {
                 files.insert(path.to_owned(), file);
                 return;
@@ -399,7 +837,7 @@ impl<T: FileLoader, U> VfsInternal<T, U> {
     }
 
     fn get_cached_files(&self) -> HashMap<PathBuf, String> {
-        let files = self.files.lock().unwrap();
+        let files = self.lock_files();
         files
             .iter()
             .filter_map(|(p, f)| match f.kind {
@@ -410,7 +848,7 @@ impl<T: FileLoader, U> VfsInternal<T, U> {
     }
 
     fn get_changes(&self) -> HashMap<PathBuf, String> {
-        let files = self.files.lock().unwrap();
+        let files = self.lock_files();
         files
             .iter()
             .filter_map(|(p, f)| match f.kind {
@@ -420,11 +858,28 @@ impl<T: FileLoader, U> VfsInternal<T, U> {
             .collect()
     }
 
+    fn cached_paths(&self) -> Vec<PathBuf> {
+        self.lock_files().keys().cloned().collect()
+    }
+
     fn has_changes(&self) -> bool {
-        let files = self.files.lock().unwrap();
+        let files = self.lock_files();
         files.values().any(|f| f.changed())
     }
 
+    fn changed_since(&self, since: Instant) -> Vec<PathBuf> {
+        let files = self.lock_files();
+        files
+            .iter()
+            .filter_map(|(p, f)| match f.kind {
+                FileKind::Text(TextFile { last_changed: Some(t), .. }) if t >= since => {
+                    Some(p.clone())
+                }
+                _ => None,
+            })
+            .collect()
+    }
+
     fn load_line(&self, path: &Path, line: span::Row<span::ZeroIndexed>) -> Result<String, Error> {
         self.ensure_file(path, |f| f.load_line(line).map(|s| s.to_owned()))
     }
@@ -438,6 +893,35 @@ impl<T: FileLoader, U> VfsInternal<T, U> {
         self.ensure_file(path, |f| f.load_lines(line_start, line_end).map(|s| s.to_owned()))
     }
 
+    fn load_context(
+        &self,
+        path: &Path,
+        center_line: span::Row<span::ZeroIndexed>,
+        radius: usize,
+    ) -> Result<Vec<(usize, String)>, Error> {
+        self.ensure_file(path, |f| {
+            let center = center_line.0 as usize;
+            let line_count = f.line_count()?;
+            if center >= line_count {
+                return Err(Error::BadLocation);
+            }
+            let start = center.saturating_sub(radius);
+            let end = (center + radius).min(line_count - 1);
+
+            let text = f.load_lines(
+                span::Row::new_zero_indexed(start as u32),
+                span::Row::new_zero_indexed(end as u32),
+            )?;
+            // Only the last line of the file has no terminator.
+            let text = if end + 1 < line_count { &text[..text.len() - 1] } else { text };
+            Ok(text
+                .split('\n')
+                .enumerate()
+                .map(|(i, line)| (start + i + 1, line.trim_end_matches('\r').to_owned()))
+                .collect())
+        })
+    }
+
     fn load_span(&self, span: span::Span<span::ZeroIndexed>) -> Result<String, Error> {
         self.ensure_file(&span.file, |f| f.load_range(span.range).map(|s| s.to_owned()))
     }
@@ -449,59 +933,105 @@ impl<T: FileLoader, U> VfsInternal<T, U> {
         self.ensure_file(path, |file| file.for_each_line(f))
     }
 
+    fn load_line_utf16(
+        &self,
+        path: &Path,
+        line: span::Row<span::ZeroIndexed>,
+    ) -> Result<Vec<u16>, Error> {
+        self.ensure_file(path, |f| f.load_line_utf16(line))
+    }
+
+    fn line_len_utf16(
+        &self,
+        path: &Path,
+        line: span::Row<span::ZeroIndexed>,
+    ) -> Result<u32, Error> {
+        self.ensure_file(path, |f| f.line_len_utf16(line))
+    }
+
+    fn content_hash(&self, path: &Path) -> Result<u64, Error> {
+        self.ensure_file(path, |f| Ok(f.content_hash()))
+    }
+
+    fn line_count(&self, path: &Path) -> Result<usize, Error> {
+        self.ensure_file(path, |f| f.line_count())
+    }
+
+    fn char_count(&self, path: &Path) -> Result<usize, Error> {
+        self.ensure_file(path, |f| f.char_count())
+    }
+
     fn load_file(&self, path: &Path) -> Result<FileContents, Error> {
         self.ensure_file(path, |f| Ok(f.contents()))
     }
 
+    fn load_bytes(&self, path: &Path) -> Result<Vec<u8>, Error> {
+        self.ensure_file(path, |f| Ok(f.kind.as_bytes().to_vec()))
+    }
+
+    fn preview_edits(&self, path: &Path, changes: &[Change]) -> Result<String, Error> {
+        let changes: Vec<&Change> = changes.iter().filter(|c| c.file() == path).collect();
+        self.ensure_file(path, |f| match f.kind {
+            FileKind::Text(ref t) => t.preview_edits(&changes),
+            FileKind::Binary(_) => Err(Error::BadFileKind),
+        })
+    }
+
     fn ensure_file<F, R>(&self, path: &Path, f: F) -> Result<R, Error>
     where
-        F: FnOnce(&File<U>) -> Result<R, Error>,
+        F: FnOnce(&mut File<U>) -> Result<R, Error>,
     {
+        let mut waiting_since = None;
         loop {
             {
-                let mut pending_files = self.pending_files.lock().unwrap();
-                let files = self.files.lock().unwrap();
-                if files.contains_key(path) {
-                    return f(&files[path]);
+                let mut pending_files = self.lock_pending_files();
+                let mut files = self.lock_files();
+                if let Some(file) = files.get_mut(path) {
+                    return f(file);
                 }
                 if !pending_files.contains_key(path) {
                     pending_files.insert(path.to_owned(), vec![]);
                     break;
                 }
+
+                // The thread loading the file may have died without removing
+                // it from `pending_files`, in which case we'd never be woken.
+                let waiting_since = *waiting_since.get_or_insert_with(Instant::now);
+                if waiting_since.elapsed() >= self.load_timeout {
+                    for t in pending_files.remove(path).unwrap() {
+                        t.unpark();
+                    }
+                    return Err(Error::Io(None, Some("load timed out".to_owned())));
+                }
                 pending_files.get_mut(path).unwrap().push(thread::current());
             }
-            thread::park();
+            thread::park_timeout(self.load_timeout);
         }
 
-        // We should not hold the locks while we read from disk.
-        let file = T::read(path);
+        // We should not hold the locks while we read from disk. If the loader
+        // panics, we must still remove the file from `pending_files` below, or
+        // the waiting threads would never be woken (they then retry the load
+        // themselves).
+        let file = panic::catch_unwind(AssertUnwindSafe(|| self.read_file(path)))
+            .unwrap_or(Err(Error::InternalError("loader panicked")));
 
         // Need to re-get the locks here.
-        let mut pending_files = self.pending_files.lock().unwrap();
-        let mut files = self.files.lock().unwrap();
+        // If the load timed out for a waiting thread, the file is no longer
+        // pending, and may even have been loaded (and changed) since.
+        let mut pending_files = self.lock_pending_files();
+        let mut files = self.lock_files();
+        for t in pending_files.remove(path).into_iter().flatten() {
+            t.unpark();
+        }
         match file {
-            Ok(file) => {
-                files.insert(path.to_owned(), file);
-                let ts = pending_files.remove(path).unwrap();
-                for t in ts {
-                    t.unpark();
-                }
-            }
-            Err(e) => {
-                let ts = pending_files.remove(path).unwrap();
-                for t in ts {
-                    t.unpark();
-                }
-                return Err(e);
-            }
+            Ok(file) => f(files.entry(path.to_owned()).or_insert(file)),
+            Err(e) => Err(e),
         }
-
-        f(&files[path])
     }
 
     fn write_file(&self, path: &Path) -> Result<(), Error> {
-        let file = {
-            let mut files = self.files.lock().unwrap();
+        let mut file = {
+            let mut files = self.lock_files();
             match files.get_mut(path) {
                 Some(f) => {
                     if let FileKind::Text(ref mut f) = f.kind {
@@ -512,13 +1042,78 @@ impl<T: FileLoader, U> VfsInternal<T, U> {
                 None => return Err(Error::FileNotCached),
             }
         };
+        if let FileKind::Text(ref mut f) = file {
+            if f.crlf {
+                f.text = f.text.replace('\n', "\r\n");
+            }
+        }
 
         T::write(path, &file)?;
         Ok(())
     }
 
+    fn disk_diff(&self, path: &Path) -> Result<Vec<Change>, Error> {
+        let cached = {
+            let files = self.lock_files();
+            match files.get(path).map(|f| &f.kind) {
+                Some(FileKind::Text(ref f)) => f.text.clone(),
+                Some(FileKind::Binary(_)) => return Err(Error::BadFileKind),
+                None => return Ok(vec![]),
+            }
+        };
+
+        // We should not hold the locks while we read from disk.
+        let on_disk = match self.read_file(path)?.kind {
+            FileKind::Text(f) => f.text,
+            FileKind::Binary(_) => return Err(Error::BadFileKind),
+        };
+
+        Ok(diff_lines(&on_disk, &cached)
+            .into_iter()
+            .rev()
+            .map(|hunk| Change::ReplaceText {
+                span: VfsSpan::from_usv(
+                    span::Span::new(
+                        span::Row::new_zero_indexed(hunk.old_start as u32),
+                        span::Row::new_zero_indexed(hunk.old_end as u32),
+                        span::Column::new_zero_indexed(0),
+                        span::Column::new_zero_indexed(0),
+                        path,
+                    ),
+                    None,
+                ),
+                text: hunk.text,
+            })
+            .collect())
+    }
+
+    fn snapshot(&self) -> VfsSnapshot {
+        let files = self.lock_files();
+        let files = files
+            .iter()
+            .filter_map(|(p, f)| match f.kind {
+                FileKind::Text(ref f) => {
+                    Some((p.clone(), SnapshotFile { text: f.text.clone(), changed: f.changed }))
+                }
+                FileKind::Binary(_) => None,
+            })
+            .collect();
+        VfsSnapshot { files }
+    }
+
+    fn restore(&self, snapshot: VfsSnapshot) {
+        for (path, file) in snapshot.files {
+            let on_disk = match self.read_file(&path).map(|f| f.kind) {
+                Ok(FileKind::Text(f)) => Some(f.text),
+                _ => None,
+            };
+            let changed = file.changed || on_disk.as_ref() != Some(&file.text);
+            self.insert_text_file(&path, file.text, changed);
+        }
+    }
+
     pub fn set_user_data(&self, path: &Path, data: Option<U>) -> Result<(), Error> {
-        let mut files = self.files.lock().unwrap();
+        let mut files = self.lock_files();
         match files.get_mut(path) {
             Some(ref mut f) => {
                 f.user_data = data;
@@ -534,7 +1129,7 @@ impl<T: FileLoader, U> VfsInternal<T, U> {
     where
         F: FnOnce(Result<(Option<&str>, &mut U), Error>) -> Result<R, Error>,
     {
-        let mut files = self.files.lock().unwrap();
+        let mut files = self.lock_files();
         let file = match files.get_mut(path) {
             Some(f) => f,
             None => return f(Err(Error::FileNotCached)),
@@ -562,7 +1157,7 @@ impl<T: FileLoader, U> VfsInternal<T, U> {
     where
         F: FnOnce(Option<&str>) -> Result<U, Error>,
     {
-        let mut files = self.files.lock().unwrap();
+        let mut files = self.lock_files();
         match files.get_mut(path) {
             Some(ref mut file) => {
                 if file.user_data.is_none() {
@@ -599,6 +1194,86 @@ fn coalesce_changes<'a>(changes: &'a [Change]) -> HashMap<&'a Path, Vec<&'a Chan
     result
 }
 
+/// Lines `old_start..old_end` of the old text, to be replaced with `text`.
+#[derive(Debug, PartialEq)]
+struct Hunk {
+    old_start: usize,
+    old_end: usize,
+    text: String,
+}
+
+/// Above this many line pairs, the lines which differ are replaced as a whole
+/// rather than computing their longest common subsequence.
+const MAX_DIFF_CELLS: usize = 1 << 22;
+
+/// Returns the smallest set of line replacements turning `old` into `new`, in
+/// the order of their positions.
+fn diff_lines(old: &str, new: &str) -> Vec<Hunk> {
+    let old_lines: Vec<_> = old.split_inclusive('\n').collect();
+    let new_lines: Vec<_> = new.split_inclusive('\n').collect();
+
+    let prefix = old_lines.iter().zip(&new_lines).take_while(|(o, n)| o == n).count();
+    let suffix = old_lines[prefix..]
+        .iter()
+        .rev()
+        .zip(new_lines[prefix..].iter().rev())
+        .take_while(|(o, n)| o == n)
+        .count();
+    let old_mid = &old_lines[prefix..old_lines.len() - suffix];
+    let new_mid = &new_lines[prefix..new_lines.len() - suffix];
+    if old_mid.is_empty() && new_mid.is_empty() {
+        return vec![];
+    }
+    if old_mid.len() * new_mid.len() > MAX_DIFF_CELLS {
+        return vec![Hunk {
+            old_start: prefix,
+            old_end: prefix + old_mid.len(),
+            text: new_mid.concat(),
+        }];
+    }
+
+    // `lcs[i][j]` is the length of the longest common subsequence of
+    // `old_mid[i..]` and `new_mid[j..]`.
+    let (n, m) = (old_mid.len(), new_mid.len());
+    let mut lcs = vec![vec![0u32; m + 1]; n + 1];
+    for i in (0..n).rev() {
+        for j in (0..m).rev() {
+            lcs[i][j] = if old_mid[i] == new_mid[j] {
+                lcs[i + 1][j + 1] + 1
+            } else {
+                lcs[i + 1][j].max(lcs[i][j + 1])
+            };
+        }
+    }
+
+    let mut hunks = vec![];
+    let mut current: Option<Hunk> = None;
+    let (mut i, mut j) = (0, 0);
+    while i < n || j < m {
+        if i < n && j < m && old_mid[i] == new_mid[j] {
+            hunks.extend(current.take());
+            i += 1;
+            j += 1;
+            continue;
+        }
+
+        let hunk = current.get_or_insert_with(|| Hunk {
+            old_start: prefix + i,
+            old_end: prefix + i,
+            text: String::new(),
+        });
+        if j < m && (i == n || lcs[i][j + 1] >= lcs[i + 1][j]) {
+            hunk.text.push_str(new_mid[j]);
+            j += 1;
+        } else {
+            hunk.old_end += 1;
+            i += 1;
+        }
+    }
+    hunks.extend(current);
+    hunks
+}
+
 fn make_line_indices(text: &str) -> Vec<u32> {
     let mut result = vec![0];
     for (i, b) in text.bytes().enumerate() {
@@ -616,6 +1291,12 @@ enum FileKind {
     Binary(Vec<u8>),
 }
 
+fn hash_bytes(bytes: &[u8]) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    hasher.write(bytes);
+    hasher.finish()
+}
+
 impl FileKind {
     fn as_bytes(&self) -> &[u8] {
         match *self {
@@ -625,6 +1306,19 @@ impl FileKind {
     }
 }
 
+/// The text files in a VFS, see `Vfs::snapshot`.
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+pub struct VfsSnapshot {
+    pub files: HashMap<PathBuf, SnapshotFile>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SnapshotFile {
+    pub text: String,
+    /// Whether the file had unsaved changes.
+    pub changed: bool,
+}
+
 #[derive(Debug, PartialEq)]
 pub enum FileContents {
     Text(String),
@@ -637,6 +1331,18 @@ struct TextFile {
     text: String,
     line_indices: Vec<u32>,
     changed: bool,
+    // When the text was last changed in memory, `None` if it is as read from disk.
+    last_changed: Option<Instant>,
+    // Each line (including its terminator) in UTF-16 code units, computed on
+    // first use.
+    utf16_lines: Option<Vec<Vec<u16>>>,
+    // Hash of the text, computed on first use.
+    content_hash: Option<u64>,
+    // Whether the CRLF line endings of the text were normalized to LF ones on
+    // load, to be restored when the file is written.
+    crlf: bool,
+    // The text as of when the file was last saved, if the VFS keeps it.
+    saved_text: Option<String>,
 }
 
 struct File<U> {
@@ -645,6 +1351,22 @@ struct File<U> {
 }
 
 impl<U> File<U> {
+    fn new_text(text: String, changed: bool) -> File<U> {
+        File {
+            kind: FileKind::Text(TextFile {
+                line_indices: make_line_indices(&text),
+                text,
+                changed,
+                last_changed: if changed { Some(Instant::now()) } else { None },
+                utf16_lines: None,
+                content_hash: None,
+                crlf: false,
+                saved_text: None,
+            }),
+            user_data: None,
+        }
+    }
+
     fn contents(&self) -> FileContents {
         match self.kind {
             FileKind::Text(ref t) => FileContents::Text(t.text.clone()),
@@ -697,6 +1419,45 @@ impl<U> File<U> {
         }
     }
 
+    fn load_line_utf16(&mut self, line: span::Row<span::ZeroIndexed>) -> Result<Vec<u16>, Error> {
+        match self.kind {
+            FileKind::Text(ref mut t) => t.load_line_utf16(line).map(<[u16]>::to_vec),
+            FileKind::Binary(_) => Err(Error::BadFileKind),
+        }
+    }
+
+    fn line_len_utf16(&mut self, line: span::Row<span::ZeroIndexed>) -> Result<u32, Error> {
+        match self.kind {
+            FileKind::Text(ref mut t) => t.line_len_utf16(line),
+            FileKind::Binary(_) => Err(Error::BadFileKind),
+        }
+    }
+
+    fn content_hash(&mut self) -> u64 {
+        match self.kind {
+            FileKind::Text(ref mut t) => {
+                let text = &t.text;
+                *t.content_hash.get_or_insert_with(|| hash_bytes(text.as_bytes()))
+            }
+            FileKind::Binary(ref b) => hash_bytes(b),
+        }
+    }
+
+    fn line_count(&self) -> Result<usize, Error> {
+        match self.kind {
+            // The indices are of the start of each line, plus the end of the text.
+            FileKind::Text(ref t) => Ok(t.line_indices.len() - 1),
+            FileKind::Binary(_) => Err(Error::BadFileKind),
+        }
+    }
+
+    fn char_count(&self) -> Result<usize, Error> {
+        match self.kind {
+            FileKind::Text(ref t) => Ok(t.text.chars().count()),
+            FileKind::Binary(_) => Err(Error::BadFileKind),
+        }
+    }
+
     fn changed(&self) -> bool {
         match self.kind {
             FileKind::Text(ref t) => t.changed,
@@ -706,37 +1467,25 @@ impl<U> File<U> {
 }
 
 impl TextFile {
+    /// Replaces the line endings of the text with LF ones if they are all
+    /// CRLF ones, recording that they were.
+    fn normalize_line_endings(&mut self) {
+        let crlf_count = self.text.matches("\r\n").count();
+        if crlf_count == 0 || crlf_count != self.text.matches('\n').count() {
+            return;
+        }
+        self.text = self.text.replace("\r\n", "\n");
+        self.line_indices = make_line_indices(&self.text);
+        self.crlf = true;
+    }
+
     fn make_change(&mut self, changes: &[&Change]) -> Result<(), Error> {
         trace!("TextFile::make_change");
         for c in changes {
             trace!("TextFile::make_change: {:?}", c);
             let new_text = match **c {
                 Change::ReplaceText { span: ref vfs_span, ref text } => {
-                    let (span, len) = (vfs_span.span(), vfs_span.len());
-
-                    let range = {
-                        let first_line = self.load_line(span.range.row_start)?;
-                        let byte_start = self.line_indices[span.range.row_start.0 as usize]
-                            + vfs_span.byte_in_str(first_line, span.range.col_start)? as u32;
-
-                        let byte_end = if let Some(len) = len {
-                            // if `len` exists, the replaced portion of text
-                            // is `len` chars starting from row_start/col_start.
-                            byte_start
-                                + vfs_span.byte_in_str(
-                                    &self.text[byte_start as usize..],
-                                    span::Column::new_zero_indexed(len as u32),
-                                )? as u32
-                        } else {
-                            // if no `len`, fall back to using row_end/col_end
-                            // for determining the tail end of replaced text.
-                            let last_line = self.load_line(span.range.row_end)?;
-                            self.line_indices[span.range.row_end.0 as usize]
-                                + vfs_span.byte_in_str(last_line, span.range.col_end)? as u32
-                        };
-
-                        (byte_start, byte_end)
-                    };
+                    let range = self.byte_range(vfs_span)?;
                     let mut new_text = self.text[..range.0 as usize].to_owned();
                     new_text.push_str(text);
                     new_text.push_str(&self.text[range.1 as usize..]);
@@ -744,15 +1493,78 @@ impl TextFile {
                 }
                 Change::AddFile { ref text, .. } => text.to_owned(),
             };
+            // Keep the line endings of a normalized file consistent.
+            let new_text = if self.crlf { new_text.replace("\r\n", "\n") } else { new_text };
 
             self.text = new_text;
             self.line_indices = make_line_indices(&self.text);
         }
 
         self.changed = true;
+        self.last_changed = Some(Instant::now());
+        self.utf16_lines = None;
+        self.content_hash = None;
         Ok(())
     }
 
+    /// Returns the text with `changes` applied, without changing the file.
+    /// Unlike for `make_change`, all changes are relative to the current text
+    /// (as the edits of an LSP `TextDocumentEdit` are), so they must not
+    /// overlap.
+    fn preview_edits(&self, changes: &[&Change]) -> Result<String, Error> {
+        let mut edits = changes
+            .iter()
+            .map(|c| match **c {
+                Change::ReplaceText { span: ref vfs_span, ref text } => {
+                    Ok((self.byte_range(vfs_span)?, text))
+                }
+                Change::AddFile { ref text, .. } => Ok(((0, self.text.len() as u32), text)),
+            })
+            .collect::<Result<Vec<_>, Error>>()?;
+        // Edits inserting at the same position stay in the given order.
+        edits.sort_by_key(|&((start, _), _)| start);
+
+        let mut new_text = String::with_capacity(self.text.len());
+        let mut end = 0;
+        for ((start, next_end), text) in edits {
+            if start < end || start > next_end {
+                return Err(Error::BadLocation);
+            }
+            new_text.push_str(&self.text[end as usize..start as usize]);
+            new_text.push_str(text);
+            end = next_end;
+        }
+        new_text.push_str(&self.text[end as usize..]);
+        Ok(new_text)
+    }
+
+    /// Returns the byte offsets of the start and end of `vfs_span`.
+    fn byte_range(&self, vfs_span: &VfsSpan) -> Result<(u32, u32), Error> {
+        let (span, len) = (vfs_span.span(), vfs_span.len());
+
+        let first_line = self.load_line(span.range.row_start)?;
+        let byte_start = self.line_indices[span.range.row_start.0 as usize]
+            + vfs_span.byte_in_str(first_line, span.range.col_start)? as u32;
+
+        let byte_end = if let Some(len) = len {
+            // if `len` exists, the replaced portion of text
+            // is `len` chars starting from row_start/col_start.
+            byte_start
+                + vfs_span.byte_in_str(
+                    &self.text[byte_start as usize..],
+                    span::Column::new_zero_indexed(len as u32),
+                )? as u32
+        } else {
+            // if no `len`, fall back to using row_end/col_end
+            // for determining the tail end of replaced text.
+            let last_line = self.load_line(span.range.row_end)?;
+            self.line_indices[span.range.row_end.0 as usize]
+                + vfs_span.byte_in_str(last_line, span.range.col_end)? as u32
+        };
+
+        Ok((byte_start, byte_end))
+    }
+
     fn load_line(&self, line: span::Row<span::ZeroIndexed>) -> Result<&str, Error> {
         self.load_lines(line, line)
     }
@@ -797,6 +1609,27 @@ impl TextFile {
         }
     }
 
+    fn load_line_utf16(&mut self, line: span::Row<span::ZeroIndexed>) -> Result<&[u16], Error> {
+        if self.utf16_lines.is_none() {
+            let mut lines = vec![];
+            self.for_each_line(|text, _| {
+                lines.push(text.encode_utf16().collect());
+                Ok(())
+            })?;
+            self.utf16_lines = Some(lines);
+        }
+
+        let lines = self.utf16_lines.as_ref().unwrap();
+        Ok(try_opt_loc!(lines.get(line.0 as usize)))
+    }
+
+    fn line_len_utf16(&mut self, line: span::Row<span::ZeroIndexed>) -> Result<u32, Error> {
+        let line = self.load_line_utf16(line)?;
+        let is_terminator = |c: &&u16| **c == u16::from(b'\n') || **c == u16::from(b'\r');
+        let terminator_len = line.iter().rev().take_while(is_terminator).count();
+        Ok((line.len() - terminator_len) as u32)
+    }
+
     fn for_each_line<F>(&self, mut f: F) -> Result<(), Error>
     where
         F: FnMut(&str, usize) -> Result<(), Error>,
@@ -846,14 +1679,15 @@ fn byte_in_str_utf16(s: &str, c: span::Column<span::ZeroIndexed>) -> Result<usiz
 }
 
 trait FileLoader {
-    fn read<U>(file_name: &Path) -> Result<File<U>, Error>;
+    // Files with one of `text_extensions` must be read as text.
+    fn read<U>(file_name: &Path, text_extensions: &HashSet<String>) -> Result<File<U>, Error>;
     fn write(file_name: &Path, file: &FileKind) -> Result<(), Error>;
 }
 
 struct RealFileLoader;
 
 impl FileLoader for RealFileLoader {
-    fn read<U>(file_name: &Path) -> Result<File<U>, Error> {
+    fn read<U>(file_name: &Path, text_extensions: &HashSet<String>) -> Result<File<U>, Error> {
         let mut file = match fs::File::open(file_name) {
             Ok(f) => f,
             Err(_) => {
@@ -877,9 +1711,18 @@ impl FileLoader for RealFileLoader {
                     line_indices: make_line_indices(&s),
                     text: s,
                     changed: false,
+                    last_changed: None,
+                    utf16_lines: None,
+                    content_hash: None,
+                    crlf: false,
+                    saved_text: None,
                 }),
                 user_data: None,
             }),
+            Err(_) if is_text_file(file_name, text_extensions) => Err(Error::Io(
+                Some(file_name.to_owned()),
+                Some(format!("File is not valid UTF-8: {}", file_name.display())),
+            )),
             Err(e) => Ok(File { kind: FileKind::Binary(e.into_bytes()), user_data: None }),
         }
     }
@@ -904,6 +1747,27 @@ impl FileLoader for RealFileLoader {
     }
 }
 
+fn is_text_file(file_name: &Path, text_extensions: &HashSet<String>) -> bool {
+    longest_extension(file_name, text_extensions.iter()).is_some()
+}
+
+/// Returns the longest of `extensions` (without the leading dot) which the
+/// name of the file ends with, e.g., `rs.in` rather than `in` for
+/// `foo.rs.in`. A name which is just the extension (e.g., `.rs`) has none.
+fn longest_extension<'a>(
+    file_name: &Path,
+    extensions: impl Iterator<Item = &'a String>,
+) -> Option<&'a String> {
+    let name = file_name.file_name().and_then(OsStr::to_str)?;
+    extensions
+        .filter(|ext| {
+            name.len() > ext.len() + 1
+                && name.ends_with(ext.as_str())
+                && name[..name.len() - ext.len()].ends_with('.')
+        })
+        .max_by_key(|ext| ext.len())
+}
+
 #[cfg(test)]
 mod tests {
     use span::Column;
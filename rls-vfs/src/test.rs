@@ -1,10 +1,15 @@
+use std::collections::HashSet;
+use std::fs;
 use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
 
 use span::{self, Column, Position, Row};
 
 use super::{
-    make_line_indices, Change, Error, File, FileContents, FileKind, FileLoader, TextFile,
-    VfsInternal, VfsSpan,
+    diff_lines, make_line_indices, Change, Error, File, FileContents, FileKind, FileLoader, Hunk,
+    Language, RealFileLoader, SnapshotFile, TextFile, VfsInternal, VfsSnapshot, VfsSpan,
 };
 
 type Span = span::Span<span::ZeroIndexed>;
@@ -12,9 +17,18 @@ type Span = span::Span<span::ZeroIndexed>;
 struct MockFileLoader;
 
 impl FileLoader for MockFileLoader {
-    fn read<U>(file_name: &Path) -> Result<File<U>, Error> {
+    fn read<U>(file_name: &Path, _text_extensions: &HashSet<String>) -> Result<File<U>, Error> {
         let text = format!("{}\nHello\nWorld\nHello, World!\n", file_name.display());
-        let text_file = TextFile { line_indices: make_line_indices(&text), text, changed: false };
+        let text_file = TextFile {
+            line_indices: make_line_indices(&text),
+            text,
+            changed: false,
+            last_changed: None,
+            utf16_lines: None,
+            content_hash: None,
+            crlf: false,
+            saved_text: None,
+        };
         Ok(File { kind: FileKind::Text(text_file), user_data: None })
     }
 
@@ -30,6 +44,20 @@ impl FileLoader for MockFileLoader {
     }
 }
 
+/// A loader which panics (slowly, so that other threads wait for it).
+struct PanickingFileLoader;
+
+impl FileLoader for PanickingFileLoader {
+    fn read<U>(_file_name: &Path, _text_extensions: &HashSet<String>) -> Result<File<U>, Error> {
+        thread::sleep(Duration::from_millis(50));
+        panic!("failed to load");
+    }
+
+    fn write(_file_name: &Path, _file: &FileKind) -> Result<(), Error> {
+        Ok(())
+    }
+}
+
 fn make_change(with_len: bool) -> Change {
     let (row_end, col_end, len) = if with_len {
         // If len is present, we shouldn't depend on row_end/col_end
@@ -112,6 +140,20 @@ fn test_cached_files() {
     assert!(files[Path::new("bar")] == "bar\nHello\nWorld\nHello, World!\n");
 }
 
+#[test]
+fn test_cached_paths() {
+    let vfs = VfsInternal::<MockFileLoader, ()>::new();
+    assert!(vfs.cached_paths().is_empty());
+    vfs.load_file(Path::new("foo")).unwrap();
+    vfs.set_file(Path::new("bar"), "Hello\n");
+    let new_file = Change::AddFile { file: PathBuf::from("baz"), text: "World\n".to_owned() };
+    vfs.on_changes(&[new_file]).unwrap();
+
+    let mut paths = vfs.cached_paths();
+    paths.sort();
+    assert_eq!(paths, vec![PathBuf::from("bar"), PathBuf::from("baz"), PathBuf::from("foo")]);
+}
+
 #[test]
 fn test_flush_file() {
     let vfs = VfsInternal::<MockFileLoader, ()>::new();
@@ -158,6 +200,22 @@ fn test_changes_with_len() {
     test_changes(true)
 }
 
+#[test]
+fn test_changed_since() {
+    let vfs = VfsInternal::<MockFileLoader, ()>::new();
+    for file in &["foo", "bar", "baz"] {
+        vfs.load_file(Path::new(file)).unwrap();
+    }
+    let since = Instant::now();
+
+    vfs.on_changes(&[make_change(false)]).unwrap();
+    vfs.set_file(Path::new("bar"), "Hello\n");
+
+    let mut changed = vfs.changed_since(since);
+    changed.sort();
+    assert_eq!(changed, vec![PathBuf::from("bar"), PathBuf::from("foo")]);
+}
+
 #[test]
 fn test_change_add_file() {
     let vfs = VfsInternal::<MockFileLoader, ()>::new();
@@ -423,6 +481,81 @@ fn test_load_lines() {
     );
 }
 
+#[test]
+fn test_load_context() {
+    let vfs = VfsInternal::<MockFileLoader, ()>::new();
+    let changes = [Change::AddFile {
+        file: PathBuf::from("foo"),
+        text: String::from("hello\r\nfrom\nthe\nother\nside\n"),
+    }];
+
+    vfs.on_changes(&changes).unwrap();
+
+    let context = |line, radius| {
+        vfs.load_context(&PathBuf::from("foo"), Row::new_zero_indexed(line), radius).unwrap()
+    };
+    let lines = |lines: &[(usize, &str)]| -> Vec<(usize, String)> {
+        lines.iter().map(|&(n, line)| (n, line.to_owned())).collect()
+    };
+    assert_eq!(context(2, 1), lines(&[(2, "from"), (3, "the"), (4, "other")]));
+    assert_eq!(context(2, 0), lines(&[(3, "the")]));
+    // Clamped at the start and end of the file, which ends with an empty line.
+    assert_eq!(context(0, 2), lines(&[(1, "hello"), (2, "from"), (3, "the")]));
+    assert_eq!(context(4, 2), lines(&[(3, "the"), (4, "other"), (5, "side"), (6, "")]));
+    assert_eq!(context(5, 1), lines(&[(5, "side"), (6, "")]));
+    assert!(vfs.load_context(&PathBuf::from("foo"), Row::new_zero_indexed(6), 1).is_err());
+}
+
+#[test]
+fn test_content_hash() {
+    let vfs = VfsInternal::<MockFileLoader, ()>::new();
+    let foo = PathBuf::from("foo");
+    let hash = vfs.content_hash(&foo).unwrap();
+    assert_eq!(vfs.content_hash(&foo).unwrap(), hash);
+    vfs.load_file(&foo).unwrap();
+    assert_eq!(vfs.content_hash(&foo).unwrap(), hash);
+
+    vfs.on_changes(&[make_change(true)]).unwrap();
+    let changed = vfs.content_hash(&foo).unwrap();
+    assert_ne!(changed, hash);
+    assert_eq!(vfs.content_hash(&foo).unwrap(), changed);
+
+    // The same contents have the same hash, whatever their history.
+    let other = PathBuf::from("other");
+    let text = match vfs.load_file(&foo).unwrap() {
+        FileContents::Text(text) => text,
+        FileContents::Binary(_) => panic!("expected a text file"),
+    };
+    vfs.on_changes(&[Change::AddFile { file: other.clone(), text }]).unwrap();
+    assert_eq!(vfs.content_hash(&other).unwrap(), changed);
+}
+
+#[test]
+fn test_saved_content() {
+    let mut vfs = VfsInternal::<MockFileLoader, ()>::new();
+    vfs.keep_saved_content = true;
+    let foo = PathBuf::from("foo");
+    vfs.load_file(&foo).unwrap();
+    assert_eq!(vfs.saved_content(&foo).unwrap(), None);
+    assert_eq!(vfs.saved_content(Path::new("bar")), Err(Error::FileNotCached));
+
+    vfs.on_changes(&[make_change(true)]).unwrap();
+    vfs.file_saved(&foo).unwrap();
+    let saved = match vfs.load_file(&foo).unwrap() {
+        FileContents::Text(text) => text,
+        FileContents::Binary(_) => panic!("expected a text file"),
+    };
+    vfs.on_changes(&[make_change_2(true)]).unwrap();
+    assert_ne!(vfs.load_file(&foo).unwrap(), FileContents::Text(saved.clone()));
+    assert_eq!(vfs.saved_content(&foo).unwrap(), Some(saved));
+
+    // Nothing is kept unless asked for.
+    let vfs = VfsInternal::<MockFileLoader, ()>::new();
+    vfs.load_file(&foo).unwrap();
+    vfs.file_saved(&foo).unwrap();
+    assert_eq!(vfs.saved_content(&foo).unwrap(), None);
+}
+
 #[test]
 fn test_load_line() {
     let vfs = VfsInternal::<MockFileLoader, ()>::new();
@@ -437,3 +570,377 @@ fn test_load_line() {
     assert_eq!(vfs.load_line(&PathBuf::from("foo"), Row::new_zero_indexed(2)).unwrap(), "the\n");
     assert_eq!(vfs.load_line(&PathBuf::from("foo"), Row::new_zero_indexed(4)).unwrap(), "side");
 }
+
+#[test]
+fn test_text_extensions() {
+    let dir = std::env::temp_dir().join(format!("rls-vfs-text-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let contents = b"fn main() {}\n\xff\n";
+    let generated = dir.join("main.gen");
+    let other = dir.join("main.bin");
+    fs::write(&generated, contents).unwrap();
+    fs::write(&other, contents).unwrap();
+
+    let vfs = VfsInternal::<RealFileLoader, ()>::with_text_extensions([".gen"]);
+    match vfs.load_file(&generated) {
+        Err(Error::Io(Some(path), _)) => assert_eq!(path, generated),
+        result => panic!("expected an IO error, got {:?}", result),
+    }
+    assert_eq!(vfs.load_file(&other), Ok(FileContents::Binary(contents.to_vec())));
+
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_register_extension() {
+    let dir = std::env::temp_dir().join(format!("rls-vfs-languages-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let template = dir.join("generated.rs.in");
+    let invalid = dir.join("invalid.rs.in");
+    let other = dir.join("notes.in");
+    fs::write(&template, "fn generated() {}\n").unwrap();
+    fs::write(&invalid, b"fn main() {}\n\xff\n").unwrap();
+    fs::write(&other, b"\xff").unwrap();
+
+    let vfs = VfsInternal::<RealFileLoader, ()>::new();
+    assert_eq!(vfs.language(Path::new("src/main.rs")), Some(Language::Rust));
+    assert_eq!(vfs.language(Path::new("Cargo.toml")), Some(Language::Toml));
+    assert_eq!(vfs.language(&template), None);
+    assert_eq!(vfs.load_file(&invalid), Ok(FileContents::Binary(b"fn main() {}\n\xff\n".to_vec())));
+
+    vfs.register_extension(".rs.in", Language::Rust);
+    assert_eq!(vfs.language(&template), Some(Language::Rust));
+    assert_eq!(vfs.language(&other), None);
+    assert_eq!(vfs.language(Path::new(".rs.in")), None);
+    assert_eq!(vfs.load_file(&template), Ok(FileContents::Text("fn generated() {}\n".to_owned())));
+    vfs.flush_file(&invalid).unwrap();
+    match vfs.load_file(&invalid) {
+        Err(Error::Io(Some(path), _)) => assert_eq!(path, invalid),
+        result => panic!("expected an IO error, got {:?}", result),
+    }
+    assert_eq!(vfs.load_file(&other), Ok(FileContents::Binary(b"\xff".to_vec())));
+
+    // The longest matching extension wins.
+    vfs.register_extension("in", Language::Toml);
+    assert_eq!(vfs.language(&template), Some(Language::Rust));
+    assert_eq!(vfs.language(&other), Some(Language::Toml));
+
+    // Unregistered extensions are treated as before.
+    vfs.register_extension("rs", Language::Toml);
+    vfs.unregister_extension("rs");
+    vfs.unregister_extension(".rs.in");
+    assert_eq!(vfs.registered_extensions(), vec![("in".to_owned(), Language::Toml)]);
+    assert_eq!(vfs.language(Path::new("src/main.rs")), Some(Language::Rust));
+    assert_eq!(vfs.language(&template), Some(Language::Toml));
+    vfs.unregister_extension("in");
+    assert_eq!(vfs.language(&template), None);
+    vfs.flush_file(&invalid).unwrap();
+    assert_eq!(vfs.load_file(&invalid), Ok(FileContents::Binary(b"fn main() {}\n\xff\n".to_vec())));
+
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_set_files() {
+    let vfs = VfsInternal::<MockFileLoader, ()>::new();
+    let mut files: Vec<(PathBuf, String)> = (0..100)
+        .map(|i| (PathBuf::from(format!("file{}.rs", i)), format!("fn f{}() {{}}\n", i)))
+        .collect();
+    files.push((PathBuf::from("file0.rs"), "fn last() {}\n".to_owned()));
+    vfs.set_files(&files);
+
+    let changes = vfs.get_changes();
+    assert_eq!(changes.len(), 100);
+    assert_eq!(changes[Path::new("file0.rs")], "fn last() {}\n");
+    for i in 1..100 {
+        let path = PathBuf::from(format!("file{}.rs", i));
+        assert_eq!(changes[&path], format!("fn f{}() {{}}\n", i));
+        assert!(!vfs.file_is_synced(&path).unwrap());
+    }
+}
+
+#[test]
+fn test_load_bytes() {
+    let dir = std::env::temp_dir().join(format!("rls-vfs-bytes-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let text = dir.join("lib.rs");
+    let binary = dir.join("logo.png");
+    fs::write(&binary, b"\x89PNG\r\n\x1a\n\xff").unwrap();
+
+    let vfs = VfsInternal::<RealFileLoader, ()>::new();
+    vfs.set_file(&text, "fn main() {}\n");
+    assert_eq!(vfs.load_bytes(&text), Ok(b"fn main() {}\n".to_vec()));
+    assert_eq!(vfs.load_bytes(&binary), Ok(b"\x89PNG\r\n\x1a\n\xff".to_vec()));
+
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_normalize_line_endings() {
+    let dir = std::env::temp_dir().join(format!("rls-vfs-crlf-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let crlf = dir.join("crlf.rs");
+    let mixed = dir.join("mixed.rs");
+    fs::write(&crlf, "fn main() {\r\n    let x = 1;\r\n}\r\n").unwrap();
+    fs::write(&mixed, "fn main() {\r\n    let x = 1;\n}\r\n").unwrap();
+
+    let mut vfs = VfsInternal::<RealFileLoader, ()>::new();
+    vfs.normalize_line_endings = true;
+    let span = |path: &Path, col_start, col_end| {
+        Span::new(
+            Row::new_zero_indexed(1),
+            Row::new_zero_indexed(1),
+            Column::new_zero_indexed(col_start),
+            Column::new_zero_indexed(col_end),
+            path,
+        )
+    };
+    let text = |path: &Path| match vfs.load_file(path).unwrap() {
+        FileContents::Text(text) => text,
+        FileContents::Binary(_) => panic!("expected a text file"),
+    };
+
+    // Lines and columns are those of the LF text.
+    assert_eq!(text(&crlf), "fn main() {\n    let x = 1;\n}\n");
+    assert_eq!(vfs.load_line(&crlf, Row::new_zero_indexed(0)).unwrap(), "fn main() {\n");
+    assert_eq!(&vfs.load_line(&crlf, Row::new_zero_indexed(1)).unwrap()[8..9], "x");
+    assert!(vfs.disk_diff(&crlf).unwrap().is_empty());
+
+    // Edits are written back with CRLF line endings.
+    let edit = Change::ReplaceText {
+        span: VfsSpan::from_usv(span(&crlf, 8, 9), None),
+        text: "y".to_owned(),
+    };
+    vfs.on_changes(&[edit]).unwrap();
+    vfs.write_file(&crlf).unwrap();
+    assert_eq!(fs::read_to_string(&crlf).unwrap(), "fn main() {\r\n    let y = 1;\r\n}\r\n");
+
+    // Mixed line endings can't be restored, so they are kept.
+    assert_eq!(text(&mixed), "fn main() {\r\n    let x = 1;\n}\r\n");
+    vfs.write_file(&mixed).unwrap();
+    assert_eq!(fs::read_to_string(&mixed).unwrap(), "fn main() {\r\n    let x = 1;\n}\r\n");
+
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_preview_edits() {
+    let vfs = VfsInternal::<MockFileLoader, ()>::new();
+    let path = Path::new("foo");
+    let replace = |row, col_start, col_end, text: &str| Change::ReplaceText {
+        span: VfsSpan::from_usv(
+            Span::new(
+                Row::new_zero_indexed(row),
+                Row::new_zero_indexed(row),
+                Column::new_zero_indexed(col_start),
+                Column::new_zero_indexed(col_end),
+                path,
+            ),
+            None,
+        ),
+        text: text.to_owned(),
+    };
+
+    // Both edits are relative to the original text.
+    let changes = [replace(2, 0, 5, "Earth"), replace(1, 0, 1, "J")];
+    assert_eq!(
+        vfs.preview_edits(path, &changes),
+        Ok("foo\nJello\nEarth\nHello, World!\n".to_owned())
+    );
+    assert_eq!(
+        vfs.load_file(path),
+        Ok(FileContents::Text("foo\nHello\nWorld\nHello, World!\n".to_owned()))
+    );
+    assert!(!vfs.has_changes());
+
+    let overlapping = [replace(3, 0, 5, "Bye"), replace(3, 3, 8, "")];
+    assert_eq!(vfs.preview_edits(path, &overlapping), Err(Error::BadLocation));
+}
+
+#[test]
+fn test_line_utf16() {
+    let vfs = VfsInternal::<MockFileLoader, ()>::new();
+    let path = Path::new("foo");
+    vfs.set_file(path, "let x = \"😢\";\r\nx\n");
+
+    let line = vfs.load_line_utf16(path, Row::new_zero_indexed(0)).unwrap();
+    assert_eq!(line, "let x = \"😢\";\r\n".encode_utf16().collect::<Vec<_>>());
+    assert_eq!(line[9..11], [0xD83D, 0xDE22]);
+    assert_eq!(vfs.line_len_utf16(path, Row::new_zero_indexed(0)), Ok(13));
+    assert_eq!(vfs.line_len_utf16(path, Row::new_zero_indexed(1)), Ok(1));
+    assert_eq!(vfs.line_len_utf16(path, Row::new_zero_indexed(2)), Ok(0));
+    assert_eq!(vfs.line_len_utf16(path, Row::new_zero_indexed(3)), Err(Error::BadLocation));
+
+    // Replace the emoji, the cached lengths must be recomputed.
+    let change = Change::ReplaceText {
+        span: VfsSpan::from_usv(
+            Span::new(
+                Row::new_zero_indexed(0),
+                Row::new_zero_indexed(0),
+                Column::new_zero_indexed(9),
+                Column::new_zero_indexed(10),
+                "foo",
+            ),
+            None,
+        ),
+        text: "abc".to_owned(),
+    };
+    vfs.on_changes(&[change]).unwrap();
+    assert_eq!(vfs.line_len_utf16(path, Row::new_zero_indexed(0)), Ok(14));
+    let line = vfs.load_line_utf16(path, Row::new_zero_indexed(0)).unwrap();
+    assert_eq!(String::from_utf16(&line).unwrap(), "let x = \"abc\";\r\n");
+}
+
+#[test]
+fn test_line_and_char_counts() {
+    let vfs = VfsInternal::<MockFileLoader, ()>::new();
+    let path = Path::new("foo");
+
+    vfs.set_file(path, "");
+    assert_eq!(vfs.line_count(path), Ok(1));
+    assert_eq!(vfs.char_count(path), Ok(0));
+
+    // Without a trailing newline.
+    vfs.set_file(path, "let é = 1;");
+    assert_eq!(vfs.line_count(path), Ok(1));
+    assert_eq!(vfs.char_count(path), Ok(10));
+
+    vfs.set_file(path, "fn main() {\r\n    😢\n}\n");
+    assert_eq!(vfs.line_count(path), Ok(4));
+    assert_eq!(vfs.char_count(path), Ok(21));
+
+    vfs.set_file(path, "a\nb");
+    assert_eq!(vfs.line_count(path), Ok(2));
+    assert_eq!(vfs.char_count(path), Ok(3));
+}
+
+#[test]
+fn test_diff_lines() {
+    let hunk = |old_start, old_end, text: &str| Hunk { old_start, old_end, text: text.to_owned() };
+
+    assert_eq!(diff_lines("a\nb\n", "a\nb\n"), vec![]);
+    assert_eq!(diff_lines("", "a"), vec![hunk(0, 0, "a")]);
+    assert_eq!(diff_lines("a\nb", "a\n"), vec![hunk(1, 2, "")]);
+    assert_eq!(
+        diff_lines("a\nb\nc\nd\ne\n", "a\nB\nc\nd\nx\ny\ne\n"),
+        vec![hunk(1, 2, "B\n"), hunk(4, 4, "x\ny\n")]
+    );
+}
+
+#[test]
+fn test_disk_diff() {
+    let dir = std::env::temp_dir().join(format!("rls-vfs-diff-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("main.rs");
+    fs::write(&path, "fn main() {\n    let x = 1;\n    println!(\"{}\", x);\n}\n").unwrap();
+
+    let vfs = VfsInternal::<RealFileLoader, ()>::new();
+    assert_eq!(vfs.disk_diff(&path).unwrap().len(), 0);
+    vfs.load_file(&path).unwrap();
+    assert_eq!(vfs.disk_diff(&path).unwrap().len(), 0);
+
+    let cached = "// Entry point.\nfn main() {\n    let x = 2;\n    println!(\"{}\", x);\n}\n";
+    vfs.set_file(&path, cached);
+    let changes = vfs.disk_diff(&path).unwrap();
+    assert_eq!(changes.len(), 2);
+
+    // Applying the changes to a fresh copy of the file on disk gives the
+    // cached text.
+    let fresh = VfsInternal::<RealFileLoader, ()>::new();
+    fresh.on_changes(&changes).unwrap();
+    assert_eq!(fresh.load_file(&path), Ok(FileContents::Text(cached.to_owned())));
+
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_snapshot_round_trip() {
+    let vfs = VfsInternal::<MockFileLoader, ()>::new();
+    vfs.load_file(Path::new("bar")).unwrap();
+    vfs.on_changes(&[make_change(false)]).unwrap();
+    vfs.set_file(Path::new("baz"), "unsaved\n");
+
+    let snapshot = vfs.snapshot();
+    assert_eq!(snapshot.files.len(), 3);
+    assert!(!snapshot.files[Path::new("bar")].changed);
+    assert!(snapshot.files[Path::new("foo")].changed);
+
+    let restored = VfsInternal::<MockFileLoader, ()>::new();
+    restored.restore(snapshot.clone());
+    assert_eq!(restored.snapshot(), snapshot);
+    assert!(restored.file_is_synced(Path::new("bar")).unwrap());
+    assert!(!restored.file_is_synced(Path::new("foo")).unwrap());
+    assert_eq!(
+        restored.load_file(Path::new("baz")).unwrap(),
+        FileContents::Text("unsaved\n".to_owned())
+    );
+
+    // Only the unsaved files are kept when flushing.
+    restored.flush_all();
+    let mut kept: Vec<_> = restored.get_cached_files().into_keys().collect();
+    kept.sort();
+    assert_eq!(kept, vec![PathBuf::from("baz"), PathBuf::from("foo")]);
+}
+
+#[test]
+fn test_restore_changed_on_disk() {
+    // The file was saved when the snapshot was taken, but has changed on disk
+    // since.
+    let mut snapshot = VfsSnapshot::default();
+    snapshot
+        .files
+        .insert(PathBuf::from("foo"), SnapshotFile { text: "old\n".to_owned(), changed: false });
+
+    let vfs = VfsInternal::<MockFileLoader, ()>::new();
+    vfs.restore(snapshot);
+    assert_eq!(vfs.load_file(Path::new("foo")).unwrap(), FileContents::Text("old\n".to_owned()));
+    assert!(!vfs.file_is_synced(Path::new("foo")).unwrap());
+}
+
+#[test]
+#[cfg(debug_assertions)]
+#[should_panic(expected = "VFS lock order violated")]
+fn test_lock_order_violation() {
+    let vfs = VfsInternal::<MockFileLoader, ()>::new();
+    // Taking the locks in the right order is fine, also repeatedly.
+    vfs.flush_all();
+    vfs.flush_all();
+    vfs.lock_out_of_order();
+}
+
+#[test]
+fn test_load_timeout() {
+    let mut vfs = VfsInternal::<MockFileLoader, ()>::new();
+    vfs.load_timeout = Duration::from_millis(50);
+
+    // Simulate a thread which died while loading the file.
+    vfs.lock_pending_files().insert(PathBuf::from("foo"), vec![]);
+
+    assert_eq!(
+        vfs.load_file(Path::new("foo")),
+        Err(Error::Io(None, Some("load timed out".to_owned())))
+    );
+    // The file is loaded afresh by the next call.
+    assert!(vfs.lock_pending_files().is_empty());
+    assert_eq!(
+        vfs.load_file(Path::new("foo")).unwrap(),
+        FileContents::Text("foo\nHello\nWorld\nHello, World!\n".to_owned())
+    );
+}
+
+#[test]
+fn test_loader_panic() {
+    let vfs = Arc::new(VfsInternal::<PanickingFileLoader, ()>::new());
+    let threads: Vec<_> = (0..4)
+        .map(|_| {
+            let vfs = Arc::clone(&vfs);
+            thread::spawn(move || vfs.load_file(Path::new("foo")))
+        })
+        .collect();
+
+    // Rather than deadlocking, each thread gets an error.
+    for t in threads {
+        assert_eq!(t.join().unwrap(), Err(Error::InternalError("loader panicked")));
+    }
+    assert!(vfs.lock_pending_files().is_empty());
+}
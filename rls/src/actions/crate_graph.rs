@@ -0,0 +1,97 @@
+//! The dependency graph of the packages of the workspace, returned by
+//! `rls/crateGraph`.
+
+use std::collections::HashSet;
+use std::fmt::Write;
+
+use crate::lsp_data::{CrateGraphData, CrateGraphEdge, CrateGraphNode};
+use crate::project_model::ProjectModel;
+
+/// Returns the packages of `project` and their dependencies on each other.
+pub fn crate_graph(project: &ProjectModel) -> CrateGraphData {
+    let nodes = project
+        .packages()
+        .map(|pkg| CrateGraphNode { id: pkg.index(), name: pkg.name(project).to_owned() })
+        .collect();
+    let mut edges: Vec<_> = project
+        .packages()
+        .flat_map(|pkg| {
+            pkg.deps(project).iter().map(move |dep| CrateGraphEdge {
+                from: pkg.index(),
+                to: dep.pkg.index(),
+                crate_name: dep.crate_name.to_string(),
+                cyclic: false,
+            })
+        })
+        .collect();
+    mark_cycles(&mut edges);
+    CrateGraphData { nodes, edges }
+}
+
+/// Flags the edges which are part of a cycle, i.e., whose dependency depends
+/// on the dependent, directly or not.
+fn mark_cycles(edges: &mut [CrateGraphEdge]) {
+    let reaches = |from: usize, to: usize, edges: &[CrateGraphEdge]| {
+        let mut seen = HashSet::new();
+        let mut stack = vec![from];
+        while let Some(node) = stack.pop() {
+            if node == to {
+                return true;
+            }
+            if seen.insert(node) {
+                stack.extend(edges.iter().filter(|e| e.from == node).map(|e| e.to));
+            }
+        }
+        false
+    };
+    let cyclic: Vec<bool> = edges.iter().map(|edge| reaches(edge.to, edge.from, edges)).collect();
+    for (edge, cyclic) in edges.iter_mut().zip(cyclic) {
+        edge.cyclic = cyclic;
+    }
+}
+
+/// Renders `graph` in the Graphviz DOT language, with the edges of cycles
+/// dashed.
+pub fn to_dot(graph: &CrateGraphData) -> String {
+    let mut dot = "digraph crates {\n".to_owned();
+    for node in &graph.nodes {
+        writeln!(dot, "    {} [label={:?}];", node.id, node.name).unwrap();
+    }
+    for edge in &graph.edges {
+        let style = if edge.cyclic { " [style=dashed]" } else { "" };
+        writeln!(dot, "    {} -> {}{};", edge.from, edge.to, style).unwrap();
+    }
+    dot.push_str("}\n");
+    dot
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn edge(from: usize, to: usize) -> CrateGraphEdge {
+        CrateGraphEdge { from, to, crate_name: format!("crate{}", to), cyclic: false }
+    }
+
+    #[test]
+    fn crate_graph_cycles_and_dot() {
+        // 0 depends on 1, which depends on 2, which dev-depends on 1.
+        let mut edges = vec![edge(0, 1), edge(1, 2), edge(2, 1)];
+        mark_cycles(&mut edges);
+        let cyclic: Vec<_> = edges.iter().map(|e| e.cyclic).collect();
+        assert_eq!(cyclic, vec![false, true, true]);
+
+        let nodes = vec![
+            CrateGraphNode { id: 0, name: "app".to_owned() },
+            CrateGraphNode { id: 1, name: "util".to_owned() },
+            CrateGraphNode { id: 2, name: "util-test".to_owned() },
+        ];
+        let dot = to_dot(&CrateGraphData { nodes, edges });
+        assert_eq!(
+            dot,
+            "digraph crates {\n    0 [label=\"app\"];\n    1 [label=\"util\"];\n    \
+             2 [label=\"util-test\"];\n    0 -> 1;\n    1 -> 2 [style=dashed];\n    \
+             2 -> 1 [style=dashed];\n}\n"
+        );
+    }
+}
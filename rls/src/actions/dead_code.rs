@@ -0,0 +1,99 @@
+//! Helpers for `rls/deadCode`, which reports the items of the workspace which
+//! are never referenced. Save-analysis doesn't record attributes, so these look
+//! for test functions and `#[cfg(test)]` code in the source.
+
+/// Returns true if the item on line `row` of `text` (zero-indexed) is a test
+/// or benchmark function, i.e., has a `#[test]` or `#[bench]` attribute (or
+/// one from a crate, e.g., `#[tokio::test]`).
+pub fn is_test_fn(text: &str, row: usize) -> bool {
+    let lines: Vec<&str> = text.lines().take(row).collect();
+    lines
+        .iter()
+        .rev()
+        .map(|line| line.trim())
+        .take_while(|line| line.starts_with("#[") || line.starts_with("///"))
+        .filter_map(|line| line.strip_prefix("#["))
+        .any(|attr| {
+            let path = attr.split(&[']', '('][..]).next().unwrap_or("");
+            let name = path.rsplit("::").next().unwrap_or("").trim();
+            name == "test" || name == "bench"
+        })
+}
+
+/// Returns the parts of `text` which are only compiled for tests, i.e., the
+/// items with a `#[cfg(test)]` attribute, such as `mod tests { ... }`.
+/// Braces in comments and literals aren't told apart from those of the code.
+pub fn cfg_test_regions(text: &str) -> Vec<&str> {
+    let mut regions = vec![];
+    let mut start = 0;
+    while let Some(i) = text[start..].find("#[cfg(test)]") {
+        let region_start = start + i;
+        let body = &text[region_start..];
+        let len = match body.find(&['{', ';'][..]) {
+            Some(j) if body[j..].starts_with(';') => j + 1,
+            Some(j) => {
+                let mut depth = 0;
+                let end = body[j..].char_indices().find(|&(_, c)| {
+                    match c {
+                        '{' => depth += 1,
+                        '}' => depth -= 1,
+                        _ => {}
+                    }
+                    depth == 0
+                });
+                end.map_or(body.len(), |(k, _)| j + k + 1)
+            }
+            None => body.len(),
+        };
+        regions.push(&body[..len]);
+        start = region_start + len;
+    }
+    regions
+}
+
+/// Returns true if `name` occurs as a word in one of `regions`.
+pub fn is_mentioned(regions: &[&str], name: &str) -> bool {
+    let is_ident_char = |c: char| c.is_alphanumeric() || c == '_';
+    regions.iter().any(|region| {
+        region.match_indices(name).any(|(i, _)| {
+            !region[..i].ends_with(is_ident_char)
+                && !region[i + name.len()..].starts_with(is_ident_char)
+        })
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_code() {
+        let text = "fn helper() {}\n\
+                    fn helpers() {}\n\
+                    #[cfg(test)]\n\
+                    use foo::bar;\n\
+                    #[cfg(test)]\n\
+                    mod tests {\n    \
+                        /// Checks it.\n    \
+                        #[test]\n    \
+                        fn check() { if true { helper(); } }\n    \
+                        #[tokio::test(flavor = \"current_thread\")]\n    \
+                        fn check_async() {}\n\
+                    }\n\
+                    fn after() {}\n";
+        let regions = cfg_test_regions(text);
+        assert_eq!(regions.len(), 2);
+        assert_eq!(regions[0], "#[cfg(test)]\nuse foo::bar;");
+        assert!(regions[1].starts_with("#[cfg(test)]\nmod tests {"));
+        assert!(regions[1].ends_with("fn check_async() {}\n}"));
+
+        assert!(is_mentioned(&regions, "helper"));
+        assert!(!is_mentioned(&regions, "helpers"));
+        assert!(!is_mentioned(&regions, "after"));
+
+        assert!(!is_test_fn(text, 0));
+        assert!(is_test_fn(text, 8));
+        assert!(is_test_fn(text, 10));
+        assert!(!is_test_fn(text, 12));
+    }
+}
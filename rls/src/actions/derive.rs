@@ -0,0 +1,195 @@
+//! Code actions adding a common derive (e.g., `Debug`) to the struct, enum or
+//! union defined at the cursor.
+
+use lazy_static::lazy_static;
+use regex::Regex;
+
+use crate::actions::field_completion::position_of;
+use crate::lsp_data::{Position, Range};
+
+/// The traits which are offered, in order.
+const DERIVES: &[&str] =
+    &["Debug", "Clone", "Copy", "Default", "PartialEq", "Eq", "Hash", "PartialOrd", "Ord"];
+
+/// An edit adding `derive` to the derives of a type.
+#[derive(Debug, PartialEq)]
+pub struct DeriveEdit {
+    pub derive: &'static str,
+    pub range: Range,
+    pub new_text: String,
+}
+
+/// Returns an edit for each of the common derives which the type defined on
+/// (zero-indexed) line `row` of `text` doesn't have yet. The derive is merged
+/// into an existing `#[derive(...)]` attribute if there is one. Returns no
+/// edits if no type is defined on that line.
+pub fn derive_edits(text: &str, row: usize) -> Vec<DeriveEdit> {
+    lazy_static! {
+        static ref ITEM_RE: Regex =
+            Regex::new(r"^(?:pub(?:\s*\([^)]*\))?\s+)?(?:struct|enum|union)\s+\w").unwrap();
+        static ref DERIVE_RE: Regex = Regex::new(r"(?m)^\s*#\[\s*derive\s*\(([^)]*)\)").unwrap();
+    }
+
+    let lines: Vec<&str> = text.split('\n').collect();
+    match lines.get(row) {
+        Some(line) if ITEM_RE.is_match(line.trim_start()) => {}
+        _ => return vec![],
+    }
+    let line_offset = |row: usize| lines[..row].iter().map(|line| line.len() + 1).sum::<usize>();
+
+    // The attributes and doc comments of the item.
+    let first = attributes_start(&lines, row);
+    let attrs_start = line_offset(first);
+    let attrs = &text[attrs_start..line_offset(row)];
+
+    let derived: Vec<&str> = DERIVE_RE
+        .captures_iter(attrs)
+        .flat_map(|c| c.get(1).unwrap().as_str().split(','))
+        // Derives may be given by path, e.g., `std::fmt::Debug`.
+        .filter_map(|derive| derive.trim().rsplit("::").next())
+        .collect();
+    let missing = DERIVES.iter().filter(|derive| !derived.contains(derive));
+
+    match DERIVE_RE.captures(attrs).and_then(|c| c.get(1)) {
+        Some(list) => {
+            let existing = list.as_str().trim_end();
+            let (line, col) = position_of(text, attrs_start + list.start() + existing.len());
+            let position = Position::new(line.into(), col.into());
+            missing
+                .map(|&derive| DeriveEdit {
+                    derive,
+                    range: Range::new(position, position),
+                    new_text: if existing.is_empty() {
+                        derive.to_owned()
+                    } else if existing.ends_with(',') {
+                        format!(" {},", derive)
+                    } else {
+                        format!(", {}", derive)
+                    },
+                })
+                .collect()
+        }
+        None => {
+            // Add the attribute after the doc comments, but before any other
+            // attributes.
+            let docs_end = (first..row).rev().find(|&r| lines[r].trim_start().starts_with("//"));
+            let insert_row = (docs_end.map_or(first, |r| r + 1)..row)
+                .find(|&r| lines[r].trim_start().starts_with("#["))
+                .unwrap_or(row);
+            let line = lines[insert_row];
+            let indent = &line[..line.len() - line.trim_start().len()];
+            let position = Position::new(insert_row as u64, indent.chars().count() as u64);
+            missing
+                .map(|&derive| DeriveEdit {
+                    derive,
+                    range: Range::new(position, position),
+                    new_text: format!("#[derive({})]\n{}", derive, indent),
+                })
+                .collect()
+        }
+    }
+}
+
+/// Returns the first line of the attributes and doc comments right above line
+/// `row`, or `row` if there are none.
+fn attributes_start(lines: &[&str], row: usize) -> usize {
+    let mut first = row;
+    while first > 0 {
+        let line = lines[first - 1].trim();
+        if is_attribute_start(line) || line.starts_with("//") {
+            first -= 1;
+        } else if line.ends_with(']') {
+            // The end of an attribute over several lines, e.g., `)]`.
+            match multiline_attribute_start(lines, first - 1) {
+                Some(start) => first = start,
+                None => break,
+            }
+        } else {
+            break;
+        }
+    }
+    first
+}
+
+/// Returns the line opening the `[` which is closed on line `end`, if that
+/// line starts an attribute.
+fn multiline_attribute_start(lines: &[&str], end: usize) -> Option<usize> {
+    // The number of `[`s which are still to be found above.
+    let mut unopened = 0;
+    for row in (0..=end).rev() {
+        let line = lines[row].trim();
+        unopened += line.matches(']').count() as i32 - line.matches('[').count() as i32;
+        if unopened <= 0 {
+            return Some(row).filter(|_| is_attribute_start(line));
+        }
+    }
+    None
+}
+
+fn is_attribute_start(line: &str) -> bool {
+    line.starts_with("#[") || line.starts_with("#![")
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn edit(derive: &str, text: &str, row: usize) -> Option<(Position, String)> {
+        derive_edits(text, row)
+            .into_iter()
+            .find(|edit| edit.derive == derive)
+            .map(|edit| (edit.range.start, edit.new_text))
+    }
+
+    #[test]
+    fn merges_into_existing_derives() {
+        let text = "#[derive(Clone)]\nstruct Foo;\n";
+        assert_eq!(edit("Debug", text, 1), Some((Position::new(0, 14), ", Debug".to_owned())));
+        assert_eq!(edit("Clone", text, 1), None);
+
+        let text = "#[derive(\n    Clone,\n    Copy,\n)]\npub enum Foo {";
+        assert_eq!(edit("Debug", text, 4), Some((Position::new(2, 9), " Debug,".to_owned())));
+        assert_eq!(edit("Copy", text, 4), None);
+
+        let text = "#[derive(std::fmt::Debug)]\n#[derive()]\nstruct Foo;";
+        assert_eq!(edit("Debug", text, 2), None);
+        assert_eq!(edit("Clone", text, 2), Some((Position::new(0, 24), ", Clone".to_owned())));
+    }
+
+    #[test]
+    fn adds_derive_after_doc_comments() {
+        let text = "/// Docs.\n#[repr(C)]\npub(crate) union Foo {";
+        assert_eq!(
+            edit("Debug", text, 2),
+            Some((Position::new(1, 0), "#[derive(Debug)]\n".to_owned()))
+        );
+
+        let text = "mod foo {\n    #[repr(C)]\n    /// Docs.\n    struct Foo;\n}";
+        assert_eq!(
+            edit("Debug", text, 3),
+            Some((Position::new(3, 4), "#[derive(Debug)]\n    ".to_owned()))
+        );
+    }
+
+    #[test]
+    fn attributes_start_with_a_hash() {
+        let text = "#![allow(dead_code)]\n#[derive(\n    Clone,\n)]\nstruct Foo;";
+        assert_eq!(attributes_start(&text.split('\n').collect::<Vec<_>>(), 4), 0);
+
+        // A line ending with a `]` which doesn't belong to an attribute.
+        let text = "let foo = [\n    1,\n]\nstruct Foo;";
+        assert_eq!(attributes_start(&text.split('\n').collect::<Vec<_>>(), 3), 3);
+        assert_eq!(
+            edit("Debug", text, 3),
+            Some((Position::new(3, 0), "#[derive(Debug)]\n".to_owned()))
+        );
+    }
+
+    #[test]
+    fn only_for_type_definitions() {
+        assert!(derive_edits("fn foo() {}\nstruct Foo;", 0).is_empty());
+        assert!(derive_edits("let x = Foo { a: 1 };", 0).is_empty());
+        assert!(derive_edits("struct Foo;", 1).is_empty());
+        assert_eq!(derive_edits("struct Foo;", 0).len(), DERIVES.len());
+    }
+}
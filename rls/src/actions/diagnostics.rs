@@ -39,6 +39,8 @@ struct CompilerMessage {
     level: String,
     spans: Vec<DiagnosticSpan>,
     children: Vec<AssociatedMessage>,
+    /// The diagnostic as rustc would print it, possibly with ANSI colors.
+    rendered: Option<String>,
 }
 
 /// Represents an emitted subdiagnostic for a certain message. Rustc also emits
@@ -56,10 +58,14 @@ struct CompilerMessageCode {
     code: String,
 }
 
+/// Parses a JSON message emitted by rustc. If `use_rendered` is set, the
+/// message as rendered by rustc is used as the diagnostic message (LSP 3.15
+/// `Diagnostic`s have no other place to keep it).
 pub fn parse_diagnostics(
     message: &str,
     cwd: &Path,
     related_information_support: bool,
+    use_rendered: bool,
 ) -> Option<ParsedDiagnostics> {
     let message = match serde_json::from_str::<CompilerMessage>(message) {
         Ok(m) => m,
@@ -102,17 +108,22 @@ pub fn parse_diagnostics(
             None
         };
 
-        let diagnostic_message = {
-            let mut diagnostic_message = message.message.clone();
-
-            if let Some(ref label) = span.label {
-                diagnostic_message.push_str(&format!("\n\n{}", label));
+        let diagnostic_message = match message.rendered {
+            Some(ref rendered) if use_rendered => {
+                strip_ansi_escapes(rendered).trim_end().to_owned()
             }
+            _ => {
+                let mut diagnostic_message = message.message.clone();
+
+                if let Some(ref label) = span.label {
+                    diagnostic_message.push_str(&format!("\n\n{}", label));
+                }
 
-            if let Some(notes) = format_notes(&message.children, span) {
-                diagnostic_message.push_str(&format!("\n\n{}", notes));
+                if let Some(notes) = format_notes(&message.children, span) {
+                    diagnostic_message.push_str(&format!("\n\n{}", notes));
+                }
+                diagnostic_message
             }
-            diagnostic_message
         };
 
         // A diagnostic source is quite likely to be clippy if it contains
@@ -151,6 +162,28 @@ pub fn parse_diagnostics(
     Some(ParsedDiagnostics { diagnostics })
 }
 
+/// Removes ANSI escape sequences (e.g., colors) from `text`.
+fn strip_ansi_escapes(text: &str) -> String {
+    let mut result = String::with_capacity(text.len());
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        if c != '\u{1b}' {
+            result.push(c);
+            continue;
+        }
+        // Control sequences are `ESC [`, parameters and a final byte in
+        // `@`..=`~`; other escapes are `ESC` and a single character.
+        if chars.next() == Some('[') {
+            for c in &mut chars {
+                if ('@'..='~').contains(&c) {
+                    break;
+                }
+            }
+        }
+    }
+    result
+}
+
 fn format_notes(children: &[AssociatedMessage], primary: &DiagnosticSpan) -> Option<String> {
     let mut notes = String::new();
 
@@ -320,7 +353,7 @@ mod diagnostic_message_test {
     ) -> ParsedDiagnostics {
         let _ = ::env_logger::try_init();
         let cwd = ::std::env::current_dir().unwrap();
-        parse_diagnostics(compiler_message, &cwd, with_related_information)
+        parse_diagnostics(compiler_message, &cwd, with_related_information, false)
             .expect("failed to parse compiler message")
     }
 
@@ -432,6 +465,30 @@ mod diagnostic_message_test {
         assert!(secondaries.is_empty(), "{:?}", secondaries);
     }
 
+    /// As `message_use_after_move`, but with the colored message rendered for
+    /// `--json=diagnostic-rendered-ansi`.
+    #[test]
+    fn message_rendered_without_ansi_escapes() {
+        let mut message: serde_json::Value =
+            serde_json::from_str(&read_fixture("compiler_message/use-after-move.json")).unwrap();
+        message["rendered"] =
+            "\u{1b}[0m\u{1b}[1m\u{1b}[38;5;9merror[E0382]\u{1b}[0m\u{1b}[0m\u{1b}[1m: \
+             use of moved value: `s`\u{1b}[0m\n   \u{1b}[0m\u{1b}[0m\u{1b}[1m\u{1b}[38;5;12m--> \
+             \u{1b}[0m\u{1b}[0msrc/lib.rs:147:26\u{1b}[0m\n\n"
+                .into();
+        let message = message.to_string();
+        let cwd = ::std::env::current_dir().unwrap();
+
+        let diag = parse_diagnostics(&message, &cwd, true, true).unwrap();
+        assert_eq!(
+            diag.to_primary_messages(),
+            vec!["error[E0382]: use of moved value: `s`\n   --> src/lib.rs:147:26"]
+        );
+
+        let diag = parse_diagnostics(&message, &cwd, true, false).unwrap();
+        assert!(diag.to_primary_messages()[0].starts_with("use of moved value: `s`\n\n"));
+    }
+
     /// ```
     /// fn mismatched_types() -> usize {
     ///     123_i32
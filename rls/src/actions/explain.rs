@@ -0,0 +1,117 @@
+//! Long-form explanations of error codes (`rustc --explain`), for the
+//! `rls/explainError` request.
+
+use std::collections::HashMap;
+use std::env;
+use std::process::Command;
+use std::sync::Mutex;
+
+use log::debug;
+
+/// Explanations returned so far, by error code.
+#[derive(Default)]
+pub struct ExplanationCache {
+    entries: Mutex<HashMap<String, String>>,
+}
+
+impl ExplanationCache {
+    /// Returns the explanation of `code` (e.g., `E0499`), obtained with
+    /// `explain` the first time a code is asked for. Anything which isn't an
+    /// error code (e.g., a lint name) gets a message saying where to look
+    /// instead.
+    pub fn explain(&self, code: &str, explain: impl FnOnce(&str) -> Option<String>) -> String {
+        let code = match normalize_code(code) {
+            Some(code) => code,
+            None => return no_error_code_message(code.trim()),
+        };
+
+        // Holding the lock while explaining makes sure each code is only
+        // explained once, even if asked for concurrently.
+        let mut entries = self.entries.lock().unwrap();
+        entries
+            .entry(code.clone())
+            .or_insert_with(|| {
+                explain(&code).unwrap_or_else(|| {
+                    format!("No extended explanation is available for {}.", code)
+                })
+            })
+            .clone()
+    }
+}
+
+/// Normalizes `E0499`, `e0499` and `0499` to `E0499`, or returns `None` if
+/// `code` isn't an error code.
+fn normalize_code(code: &str) -> Option<String> {
+    let code = code.trim();
+    let digits = code.strip_prefix(|c| c == 'E' || c == 'e').unwrap_or(code);
+    if digits.len() == 4 && digits.chars().all(|c| c.is_ascii_digit()) {
+        Some(format!("E{}", digits))
+    } else {
+        None
+    }
+}
+
+fn no_error_code_message(code: &str) -> String {
+    format!(
+        "`{}` is not an error code, so it has no extended explanation. If it's a lint, see \
+         https://doc.rust-lang.org/rustc/lints/listing/index.html (or \
+         https://rust-lang.github.io/rust-clippy/master/ for Clippy lints).",
+        code
+    )
+}
+
+/// Runs `rustc --explain <code>` through the RLS rustc shim (or `$RUSTC`, as
+/// for builds), returning `None` if there is no explanation for `code`.
+pub fn rustc_explain(code: &str) -> Option<String> {
+    let rustc = env::var("RUSTC")
+        .ok()
+        .or_else(|| env::current_exe().ok().and_then(|x| x.to_str().map(String::from)))?;
+
+    let output = Command::new(rustc)
+        .env(crate::RUSTC_SHIM_ENV_VAR_NAME, "1")
+        .arg("--explain")
+        .arg(code)
+        .output()
+        .map_err(|e| debug!("rustc_explain: couldn't run rustc: {}", e))
+        .ok()?;
+    if !output.status.success() {
+        debug!("rustc_explain: {}", String::from_utf8_lossy(&output.stderr).trim());
+        return None;
+    }
+
+    let explanation = String::from_utf8(output.stdout).ok()?;
+    Some(explanation.trim().to_owned()).filter(|s| !s.is_empty())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::cell::Cell;
+
+    #[test]
+    fn explanations_are_cached() {
+        let cache = ExplanationCache::default();
+        let calls = Cell::new(0);
+        let stub = |code: &str| {
+            calls.set(calls.get() + 1);
+            Some(format!("Explanation of {}", code))
+        };
+
+        assert_eq!(cache.explain("E0499", stub), "Explanation of E0499");
+        assert_eq!(cache.explain("0499", stub), "Explanation of E0499");
+        assert_eq!(calls.get(), 1);
+
+        assert_eq!(cache.explain("E0382", stub), "Explanation of E0382");
+        assert_eq!(calls.get(), 2);
+    }
+
+    #[test]
+    fn lints_have_no_explanation() {
+        let cache = ExplanationCache::default();
+        let explanation = cache.explain("unused_variables", |_| panic!("not an error code"));
+        assert!(explanation.starts_with("`unused_variables` is not an error code"));
+
+        let explanation = cache.explain("E0001", |_| None);
+        assert_eq!(explanation, "No extended explanation is available for E0001.");
+    }
+}
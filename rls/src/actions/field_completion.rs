@@ -0,0 +1,270 @@
+//! Completion of field names in struct literals (e.g., `Foo { x: 3, | }`),
+//! using the fields of the struct from the analysis data.
+
+use std::path::Path;
+
+use rls_analysis::DefKind;
+use rls_span as span;
+use rls_vfs::FileContents;
+
+use crate::actions::InitActionContext;
+use crate::lsp_data::{CompletionItem, CompletionItemKind, Position};
+use crate::Span;
+
+/// Keywords which can come right before a path followed by `{` where that
+/// isn't a struct literal, e.g., `impl Foo {` or `match foo {`.
+const NON_LITERAL_KEYWORDS: &[&str] = &[
+    "as", "dyn", "enum", "for", "if", "impl", "in", "match", "mod", "struct", "trait", "union",
+    "where", "while",
+];
+
+/// A struct literal, with the cursor where a field name goes.
+#[derive(Debug, PartialEq)]
+struct StructLiteral {
+    /// The byte offset of the struct's name (the last segment of its path).
+    name_start: usize,
+    name_end: usize,
+    /// The fields which are already given, before or after the cursor.
+    given: Vec<String>,
+}
+
+/// Returns the fields of the struct in whose literal the cursor is, leaving
+/// out those already given. Returns `None` if the cursor isn't at the name of
+/// a field in a struct literal, or the struct isn't known.
+pub fn complete(
+    ctx: &InitActionContext,
+    file_path: &Path,
+    position: Position,
+) -> Option<Vec<CompletionItem>> {
+    let text = match ctx.vfs.load_file(file_path).ok()? {
+        FileContents::Text(text) => text,
+        FileContents::Binary(_) => return None,
+    };
+    let offset = offset_of(&text, position)?;
+    let literal = struct_literal_at(&text, offset)?;
+
+    let (row, col_start) = position_of(&text, literal.name_start);
+    let (_, col_end) = position_of(&text, literal.name_end);
+    let name_span = Span::new(
+        span::Row::new_zero_indexed(row),
+        span::Row::new_zero_indexed(row),
+        span::Column::new_zero_indexed(col_start),
+        span::Column::new_zero_indexed(col_end),
+        file_path,
+    );
+    let id = ctx.analysis.id(&name_span).ok()?;
+    let def = ctx.analysis.get_def(id).ok()?;
+    if def.kind != DefKind::Struct && def.kind != DefKind::StructVariant {
+        return None;
+    }
+
+    let mut fields = ctx
+        .analysis
+        .for_each_child_def(id, |_, def| def.clone())
+        .ok()?
+        .into_iter()
+        .filter(|def| def.kind == DefKind::Field)
+        .collect::<Vec<_>>();
+    // Fields of tuple structs are numbered, which isn't worth completing.
+    if fields.iter().any(|field| field.name.starts_with(|c: char| c.is_ascii_digit())) {
+        return None;
+    }
+    fields.sort_by(|a, b| a.span.cmp(&b.span));
+
+    Some(
+        fields
+            .into_iter()
+            .filter(|field| !literal.given.contains(&field.name))
+            .map(|field| {
+                // The value of a field is its type.
+                let mut item = CompletionItem::new_simple(field.name, field.value);
+                item.kind = Some(CompletionItemKind::Field);
+                item
+            })
+            .collect(),
+    )
+}
+
+/// Finds the struct literal around the cursor at byte `offset` of `text`, if
+/// the cursor is where a field name goes (i.e., not in a field's value or
+/// after the `..base` of functional update syntax).
+fn struct_literal_at(text: &str, offset: usize) -> Option<StructLiteral> {
+    let open = unclosed_brace_before(text, offset)?;
+    let (name_start, name_end) = path_name_before(text, open)?;
+
+    let before: Vec<&str> = top_level_segments(&text[open + 1..offset]);
+    let (current, previous) = before.split_last()?;
+    let current = current.trim();
+    if !current.chars().all(is_ident_char) || previous.iter().any(|s| s.trim().starts_with("..")) {
+        return None;
+    }
+
+    let close = matching_brace_after(text, offset).unwrap_or_else(|| text.len());
+    let after = top_level_segments(&text[offset..close]);
+    let given = previous
+        .iter()
+        .chain(after.iter().skip(1))
+        .filter_map(|segment| {
+            let name: String = segment.trim().chars().take_while(|&c| is_ident_char(c)).collect();
+            Some(name).filter(|name| !name.is_empty())
+        })
+        .collect();
+
+    Some(StructLiteral { name_start, name_end, given })
+}
+
+/// Returns the offset of the innermost `{` before `offset` which isn't closed
+/// before it.
+fn unclosed_brace_before(text: &str, offset: usize) -> Option<usize> {
+    let mut depth = 0;
+    for (i, c) in text[..offset].char_indices().rev() {
+        match c {
+            '}' | ')' | ']' => depth += 1,
+            '(' | '[' if depth == 0 => return None,
+            '{' if depth == 0 => return Some(i),
+            '{' | '(' | '[' => depth -= 1,
+            ';' if depth == 0 => return None,
+            _ => {}
+        }
+    }
+    None
+}
+
+/// Returns the offset of the `}` closing the brace the cursor at `offset` is
+/// in.
+fn matching_brace_after(text: &str, offset: usize) -> Option<usize> {
+    let mut depth = 0;
+    for (i, c) in text[offset..].char_indices() {
+        match c {
+            '{' | '(' | '[' => depth += 1,
+            '}' if depth == 0 => return Some(offset + i),
+            '}' | ')' | ']' => depth -= 1,
+            _ => {}
+        }
+    }
+    None
+}
+
+/// Returns the range of the last segment of the path (e.g., `Bar` in
+/// `foo::Bar {`) right before the `{` at `open`, unless what precedes it shows
+/// that it isn't a struct literal.
+fn path_name_before(text: &str, open: usize) -> Option<(usize, usize)> {
+    let name_end = text[..open].trim_end().len();
+    let name_start = text[..name_end]
+        .char_indices()
+        .rev()
+        .take_while(|&(_, c)| is_ident_char(c))
+        .last()
+        .map(|(i, _)| i)?;
+    if text[name_start..name_end].starts_with(|c: char| c.is_ascii_digit()) {
+        return None;
+    }
+
+    let mut path_start = name_start;
+    while text[..path_start].ends_with("::") {
+        path_start = text[..path_start - 2].trim_end_matches(is_ident_char).len();
+    }
+    let preceding = text[..path_start].trim_end();
+    let keyword = preceding.rsplit(|c: char| !is_ident_char(c)).next().unwrap_or("");
+    if preceding.ends_with("->") || NON_LITERAL_KEYWORDS.contains(&keyword) {
+        return None;
+    }
+    Some((name_start, name_end))
+}
+
+/// Splits `text` at the commas which aren't nested in brackets.
+fn top_level_segments(text: &str) -> Vec<&str> {
+    let mut segments = vec![];
+    let mut depth = 0;
+    let mut start = 0;
+    for (i, c) in text.char_indices() {
+        match c {
+            '{' | '(' | '[' => depth += 1,
+            '}' | ')' | ']' => depth -= 1,
+            ',' if depth == 0 => {
+                segments.push(&text[start..i]);
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    segments.push(&text[start..]);
+    segments
+}
+
+fn is_ident_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_'
+}
+
+/// Converts an LSP position to a byte offset in `text`.
+pub(crate) fn offset_of(text: &str, position: Position) -> Option<usize> {
+    let line_start = if position.line == 0 {
+        0
+    } else {
+        text.match_indices('\n').nth(position.line as usize - 1)?.0 + 1
+    };
+    let line = text[line_start..].split('\n').next()?;
+    let col = line.char_indices().nth(position.character as usize).map_or(line.len(), |(i, _)| i);
+    Some(line_start + col)
+}
+
+/// Converts a byte offset in `text` to a (row, column) position.
+pub(crate) fn position_of(text: &str, offset: usize) -> (u32, u32) {
+    let before = &text[..offset];
+    let line_start = before.rfind('\n').map_or(0, |i| i + 1);
+    (before.matches('\n').count() as u32, before[line_start..].chars().count() as u32)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    // Returns the struct name and the given fields of the literal at the `|`
+    // in `text`.
+    fn literal_at(text: &str) -> Option<(String, Vec<String>)> {
+        let offset = text.find('|').unwrap();
+        let text = text.replacen('|', "", 1);
+        let literal = struct_literal_at(&text, offset)?;
+        Some((text[literal.name_start..literal.name_end].to_owned(), literal.given))
+    }
+
+    fn literal(name: &str, given: &[&str]) -> Option<(String, Vec<String>)> {
+        Some((name.to_owned(), given.iter().map(|&s| s.to_owned()).collect()))
+    }
+
+    #[test]
+    fn finds_struct_literals() {
+        assert_eq!(literal_at("let f = Foo { x: 3, | };"), literal("Foo", &["x"]));
+        assert_eq!(
+            literal_at("let f = a::Foo {\n    y: vec![1, 2],\n    |\n};"),
+            literal("Foo", &["y"])
+        );
+        assert_eq!(literal_at("Foo { x: 1, |, z: 2 }"), literal("Foo", &["x", "z"]));
+        assert_eq!(literal_at("Bar { x: Foo { a: 1 }, y|"), literal("Bar", &["x"]));
+        assert_eq!(literal_at("Foo { x: 1, |..Default::default() }"), literal("Foo", &["x"]));
+    }
+
+    #[test]
+    fn ignores_other_positions() {
+        // In a value.
+        assert_eq!(literal_at("Foo { x: | }"), None);
+        assert_eq!(literal_at("Foo { x: bar(|) }"), None);
+        // After functional update syntax.
+        assert_eq!(literal_at("Foo { x: 1, ..base, | }"), None);
+        // Blocks.
+        assert_eq!(literal_at("impl Foo { | }"), None);
+        assert_eq!(literal_at("match Foo { | }"), None);
+        assert_eq!(literal_at("fn foo() -> Foo { | }"), None);
+        assert_eq!(literal_at("fn foo() { let x = 1; | }"), None);
+        // Tuple struct literals.
+        assert_eq!(literal_at("Foo(1, |)"), None);
+    }
+
+    #[test]
+    fn converts_positions() {
+        let text = "fn main() {\n    let é = Foo { | };\n}\n";
+        let offset = text.find('|').unwrap();
+        assert_eq!(offset_of(text, Position::new(1, 18)), Some(offset));
+        assert_eq!(position_of(text, offset), (1, 18));
+    }
+}
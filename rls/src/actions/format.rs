@@ -1,6 +1,7 @@
 //! Code formatting using Rustfmt -- by default using statically-linked one or
 //! possibly running Rustfmt binary specified by the user.
 
+use std::collections::HashMap;
 use std::env::temp_dir;
 use std::fmt;
 use std::fs::File;
@@ -12,7 +13,9 @@ use std::string::FromUtf8Error;
 use log::debug;
 use lsp_types::{Position, Range, TextEdit};
 use rand::{distributions, thread_rng, Rng};
-use rustfmt_nightly::{Config, Input, ModifiedLines, NewlineStyle, Session};
+use rustfmt_nightly::{
+    Config, FileLines, FileName, Input, ModifiedLines, NewlineStyle, Range as RustfmtRange, Session,
+};
 
 /// Specifies which `rustfmt` to use.
 #[derive(Clone)]
@@ -28,6 +31,11 @@ pub enum Rustfmt {
 pub enum Error {
     /// Generic variant of `Error::Rustfmt` error.
     Failed,
+    /// The input couldn't be parsed. Holds Rustfmt's report, which may be
+    /// empty.
+    Parse(String),
+    /// The external Rustfmt exited unsuccessfully, with this error output.
+    External(String),
     Rustfmt(rustfmt_nightly::ErrorKind),
     Io(std::io::Error),
     ConfigTomlOutput(String),
@@ -40,6 +48,15 @@ impl fmt::Display for Error {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
             Error::Failed => write!(f, "Formatting could not be completed."),
+            Error::Parse(report) if report.is_empty() => {
+                write!(f, "Formatting could not be completed because the code has syntax errors.")
+            }
+            Error::Parse(report) => write!(
+                f,
+                "Formatting could not be completed because the code has syntax errors:\n{}",
+                report
+            ),
+            Error::External(stderr) => write!(f, "Rustfmt failed: {}", stderr),
             Error::Rustfmt(err) => write!(f, "Could not format source code: {}", err),
             Error::Io(err) => write!(f, "Encountered I/O error: {}", err),
             Error::ConfigTomlOutput(err) => {
@@ -126,6 +143,103 @@ impl Rustfmt {
             })
             .collect())
     }
+
+    /// Formats only the lines from `start` to `end` (zero-based, inclusive) of
+    /// `input`, widened to whole statements so that a range splitting one
+    /// doesn't produce invalid code. Edits outside those lines are dropped.
+    pub fn calc_range_text_edits(
+        &self,
+        input: String,
+        mut cfg: Config,
+        start: u64,
+        end: u64,
+    ) -> Result<Vec<TextEdit>, Error> {
+        let (start, end) = snap_to_statements(&input, start as usize, end as usize);
+
+        // Rustfmt's line indices are 1-based
+        let mut ranges = HashMap::new();
+        ranges.insert(FileName::Stdin, vec![RustfmtRange::new(start + 1, end + 1)]);
+        cfg.set().file_lines(FileLines::from_ranges(ranges));
+
+        let (start, end) = (start as u64, end as u64);
+        Ok(self
+            .calc_text_edits(input, cfg)?
+            .into_iter()
+            .filter(|edit| {
+                let inside = edit.range.start.line >= start && edit.range.end.line <= end + 1;
+                if !inside {
+                    debug!("calc_range_text_edits: dropping edit outside the range: {:?}", edit);
+                }
+                inside
+            })
+            .collect())
+    }
+}
+
+/// Returns the lines to format after `ch` was typed right before `position`:
+/// from the `{` to the `}` of a block closed by a `}`, or the line finished by a
+/// newline. Returns `None` for other characters.
+pub fn on_type_lines(text: &str, position: Position, ch: &str) -> Option<(u64, u64)> {
+    match ch {
+        "}" => {
+            let line_start: usize =
+                text.split('\n').take(position.line as usize).map(|l| l.len() + 1).sum();
+            let line = text.get(line_start..)?.split('\n').next()?;
+            let (close, c) =
+                line.char_indices().nth((position.character as usize).checked_sub(1)?)?;
+            if c != '}' {
+                return None;
+            }
+
+            let mut depth = 0;
+            for (i, c) in text[..line_start + close].char_indices().rev() {
+                match c {
+                    '}' => depth += 1,
+                    '{' if depth == 0 => {
+                        return Some((text[..i].matches('\n').count() as u64, position.line));
+                    }
+                    '{' => depth -= 1,
+                    _ => {}
+                }
+            }
+            None
+        }
+        "\n" => position.line.checked_sub(1).map(|line| (line, line)),
+        _ => None,
+    }
+}
+
+/// Widens the lines from `start` to `end` (zero-based, inclusive) so that they
+/// start and end at statement boundaries, i.e., outside of any parentheses or
+/// brackets and after a line ending in `;`, `,`, `{` or `}`.
+fn snap_to_statements(text: &str, start: usize, end: usize) -> (usize, usize) {
+    let lines: Vec<&str> = text.lines().collect();
+    if lines.is_empty() {
+        return (start, end);
+    }
+    let last = lines.len() - 1;
+    let (start, end) = (start.min(last), end.min(last));
+
+    // Whether a statement can end at the end of each line.
+    let mut depth = 0i32;
+    let boundaries: Vec<bool> = lines
+        .iter()
+        .map(|line| {
+            let code = line.split("//").next().unwrap_or("").trim_end();
+            for c in code.chars() {
+                match c {
+                    '(' | '[' => depth += 1,
+                    ')' | ']' => depth -= 1,
+                    _ => {}
+                }
+            }
+            depth <= 0 && (code.is_empty() || code.ends_with(&[';', ',', '{', '}'][..]))
+        })
+        .collect();
+
+    let start = (0..=start).rev().find(|&i| i == 0 || boundaries[i - 1]).unwrap_or(0);
+    let end = (end..=last).find(|&i| boundaries[i]).unwrap_or(last);
+    (start, end)
 }
 
 fn format_external(
@@ -151,6 +265,11 @@ fn format_external(
     }
 
     let output = rustfmt.wait_with_output()?;
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr).trim().to_owned();
+        debug!("reformat: external rustfmt failed: {}", stderr);
+        return Err(Error::External(stderr));
+    }
     Ok(String::from_utf8(output.stdout)?)
 }
 
@@ -164,7 +283,12 @@ fn format_internal(input: String, config: Config) -> Result<String, Error> {
             Ok(report) => {
                 // `Session::format` returns `Ok` even if there are any errors, i.e., parsing
                 // errors.
-                if session.has_operational_errors() || session.has_parsing_errors() {
+                if session.has_parsing_errors() {
+                    debug!("reformat: format_input failed: has parsing errors");
+
+                    return Err(Error::Parse(report.to_string().trim().to_owned()));
+                }
+                if session.has_operational_errors() {
                     debug!("reformat: format_input failed: has errors, report = {}", report);
 
                     return Err(Error::Failed);
@@ -230,6 +354,7 @@ fn rustfmt_args(config: &Config, config_path: &Path) -> Vec<String> {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::build::Edition;
     use crate::config::FmtConfig;
     use lsp_types::{Position, Range, TextEdit};
     use rustfmt_nightly::FileLines;
@@ -268,6 +393,78 @@ mod tests {
         );
     }
 
+    #[test]
+    fn calc_range_text_edits() {
+        let input = "fn main() {\n    let x  =  1;\n    let y  =  2;\n}\n";
+        let config = FmtConfig::default().get_rustfmt_config().clone();
+        let edits =
+            Rustfmt::Internal.calc_range_text_edits(input.to_string(), config, 1, 1).unwrap();
+
+        // Only the requested line is formatted, not `let y`.
+        assert_eq!(
+            edits,
+            vec![TextEdit {
+                range: Range { start: Position::new(1, 0), end: Position::new(2, 0) },
+                new_text: "    let x = 1;\n".to_owned(),
+            }]
+        );
+    }
+
+    #[test]
+    fn on_type_formatting_of_block() {
+        let input = "fn foo()  {  }\n\nfn main() {\nif true {\nlet x  =  1;\n}\n}\n";
+        let (start, end) = on_type_lines(input, Position::new(5, 1), "}").unwrap();
+        assert_eq!((start, end), (3, 5));
+
+        let config = FmtConfig::default().get_rustfmt_config().clone();
+        let edits =
+            Rustfmt::Internal.calc_range_text_edits(input.to_string(), config, start, end).unwrap();
+
+        // The block is reindented, but `foo` and `main` are left alone.
+        assert_eq!(
+            edits,
+            vec![TextEdit {
+                range: Range { start: Position::new(3, 0), end: Position::new(6, 0) },
+                new_text: "    if true {\n        let x = 1;\n    }\n".to_owned(),
+            }]
+        );
+
+        assert_eq!(on_type_lines(input, Position::new(4, 0), "\n"), Some((3, 3)));
+        assert_eq!(on_type_lines(input, Position::new(5, 0), "}"), None);
+        assert_eq!(on_type_lines(input, Position::new(5, 1), ";"), None);
+    }
+
+    #[test]
+    fn range_snaps_to_statements() {
+        let input = "fn main() {\n    let x = foo(\n        a,\n        b);\n    let y = 2;\n}\n";
+        assert_eq!(snap_to_statements(input, 2, 2), (1, 3));
+        assert_eq!(snap_to_statements(input, 4, 4), (4, 4));
+        assert_eq!(snap_to_statements(input, 3, 10), (1, 5));
+    }
+
+    #[test]
+    fn formats_with_configured_edition() {
+        let format = |edition| {
+            let config = FmtConfig::with_options(Path::new("."), None, Some(edition));
+            let input = "fn main() { let _ = async {  1  }; }\n".to_owned();
+            Rustfmt::Internal.format(input, config.get_rustfmt_config().clone())
+        };
+
+        // `async` blocks only exist as of the 2018 edition.
+        assert!(format(Edition::Edition2015).is_err());
+        assert!(format(Edition::Edition2018).unwrap().contains("let _ = async { 1 };"));
+    }
+
+    #[test]
+    fn syntax_errors_are_reported() {
+        let config = FmtConfig::default().get_rustfmt_config().clone();
+        let input = "fn main() {\n    let x = ;\n}\n".to_owned();
+        let err = Rustfmt::Internal.calc_text_edits(input, config).unwrap_err();
+
+        assert!(matches!(err, Error::Parse(_)));
+        assert!(err.to_string().contains("the code has syntax errors"));
+    }
+
     #[test]
     fn no_empty_file_lines() {
         let config_with_lines = {
@@ -0,0 +1,100 @@
+//! Tells whether the loaded analysis data reflects the current contents of the
+//! open documents, as reported by `rls/analysisFreshness`. Features which use
+//! the analysis data return stale results for documents changed since the
+//! build it came from was requested.
+
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::SystemTime;
+
+use rls_vfs::Vfs;
+
+/// The contents of the open documents as of when a build was requested.
+#[derive(Clone, Debug)]
+pub struct Snapshot {
+    requested_at: SystemTime,
+    hashes: HashMap<PathBuf, u64>,
+}
+
+impl Snapshot {
+    /// Takes a snapshot of the contents of `open_files` in the `vfs`.
+    pub fn take(vfs: &Vfs, open_files: &[PathBuf]) -> Snapshot {
+        let hashes = open_files
+            .iter()
+            .filter_map(|path| Some((path.clone(), vfs.content_hash(path).ok()?)))
+            .collect();
+        Snapshot { requested_at: SystemTime::now(), hashes }
+    }
+
+    /// Returns true if the document at `path` in the `vfs` is unchanged since
+    /// the snapshot. Documents which weren't open then were built from the
+    /// file on disk, so they are unchanged if they match it and it wasn't
+    /// modified since.
+    fn is_fresh(&self, vfs: &Vfs, path: &Path) -> bool {
+        match self.hashes.get(path) {
+            Some(&hash) => vfs.content_hash(path).ok() == Some(hash),
+            None => {
+                let modified = fs::metadata(path).and_then(|metadata| metadata.modified());
+                modified.map_or(false, |modified| modified <= self.requested_at)
+                    && fs::read(path).ok() == vfs.load_bytes(path).ok()
+            }
+        }
+    }
+}
+
+/// Keeps the snapshot of the build whose analysis data is loaded, if any.
+#[derive(Debug, Default)]
+pub struct Freshness {
+    loaded: Mutex<Option<Snapshot>>,
+}
+
+impl Freshness {
+    /// Records that the analysis data of the build of `snapshot` was loaded,
+    /// unless that of a build requested later was loaded already.
+    pub fn record_loaded(&self, snapshot: Snapshot) {
+        let mut loaded = self.loaded.lock().unwrap();
+        if loaded.as_ref().map_or(true, |loaded| loaded.requested_at <= snapshot.requested_at) {
+            *loaded = Some(snapshot);
+        }
+    }
+
+    /// Returns true if the loaded analysis data reflects the contents of the
+    /// document at `path` in the `vfs`. It doesn't if none was loaded yet.
+    pub fn is_fresh(&self, vfs: &Vfs, path: &Path) -> bool {
+        self.loaded.lock().unwrap().as_ref().map_or(false, |loaded| loaded.is_fresh(vfs, path))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn freshness_of_documents() {
+        let vfs = Vfs::new();
+        let main = Path::new("/nonexistent/src/main.rs");
+        vfs.set_file(main, "fn main() {}\n");
+        let freshness = Freshness::default();
+        assert!(!freshness.is_fresh(&vfs, main));
+
+        let snapshot = Snapshot::take(&vfs, &[main.to_owned()]);
+        freshness.record_loaded(snapshot.clone());
+        assert!(freshness.is_fresh(&vfs, main));
+        vfs.set_file(main, "fn main() { foo(); }\n");
+        assert!(!freshness.is_fresh(&vfs, main));
+        vfs.set_file(main, "fn main() {}\n");
+        assert!(freshness.is_fresh(&vfs, main));
+
+        // Neither open when the build was requested nor on disk.
+        let lib = Path::new("/nonexistent/src/lib.rs");
+        vfs.set_file(lib, "");
+        assert!(!freshness.is_fresh(&vfs, lib));
+
+        // An older build doesn't replace the data of a newer one.
+        let older = snapshot.requested_at - std::time::Duration::from_secs(60);
+        freshness.record_loaded(Snapshot { requested_at: older, hashes: HashMap::new() });
+        assert!(freshness.is_fresh(&vfs, main));
+    }
+}
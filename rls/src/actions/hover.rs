@@ -627,7 +627,9 @@ fn racer_match_to_def(ctx: &InitActionContext, m: &racer::Match) -> Option<Def>
             qualname: "".to_string(),
             distro_crate: false,
             parent: None,
+            decl_id: None,
             docs,
+            sig: None,
         };
         trace!(
             "racer_match_to_def: Def {{ kind: {:?}, span: {:?}, name: {:?}, \
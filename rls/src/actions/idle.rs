@@ -0,0 +1,151 @@
+//! Dropping the analysis data when the server has been idle for a while (see
+//! the `idle_unload_minutes` option), to reclaim memory. The data is reloaded
+//! by the analysis host as soon as it's needed again.
+
+use std::collections::HashSet;
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use log::{debug, info};
+
+use crate::actions::InitActionContext;
+
+/// How often to check whether the server is idle.
+const CHECK_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Keeps track of the last message from the client and the open documents.
+pub struct IdleTracker {
+    last_activity: Mutex<Instant>,
+    open_files: Mutex<HashSet<PathBuf>>,
+    /// Whether the thread started by `watch` is running.
+    watching: AtomicBool,
+}
+
+impl Default for IdleTracker {
+    fn default() -> Self {
+        IdleTracker {
+            last_activity: Mutex::new(Instant::now()),
+            open_files: Mutex::default(),
+            watching: AtomicBool::new(false),
+        }
+    }
+}
+
+impl IdleTracker {
+    /// Records a message from the client.
+    pub fn touch(&self) {
+        *self.last_activity.lock().unwrap() = Instant::now();
+    }
+
+    pub fn file_opened(&self, path: &Path) {
+        self.open_files.lock().unwrap().insert(path.to_owned());
+    }
+
+    pub fn file_closed(&self, path: &Path) {
+        self.open_files.lock().unwrap().remove(path);
+    }
+
+    /// Returns the documents which are open, sorted by path.
+    pub fn open_files(&self) -> Vec<PathBuf> {
+        let mut files: Vec<_> = self.open_files.lock().unwrap().iter().cloned().collect();
+        files.sort();
+        files
+    }
+
+    /// Returns true if, as of `now`, there hasn't been any message for
+    /// `timeout` and no documents are open.
+    pub fn is_idle(&self, timeout: Duration, now: Instant) -> bool {
+        let last_activity = *self.last_activity.lock().unwrap();
+        self.open_files.lock().unwrap().is_empty()
+            && now.saturating_duration_since(last_activity) >= timeout
+    }
+}
+
+/// Unloads the analysis data if `ctx` has been idle for longer than the
+/// configured timeout, as of `now`. Returns true if it was unloaded.
+pub fn unload_if_idle(ctx: &InitActionContext, now: Instant) -> bool {
+    let minutes = match ctx.config.lock().unwrap().idle_unload_minutes {
+        Some(minutes) => minutes,
+        None => return false,
+    };
+    let timeout = Duration::from_secs(minutes.saturating_mul(60));
+    if !ctx.idle.is_idle(timeout, now) || ctx.active_build_count.load(Ordering::SeqCst) > 0 {
+        return false;
+    }
+
+    let index = index_path(ctx);
+    match ctx.analysis.unload(&index, &ctx.current_project, &ctx.current_project) {
+        Ok(()) => {
+            info!("idle for {} minutes, unloaded analysis data to {}", minutes, index.display());
+            true
+        }
+        Err(e) => {
+            debug!("unload_if_idle: could not unload analysis data: {:?}", e);
+            false
+        }
+    }
+}
+
+/// Removes the file the analysis data was unloaded to, if any. The analysis
+/// host removes it itself once the data is loaded again.
+pub fn remove_index(ctx: &InitActionContext) {
+    let _ = fs::remove_file(index_path(ctx));
+}
+
+fn index_path(ctx: &InitActionContext) -> PathBuf {
+    env::temp_dir().join(format!("rls-analysis-{}.idx", ctx.pid))
+}
+
+/// Periodically checks whether to unload the analysis data, until the server
+/// shuts down or the `idle_unload_minutes` option is unset. Does nothing if
+/// the option isn't set or a previous call is still checking.
+pub fn watch(ctx: InitActionContext) {
+    {
+        // The config is locked while `watching` changes, so that the thread
+        // can't stop just after we've seen it running.
+        let config = ctx.config.lock().unwrap();
+        if config.idle_unload_minutes.is_none() || ctx.idle.watching.swap(true, Ordering::SeqCst) {
+            return;
+        }
+    }
+    thread::spawn(move || loop {
+        thread::sleep(CHECK_INTERVAL);
+        {
+            let config = ctx.config.lock().unwrap();
+            if ctx.shut_down.load(Ordering::SeqCst) || config.idle_unload_minutes.is_none() {
+                ctx.idle.watching.store(false, Ordering::SeqCst);
+                return;
+            }
+        }
+        unload_if_idle(&ctx, Instant::now());
+    });
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn idle_without_activity_or_open_files() {
+        let tracker = IdleTracker::default();
+        let timeout = Duration::from_secs(60);
+        let start = Instant::now();
+        tracker.touch();
+
+        assert!(!tracker.is_idle(timeout, start));
+        assert!(tracker.is_idle(timeout, start + Duration::from_secs(61)));
+
+        tracker.file_opened(Path::new("src/main.rs"));
+        assert!(!tracker.is_idle(timeout, start + Duration::from_secs(61)));
+        tracker.file_closed(Path::new("src/main.rs"));
+        assert!(tracker.is_idle(timeout, start + Duration::from_secs(61)));
+
+        tracker.touch();
+        assert!(!tracker.is_idle(timeout, Instant::now()));
+    }
+}
@@ -0,0 +1,266 @@
+//! Code actions adding a `use` for a name which the compiler couldn't resolve,
+//! with the paths of the defs with that name from the analysis data, and
+//! sorting the `use`s of a file.
+
+use lazy_static::lazy_static;
+use regex::Regex;
+
+use crate::lsp_data::{Position, Range};
+
+/// A top-level `use` declaration, possibly over several lines.
+#[derive(Debug)]
+struct UseDecl {
+    start_row: usize,
+    end_row: usize,
+    /// The declaration with its lines joined, e.g., `use std::fmt;`.
+    text: String,
+}
+
+/// Returns the name which the message of a diagnostic says couldn't be
+/// resolved, e.g., `HashMap` for "cannot find type `HashMap` in this scope".
+pub fn unresolved_name(message: &str) -> Option<&str> {
+    lazy_static! {
+        static ref UNRESOLVED_RE: Regex = Regex::new(concat!(
+            r"^(?:cannot find [a-z ,]+ `(\w+)` in this scope",
+            r"|failed to resolve: use of undeclared (?:type|crate)(?: or module)? `(\w+)`)",
+        ))
+        .unwrap();
+    }
+
+    let captures = UNRESOLVED_RE.captures(message)?;
+    captures.get(1).or_else(|| captures.get(2)).map(|name| name.as_str())
+}
+
+/// Ranks a path by how close its crate is to the file: the same crate first,
+/// then other crates (e.g., dependencies), then the standard library.
+pub fn proximity(path: &str) -> u8 {
+    match path.split("::").next() {
+        Some("crate") => 0,
+        Some("std") => 2,
+        Some("core") | Some("alloc") => 3,
+        _ => 1,
+    }
+}
+
+/// Returns the position and text of a `use` declaration importing `path`
+/// into `text`, or `None` if it's already imported. The declaration goes
+/// into the group of `use`s at the top level of the file for the same kind of
+/// crate (the standard library, other crates or the same crate), in sorted
+/// order. If there is no such group, it starts a new one.
+pub fn import_edit(text: &str, path: &str) -> Option<(Position, String)> {
+    let new_decl = format!("use {};", path);
+    let decls = use_decls(text);
+    if decls.iter().any(|decl| decl.text == new_decl) {
+        return None;
+    }
+
+    let groups = use_groups(&decls);
+    let group_kind = |group: &[UseDecl]| kind(use_path(&group[0].text));
+    let edit = match groups.iter().find(|group| group_kind(group) == kind(path)) {
+        Some(group) => {
+            let row = group
+                .iter()
+                .find(|decl| use_path(&decl.text) > path)
+                .map_or(group[group.len() - 1].end_row + 1, |decl| decl.start_row);
+            (row, format!("{}\n", new_decl))
+        }
+        // The standard library comes first.
+        None if kind(path) == CrateKind::Std && !groups.is_empty() => {
+            (groups[0][0].start_row, format!("{}\n\n", new_decl))
+        }
+        None => match groups.last() {
+            Some(group) => (group[group.len() - 1].end_row + 1, format!("\n{}\n", new_decl)),
+            None => (first_item_row(text), format!("{}\n\n", new_decl)),
+        },
+    };
+    Some((Position::new(edit.0 as u64, 0), edit.1))
+}
+
+/// Returns the edits sorting the `use` declarations at the top level of `text`
+/// within each group and removing duplicates, or nothing if they're already
+/// in order.
+pub fn organize_edits(text: &str) -> Vec<(Range, String)> {
+    let lines: Vec<&str> = text.lines().collect();
+    let decls = use_decls(text);
+    let mut edits = vec![];
+    for group in use_groups(&decls) {
+        let mut sorted: Vec<&UseDecl> = group.iter().collect();
+        sorted.sort_by(|a, b| use_path(&a.text).cmp(use_path(&b.text)));
+        sorted.dedup_by(|a, b| a.text == b.text);
+        let unchanged = sorted.len() == group.len()
+            && sorted.iter().zip(group).all(|(a, b)| a.start_row == b.start_row);
+        if unchanged {
+            continue;
+        }
+
+        let new_text: String = sorted
+            .iter()
+            .flat_map(|decl| &lines[decl.start_row..=decl.end_row])
+            .map(|line| format!("{}\n", line))
+            .collect();
+        let start = Position::new(group[0].start_row as u64, 0);
+        let end = Position::new(group[group.len() - 1].end_row as u64 + 1, 0);
+        edits.push((Range::new(start, end), new_text));
+    }
+    edits
+}
+
+/// Splits the declarations into groups separated by blank lines (or anything
+/// else).
+fn use_groups(decls: &[UseDecl]) -> Vec<&[UseDecl]> {
+    let mut groups = vec![];
+    let mut start = 0;
+    for i in 1..=decls.len() {
+        if i == decls.len() || decls[i].start_row != decls[i - 1].end_row + 1 {
+            groups.push(&decls[start..i]);
+            start = i;
+        }
+    }
+    groups
+}
+
+#[derive(Debug, PartialEq)]
+enum CrateKind {
+    Std,
+    External,
+    Local,
+}
+
+fn kind(path: &str) -> CrateKind {
+    match path.trim_start_matches("::").split("::").next() {
+        Some("std") | Some("core") | Some("alloc") => CrateKind::Std,
+        Some("crate") | Some("self") | Some("super") => CrateKind::Local,
+        _ => CrateKind::External,
+    }
+}
+
+/// Returns what is imported by a `use` declaration, e.g., `std::fmt` for
+/// `pub use std::fmt;`.
+fn use_path(decl: &str) -> &str {
+    let decl = decl.trim_end_matches(';');
+    decl.find("use ").map_or(decl, |i| decl[i + 4..].trim())
+}
+
+/// Finds the `use` declarations at the top level of `text`.
+fn use_decls(text: &str) -> Vec<UseDecl> {
+    lazy_static! {
+        static ref USE_RE: Regex = Regex::new(r"^(?:pub(?:\([^)]*\))?\s+)?use\s").unwrap();
+    }
+
+    let lines: Vec<&str> = text.lines().collect();
+    let mut decls = vec![];
+    let mut row = 0;
+    while row < lines.len() {
+        if !USE_RE.is_match(lines[row]) {
+            row += 1;
+            continue;
+        }
+        let start_row = row;
+        let mut decl = lines[row].trim().to_owned();
+        while !decl.ends_with(';') && row + 1 < lines.len() {
+            row += 1;
+            decl.push_str(lines[row].trim());
+        }
+        decls.push(UseDecl { start_row, end_row: row, text: decl });
+        row += 1;
+    }
+    decls
+}
+
+/// Returns the first line after the crate's or module's docs and attributes
+/// at the start of `text`.
+fn first_item_row(text: &str) -> usize {
+    text.lines()
+        .position(|line| {
+            let line = line.trim();
+            !line.is_empty() && !line.starts_with("//!") && !line.starts_with("#![")
+        })
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn finds_unresolved_names() {
+        let message = "cannot find type `HashMap` in this scope\n\nnot found in this scope";
+        assert_eq!(unresolved_name(message), Some("HashMap"));
+        assert_eq!(unresolved_name("cannot find function `foo` in this scope"), Some("foo"));
+        assert_eq!(
+            unresolved_name("cannot find struct, variant or union type `Foo` in this scope"),
+            Some("Foo")
+        );
+        assert_eq!(
+            unresolved_name("failed to resolve: use of undeclared type `HashMap`"),
+            Some("HashMap")
+        );
+        assert_eq!(unresolved_name("unused variable: `x`"), None);
+        assert_eq!(unresolved_name("cannot find macro `foo` in module `bar`"), None);
+    }
+
+    #[test]
+    fn ranks_paths() {
+        let mut paths = vec!["core::fmt::Write", "std::fmt::Write", "crate::Write", "log::Write"];
+        paths.sort_by_key(|path| proximity(path));
+        assert_eq!(paths, ["crate::Write", "log::Write", "std::fmt::Write", "core::fmt::Write"]);
+    }
+
+    #[test]
+    fn adds_use_to_matching_group() {
+        let text = "//! Docs.\n\nuse std::fmt;\nuse std::sync::{\n    Arc,\n    Mutex,\n};\n\n\
+                    use log::trace;\n\nfn main() {}\n";
+        assert_eq!(
+            import_edit(text, "std::collections::HashMap"),
+            Some((Position::new(2, 0), "use std::collections::HashMap;\n".to_owned()))
+        );
+        assert_eq!(
+            import_edit(text, "std::time::Duration"),
+            Some((Position::new(7, 0), "use std::time::Duration;\n".to_owned()))
+        );
+        assert_eq!(
+            import_edit(text, "regex::Regex"),
+            Some((Position::new(9, 0), "use regex::Regex;\n".to_owned()))
+        );
+        assert_eq!(
+            import_edit(text, "crate::foo::Foo"),
+            Some((Position::new(9, 0), "\nuse crate::foo::Foo;\n".to_owned()))
+        );
+        assert_eq!(import_edit(text, "std::fmt"), None);
+    }
+
+    #[test]
+    fn organizes_uses() {
+        let text = "use std::sync::{\n    Arc,\n};\nuse std::fmt;\nuse std::fmt;\n\n\
+                    use log::trace;\nuse crate::foo;\n\nfn main() {}\n";
+        assert_eq!(
+            organize_edits(text),
+            vec![
+                (
+                    Range::new(Position::new(0, 0), Position::new(5, 0)),
+                    "use std::fmt;\nuse std::sync::{\n    Arc,\n};\n".to_owned()
+                ),
+                (
+                    Range::new(Position::new(6, 0), Position::new(8, 0)),
+                    "use crate::foo;\nuse log::trace;\n".to_owned()
+                ),
+            ]
+        );
+        assert_eq!(organize_edits("use std::fmt;\nuse std::io;\n"), vec![]);
+    }
+
+    #[test]
+    fn adds_first_use() {
+        let text = "//! Docs.\n#![allow(dead_code)]\n\nfn main() {}\n";
+        assert_eq!(
+            import_edit(text, "std::collections::HashMap"),
+            Some((Position::new(3, 0), "use std::collections::HashMap;\n\n".to_owned()))
+        );
+
+        let text = "use log::trace;\n";
+        assert_eq!(
+            import_edit(text, "std::fmt"),
+            Some((Position::new(0, 0), "use std::fmt;\n\n".to_owned()))
+        );
+    }
+}
@@ -0,0 +1,94 @@
+//! Timing of the handling of requests, reported in `rls/metrics`
+//! notifications when the `request_metrics` option is set.
+
+use std::collections::{BTreeMap, VecDeque};
+use std::sync::Mutex;
+use std::time::Duration;
+
+use crate::lsp_data::{MethodMetrics, MetricsParams};
+
+/// The number of latest durations of each method which the percentiles are
+/// computed from.
+const WINDOW: usize = 100;
+
+/// The handling times of the requests of each method.
+#[derive(Debug, Default)]
+pub struct RequestMetrics {
+    methods: Mutex<BTreeMap<&'static str, Timings>>,
+}
+
+#[derive(Debug, Default)]
+struct Timings {
+    count: u64,
+    recent: VecDeque<Duration>,
+}
+
+impl RequestMetrics {
+    /// Records that a request of `method` took `duration` to handle.
+    pub fn record(&self, method: &'static str, duration: Duration) {
+        let mut methods = self.methods.lock().unwrap();
+        let timings = methods.entry(method).or_default();
+        timings.count += 1;
+        if timings.recent.len() == WINDOW {
+            timings.recent.pop_front();
+        }
+        timings.recent.push_back(duration);
+    }
+
+    /// Returns the number of requests of each method handled so far and the
+    /// percentiles of their latest durations, ordered by method.
+    pub fn report(&self) -> MetricsParams {
+        let methods = self.methods.lock().unwrap();
+        let methods = methods
+            .iter()
+            .map(|(method, timings)| {
+                let mut recent: Vec<_> = timings.recent.iter().cloned().collect();
+                recent.sort();
+                let percentile = |p: usize| {
+                    let i = (recent.len() * p / 100).min(recent.len() - 1);
+                    recent[i].as_micros() as f64 / 1000.0
+                };
+                MethodMetrics {
+                    method: (*method).to_owned(),
+                    count: timings.count,
+                    p50_ms: percentile(50),
+                    p90_ms: percentile(90),
+                    p99_ms: percentile(99),
+                    max_ms: percentile(100),
+                }
+            })
+            .collect();
+        MetricsParams { methods }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn request_metrics() {
+        let metrics = RequestMetrics::default();
+        assert!(metrics.report().methods.is_empty());
+
+        for ms in 1..=10 {
+            metrics.record("textDocument/hover", Duration::from_millis(ms));
+        }
+        for ms in 0..(WINDOW as u64 + 20) {
+            metrics.record("textDocument/definition", Duration::from_millis(ms));
+        }
+
+        let report = metrics.report();
+        let methods: Vec<_> = report.methods.iter().map(|m| (&*m.method, m.count)).collect();
+        assert_eq!(methods, vec![("textDocument/definition", 120), ("textDocument/hover", 10)]);
+
+        let hover = &report.methods[1];
+        assert_eq!(
+            (hover.p50_ms, hover.p90_ms, hover.p99_ms, hover.max_ms),
+            (6.0, 10.0, 10.0, 10.0)
+        );
+        // Only the latest durations count.
+        let definition = &report.methods[0];
+        assert_eq!((definition.p50_ms, definition.max_ms), (70.0, 119.0));
+    }
+}
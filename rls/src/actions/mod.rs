@@ -4,23 +4,33 @@
 use crate::config::Config;
 use crate::config::FmtConfig;
 use crate::Span;
+use cargo::core::PackageId;
 use log::{debug, error, info, trace};
-use rls_analysis::AnalysisHost;
+use lsp_types::notification::{PublishDiagnostics, ShowMessage};
+use rls_analysis::{AResult, AnalysisHost};
 use rls_span as span;
-use rls_vfs::{FileContents, Vfs};
+use rls_vfs::{FileContents, Language, Vfs};
 use serde_json::{self, json};
 use url::Url;
 use walkdir::WalkDir;
 
+use crate::actions::explain::ExplanationCache;
 use crate::actions::format::Rustfmt;
+use crate::actions::freshness::{Freshness, Snapshot};
+use crate::actions::idle::IdleTracker;
+use crate::actions::metrics::RequestMetrics;
 use crate::actions::post_build::{AnalysisQueue, BuildResults, PostBuildHandler};
-use crate::actions::progress::{BuildDiagnosticsNotifier, BuildProgressNotifier};
+use crate::actions::progress::{
+    BuildDiagnosticsNotifier, BuildProgressNotifier, PrewarmProgressNotifier, ProgressNotifier,
+    ServerStatus,
+};
+use crate::actions::semantic_tokens::TokensCache;
 use crate::build::*;
 use crate::concurrency::{ConcurrentJob, Jobs};
 use crate::lsp_data;
 use crate::lsp_data::*;
-use crate::project_model::{ProjectModel, RacerFallbackModel, RacerProjectModel};
-use crate::server::Output;
+use crate::project_model::{racer_edition, ProjectModel, RacerFallbackModel, RacerProjectModel};
+use crate::server::{Notification, Output};
 
 use std::collections::{HashMap, HashSet};
 use std::convert::TryFrom;
@@ -29,33 +39,57 @@ use std::path::{Path, PathBuf};
 use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 use std::sync::{Arc, Mutex};
 use std::thread;
+use std::time::Duration;
 
-// TODO: Support non-`file` URI schemes in VFS. We're currently ignoring them because
-// we don't want to crash the RLS in case a client opens a file under different URI scheme
-// like with git:/ or perforce:/ (Probably even http:/? We currently don't support remote schemes).
+// Documents under URI schemes other than `file` and `untitled` (e.g., git:/ or
+// perforce:/) are not supported by the VFS. Notifications about them can't be
+// answered, so they're ignored.
 macro_rules! ignore_non_file_uri {
     ($expr: expr, $uri: expr, $log_name: expr) => {
         $expr.map_err(|_| {
-            trace!("{}: Non-`file` URI scheme, ignoring: {:?}", $log_name, $uri);
+            trace!("{}: Unsupported URI scheme, ignoring: {:?}", $log_name, $uri);
             ()
         })
     };
 }
 
+// Requests on documents under unsupported URI schemes are answered with an
+// `InvalidParams` error.
 macro_rules! parse_file_path {
     ($uri: expr, $log_name: expr) => {
-        ignore_non_file_uri!(parse_file_path($uri), $uri, $log_name)
+        parse_file_path($uri).map_err(|e| {
+            trace!("{}: Can't handle URI {:?}: {}", $log_name, $uri, e);
+            $crate::server::ResponseError::Message(
+                jsonrpc_core::types::ErrorCode::InvalidParams,
+                format!("Unsupported document URI `{}`: {}", $uri, e),
+            )
+        })
     };
 }
 
+pub mod crate_graph;
+pub mod dead_code;
+pub mod derive;
 pub mod diagnostics;
+pub mod explain;
+pub mod field_completion;
 pub mod format;
+pub mod freshness;
 pub mod hover;
+pub mod idle;
+pub mod imports;
+pub mod metrics;
 pub mod notifications;
+pub mod outline;
 pub mod post_build;
 pub mod progress;
+pub mod refactor;
 pub mod requests;
 pub mod run;
+pub mod semantic_tokens;
+pub mod todo_comments;
+pub mod trait_stubs;
+pub mod variant_completion;
 pub mod work_pool;
 
 /// Persistent context shared across all requests and notifications.
@@ -86,7 +120,7 @@ impl ActionContext {
     ) -> Result<(), ()> {
         let ctx = match *self {
             ActionContext::Uninit(ref uninit) => {
-                let ctx = InitActionContext::new(
+                let mut ctx = InitActionContext::new(
                     Arc::clone(&uninit.analysis),
                     Arc::clone(&uninit.vfs),
                     Arc::clone(&uninit.config),
@@ -95,6 +129,12 @@ impl ActionContext {
                     uninit.pid,
                     init_options.cmd_run,
                 );
+                ctx.client_supports_cmd_debug = init_options.cmd_debug;
+                ctx.disabled_capabilities = Arc::new(init_options.disabled_capabilities().to_vec());
+                let build_out = out.clone();
+                ctx.cargo_build = Arc::new(move |ctx: &InitActionContext| {
+                    ctx.build_current_project(BuildPriority::Cargo, &build_out)
+                });
                 ctx.init(init_options, out);
                 ctx
             }
@@ -141,6 +181,22 @@ pub struct InitActionContext {
     // Whether we've shown an error message from Cargo since the last successful
     // build.
     shown_cargo_error: Arc<AtomicBool>,
+    // Whether a build has been requested since initialization.
+    build_requested: Arc<AtomicBool>,
+    // Whether the loaded analysis data reflects the open documents, reported
+    // by `rls/analysisFreshness`.
+    freshness: Arc<Freshness>,
+    // The time taken to handle requests, see the `request_metrics` option.
+    metrics: Arc<RequestMetrics>,
+    // What the RLS is currently doing, reported by `rls/status`.
+    status: Arc<ServerStatus>,
+    // Semantic tokens last sent for each document.
+    semantic_tokens: Arc<TokensCache>,
+    // Explanations of error codes returned by `rls/explainError`.
+    explanations: Arc<ExplanationCache>,
+    // When the client was last heard from and which documents it has open,
+    // to unload the analysis data when idle.
+    idle: Arc<IdleTracker>,
     // Set to true when a potentially mutating request is received. Set to false
     // if a change arrives. We can thus tell if the RLS has been quiescent while
     // waiting to mutate the client state.
@@ -152,9 +208,18 @@ pub struct InitActionContext {
     jobs: Arc<Mutex<Jobs>>,
     client_capabilities: Arc<lsp_data::ClientCapabilities>,
     client_supports_cmd_run: bool,
+    client_supports_cmd_debug: bool,
     /// Set/confirmed true once a `workspace/didChangeWatchedFile` is processed
     /// Used to avoid other notifications like didSave causing double cargo builds
     client_use_change_watched: bool,
+    /// Changes to the watched files which are yet to be acted on.
+    watched_changes: Arc<Mutex<WatchedChanges>>,
+    /// The capabilities left out of those advertised to the client on
+    /// initialization, see `Config::disabled_capabilities`.
+    disabled_capabilities: Arc<Vec<String>>,
+    /// Requests a build of the project which re-runs Cargo, reporting to the
+    /// client's `Output`, which request handlers don't have.
+    cargo_build: Arc<dyn Fn(&InitActionContext) + Send + Sync>,
     /// Whether the server is performing cleanup (after having received
     /// 'shutdown' request), just before final 'exit' request.
     pub shut_down: Arc<AtomicBool>,
@@ -205,16 +270,42 @@ impl InitActionContext {
             file_to_crates: Arc::default(),
             active_build_count: Arc::new(AtomicUsize::new(0)),
             shown_cargo_error: Arc::new(AtomicBool::new(false)),
+            build_requested: Arc::new(AtomicBool::new(false)),
+            freshness: Arc::default(),
+            metrics: Arc::default(),
+            status: Arc::default(),
+            semantic_tokens: Arc::default(),
+            explanations: Arc::default(),
+            idle: Arc::default(),
             quiescent: Arc::new(AtomicBool::new(false)),
             prev_changes: Arc::default(),
             client_capabilities: Arc::new(client_capabilities),
             client_supports_cmd_run,
+            client_supports_cmd_debug: false,
             client_use_change_watched: false,
+            watched_changes: Arc::default(),
+            disabled_capabilities: Arc::default(),
+            cargo_build: Arc::new(|_| {}),
             shut_down: Arc::new(AtomicBool::new(false)),
             pid,
         }
     }
 
+    /// Returns `true` if the capability which requests with `method` belong
+    /// to was disabled on initialization.
+    pub fn is_method_disabled(&self, method: &str) -> bool {
+        capability_of(method).map_or(false, |capability| {
+            self.disabled_capabilities.iter().any(|disabled| disabled == capability)
+        })
+    }
+
+    /// Returns `true` if `command` (e.g., `rls.rebuild-1234`) was disabled on
+    /// initialization.
+    pub fn is_command_disabled(&self, command: &str) -> bool {
+        let name = command.rsplitn(2, '-').last().unwrap_or(command);
+        self.disabled_capabilities.iter().any(|disabled| disabled == name)
+    }
+
     pub fn invalidate_project_model(&self) {
         *self.project_model.lock().unwrap() = None;
     }
@@ -233,6 +324,15 @@ impl InitActionContext {
         }
     }
 
+    /// Returns the id of the package which `file` belongs to, using the crates
+    /// it was compiled into by the last build, if any, and the package
+    /// directories otherwise.
+    pub fn owning_package(&self, file: &Path) -> Option<PackageId> {
+        let project = self.project_model().ok()?;
+        let crates = self.file_to_crates.lock().unwrap().get(file).cloned().unwrap_or_default();
+        project.owning_package(file, &crates)
+    }
+
     pub fn racer_cache(&self) -> racer::FileCache {
         struct RacerVfs(Arc<Vfs>);
         impl racer::FileLoader for RacerVfs {
@@ -250,11 +350,12 @@ impl InitActionContext {
     }
 
     pub fn racer_session<'c>(&self, cache: &'c racer::FileCache) -> racer::Session<'c> {
+        let default_edition = self.config.lock().unwrap().default_edition.map(racer_edition);
         let pm: Box<dyn racer::ProjectModelProvider> = match self.project_model() {
-            Ok(pm) => Box::new(RacerProjectModel(pm)),
+            Ok(pm) => Box::new(RacerProjectModel(pm, default_edition)),
             Err(e) => {
                 error!("failed to fetch project model, using fallback: {}", e);
-                Box::new(RacerFallbackModel)
+                Box::new(RacerFallbackModel(default_edition))
             }
         };
         racer::Session::with_project_model(cache, pm)
@@ -275,8 +376,15 @@ impl InitActionContext {
         Rustfmt::from(rustfmt)
     }
 
+    /// Locks config to read the `rustfmt_config_path` and `rustfmt_edition`
+    /// keys.
     fn fmt_config(&self) -> FmtConfig {
-        FmtConfig::from(&self.current_project)
+        let config = self.config.lock().unwrap();
+        FmtConfig::with_options(
+            &self.current_project,
+            config.rustfmt_config_path.as_deref(),
+            config.rustfmt_edition,
+        )
     }
 
     fn file_edition(&self, file: PathBuf) -> Option<Edition> {
@@ -300,6 +408,28 @@ impl InitActionContext {
         }
     }
 
+    /// Records a message from the client, which keeps the analysis data from
+    /// being unloaded for inactivity.
+    pub fn record_activity(&self) {
+        self.idle.touch();
+    }
+
+    /// Makes the VFS treat files with the extensions of the
+    /// `rust_file_extensions` option as Rust, and no longer those with
+    /// extensions which were dropped from it.
+    fn register_file_extensions(&self) {
+        let config = self.config.lock().unwrap();
+        let extensions = config.rust_file_extensions();
+        for (extension, _) in self.vfs.registered_extensions() {
+            if !extensions.contains(&extension.as_str()) {
+                self.vfs.unregister_extension(&extension);
+            }
+        }
+        for extension in extensions {
+            self.vfs.register_extension(extension, Language::Rust);
+        }
+    }
+
     fn init<O: Output>(&self, init_options: InitializationOptions, out: &O) {
         let current_project = self.current_project.clone();
 
@@ -311,24 +441,91 @@ impl InitActionContext {
             }
             config.needs_inference()
         };
-
-        if needs_inference {
-            let config = Arc::clone(&self.config);
-            // Spawn another thread since we're shelling out to Cargo and this can
-            // cause a non-trivial amount of time due to disk access
-            thread::spawn(move || {
-                let mut config = config.lock().unwrap();
+        self.register_file_extensions();
+        idle::watch(self.clone());
+
+        let config = Arc::clone(&self.config);
+        let validation_out = out.clone();
+        // Spawn another thread since we're shelling out to Cargo and this can
+        // cause a non-trivial amount of time due to disk access
+        thread::spawn(move || {
+            let mut config = config.lock().unwrap();
+            if needs_inference {
                 if let Err(e) = config.infer_defaults(&current_project) {
                     debug!("Encountered an error while trying to infer config defaults: {:?}", e);
                 }
-            });
-        }
+            }
 
+            match config.validate_project(&current_project) {
+                Ok(warnings) => {
+                    for warning in warnings {
+                        validation_out.notify(Notification::<ShowMessage>::new(
+                            ShowMessageParams {
+                                typ: MessageType::Warning,
+                                message: warning.to_string(),
+                            },
+                        ));
+                    }
+                }
+                Err(e) => debug!("Encountered an error while trying to validate config: {:?}", e),
+            }
+        });
+
+        if !init_options.prewarm_files.is_empty() {
+            self.prewarm(init_options.prewarm_files, out);
+        }
         if !init_options.omit_init_build {
             self.build_current_project(BuildPriority::Cargo, out);
         }
     }
 
+    /// Reads `files` into the VFS and, unless a build loaded it already, loads
+    /// the analysis data left on disk by a previous session. This happens on
+    /// the thread of the analysis queue, so as not to delay the initialization,
+    /// and before the analysis data of any later build is loaded, so as not to
+    /// overwrite it.
+    fn prewarm<O: Output>(&self, files: Vec<PathBuf>, out: &O) {
+        let (job, token) = ConcurrentJob::new();
+        self.add_job(job);
+
+        let ctx = self.clone();
+        let notifier =
+            PrewarmProgressNotifier::new(out.clone(), self.client_capabilities.work_done_progress);
+        self.analysis_queue.enqueue_load(move || {
+            let _token = token;
+            notifier.notify_begin_progress();
+            for file in files {
+                let path = ctx.current_project.join(file);
+                if let Err(e) = ctx.vfs.load_file(&path) {
+                    debug!("Couldn't prewarm {}: {:?}", path.display(), e);
+                }
+            }
+
+            if ctx.analysis.def_roots().map_or(true, |roots| roots.is_empty()) {
+                let (crate_blacklist, excludes, target) = {
+                    let config = ctx.config.lock().unwrap();
+                    (
+                        config.crate_blacklist(),
+                        config.analysis_excludes(&ctx.current_project),
+                        config.analysis_target.into(),
+                    )
+                };
+                let project = &ctx.current_project;
+                let loaded = ctx
+                    .analysis
+                    .set_crate_exclusion(move |root| excludes.is_excluded(root))
+                    .and_then(|()| ctx.analysis.set_target(target))
+                    .and_then(|()| {
+                        ctx.analysis.reload_with_blacklist(project, project, &crate_blacklist.0[..])
+                    });
+                if let Err(e) = loaded {
+                    debug!("Couldn't prewarm the analysis data: {:?}", e);
+                }
+            }
+            notifier.notify_end_progress();
+        });
+    }
+
     fn build<O: Output>(&self, project_path: &Path, priority: BuildPriority, out: &O) {
         let (job, token) = ConcurrentJob::new();
         self.add_job(job);
@@ -343,18 +540,35 @@ impl InitActionContext {
                 project_path: project_path.to_owned(),
                 show_warnings: config.show_warnings,
                 related_information_support: self.client_capabilities.related_information_support,
+                use_rendered_diagnostics: config.use_rendered_diagnostics,
+                todo_tags: Some(config.todo_tags.clone()).filter(|_| config.show_todo_diagnostics),
+                vfs: Arc::clone(&self.vfs),
                 shown_cargo_error: Arc::clone(&self.shown_cargo_error),
+                freshness: Arc::clone(&self.freshness),
+                snapshot: Snapshot::take(&self.vfs, &self.idle.open_files()),
                 active_build_count: Arc::clone(&self.active_build_count),
-                crate_blacklist: config.crate_blacklist.as_ref().clone(),
-                notifier: Box::new(BuildDiagnosticsNotifier::new(out.clone())),
+                crate_blacklist: config.crate_blacklist(),
+                analysis_excludes: config.analysis_excludes(project_path),
+                analysis_target: config.analysis_target.into(),
+                notifier: Box::new(BuildDiagnosticsNotifier::new(
+                    out.clone(),
+                    Arc::clone(&self.status),
+                    self.client_capabilities.work_done_progress,
+                )),
                 blocked_threads: vec![],
                 _token: token,
             }
         };
 
-        let notifier = Box::new(BuildProgressNotifier::new(out.clone()));
+        let notifier = Box::new(BuildProgressNotifier::new(
+            out.clone(),
+            Arc::clone(&self.status),
+            self.client_capabilities.work_done_progress,
+        ));
 
         self.active_build_count.fetch_add(1, Ordering::SeqCst);
+        self.build_requested.store(true, Ordering::SeqCst);
+        self.status.begin_build(out);
         self.build_queue.request_build(project_path, priority, notifier, pbh);
     }
 
@@ -362,6 +576,85 @@ impl InitActionContext {
         self.build(&self.current_project, priority, out);
     }
 
+    /// Requests a build of the project which re-runs Cargo, even if
+    /// `build_on_save` is set. A build which is already waiting in the queue
+    /// is replaced by it.
+    pub fn rebuild(&self) {
+        (self.cargo_build)(self);
+    }
+
+    /// Acts on changes to the watched files once no more have arrived for
+    /// `WATCHED_FILES_DEBOUNCE`: reloads the analysis data from scratch if the
+    /// target directory was deleted, re-infers the config if a manifest
+    /// changed, and rebuilds the project with Cargo.
+    fn on_watched_changes<O: Output>(&self, manifest: bool, target_deleted: bool, out: O) {
+        let generation = {
+            let mut changes = self.watched_changes.lock().unwrap();
+            changes.generation += 1;
+            changes.manifest |= manifest;
+            changes.target_deleted |= target_deleted;
+            changes.generation
+        };
+
+        let (job, token) = ConcurrentJob::new();
+        self.add_job(job);
+        let ctx = self.clone();
+        thread::spawn(move || {
+            let _token = token;
+            thread::sleep(WATCHED_FILES_DEBOUNCE);
+
+            let (manifest, target_deleted) = {
+                let mut changes = ctx.watched_changes.lock().unwrap();
+                // Leave the changes to the thread of a later one.
+                if changes.generation != generation {
+                    return;
+                }
+                let taken = (changes.manifest, changes.target_deleted);
+                changes.manifest = false;
+                changes.target_deleted = false;
+                taken
+            };
+
+            if target_deleted {
+                if let Err(e) = ctx.reload_analysis() {
+                    debug!("Encountered an error while reloading the analysis data: {:?}", e);
+                }
+            }
+            if manifest {
+                ctx.invalidate_project_model();
+                let mut config = ctx.config.lock().unwrap();
+                if let Err(e) = config.reinfer_defaults(&ctx.current_project) {
+                    debug!("Encountered an error while trying to infer config defaults: {:?}", e);
+                }
+            }
+            ctx.build_current_project(BuildPriority::Cargo, &out);
+        });
+    }
+
+    /// Reloads the analysis data of the project from scratch, without building.
+    fn reload_analysis(&self) -> AResult<()> {
+        let blacklist = self.config.lock().unwrap().crate_blacklist();
+        let project = &self.current_project;
+        self.analysis.hard_reload_with_blacklist(project, project, &blacklist.0[..])
+    }
+
+    /// Publishes the diagnostics of the last build again, e.g. after
+    /// `show_warnings` was changed.
+    fn republish_diagnostics<O: Output>(&self, out: &O) {
+        let show_warnings = self.config.lock().unwrap().show_warnings;
+        let results = self.previous_build_results.lock().unwrap();
+        for (path, diagnostics) in results.iter() {
+            let params = post_build::publish_diagnostics_params(path, diagnostics, show_warnings);
+            out.notify(Notification::<PublishDiagnostics>::new(params));
+        }
+    }
+
+    /// Returns where to record the time taken to handle requests, if the
+    /// `request_metrics` option is set.
+    pub fn request_metrics(&self) -> Option<Arc<RequestMetrics>> {
+        Some(Arc::clone(&self.metrics)).filter(|_| self.config.lock().unwrap().request_metrics)
+    }
+
     pub fn add_job(&self, job: ConcurrentJob) {
         self.jobs.lock().unwrap().add(job);
     }
@@ -504,6 +797,38 @@ fn find_word_at_pos(line: &str, pos: Column) -> (Column, Column) {
     (span::Column::new_zero_indexed(start), span::Column::new_zero_indexed(end))
 }
 
+/// Returns the capability of `config::DISABLEABLE_CAPABILITIES` which requests
+/// with `method` belong to, e.g., `completion` for `completionItem/resolve`.
+fn capability_of(method: &str) -> Option<&'static str> {
+    let name = match method {
+        "completionItem/resolve" => "completion",
+        "codeLens/resolve" => "codeLens",
+        "textDocument/prepareCallHierarchy" => "callHierarchy",
+        "textDocument/prepareTypeHierarchy" => "typeHierarchy",
+        _ if method.starts_with("textDocument/semanticTokens/") => "semanticTokens",
+        _ if method.starts_with("callHierarchy/") => "callHierarchy",
+        _ if method.starts_with("typeHierarchy/") => "typeHierarchy",
+        _ => method.strip_prefix("textDocument/").or_else(|| method.strip_prefix("workspace/"))?,
+    };
+    crate::config::DISABLEABLE_CAPABILITIES.iter().find(|&&capability| capability == name).copied()
+}
+
+/// Time to wait after a change to a watched file for more changes (e.g.,
+/// `cargo update` rewriting `Cargo.lock`) before acting on them.
+const WATCHED_FILES_DEBOUNCE: Duration = Duration::from_millis(200);
+
+/// Changes to the watched files which haven't been acted on yet.
+#[derive(Debug, Default)]
+struct WatchedChanges {
+    /// Incremented for each batch of relevant changes, so that only the thread
+    /// of the last one acts on them.
+    generation: u64,
+    /// A `Cargo.toml` or `Cargo.lock` changed.
+    manifest: bool,
+    /// The `target` directory was deleted.
+    target_deleted: bool,
+}
+
 /// Client file-watching request / filtering logic
 /// We want to watch workspace 'Cargo.toml', root 'Cargo.lock' & the root 'target' dir
 pub struct FileWatch {
@@ -587,6 +912,14 @@ impl FileWatch {
         self.relevant_change_kind(&change.uri, change.typ)
     }
 
+    /// Returns if a relevant change is the deletion of the `target` directory,
+    /// rather than a change to a manifest.
+    pub fn is_target_deletion(&self, change: &FileEvent) -> bool {
+        change.typ == FileChangeType::Deleted
+            && change.uri.as_str().ends_with("/target")
+            && self.is_relevant(change)
+    }
+
     #[inline]
     pub fn is_relevant_save_doc(&self, did_save: &DidSaveTextDocumentParams) -> bool {
         self.relevant_change_kind(&did_save.text_document.uri, FileChangeType::Changed)
@@ -597,6 +930,19 @@ impl FileWatch {
 mod test {
     use super::*;
 
+    #[test]
+    fn capabilities_of_methods() {
+        assert_eq!(capability_of("textDocument/formatting"), Some("formatting"));
+        assert_eq!(capability_of("workspace/symbol"), Some("symbol"));
+        assert_eq!(capability_of("codeLens/resolve"), Some("codeLens"));
+        assert_eq!(capability_of("textDocument/semanticTokens/full/delta"), Some("semanticTokens"));
+        assert_eq!(capability_of("textDocument/prepareCallHierarchy"), Some("callHierarchy"));
+        assert_eq!(capability_of("callHierarchy/incomingCalls"), Some("callHierarchy"));
+        assert_eq!(capability_of("typeHierarchy/subtypes"), Some("typeHierarchy"));
+        assert_eq!(capability_of("textDocument/didOpen"), None);
+        assert_eq!(capability_of("rls/status"), None);
+    }
+
     #[test]
     fn test_find_word_at_pos() {
         fn assert_range(test_str: &'static str, range: (u32, u32)) {
@@ -653,6 +999,19 @@ mod test {
         assert!(!watch.is_relevant(&change("file:///Cargo.toml")));
     }
 
+    #[cfg(not(windows))]
+    #[test]
+    fn file_watch_target_deletion() {
+        let watch = FileWatch::from_project_root("/some/dir".into());
+        let deletion =
+            |url: &str| FileEvent::new(Url::parse(url).unwrap(), FileChangeType::Deleted);
+
+        assert!(watch.is_target_deletion(&deletion("file:///some/dir/target")));
+        assert!(!watch.is_target_deletion(&change("file:///some/dir/target")));
+        assert!(!watch.is_target_deletion(&deletion("file:///some/dir/inner/target")));
+        assert!(!watch.is_target_deletion(&deletion("file:///some/dir/Cargo.toml")));
+    }
+
     #[cfg(not(windows))]
     #[test]
     fn did_save_relevant_files() {
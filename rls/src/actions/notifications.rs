@@ -1,6 +1,6 @@
 //! One-way notifications that the RLS receives from the client.
 
-use crate::actions::{FileWatch, InitActionContext, VersionOrdering};
+use crate::actions::{idle, FileWatch, InitActionContext, VersionOrdering};
 use crate::Span;
 use log::{debug, trace, warn};
 use rls_vfs::{Change, VfsSpan};
@@ -8,14 +8,16 @@ use std::sync::atomic::Ordering;
 use std::sync::Arc;
 
 use crate::build::*;
-use crate::lsp_data::request::{RangeFormatting, RegisterCapability, UnregisterCapability};
+use crate::lsp_data::request::{
+    OnTypeFormatting, RangeFormatting, RegisterCapability, UnregisterCapability,
+};
 use crate::lsp_data::*;
 use crate::server::Request;
 use lsp_types::notification::ShowMessage;
 
 pub use crate::lsp_data::notification::{
     Cancel, DidChangeConfiguration, DidChangeTextDocument, DidChangeWatchedFiles,
-    DidOpenTextDocument, DidSaveTextDocument, Initialized,
+    DidCloseTextDocument, DidOpenTextDocument, DidSaveTextDocument, Initialized,
 };
 
 use crate::server::{BlockingNotificationAction, Notification, Output};
@@ -54,9 +56,32 @@ impl BlockingNotificationAction for DidOpenTextDocument {
         _out: O,
     ) -> Result<(), ()> {
         trace!("on_open: {:?}", params.text_document.uri);
-        let file_path = parse_file_path!(&params.text_document.uri, "on_open")?;
+        let file_path = ignore_non_file_uri!(
+            parse_file_path(&params.text_document.uri),
+            &params.text_document.uri,
+            "on_open"
+        )?;
         ctx.reset_change_version(&file_path);
         ctx.vfs.set_file(&file_path, &params.text_document.text);
+        ctx.idle.file_opened(&file_path);
+        Ok(())
+    }
+}
+
+impl BlockingNotificationAction for DidCloseTextDocument {
+    fn handle<O: Output>(
+        params: Self::Params,
+        ctx: &mut InitActionContext,
+        _out: O,
+    ) -> Result<(), ()> {
+        trace!("on_close: {:?}", params.text_document.uri);
+        let file_path = ignore_non_file_uri!(
+            parse_file_path(&params.text_document.uri),
+            &params.text_document.uri,
+            "on_close"
+        )?;
+        ctx.idle.file_closed(&file_path);
+        ctx.semantic_tokens.remove(&file_path);
         Ok(())
     }
 }
@@ -74,7 +99,11 @@ impl BlockingNotificationAction for DidChangeTextDocument {
         }
 
         ctx.quiescent.store(false, Ordering::SeqCst);
-        let file_path = parse_file_path!(&params.text_document.uri, "on_change")?;
+        let file_path = ignore_non_file_uri!(
+            parse_file_path(&params.text_document.uri),
+            &params.text_document.uri,
+            "on_change"
+        )?;
         let version_num = params.text_document.version.unwrap();
 
         match ctx.check_change_version(&file_path, version_num) {
@@ -158,14 +187,21 @@ impl BlockingNotificationAction for DidChangeConfiguration {
             }
             Err(err) => {
                 warn!("Received unactionable config: {:?} (error: {:?})", params.settings, err);
+                out.notify(Notification::<ShowMessage>::new(ShowMessageParams {
+                    typ: MessageType::Error,
+                    message: "Ignoring invalid RLS configuration, the previous configuration is \
+                              still in use"
+                        .to_owned(),
+                }));
                 return Err(());
             }
         };
 
         let unstable_features = new_config.unstable_features;
 
-        {
+        let (needs_rebuild, show_warnings_changed) = {
             let mut config = ctx.config.lock().unwrap();
+            let old_config = config.clone();
 
             // User may specify null (to be inferred) options, in which case
             // we schedule further inference on a separate thread not to block
@@ -190,33 +226,59 @@ impl BlockingNotificationAction for DidChangeConfiguration {
                     }
                 });
             }
-        }
+
+            (old_config.needs_rebuild(&config), old_config.show_warnings != config.show_warnings)
+        };
+        ctx.register_file_extensions();
+        idle::watch(ctx.clone());
         // We do a clean build so that if we've changed any relevant options
-        // for Cargo, we'll notice them. But if nothing relevant changes
-        // then we don't do unnecessary building (i.e., we don't delete
-        // artifacts on disk).
-        ctx.build_current_project(BuildPriority::Cargo, &out);
+        // for Cargo, we'll notice them. Options which don't affect the build
+        // are applied to the results we already have. The first configuration
+        // also starts the build if the client asked to omit the initial one.
+        if needs_rebuild || !ctx.build_requested.load(Ordering::SeqCst) {
+            ctx.build_current_project(BuildPriority::Cargo, &out);
+        } else if show_warnings_changed {
+            ctx.republish_diagnostics(&out);
+        }
 
         const RANGE_FORMATTING_ID: &str = "rls-range-formatting";
+        const ON_TYPE_FORMATTING_ID: &str = "rls-on-type-formatting";
         // FIXME should handle the response
         let id = out.provide_id();
         if unstable_features {
-            let params = RegistrationParams {
-                registrations: vec![Registration {
+            let mut registrations = vec![
+                Registration {
                     id: RANGE_FORMATTING_ID.to_owned(),
                     method: <RangeFormatting as LSPRequest>::METHOD.to_owned(),
                     register_options: None,
-                }],
-            };
+                },
+                Registration {
+                    id: ON_TYPE_FORMATTING_ID.to_owned(),
+                    method: <OnTypeFormatting as LSPRequest>::METHOD.to_owned(),
+                    register_options: Some(serde_json::json!({
+                        "documentSelector": null,
+                        "firstTriggerCharacter": "}",
+                        "moreTriggerCharacter": ["\n"],
+                    })),
+                },
+            ];
+            registrations.retain(|registration| !ctx.is_method_disabled(&registration.method));
+            let params = RegistrationParams { registrations };
 
             let request = Request::<RegisterCapability>::new(id, params);
             out.request(request);
         } else {
             let params = UnregistrationParams {
-                unregisterations: vec![Unregistration {
-                    id: RANGE_FORMATTING_ID.to_owned(),
-                    method: <RangeFormatting as LSPRequest>::METHOD.to_owned(),
-                }],
+                unregisterations: vec![
+                    Unregistration {
+                        id: RANGE_FORMATTING_ID.to_owned(),
+                        method: <RangeFormatting as LSPRequest>::METHOD.to_owned(),
+                    },
+                    Unregistration {
+                        id: ON_TYPE_FORMATTING_ID.to_owned(),
+                        method: <OnTypeFormatting as LSPRequest>::METHOD.to_owned(),
+                    },
+                ],
             };
 
             let request = Request::<UnregisterCapability>::new(id, params);
@@ -232,7 +294,11 @@ impl BlockingNotificationAction for DidSaveTextDocument {
         ctx: &mut InitActionContext,
         out: O,
     ) -> Result<(), ()> {
-        let file_path = parse_file_path!(&params.text_document.uri, "on_save")?;
+        let file_path = ignore_non_file_uri!(
+            parse_file_path(&params.text_document.uri),
+            &params.text_document.uri,
+            "on_save"
+        )?;
 
         ctx.vfs.file_saved(&file_path).unwrap();
 
@@ -260,9 +326,13 @@ impl BlockingNotificationAction for DidChangeWatchedFiles {
         ctx.client_use_change_watched = true;
         let file_watch = FileWatch::new(&ctx);
 
-        if params.changes.iter().any(|c| file_watch.is_relevant(c)) {
-            ctx.build_current_project(BuildPriority::Cargo, &out);
-            ctx.invalidate_project_model();
+        let target_deleted = params.changes.iter().any(|c| file_watch.is_target_deletion(c));
+        let manifest = params
+            .changes
+            .iter()
+            .any(|c| file_watch.is_relevant(c) && !file_watch.is_target_deletion(c));
+        if manifest || target_deleted {
+            ctx.on_watched_changes(manifest, target_deleted, out);
         }
 
         Ok(())
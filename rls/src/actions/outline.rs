@@ -0,0 +1,125 @@
+//! A compact outline of a file for the `rls/outline` request, e.g., for
+//! minimaps: the items from the analysis data with the lines they span.
+
+use std::path::Path;
+
+use rls_analysis::DefKind;
+use rls_vfs::FileContents;
+
+use crate::actions::field_completion::{offset_of, position_of};
+use crate::actions::InitActionContext;
+use crate::lsp_data::{source_kind_from_def_kind, OutlineItem, Position};
+
+/// Returns the outline of the file, in order of position. Items whose end
+/// can't be found in the text (e.g., because it isn't loaded) end on the line
+/// of their name.
+pub fn outline(ctx: &InitActionContext, file_path: &Path) -> Vec<OutlineItem> {
+    let items = ctx.analysis.outline(file_path).unwrap_or_default();
+    let text = match ctx.vfs.load_file(file_path) {
+        Ok(FileContents::Text(text)) => Some(text),
+        _ => None,
+    };
+
+    items
+        .into_iter()
+        .map(|item| {
+            let start_line = item.span.range.row_start.0;
+            let start_col = item.span.range.col_start.0;
+            let end_line = text
+                .as_ref()
+                .and_then(|text| item_end_row(text, start_line, start_col, item.kind))
+                .unwrap_or(start_line);
+            OutlineItem {
+                kind: source_kind_from_def_kind(item.kind),
+                name: item.name,
+                start_line: start_line.into(),
+                end_line: end_line.into(),
+                depth: item.depth,
+            }
+        })
+        .collect()
+}
+
+/// Returns the (zero-indexed) line on which the item whose name is at `row`
+/// and `col` of `text` ends: the line of the `}` closing its body, or of the
+/// `;` (or, for an enum variant, `,`) ending it.
+fn item_end_row(text: &str, row: u32, col: u32, kind: DefKind) -> Option<u32> {
+    item_end(text, row, col, kind).map(|(row, _)| row)
+}
+
+/// Returns the (zero-indexed) line and column of the character ending the
+/// item whose name is at `row` and `col` of `text`, as for `item_end_row`.
+pub(crate) fn item_end(text: &str, row: u32, col: u32, kind: DefKind) -> Option<(u32, u32)> {
+    let name_start = offset_of(text, Position::new(row.into(), col.into()))?;
+    let is_variant = kind == DefKind::TupleVariant || kind == DefKind::StructVariant;
+
+    // The depth of parentheses and brackets (e.g., of parameters), or of
+    // braces once the body has started.
+    let mut depth = 0;
+    let mut in_body = false;
+    for (i, c) in text[name_start..].char_indices() {
+        let offset = name_start + i;
+        match c {
+            '(' | '[' => depth += 1,
+            ')' | ']' => depth -= 1,
+            '{' if depth == 0 && !in_body => {
+                in_body = true;
+                depth = 1;
+            }
+            '{' if in_body => depth += 1,
+            '}' if in_body && depth == 1 => return Some(position_of(text, offset)),
+            '}' if in_body => depth -= 1,
+            // The end of the block the item is in.
+            '}' => return None,
+            ';' if depth == 0 && !in_body => return Some(position_of(text, offset)),
+            ',' if depth == 0 && !in_body && is_variant => return Some(position_of(text, offset)),
+            _ => {}
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const TEXT: &str = "struct Foo {
+    f: u32,
+}
+
+fn foo<F>(x: [u8; 2], f: F) -> u32
+where
+    F: Fn(u32) -> u32,
+{
+    if true { 1 } else { 2 }
+}
+
+mod test_module;
+
+enum FooEnum {
+    TupleVariant(u32, u8),
+    StructVariant {
+        x: u8,
+    },
+    Last
+}
+";
+
+    #[test]
+    fn finds_item_ends() {
+        assert_eq!(item_end_row(TEXT, 0, 7, DefKind::Struct), Some(2));
+        assert_eq!(item_end_row(TEXT, 4, 3, DefKind::Function), Some(9));
+        assert_eq!(item_end_row(TEXT, 11, 4, DefKind::Mod), Some(11));
+        assert_eq!(item_end_row(TEXT, 13, 5, DefKind::Enum), Some(19));
+        assert_eq!(item_end(TEXT, 4, 3, DefKind::Function), Some((9, 0)));
+        assert_eq!(item_end(TEXT, 11, 4, DefKind::Mod), Some((11, 15)));
+    }
+
+    #[test]
+    fn finds_variant_ends() {
+        assert_eq!(item_end_row(TEXT, 14, 4, DefKind::TupleVariant), Some(14));
+        assert_eq!(item_end_row(TEXT, 15, 4, DefKind::StructVariant), Some(17));
+        // Without a trailing comma, the variant ends with the enum.
+        assert_eq!(item_end_row(TEXT, 18, 4, DefKind::TupleVariant), None);
+    }
+}
@@ -13,17 +13,20 @@ use std::sync::{Arc, Mutex};
 use std::thread::{self, Thread};
 
 use crate::actions::diagnostics::{parse_diagnostics, Diagnostic, ParsedDiagnostics, Suggestion};
+use crate::actions::freshness::{Freshness, Snapshot};
 use crate::actions::progress::DiagnosticsNotifier;
+use crate::actions::todo_comments::todo_diagnostics;
 use crate::build::{BuildResult, Crate};
 use crate::concurrency::JobToken;
-use crate::config::CrateBlacklist;
+use crate::config::{AnalysisExcludes, CrateBlacklist};
 use crate::lsp_data::{PublishDiagnosticsParams, Range};
 
 use itertools::Itertools;
 use log::{trace, warn};
 use lsp_types::DiagnosticSeverity;
-use rls_analysis::AnalysisHost;
+use rls_analysis::{AnalysisHost, Target};
 use rls_data::Analysis;
+use rls_vfs::{FileContents, Vfs};
 use url::Url;
 
 pub type BuildResults = HashMap<PathBuf, Vec<(Diagnostic, Vec<Suggestion>)>>;
@@ -36,8 +39,17 @@ pub struct PostBuildHandler {
     pub project_path: PathBuf,
     pub show_warnings: bool,
     pub crate_blacklist: CrateBlacklist,
+    pub analysis_excludes: AnalysisExcludes,
+    pub analysis_target: Target,
     pub related_information_support: bool,
+    pub use_rendered_diagnostics: bool,
+    /// The tags of comments to publish diagnostics for, if enabled.
+    pub todo_tags: Option<Vec<String>>,
+    pub vfs: Arc<Vfs>,
     pub shown_cargo_error: Arc<AtomicBool>,
+    pub freshness: Arc<Freshness>,
+    /// The open documents as of when the build was requested.
+    pub snapshot: Snapshot,
     pub active_build_count: Arc<AtomicUsize>,
     pub notifier: Box<dyn DiagnosticsNotifier>,
     pub blocked_threads: Vec<thread::Thread>,
@@ -52,7 +64,7 @@ impl PostBuildHandler {
                 self.notifier.notify_begin_diagnostics();
 
                 // Emit appropriate diagnostics using the ones from build.
-                self.handle_messages(&cwd, &messages);
+                self.handle_messages(&cwd, &messages, input_files.keys());
                 let analysis_queue = Arc::clone(&self.analysis_queue);
 
                 {
@@ -147,7 +159,12 @@ impl PostBuildHandler {
         self.emit_notifications(&results);
     }
 
-    fn handle_messages(&self, cwd: &Path, messages: &[String]) {
+    fn handle_messages<'a>(
+        &self,
+        cwd: &Path,
+        messages: &[String],
+        input_files: impl Iterator<Item = &'a PathBuf>,
+    ) {
         // These notifications will include empty sets of errors for files
         // which had errors, but now don't. This instructs the IDE to clear
         // errors for those files.
@@ -161,28 +178,59 @@ impl PostBuildHandler {
         let file_diagnostics = messages
             .iter()
             .unique()
-            .filter_map(|msg| parse_diagnostics(msg, cwd, self.related_information_support))
+            .filter_map(|msg| {
+                parse_diagnostics(
+                    msg,
+                    cwd,
+                    self.related_information_support,
+                    self.use_rendered_diagnostics,
+                )
+            })
             .flat_map(|ParsedDiagnostics { diagnostics }| diagnostics);
 
         for (file_path, diagnostics) in file_diagnostics {
             results.entry(file_path).or_insert_with(Vec::new).extend(diagnostics);
         }
 
+        if let Some(ref tags) = self.todo_tags {
+            for path in input_files.filter(|path| path.starts_with(&self.project_path)) {
+                let text = match self.vfs.load_file(path) {
+                    Ok(FileContents::Text(text)) => text,
+                    _ => continue,
+                };
+                let diagnostics = todo_diagnostics(&text, tags);
+                if !diagnostics.is_empty() {
+                    let entry = results.entry(path.clone()).or_insert_with(Vec::new);
+                    entry.extend(diagnostics.into_iter().map(|diag| (diag, vec![])));
+                }
+            }
+        }
+
         self.emit_notifications(&results);
     }
 
     fn reload_analysis_from_disk(&self, cwd: &Path) {
+        self.exclude_crates();
+        self.analysis.set_target(self.analysis_target).unwrap();
         self.analysis
             .reload_with_blacklist(&self.project_path, cwd, &self.crate_blacklist.0[..])
             .unwrap();
     }
 
     fn reload_analysis_from_memory(&self, cwd: &Path, analysis: Vec<Analysis>) {
+        self.exclude_crates();
+        self.analysis.set_target(self.analysis_target).unwrap();
         self.analysis
             .reload_from_analysis(analysis, &self.project_path, cwd, &self.crate_blacklist.0[..])
             .unwrap();
     }
 
+    /// Makes the analysis skip crates under `analysis_exclude_globs`.
+    fn exclude_crates(&self) {
+        let excludes = self.analysis_excludes.clone();
+        self.analysis.set_crate_exclusion(move |root| excludes.is_excluded(root)).unwrap();
+    }
+
     fn finalize(mut self) {
         // the end message must be dispatched before waking up
         // the blocked threads, or we might see "done":true message
@@ -200,23 +248,30 @@ impl PostBuildHandler {
 
     fn emit_notifications(&self, build_results: &BuildResults) {
         for (path, diagnostics) in build_results {
-            let params = PublishDiagnosticsParams {
-                uri: Url::from_file_path(path).unwrap(),
-                diagnostics: diagnostics
-                    .iter()
-                    .map(|(diag, _)| diag)
-                    .filter(|diag| {
-                        self.show_warnings || diag.severity != Some(DiagnosticSeverity::Warning)
-                    })
-                    .cloned()
-                    .collect(),
-            };
-
+            let params = publish_diagnostics_params(path, diagnostics, self.show_warnings);
             self.notifier.notify_publish_diagnostics(params);
         }
     }
 }
 
+/// Returns the notification publishing the `diagnostics` of the file at
+/// `path`, leaving out warnings unless `show_warnings` is set.
+pub fn publish_diagnostics_params(
+    path: &Path,
+    diagnostics: &[(Diagnostic, Vec<Suggestion>)],
+    show_warnings: bool,
+) -> PublishDiagnosticsParams {
+    PublishDiagnosticsParams {
+        uri: Url::from_file_path(path).unwrap(),
+        diagnostics: diagnostics
+            .iter()
+            .map(|(diag, _)| diag)
+            .filter(|diag| show_warnings || diag.severity != Some(DiagnosticSeverity::Warning))
+            .cloned()
+            .collect(),
+    }
+}
+
 // Queue up analysis tasks and execute them on the same thread (this is slower
 // than executing in parallel, but allows us to skip indexing tasks).
 pub struct AnalysisQueue {
@@ -268,6 +323,14 @@ impl AnalysisQueue {
         self.worker_thread.unpark();
     }
 
+    /// Queues `load`, which loads analysis data other than that of a build,
+    /// behind the analysis tasks already queued. The data of builds queued
+    /// later is loaded after it, so the newer data wins.
+    pub fn enqueue_load(&self, load: impl FnOnce() + Send + 'static) {
+        self.queue.lock().unwrap().push(QueuedJob::Load(Box::new(load)));
+        self.worker_thread.unpark();
+    }
+
     fn run_worker_thread(queue: Arc<Mutex<Vec<QueuedJob>>>) {
         loop {
             let job = {
@@ -281,6 +344,7 @@ impl AnalysisQueue {
             match job {
                 Some(QueuedJob::Terminate) => return,
                 Some(QueuedJob::Job(job)) => job.process(),
+                Some(QueuedJob::Load(load)) => load(),
                 None => thread::park(),
             }
         }
@@ -300,6 +364,7 @@ impl Drop for AnalysisQueue {
 #[allow(clippy::large_enum_variant)]
 enum QueuedJob {
     Job(Job),
+    Load(Box<dyn FnOnce() + Send>),
     Terminate,
 }
 
@@ -307,7 +372,7 @@ impl QueuedJob {
     fn unwrap_job(self) -> Job {
         match self {
             QueuedJob::Job(job) => job,
-            QueuedJob::Terminate => panic!("Expected Job"),
+            QueuedJob::Load(_) | QueuedJob::Terminate => panic!("Expected Job"),
         }
     }
 }
@@ -351,6 +416,7 @@ impl Job {
             trace!("reloading from memory: {:?}", self.cwd);
             self.handler.reload_analysis_from_memory(&self.cwd, self.analysis);
         }
+        self.handler.freshness.record_loaded(self.handler.snapshot.clone());
 
         self.handler.finalize();
     }
@@ -1,7 +1,16 @@
-use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
 
-use crate::server::{Notification, Output};
+use crate::lsp_data::{
+    BuildRecovery, BuildRecoveryParams, ServerState, StatusChanged, StatusParams, WorkDoneProgress,
+    WorkDoneProgressCreate, WorkDoneProgressCreateParams, WorkDoneProgressParams,
+    WorkDoneProgressValue,
+};
+use crate::server::{Notification, Output, Request};
 use lazy_static::lazy_static;
+use log::debug;
 use lsp_types::notification::{Progress, PublishDiagnostics, ShowMessage};
 use lsp_types::{MessageType, ProgressParams, PublishDiagnosticsParams, ShowMessageParams};
 
@@ -27,6 +36,66 @@ pub trait DiagnosticsNotifier: Send {
     fn notify_publish_diagnostics(&self, _: PublishDiagnosticsParams);
     fn notify_error_diagnostics(&self, msg: String);
     fn notify_end_diagnostics(&self);
+    fn notify_build_recovery(&self, _: BuildRecoveryParams);
+}
+
+/// The status of the RLS, as returned for `rls/status` requests. Changes are
+/// sent to the client as `rls/statusChanged` notifications.
+#[derive(Default)]
+pub struct ServerStatus {
+    inner: Mutex<StatusInner>,
+}
+
+#[derive(Default)]
+struct StatusInner {
+    params: StatusParams,
+    // Builds which were requested and whose post-build processing hasn't
+    // finished yet.
+    active_builds: usize,
+    build_failed: bool,
+}
+
+impl ServerStatus {
+    pub fn current(&self) -> StatusParams {
+        self.inner.lock().unwrap().params.clone()
+    }
+
+    /// Called when a build is requested.
+    pub fn begin_build<O: Output>(&self, out: &O) {
+        self.update(out, |inner| {
+            inner.active_builds += 1;
+            inner.build_failed = false;
+            inner.params.state = ServerState::Building;
+            inner.params.phase = None;
+        });
+    }
+
+    /// Called once a build and its post-build processing are done, however
+    /// they ended (including by panicking).
+    fn end_build<O: Output>(&self, out: &O) {
+        self.update(out, |inner| {
+            inner.active_builds = inner.active_builds.saturating_sub(1);
+            if thread::panicking() {
+                inner.params.last_error = Some("The build panicked".to_owned());
+            }
+            if inner.active_builds == 0 {
+                inner.params.state =
+                    if inner.build_failed { ServerState::Error } else { ServerState::Idle };
+                inner.params.phase = None;
+            }
+        });
+    }
+
+    fn update<O: Output>(&self, out: &O, f: impl FnOnce(&mut StatusInner)) {
+        let mut inner = self.inner.lock().unwrap();
+        let old = inner.params.clone();
+        f(&mut inner);
+        // Notify while holding the lock, so notifications from different
+        // threads are sent in the order of the changes.
+        if inner.params != old {
+            out.notify(Notification::<StatusChanged>::new(inner.params.clone()));
+        }
+    }
 }
 
 /// Generates a new progress params with a unique ID and the given title.
@@ -45,78 +114,221 @@ fn new_progress_params(title: String) -> ProgressParams {
     }
 }
 
-/// Notifier of progress for the build (window/progress notifications).
+/// How long to wait for the client to create a work done progress.
+const WORK_DONE_PROGRESS_CREATE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// One chain of progress notifications, from its beginning to its end. These
+/// are `window/progress` notifications, or, if the client supports work done
+/// progress, a `window/workDoneProgress/create` request (whose token is the ID
+/// of the params) followed by `$/progress` notifications once the client
+/// created the progress. If it fails to, the progress isn't reported at all.
+struct ProgressChain {
+    // These params are used as a template and are cloned for each
+    // message that is actually notified.
+    params: ProgressParams,
+    work_done_progress: bool,
+    // Whether the client created the work done progress.
+    created: AtomicBool,
+}
+
+impl ProgressChain {
+    fn new(title: &str, work_done_progress: bool) -> ProgressChain {
+        ProgressChain {
+            params: new_progress_params(title.into()),
+            work_done_progress,
+            created: AtomicBool::new(false),
+        }
+    }
+
+    fn begin<O: Output>(&self, out: &O) {
+        if self.work_done_progress {
+            let params = WorkDoneProgressCreateParams { token: self.params.id.clone() };
+            let response = out.request_with_response(Request::<WorkDoneProgressCreate>::new(
+                out.provide_id(),
+                params,
+            ));
+            // Nothing may be reported before the client responds.
+            match response.recv_timeout(WORK_DONE_PROGRESS_CREATE_TIMEOUT) {
+                Ok(Ok(_)) => self.created.store(true, Ordering::SeqCst),
+                Ok(Err(e)) => {
+                    debug!("Couldn't create progress {}: {:?}", self.params.id, e);
+                    return;
+                }
+                Err(e) => {
+                    debug!("No response creating progress {}: {:?}", self.params.id, e);
+                    return;
+                }
+            }
+            self.notify_work_done(
+                out,
+                WorkDoneProgressValue::Begin {
+                    title: self.params.title.clone(),
+                    message: None,
+                    percentage: None,
+                },
+            );
+        } else {
+            out.notify(Notification::<Progress>::new(self.params.clone()));
+        }
+    }
+
+    fn report<O: Output>(&self, out: &O, message: Option<String>, percentage: Option<f64>) {
+        if self.work_done_progress {
+            // Work done percentages go from 0 to 100.
+            let percentage = percentage.map(|p| (p * 100.0).round() as u32);
+            self.notify_work_done(out, WorkDoneProgressValue::Report { message, percentage });
+        } else {
+            let params = ProgressParams { message, percentage, ..self.params.clone() };
+            out.notify(Notification::<Progress>::new(params));
+        }
+    }
+
+    fn end<O: Output>(&self, out: &O) {
+        if self.work_done_progress {
+            self.notify_work_done(out, WorkDoneProgressValue::End { message: None });
+        } else {
+            let params = ProgressParams { done: Some(true), ..self.params.clone() };
+            out.notify(Notification::<Progress>::new(params));
+        }
+    }
+
+    fn notify_work_done<O: Output>(&self, out: &O, value: WorkDoneProgressValue) {
+        if !self.created.load(Ordering::SeqCst) {
+            return;
+        }
+        let params = WorkDoneProgressParams { token: self.params.id.clone(), value };
+        out.notify(Notification::<WorkDoneProgress>::new(params));
+    }
+}
+
+/// Notifier of progress for the build (window/progress or $/progress notifications).
 /// the same instance is used for the entirety of one single build.
 pub struct BuildProgressNotifier<O: Output> {
     out: O,
-    // These params are used as a template and are cloned for each
-    // message that is actually notified.
-    progress_params: ProgressParams,
+    progress: ProgressChain,
+    status: Arc<ServerStatus>,
 }
 
 impl<O: Output> BuildProgressNotifier<O> {
-    pub fn new(out: O) -> BuildProgressNotifier<O> {
-        BuildProgressNotifier { out, progress_params: new_progress_params("Building".into()) }
+    pub fn new(
+        out: O,
+        status: Arc<ServerStatus>,
+        work_done_progress: bool,
+    ) -> BuildProgressNotifier<O> {
+        BuildProgressNotifier {
+            out,
+            progress: ProgressChain::new("Building", work_done_progress),
+            status,
+        }
     }
 }
 
 impl<O: Output> ProgressNotifier for BuildProgressNotifier<O> {
     fn notify_begin_progress(&self) {
-        let params = self.progress_params.clone();
-        self.out.notify(Notification::<Progress>::new(params));
+        self.progress.begin(&self.out);
     }
     fn notify_progress(&self, update: ProgressUpdate) {
-        let mut params = self.progress_params.clone();
         match update {
-            ProgressUpdate::Message(s) => params.message = Some(s),
-            ProgressUpdate::Percentage(p) => params.percentage = Some(p),
+            ProgressUpdate::Message(s) => {
+                self.status.update(&self.out, |inner| inner.params.phase = Some(s.clone()));
+                self.progress.report(&self.out, Some(s), None);
+            }
+            ProgressUpdate::Percentage(p) => self.progress.report(&self.out, None, Some(p)),
         }
-        self.out.notify(Notification::<Progress>::new(params));
     }
     fn notify_end_progress(&self) {
-        let mut params = self.progress_params.clone();
-        params.done = Some(true);
-        self.out.notify(Notification::<Progress>::new(params));
+        self.progress.end(&self.out);
+    }
+}
+
+/// Notifier of progress for pre-warming the caches when the RLS is initialized
+/// (see the `prewarmFiles` initialization option).
+pub struct PrewarmProgressNotifier<O: Output> {
+    out: O,
+    progress: ProgressChain,
+}
+
+impl<O: Output> PrewarmProgressNotifier<O> {
+    pub fn new(out: O, work_done_progress: bool) -> PrewarmProgressNotifier<O> {
+        PrewarmProgressNotifier {
+            out,
+            progress: ProgressChain::new("Prewarming", work_done_progress),
+        }
+    }
+}
+
+impl<O: Output> ProgressNotifier for PrewarmProgressNotifier<O> {
+    fn notify_begin_progress(&self) {
+        self.progress.begin(&self.out);
+    }
+    fn notify_progress(&self, update: ProgressUpdate) {
+        match update {
+            ProgressUpdate::Message(s) => self.progress.report(&self.out, Some(s), None),
+            ProgressUpdate::Percentage(p) => self.progress.report(&self.out, None, Some(p)),
+        }
+    }
+    fn notify_end_progress(&self) {
+        self.progress.end(&self.out);
     }
 }
 
 /// Notifier of diagnostics after the build has completed.
 pub struct BuildDiagnosticsNotifier<O: Output> {
     out: O,
-    // These params are used as a template, and are cloned for each
-    // message that is actually notified.
-    progress_params: ProgressParams,
+    progress: ProgressChain,
+    // The notifier lives as long as the build's post-build handler, so the
+    // build is considered done once it's dropped.
+    status: Arc<ServerStatus>,
 }
 
 impl<O: Output> BuildDiagnosticsNotifier<O> {
-    pub fn new(out: O) -> BuildDiagnosticsNotifier<O> {
+    pub fn new(
+        out: O,
+        status: Arc<ServerStatus>,
+        work_done_progress: bool,
+    ) -> BuildDiagnosticsNotifier<O> {
         BuildDiagnosticsNotifier {
             out,
             // We emit diagnostics then index, since emitting diagnostics is really
             // quick and always has a message, "indexing" is usually a more useful
             // title.
-            progress_params: new_progress_params("Indexing".into()),
+            progress: ProgressChain::new("Indexing", work_done_progress),
+            status,
         }
     }
 }
 
+impl<O: Output> Drop for BuildDiagnosticsNotifier<O> {
+    fn drop(&mut self) {
+        self.status.end_build(&self.out);
+    }
+}
+
 impl<O: Output> DiagnosticsNotifier for BuildDiagnosticsNotifier<O> {
     fn notify_begin_diagnostics(&self) {
-        let params = self.progress_params.clone();
-        self.out.notify(Notification::<Progress>::new(params));
+        self.status.update(&self.out, |inner| {
+            inner.params.state = ServerState::Indexing;
+            inner.params.phase = None;
+        });
+        self.progress.begin(&self.out);
     }
     fn notify_publish_diagnostics(&self, params: PublishDiagnosticsParams) {
         self.out.notify(Notification::<PublishDiagnostics>::new(params));
     }
     fn notify_error_diagnostics(&self, message: String) {
+        self.status.update(&self.out, |inner| {
+            inner.build_failed = true;
+            inner.params.last_error = Some(message.clone());
+        });
         self.out.notify(Notification::<ShowMessage>::new(ShowMessageParams {
             typ: MessageType::Error,
             message,
         }));
     }
     fn notify_end_diagnostics(&self) {
-        let mut params = self.progress_params.clone();
-        params.done = Some(true);
-        self.out.notify(Notification::<Progress>::new(params));
+        self.progress.end(&self.out);
+    }
+    fn notify_build_recovery(&self, params: BuildRecoveryParams) {
+        self.out.notify(Notification::<BuildRecovery>::new(params));
     }
 }
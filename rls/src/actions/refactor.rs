@@ -0,0 +1,1006 @@
+//! Code actions refactoring the selected code, which are offered without a
+//! diagnostic: extracting an expression into a variable or statements into a
+//! function, inlining a variable and converting between `if let` and `match`.
+
+use lazy_static::lazy_static;
+use regex::Regex;
+use rls_analysis::{EnumVariant, VariantKind};
+
+use crate::actions::field_completion::offset_of;
+use crate::lsp_data::{Position, Range};
+
+/// The name of the variable an expression is extracted into.
+const EXTRACTED_NAME: &str = "value";
+/// The name of the function statements are extracted into.
+const EXTRACTED_FN_NAME: &str = "extracted";
+
+/// A `let` statement binding a single, immutable variable on one line.
+#[derive(Debug, PartialEq)]
+pub struct LetBinding<'a> {
+    pub name: &'a str,
+    /// The (char) column of the name.
+    pub name_col: usize,
+    pub value: &'a str,
+}
+
+/// Whole lines of statements selected to be extracted into a function.
+#[derive(Debug, PartialEq)]
+pub struct StatementBlock {
+    /// From the start of the first line to the start of the line after the
+    /// last one.
+    pub range: Range,
+    pub text: String,
+    pub indent: String,
+}
+
+/// A local variable used by a `StatementBlock`.
+#[derive(Debug, PartialEq)]
+pub struct BlockLocal {
+    pub name: String,
+    pub ty: String,
+    /// Whether the variable is declared in the block, rather than before it.
+    pub declared_inside: bool,
+    /// Whether the variable is declared `mut`.
+    pub mutable: bool,
+    /// Whether the variable is used after the block.
+    pub used_after: bool,
+}
+
+/// Returns the edits extracting the expression selected by `range` into a
+/// variable declared right before the statement it's in, or `None` if the
+/// selection isn't (roughly) an expression on one line inside a body.
+pub fn extract_variable_edits(text: &str, range: Range) -> Option<Vec<(Range, String)>> {
+    if range.start.line != range.end.line || range.start == range.end {
+        return None;
+    }
+    let start = offset_of(text, range.start)?;
+    let end = offset_of(text, range.end)?;
+    let expr = text.get(start..end)?.trim();
+    if expr.is_empty() || expr.contains(|c| c == ';' || c == '{' || c == '}') || !balanced(expr) {
+        return None;
+    }
+
+    let lines: Vec<&str> = text.split('\n').collect();
+    let row = statement_start(&lines, range.start.line as usize);
+    let line = lines[row];
+    let indent = &line[..line.len() - line.trim_start().len()];
+    // Statements are only found in bodies, which are indented.
+    if indent.is_empty() {
+        return None;
+    }
+    // Extracting the whole value of a `let` would only rename its variable.
+    if let_binding(line).map_or(false, |binding| binding.value == expr) {
+        return None;
+    }
+
+    let position = Position::new(row as u64, indent.chars().count() as u64);
+    Some(vec![
+        (Range::new(position, position), format!("let {} = {};\n{}", EXTRACTED_NAME, expr, indent)),
+        (range, EXTRACTED_NAME.to_owned()),
+    ])
+}
+
+/// Returns the statements on the lines selected by `range`, or `None` if the
+/// lines aren't (roughly) whole statements inside a body.
+pub fn statement_block(text: &str, range: Range) -> Option<StatementBlock> {
+    let lines: Vec<&str> = text.split('\n').collect();
+    let start_row = range.start.line as usize;
+    let mut end_row = range.end.line as usize;
+    if range.end.character == 0 && end_row > start_row {
+        end_row -= 1;
+    }
+    if end_row >= lines.len() || statement_start(&lines, start_row) != start_row {
+        return None;
+    }
+
+    let block_lines = &lines[start_row..=end_row];
+    let first = block_lines[0];
+    let indent = &first[..first.len() - first.trim_start().len()];
+    let last = block_lines.iter().rev().find(|line| !line.trim().is_empty())?;
+    if indent.is_empty()
+        || first.trim().is_empty()
+        || !last.trim_end().ends_with(|c| c == ';' || c == '}')
+        || block_lines.iter().any(|line| !line.trim().is_empty() && !line.starts_with(indent))
+    {
+        return None;
+    }
+
+    let mut text = block_lines.join("\n");
+    text.push('\n');
+    let mut depth = 0;
+    for c in strip_strings_and_comments(&text).chars() {
+        match c {
+            '(' | '[' | '{' => depth += 1,
+            ')' | ']' | '}' if depth == 0 => return None,
+            ')' | ']' | '}' => depth -= 1,
+            _ => {}
+        }
+    }
+    if depth != 0 {
+        return None;
+    }
+
+    let range =
+        Range::new(Position::new(start_row as u64, 0), Position::new(end_row as u64 + 1, 0));
+    Some(StatementBlock { range, text, indent: indent.to_owned() })
+}
+
+/// Returns the edits extracting `block` into a function, which is added after
+/// the function the block is in. `locals` are the local variables used by the
+/// block, in the order of their first use in it: those declared before the
+/// block become parameters, and those used after it are returned. Returns an
+/// explanation if the block can't be extracted.
+pub fn extract_function_edits(
+    text: &str,
+    block: &StatementBlock,
+    locals: &[BlockLocal],
+) -> Result<Vec<(Range, String)>, String> {
+    lazy_static! {
+        static ref RETURN_RE: Regex = Regex::new(r"\breturn\b|[\w)\]]\?").unwrap();
+        static ref BREAK_RE: Regex = Regex::new(r"\b(?:break|continue)\b").unwrap();
+        static ref LOOP_RE: Regex = Regex::new(r"\b(?:loop|while|for)\b").unwrap();
+        static ref SELF_RE: Regex = Regex::new(r"\bself\b").unwrap();
+        static ref FN_RE: Regex = Regex::new(
+            r"^(?P<indent>\s*)(?:pub(?:\([^)]*\))?\s+)?(?:(?:const|async|unsafe)\s+)*fn\s"
+        )
+        .unwrap();
+    }
+
+    let code = strip_strings_and_comments(&block.text);
+    if RETURN_RE.is_match(&code) {
+        return Err("the statements can return early from the function".to_owned());
+    }
+    if BREAK_RE.is_match(&code) && !LOOP_RE.is_match(&code) {
+        return Err("the statements break out of a loop around them".to_owned());
+    }
+    if SELF_RE.is_match(&code) {
+        return Err("the statements use `self`".to_owned());
+    }
+
+    // Find the function the block is in, to add the new one after it.
+    let lines: Vec<&str> = text.split('\n').collect();
+    let start_row = block.range.start.line as usize;
+    let end_row = block.range.end.line as usize;
+    let fn_indent = lines[..start_row]
+        .iter()
+        .rev()
+        .filter_map(|line| FN_RE.captures(line))
+        .map(|captures| captures.name("indent").unwrap().as_str())
+        .find(|indent| indent.len() < block.indent.len())
+        .ok_or_else(|| "the statements aren't in a function".to_owned())?;
+    let close = format!("{}}}", fn_indent);
+    let close_row = (end_row..lines.len())
+        .find(|&row| lines[row].trim_end() == close)
+        .ok_or_else(|| "the end of the function around the statements wasn't found".to_owned())?;
+
+    let mut params = vec![];
+    let mut args = vec![];
+    let mut declared = vec![];
+    let mut assigned = vec![];
+    for local in locals {
+        if local.ty.is_empty() || local.ty.contains("[closure") {
+            return Err(format!("the type of `{}` can't be written down", local.name));
+        }
+        if local.declared_inside {
+            if local.used_after {
+                declared.push(local);
+            }
+            continue;
+        }
+
+        let modified = is_assigned(&code, &local.name);
+        if modified && local.used_after {
+            assigned.push(local);
+        }
+        // A variable which isn't used afterwards can be moved into the
+        // function, in case the statements move it.
+        let (param, arg) = if modified || local.mutable && !local.used_after && !is_copy(&local.ty)
+        {
+            (format!("mut {}: {}", local.name, local.ty), local.name.clone())
+        } else if local.mutable && !is_copy(&local.ty) {
+            (format!("{}: &mut {}", local.name, local.ty), format!("&mut {}", local.name))
+        } else if local.used_after && !is_copy(&local.ty) {
+            (format!("{}: &{}", local.name, local.ty), format!("&{}", local.name))
+        } else {
+            (format!("{}: {}", local.name, local.ty), local.name.clone())
+        };
+        params.push(param);
+        args.push(arg);
+    }
+
+    if !declared.is_empty() && !assigned.is_empty() {
+        return Err("the statements both declare and modify variables used after them".to_owned());
+    }
+    let returned = if declared.is_empty() { assigned } else { declared };
+    if let Some(local) = returned.iter().find(|local| local.ty.contains('&')) {
+        return Err(format!(
+            "`{}` borrows data which can't be returned from the function",
+            local.name
+        ));
+    }
+
+    let tuple = |items: Vec<&str>| {
+        if items.len() == 1 {
+            items[0].to_owned()
+        } else {
+            format!("({})", items.join(", "))
+        }
+    };
+    let ret_ty = if returned.is_empty() {
+        String::new()
+    } else {
+        format!(" -> {}", tuple(returned.iter().map(|local| &local.ty[..]).collect()))
+    };
+    let ret_value = tuple(returned.iter().map(|local| &local.name[..]).collect());
+
+    // Methods are in an `impl`, so the new function is an associated one.
+    let path = if fn_indent.is_empty() { "" } else { "Self::" };
+    let call = format!("{}{}({})", path, EXTRACTED_FN_NAME, args.join(", "));
+    let call = if returned.is_empty() {
+        format!("{}{};\n", block.indent, call)
+    } else if returned[0].declared_inside {
+        format!("{}let {} = {};\n", block.indent, ret_value, call)
+    } else {
+        format!("{}{} = {};\n", block.indent, ret_value, call)
+    };
+
+    let body_indent = format!("{}    ", fn_indent);
+    let mut function =
+        format!("\n{}fn {}({}){} {{\n", fn_indent, EXTRACTED_FN_NAME, params.join(", "), ret_ty);
+    for line in block.text.lines() {
+        if !line.trim().is_empty() {
+            function.push_str(&body_indent);
+            function.push_str(&line[block.indent.len()..]);
+        }
+        function.push('\n');
+    }
+    if !returned.is_empty() {
+        function.push_str(&format!("{}{}\n", body_indent, ret_value));
+    }
+    function.push_str(&close);
+    function.push('\n');
+
+    let after_fn = Position::new(close_row as u64 + 1, 0);
+    Ok(vec![(block.range, call), (Range::new(after_fn, after_fn), function)])
+}
+
+/// Returns true if `name` is assigned to (directly) in `text`.
+fn is_assigned(text: &str, name: &str) -> bool {
+    let assignment = format!(r"\b{}\s*(?:[-+*/%&|^]|<<|>>)?=[^=]", regex::escape(name));
+    Regex::new(&assignment).unwrap().is_match(text)
+}
+
+/// Returns `text` with its comments and the contents of its string and
+/// character literals replaced by spaces, so that they aren't mistaken for
+/// code. Lines and (char) columns are kept.
+fn strip_strings_and_comments(text: &str) -> String {
+    let chars: Vec<char> = text.chars().collect();
+    let is_ident = |c: char| c.is_alphanumeric() || c == '_';
+    let starts_token = |i: usize| i == 0 || !is_ident(chars[i - 1]);
+    let blank = |c: char| if c == '\n' { '\n' } else { ' ' };
+    let mut result = String::with_capacity(text.len());
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        let next = chars.get(i + 1).copied();
+        if c == '/' && next == Some('/') {
+            while i < chars.len() && chars[i] != '\n' {
+                result.push(' ');
+                i += 1;
+            }
+        } else if c == '/' && next == Some('*') {
+            // Block comments nest.
+            let mut depth = 0;
+            while i < chars.len() {
+                let pair = (chars[i], chars.get(i + 1).copied());
+                if pair == ('/', Some('*')) || pair == ('*', Some('/')) {
+                    depth += if pair.0 == '/' { 1 } else { -1 };
+                    result.push_str("  ");
+                    i += 2;
+                    if depth == 0 {
+                        break;
+                    }
+                } else {
+                    result.push(blank(chars[i]));
+                    i += 1;
+                }
+            }
+        } else if c == 'r' && (starts_token(i) || chars[i - 1] == 'b' && starts_token(i - 1)) {
+            let hashes = chars[i + 1..].iter().take_while(|&&c| c == '#').count();
+            let quote = i + 1 + hashes;
+            if chars.get(quote) != Some(&'"') {
+                result.push(c);
+                i += 1;
+                continue;
+            }
+            result.extend(&chars[i..=quote]);
+            i = quote + 1;
+            while i < chars.len() {
+                let closes = chars[i] == '"'
+                    && chars[i + 1..].iter().take(hashes).filter(|&&c| c == '#').count() == hashes;
+                if closes {
+                    result.extend(&chars[i..=i + hashes]);
+                    i += hashes + 1;
+                    break;
+                }
+                result.push(blank(chars[i]));
+                i += 1;
+            }
+        } else if c == '"' || c == '\'' && (next == Some('\\') || chars.get(i + 2) == Some(&'\'')) {
+            // A `'` which doesn't start a character literal starts a lifetime.
+            result.push(c);
+            i += 1;
+            while i < chars.len() {
+                if chars[i] == '\\' && i + 1 < chars.len() {
+                    result.push(' ');
+                    result.push(blank(chars[i + 1]));
+                    i += 2;
+                } else if chars[i] == c {
+                    result.push(c);
+                    i += 1;
+                    break;
+                } else {
+                    result.push(blank(chars[i]));
+                    i += 1;
+                }
+            }
+        } else {
+            result.push(c);
+            i += 1;
+        }
+    }
+    result
+}
+
+/// Returns true if `ty` is (certainly) a `Copy` type, which can be passed by
+/// value without moving it.
+fn is_copy(ty: &str) -> bool {
+    const PRIMITIVES: &[&str] = &[
+        "bool", "char", "f32", "f64", "i8", "i16", "i32", "i64", "i128", "isize", "u8", "u16",
+        "u32", "u64", "u128", "usize", "()",
+    ];
+    PRIMITIVES.contains(&ty) || ty.starts_with('&') && !ty.starts_with("&mut")
+}
+
+/// Returns the row of the statement which line `row` is part of, i.e., the
+/// first line after one ending a statement or starting a block.
+fn statement_start(lines: &[&str], row: usize) -> usize {
+    let mut start = row;
+    while start > 0 {
+        let prev = lines[start - 1].trim_end();
+        if prev.trim().is_empty()
+            || prev.ends_with(|c| c == ';' || c == '{' || c == '}')
+            || prev.trim_start().starts_with("//")
+        {
+            break;
+        }
+        start -= 1;
+    }
+    start
+}
+
+/// Returns true if the brackets in `text` are balanced.
+fn balanced(text: &str) -> bool {
+    let mut depth = 0;
+    for c in text.chars() {
+        match c {
+            '(' | '[' => depth += 1,
+            ')' | ']' if depth == 0 => return false,
+            ')' | ']' => depth -= 1,
+            _ => {}
+        }
+    }
+    depth == 0
+}
+
+/// Parses a `let` statement binding a single, immutable variable which takes
+/// up `line`.
+pub fn let_binding(line: &str) -> Option<LetBinding<'_>> {
+    lazy_static! {
+        static ref LET_RE: Regex =
+            Regex::new(r"^\s*let\s+(?P<name>\w+)\s*(?::[^=]+)?=\s*(?P<value>.+?)\s*;\s*$").unwrap();
+    }
+
+    let captures = LET_RE.captures(line)?;
+    let name = captures.name("name").unwrap();
+    let value = captures.name("value").unwrap().as_str();
+    if name.as_str() == "mut" || name.as_str() == "_" || !balanced(value) {
+        return None;
+    }
+    Some(LetBinding { name: name.as_str(), name_col: line[..name.start()].chars().count(), value })
+}
+
+/// Returns `true` if `value` is a path (e.g., `x` or `foo::BAR`) or a literal,
+/// which can be inlined into several uses without evaluating anything more
+/// than once.
+pub fn is_path_or_literal(value: &str) -> bool {
+    lazy_static! {
+        static ref PATH_OR_LITERAL_RE: Regex = Regex::new(concat!(
+            r"^(?:",
+            r"(?:::)?\w+(?:::\w+)*",
+            r#"|-?\d[\w.]*"#,
+            r#"|b?"(?:[^"\\]|\\.)*""#,
+            r##"|b?r#*"[^"]*"#*"##,
+            r#"|b?'(?:[^'\\]|\\[^']+)'"#,
+            r")$"
+        ))
+        .unwrap();
+    }
+
+    PATH_OR_LITERAL_RE.is_match(value)
+}
+
+/// Returns the text replacing a use of an inlined variable whose value is
+/// `value`, which is parenthesized unless it's a single term (e.g., a path,
+/// a literal or a call) which can't be split up by the surrounding code.
+pub fn inlined_value(value: &str) -> String {
+    let mut depth = 0;
+    let mut in_str = false;
+    let mut prev = ' ';
+    let mut single_term = true;
+    for (i, c) in value.char_indices() {
+        match c {
+            '"' if prev != '\\' => in_str = !in_str,
+            _ if in_str => {}
+            '(' | '[' | '{' => depth += 1,
+            ')' | ']' | '}' => depth -= 1,
+            _ if depth > 0 => {}
+            // Macros, but not negation.
+            '!' if i > 0 => {}
+            _ if c.is_alphanumeric() || c == '_' || c == '.' || c == ':' || c == '\'' => {}
+            _ => single_term = false,
+        }
+        prev = c;
+    }
+
+    if single_term {
+        value.to_owned()
+    } else {
+        format!("({})", value)
+    }
+}
+
+lazy_static! {
+    static ref IF_LET_RE: Regex =
+        Regex::new(r"^(?P<indent>\s*)if let (?P<pattern>.+?) = (?P<expr>.+?)\s*\{\s*$").unwrap();
+    static ref MATCH_RE: Regex =
+        Regex::new(r"^(?P<indent>\s*)match (?P<expr>.+?)\s*\{\s*$").unwrap();
+}
+
+/// Returns the pattern of the `if let` starting `line` and its (char) column.
+pub fn if_let_pattern(line: &str) -> Option<(&str, usize)> {
+    let pattern = IF_LET_RE.captures(line)?.name("pattern")?;
+    Some((pattern.as_str(), line[..pattern.start()].chars().count()))
+}
+
+/// Returns the pattern matching the variants of an enum with two `variants`
+/// which `pattern` doesn't match, e.g., `None` for `Some(x)`, or `None` if
+/// the enum has more variants or `pattern` isn't one of a variant.
+pub fn other_variant_pattern(pattern: &str, variants: &[EnumVariant]) -> Option<String> {
+    lazy_static! {
+        static ref VARIANT_RE: Regex = Regex::new(r"^(?P<path>(?:\w+::)*)(?P<name>\w+)").unwrap();
+    }
+
+    let captures = VARIANT_RE.captures(pattern)?;
+    let name = &captures["name"];
+    let other = match variants {
+        [first, second] if first.name == name => second,
+        [first, second] if second.name == name => first,
+        _ => return None,
+    };
+    let fields = match other.kind {
+        VariantKind::Unit => "",
+        VariantKind::Tuple => "(..)",
+        VariantKind::Struct => " { .. }",
+    };
+    Some(format!("{}{}{}", &captures["path"], other.name, fields))
+}
+
+/// Returns the edit converting the `if let` (and its `else`) which starts on
+/// line `row` into a `match`. The arm of the `else` has `other_pattern`.
+pub fn if_let_to_match_edit(
+    text: &str,
+    row: usize,
+    other_pattern: &str,
+) -> Option<(Range, String)> {
+    let lines: Vec<&str> = text.split('\n').collect();
+    let captures = IF_LET_RE.captures(lines.get(row)?)?;
+    let indent = &captures["indent"];
+    let (then_row, then_col) = block_end(&lines, row, lines[row].rfind('{')?)?;
+
+    let rest = &lines[then_row][then_col + 1..];
+    let (end, else_lines) = if rest.trim_start().starts_with("else") {
+        if rest.trim() != "else {" {
+            return None;
+        }
+        let open_col = then_col + 1 + rest.rfind('{')?;
+        let (else_row, else_col) = block_end(&lines, then_row, open_col)?;
+        ((else_row, else_col), Some(&lines[then_row + 1..else_row]))
+    } else {
+        ((then_row, then_col), None)
+    };
+
+    let arm = |pattern: &str, body: Option<&[&str]>| {
+        let mut arm = format!("{}    {} => {{", indent, pattern);
+        match body {
+            Some(body) if body.iter().any(|line| !line.trim().is_empty()) => {
+                arm.push('\n');
+                for line in body {
+                    if !line.trim().is_empty() {
+                        arm.push_str("    ");
+                        arm.push_str(line);
+                    }
+                    arm.push('\n');
+                }
+                arm.push_str(&format!("{}    }}\n", indent));
+            }
+            _ => arm.push_str("}\n"),
+        }
+        arm
+    };
+    let mut new_text = format!("match {} {{\n", &captures["expr"]);
+    new_text.push_str(&arm(&captures["pattern"], Some(&lines[row + 1..then_row])));
+    new_text.push_str(&arm(other_pattern, else_lines));
+    new_text.push_str(&format!("{}}}", indent));
+
+    Some((block_range(&lines, row, indent, end), new_text))
+}
+
+/// Returns the edit converting the `match` with two arms which starts on line
+/// `row` into an `if let`, or `None` if both arms bind variables.
+pub fn match_to_if_let_edit(text: &str, row: usize) -> Option<(Range, String)> {
+    let lines: Vec<&str> = text.split('\n').collect();
+    let captures = MATCH_RE.captures(lines.get(row)?)?;
+    let indent = &captures["indent"];
+    let (end_row, end_col) = block_end(&lines, row, lines[row].rfind('{')?)?;
+
+    let mut body = lines[row + 1..end_row].join("\n");
+    body.push_str(&lines[end_row][..end_col]);
+    let arms = match_arms(&body)?;
+    let ((pattern, then_body), (_, else_body)) = match arms[..] {
+        [first, second] if !binds(second.0) => (first, second),
+        [first, second] if !binds(first.0) => (second, first),
+        _ => return None,
+    };
+
+    let body_lines = |body: &str| -> Vec<String> {
+        let inner = if body.starts_with('{') { &body[1..body.len() - 1] } else { body };
+        if !inner.contains('\n') {
+            let inner = inner.trim();
+            return if inner.is_empty() || inner == "()" {
+                vec![]
+            } else {
+                vec![format!("{}    {}", indent, inner)]
+            };
+        }
+        let lines: Vec<&str> = inner.split('\n').collect();
+        let first = lines.iter().position(|line| !line.trim().is_empty()).unwrap_or(lines.len());
+        let last = lines.iter().rposition(|line| !line.trim().is_empty()).map_or(0, |i| i + 1);
+        lines[first..last.max(first)]
+            .iter()
+            .map(|line| line.strip_prefix("    ").unwrap_or(line).trim_end().to_owned())
+            .collect()
+    };
+
+    let mut new_text = format!("if let {} = {} {{\n", pattern, &captures["expr"]);
+    for line in body_lines(then_body) {
+        new_text.push_str(&line);
+        new_text.push('\n');
+    }
+    let else_lines = body_lines(else_body);
+    if !else_lines.is_empty() {
+        new_text.push_str(&format!("{}}} else {{\n", indent));
+        for line in else_lines {
+            new_text.push_str(&line);
+            new_text.push('\n');
+        }
+    }
+    new_text.push_str(&format!("{}}}", indent));
+
+    Some((block_range(&lines, row, indent, (end_row, end_col)), new_text))
+}
+
+/// Returns the range from after the indentation of line `row` to after the
+/// brace at (byte) column `end.1` of line `end.0`.
+fn block_range(lines: &[&str], row: usize, indent: &str, end: (usize, usize)) -> Range {
+    let start = Position::new(row as u64, indent.chars().count() as u64);
+    let end_col = lines[end.0][..=end.1].chars().count();
+    Range::new(start, Position::new(end.0 as u64, end_col as u64))
+}
+
+/// Returns the row and (byte) column of the brace closing the one at (byte)
+/// column `col` of line `row`, if it is the first thing on its line.
+fn block_end(lines: &[&str], row: usize, col: usize) -> Option<(usize, usize)> {
+    let mut depth = 0;
+    let mut in_str = false;
+    let mut prev = ' ';
+    for (r, line) in lines.iter().enumerate().skip(row) {
+        let start = if r == row { col } else { 0 };
+        for (i, c) in line[start..].char_indices() {
+            match c {
+                '"' if prev != '\\' => in_str = !in_str,
+                _ if in_str => {}
+                '{' => depth += 1,
+                '}' if depth == 1 => {
+                    let i = start + i;
+                    return if line[..i].trim().is_empty() { Some((r, i)) } else { None };
+                }
+                '}' => depth -= 1,
+                _ => {}
+            }
+            prev = c;
+        }
+    }
+    None
+}
+
+/// Splits the body of a `match` into the patterns and bodies of its arms, or
+/// returns `None` if an arm has a guard.
+fn match_arms(body: &str) -> Option<Vec<(&str, &str)>> {
+    let mut arms = vec![];
+    let mut rest = body.trim_start();
+    while !rest.is_empty() {
+        let arrow = top_level_find(rest, "=>")?;
+        let pattern = rest[..arrow].trim();
+        if pattern.contains(" if ") {
+            return None;
+        }
+        rest = rest[arrow + 2..].trim_start();
+        let end = if rest.starts_with('{') {
+            top_level_find(&rest[1..], "}").map_or(rest.len(), |i| i + 2)
+        } else {
+            top_level_find(rest, ",").unwrap_or_else(|| rest.len())
+        };
+        arms.push((pattern, rest[..end].trim()));
+        rest = rest[end..].trim_start();
+        rest = rest.strip_prefix(',').unwrap_or(rest).trim_start();
+    }
+    Some(arms)
+}
+
+/// Returns the (byte) offset of the first `needle` in `text` which isn't in
+/// brackets or a string.
+fn top_level_find(text: &str, needle: &str) -> Option<usize> {
+    let mut depth = 0;
+    let mut in_str = false;
+    let mut prev = ' ';
+    for (i, c) in text.char_indices() {
+        if !in_str && depth == 0 && text[i..].starts_with(needle) {
+            return Some(i);
+        }
+        match c {
+            '"' if prev != '\\' => in_str = !in_str,
+            _ if in_str => {}
+            '(' | '[' | '{' => depth += 1,
+            ')' | ']' | '}' => depth -= 1,
+            _ => {}
+        }
+        prev = c;
+    }
+    None
+}
+
+/// Returns true if `pattern` (probably) binds a variable, e.g., `Some(x)`, as
+/// opposed to `None`, `Foo::Bar { .. }` or `_`.
+fn binds(pattern: &str) -> bool {
+    lazy_static! {
+        static ref IDENT_RE: Regex = Regex::new(r"[A-Za-z_]\w*").unwrap();
+    }
+
+    IDENT_RE.find_iter(pattern).any(|ident| {
+        let name = ident.as_str();
+        let before = pattern[..ident.start()].trim_end();
+        let after = pattern[ident.end()..].trim_start();
+        name.starts_with(|c: char| c.is_lowercase() || c == '_')
+            && !["_", "ref", "mut", "true", "false", "box"].contains(&name)
+            && !before.ends_with("::")
+            && !after.starts_with(|c| c == '(' || c == '{' || c == ':' || c == '!')
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn range(line: u64, start: u64, end: u64) -> Range {
+        Range::new(Position::new(line, start), Position::new(line, end))
+    }
+
+    fn selection(start_line: u64, start: u64, end_line: u64, end: u64) -> Range {
+        Range::new(Position::new(start_line, start), Position::new(end_line, end))
+    }
+
+    const TEXT: &str = "fn main() {
+    let x = 1;
+    println!(\"{}\", x * (2 + 3));
+    let total = foo(x,
+                    x + 1);
+}
+";
+
+    #[test]
+    fn extracts_variables() {
+        let edits = extract_variable_edits(TEXT, range(2, 23, 30)).unwrap();
+        assert_eq!(
+            edits,
+            vec![
+                (range(2, 4, 4), "let value = (2 + 3);\n    ".to_owned()),
+                (range(2, 23, 30), "value".to_owned()),
+            ]
+        );
+
+        // The `let` goes before the whole statement.
+        let edits = extract_variable_edits(TEXT, range(4, 20, 25)).unwrap();
+        assert_eq!(edits[0], (range(3, 4, 4), "let value = x + 1;\n    ".to_owned()));
+    }
+
+    #[test]
+    fn extracts_only_expressions() {
+        // Empty selections.
+        assert_eq!(extract_variable_edits(TEXT, range(2, 23, 23)), None);
+        // Whole statements.
+        assert_eq!(extract_variable_edits(TEXT, range(1, 4, 14)), None);
+        // Unbalanced brackets.
+        assert_eq!(extract_variable_edits(TEXT, range(2, 24, 30)), None);
+        // The value of a `let`.
+        assert_eq!(extract_variable_edits(TEXT, range(1, 12, 13)), None);
+        // Outside of a body.
+        assert_eq!(extract_variable_edits(TEXT, range(0, 3, 7)), None);
+    }
+
+    const FN_TEXT: &str = "fn main() {
+    let x = 1;
+    let y = x + 1;
+    println!(\"{}\", y);
+    println!(\"{}\", x);
+}
+";
+
+    fn local(name: &str, ty: &str, declared_inside: bool, used_after: bool) -> BlockLocal {
+        BlockLocal {
+            name: name.to_owned(),
+            ty: ty.to_owned(),
+            declared_inside,
+            mutable: false,
+            used_after,
+        }
+    }
+
+    #[test]
+    fn finds_statement_blocks() {
+        let block = statement_block(FN_TEXT, selection(2, 4, 3, 23)).unwrap();
+        assert_eq!(block.range, Range::new(Position::new(2, 0), Position::new(4, 0)));
+        assert_eq!(block.text, "    let y = x + 1;\n    println!(\"{}\", y);\n");
+        assert_eq!(block.indent, "    ");
+
+        // Selecting up to the start of the next line.
+        let block = statement_block(FN_TEXT, selection(2, 0, 4, 0)).unwrap();
+        assert_eq!(block.range, Range::new(Position::new(2, 0), Position::new(4, 0)));
+
+        // Not in a body.
+        assert_eq!(statement_block(FN_TEXT, selection(0, 0, 1, 14)), None);
+        // Unbalanced.
+        assert_eq!(statement_block(TEXT, selection(3, 4, 3, 22)), None);
+        // Not the start of a statement.
+        assert_eq!(statement_block(TEXT, selection(4, 0, 4, 27)), None);
+    }
+
+    #[test]
+    fn extracts_functions() {
+        let block = statement_block(FN_TEXT, selection(2, 4, 3, 23)).unwrap();
+        let locals = [local("x", "i32", false, true), local("y", "i32", true, false)];
+        let edits = extract_function_edits(FN_TEXT, &block, &locals).unwrap();
+        assert_eq!(
+            edits,
+            vec![
+                (block.range, "    extracted(x);\n".to_owned()),
+                (
+                    Range::new(Position::new(6, 0), Position::new(6, 0)),
+                    "\nfn extracted(x: i32) {\n    let y = x + 1;\n    println!(\"{}\", y);\n}\n"
+                        .to_owned()
+                ),
+            ]
+        );
+
+        // Returning a variable used afterwards, and borrowing one.
+        let block = statement_block(FN_TEXT, selection(2, 4, 2, 18)).unwrap();
+        let locals = [local("x", "String", false, true), local("y", "usize", true, true)];
+        let edits = extract_function_edits(FN_TEXT, &block, &locals).unwrap();
+        assert_eq!(edits[0].1, "    let y = extracted(&x);\n");
+        assert_eq!(
+            edits[1].1,
+            "\nfn extracted(x: &String) -> usize {\n    let y = x + 1;\n    y\n}\n"
+        );
+    }
+
+    #[test]
+    fn explains_unextractable_functions() {
+        let text = "fn foo(v: &[u32]) -> u32 {
+    let first = &v[0];
+    let total = first + 1;
+    if total > 2 {
+        return 0;
+    }
+    *first
+}
+";
+        let block = statement_block(text, selection(2, 0, 6, 0)).unwrap();
+        let locals = [local("first", "&u32", false, true), local("total", "u32", true, false)];
+        assert!(extract_function_edits(text, &block, &locals).unwrap_err().contains("return"));
+
+        let block = statement_block(text, selection(1, 0, 2, 0)).unwrap();
+        let locals = [local("v", "&[u32]", false, true), local("first", "&u32", true, true)];
+        assert!(extract_function_edits(text, &block, &locals).unwrap_err().contains("borrows"));
+    }
+
+    #[test]
+    fn extracts_functions_moving_variables() {
+        let text = "fn main() {
+    let mut names = vec![];
+    names.push(\"a\");
+    drop(names);
+}
+";
+        let block = statement_block(text, selection(2, 0, 4, 0)).unwrap();
+        let mut names = local("names", "Vec<&str>", false, false);
+        names.mutable = true;
+        let edits = extract_function_edits(text, &block, &[names]).unwrap();
+        assert_eq!(edits[0].1, "    extracted(names);\n");
+        assert!(edits[1].1.starts_with("\nfn extracted(mut names: Vec<&str>) {\n"));
+    }
+
+    #[test]
+    fn ignores_strings_and_comments() {
+        let text = "fn main() {
+    let x = 1;
+    println!(\"{}?) return\", x); // return x?
+    let c = '(';
+}
+";
+        let block = statement_block(text, selection(2, 0, 4, 0)).unwrap();
+        let locals = [local("x", "i32", false, false), local("c", "char", true, false)];
+        assert!(extract_function_edits(text, &block, &locals).is_ok());
+    }
+
+    #[test]
+    fn strips_strings_and_comments() {
+        assert_eq!(
+            strip_strings_and_comments("f(\"a?\\\"\", 'b', '\\'') /* x /* y */ z */ + 1 // c"),
+            "f(\"    \", ' ', '  ')                   + 1     "
+        );
+        assert_eq!(
+            strip_strings_and_comments("r#\"a\"b\"# + br\"c\" + 'a + &'static str"),
+            "r#\"   \"# + br\" \" + 'a + &'static str"
+        );
+        assert_eq!(strip_strings_and_comments("x // a\ny"), "x     \ny");
+    }
+
+    #[test]
+    fn converts_if_let_to_match() {
+        let text = "fn foo(x: Option<u32>) {
+    if let Some(x) = x {
+        println!(\"{}\", x);
+    } else {
+        println!(\"none\");
+    }
+    if let Some(_) = x {
+
+        bar();
+    }
+}
+";
+        let (range, new_text) = if_let_to_match_edit(text, 1, "None").unwrap();
+        assert_eq!(range, selection(1, 4, 5, 5));
+        assert_eq!(
+            new_text,
+            "match x {
+        Some(x) => {
+            println!(\"{}\", x);
+        }
+        None => {
+            println!(\"none\");
+        }
+    }"
+        );
+
+        // Without an `else`.
+        let (range, new_text) = if_let_to_match_edit(text, 6, "_").unwrap();
+        assert_eq!(range, selection(6, 4, 9, 5));
+        assert_eq!(new_text, "match x {\n        Some(_) => {\n\n            bar();\n        }\n        _ => {}\n    }");
+
+        assert_eq!(if_let_to_match_edit(text, 2, "_"), None);
+    }
+
+    #[test]
+    fn converts_match_to_if_let() {
+        let text = "fn foo(x: Result<u32, ()>) {
+    match x {
+        Err(()) => {}
+        Ok(x) => {
+            println!(\"{}\", x);
+        }
+    }
+    match x {
+        Ok(x) => bar(x),
+        Err(e) => baz(e),
+    }
+}
+";
+        let (range, new_text) = match_to_if_let_edit(text, 1).unwrap();
+        assert_eq!(range, selection(1, 4, 6, 5));
+        assert_eq!(new_text, "if let Ok(x) = x {\n        println!(\"{}\", x);\n    }");
+
+        // Both arms bind variables.
+        assert_eq!(match_to_if_let_edit(text, 7), None);
+    }
+
+    #[test]
+    fn finds_other_variant_patterns() {
+        let variant = |name: &str, kind| EnumVariant {
+            id: rls_analysis::NULL,
+            name: name.to_owned(),
+            kind,
+            fields: vec![],
+        };
+        let option = [variant("None", VariantKind::Unit), variant("Some", VariantKind::Tuple)];
+        assert_eq!(other_variant_pattern("Some(x)", &option), Some("None".to_owned()));
+        assert_eq!(
+            other_variant_pattern("Option::None", &option),
+            Some("Option::Some(..)".to_owned())
+        );
+        assert_eq!(other_variant_pattern("Ok(x)", &option), None);
+
+        let shapes = [
+            variant("Circle", VariantKind::Tuple),
+            variant("Square", VariantKind::Struct),
+            variant("Point", VariantKind::Unit),
+        ];
+        assert_eq!(other_variant_pattern("Shape::Point", &shapes), None);
+    }
+
+    #[test]
+    fn parses_let_bindings() {
+        assert_eq!(
+            let_binding("    let x: u32 = foo(1, 2);"),
+            Some(LetBinding { name: "x", name_col: 8, value: "foo(1, 2)" })
+        );
+        assert_eq!(let_binding("let mut x = 1;"), None);
+        assert_eq!(let_binding("let (a, b) = (1, 2);"), None);
+        assert_eq!(let_binding("let x = foo(1,"), None);
+    }
+
+    #[test]
+    fn parenthesizes_inlined_values() {
+        assert_eq!(inlined_value("foo::bar(1 + 2)"), "foo::bar(1 + 2)");
+        assert_eq!(inlined_value("vec![1, 2]"), "vec![1, 2]");
+        assert_eq!(inlined_value("\"a b\""), "\"a b\"");
+        assert_eq!(inlined_value("x.len()"), "x.len()");
+        assert_eq!(inlined_value("1 + 2"), "(1 + 2)");
+        assert_eq!(inlined_value("!done"), "(!done)");
+        assert_eq!(inlined_value("x as u64"), "(x as u64)");
+    }
+
+    #[test]
+    fn finds_paths_and_literals() {
+        let paths_and_literals = [
+            "x",
+            "foo::BAR",
+            "::std::u32::MAX",
+            "true",
+            "-1",
+            "1_000u32",
+            "2.5",
+            "\"a \\\" b\"",
+            "b\"ab\"",
+            "r#\"a\"#",
+            "'\\n'",
+            "b'\\x7f'",
+        ];
+        for value in &paths_and_literals {
+            assert!(is_path_or_literal(value), "{}", value);
+        }
+        for value in &["foo()", "x.len()", "1 + 2", "\"a\" + b", "vec![1]", "Foo { x: 1 }", "!x"] {
+            assert!(!is_path_or_literal(value), "{}", value);
+        }
+    }
+}
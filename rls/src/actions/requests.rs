@@ -6,31 +6,52 @@ use std::sync::atomic::Ordering;
 
 use itertools::Itertools;
 use jsonrpc_core::types::ErrorCode;
+use lazy_static::lazy_static;
 use log::{debug, trace, warn};
-use rls_analysis::SymbolQuery;
+use regex::Regex;
+use rls_analysis::{AnalysisHost, Def, ExpansionRefs, FindRefsOptions, Id, SymbolQuery};
 use rls_data as data;
 use rls_span as span;
-use rls_vfs::FileContents;
-use rustfmt_nightly::{Edition as RustfmtEdition, FileLines, FileName, Range as RustfmtRange};
+use rls_vfs::{FileContents, Language};
+use rustfmt_nightly::Edition as RustfmtEdition;
 use serde_derive::{Deserialize, Serialize};
 use url::Url;
 
+use crate::actions::crate_graph;
+use crate::actions::dead_code;
+use crate::actions::derive;
+use crate::actions::explain;
+use crate::actions::field_completion;
+use crate::actions::format;
 use crate::actions::hover;
-use crate::actions::run::collect_run_actions;
+use crate::actions::imports;
+use crate::actions::outline;
+use crate::actions::refactor;
+use crate::actions::run::{self, collect_run_actions};
+use crate::actions::semantic_tokens;
+use crate::actions::trait_stubs;
+use crate::actions::variant_completion;
 use crate::actions::InitActionContext;
-use crate::build::Edition;
 use crate::lsp_data;
 use crate::lsp_data::request::ApplyWorkspaceEdit;
 pub use crate::lsp_data::request::{
-    CodeActionRequest as CodeAction, CodeLensRequest, Completion,
+    CodeActionRequest as CodeAction, CodeLensRequest, CodeLensResolve, Completion,
     DocumentHighlightRequest as DocumentHighlight, DocumentSymbolRequest as Symbols,
-    ExecuteCommand, Formatting, GotoDefinition as Definition, GotoImplementation as Implementation,
-    HoverRequest as Hover, RangeFormatting, References, Rename,
-    ResolveCompletionItem as ResolveCompletion, WorkspaceSymbol,
+    ExecuteCommand, Formatting, GotoDeclaration as Declaration, GotoDefinition as Definition,
+    GotoImplementation as Implementation, HoverRequest as Hover, OnTypeFormatting, RangeFormatting,
+    References, Rename, ResolveCompletionItem as ResolveCompletion, WorkspaceSymbol,
 };
 use crate::lsp_data::*;
+pub use crate::lsp_data::{
+    ActiveCfgs, AnalysisFreshness, BatchDefinition, CallHierarchyIncomingCalls,
+    CallHierarchyOutgoingCalls, CallHierarchyPrepare, CrateGraph, DeadCode, ExplainError,
+    GroupedReferences, ImplementMissingItems, InlayHints, NearestTest, Outline, QualifiedPath,
+    RegexSymbol, SemanticTokensFull, SemanticTokensFullDelta, Status, ToggleImpl,
+    TypeHierarchyPrepare, TypeHierarchySubtypes, TypeHierarchySupertypes, TypeOfSelection,
+};
 use crate::server;
 use crate::server::{Ack, Output, Request, RequestAction, ResponseError, ResponseWithMessage};
+use crate::Span;
 
 /// The result of a deglob action for a single wildcard import.
 ///
@@ -55,25 +76,59 @@ impl RequestAction for WorkspaceSymbol {
         ctx: InitActionContext,
         params: Self::Params,
     ) -> Result<Self::Response, ResponseError> {
+        let excludes = ctx.config.lock().unwrap().analysis_excludes(&ctx.current_project);
         let analysis = ctx.analysis;
         let query = SymbolQuery::subsequence(&params.query).limit(512);
         let defs = analysis.query_defs(query).unwrap_or_else(|_| vec![]);
 
         Ok(defs
             .into_iter()
+            .filter(|d| !excludes.is_excluded(&d.span.file))
             // Sometimes analysis will return duplicate symbols
             // for the same location, fix that up.
             .unique_by(|d| (d.span.clone(), d.name.clone()))
-            .map(|d| SymbolInformation {
-                name: d.name,
-                kind: source_kind_from_def_kind(d.kind),
-                location: ls_util::rls_to_location(&d.span),
-                container_name: d
-                    .parent
-                    .and_then(|id| analysis.get_def(id).ok())
-                    .map(|parent| parent.name),
-                deprecated: None,
-            })
+            .map(|d| symbol_information(&analysis, d))
+            .collect())
+    }
+}
+
+/// Returns the workspace symbol for `def`.
+fn symbol_information(analysis: &AnalysisHost, def: Def) -> SymbolInformation {
+    SymbolInformation {
+        name: def.name,
+        kind: source_kind_from_def_kind(def.kind),
+        location: ls_util::rls_to_location(&def.span),
+        container_name: def
+            .parent
+            .and_then(|id| analysis.get_def(id).ok())
+            .map(|parent| parent.name),
+        deprecated: None,
+    }
+}
+
+impl RequestAction for RegexSymbol {
+    type Response = Vec<SymbolInformation>;
+
+    fn fallback_response() -> Result<Self::Response, ResponseError> {
+        Ok(vec![])
+    }
+
+    fn handle(
+        ctx: InitActionContext,
+        params: Self::Params,
+    ) -> Result<Self::Response, ResponseError> {
+        let query = SymbolQuery::regex(&params.pattern).map_err(|e| {
+            ResponseError::Message(ErrorCode::InvalidParams, format!("Invalid pattern: {}", e))
+        })?;
+        let excludes = ctx.config.lock().unwrap().analysis_excludes(&ctx.current_project);
+        let analysis = ctx.analysis;
+        let defs = analysis.query_defs(query.limit(512)).unwrap_or_else(|_| vec![]);
+
+        Ok(defs
+            .into_iter()
+            .filter(|d| !excludes.is_excluded(&d.span.file))
+            .unique_by(|d| (d.span.clone(), d.name.clone()))
+            .map(|d| symbol_information(&analysis, d))
             .collect())
     }
 }
@@ -116,6 +171,39 @@ impl RequestAction for Symbols {
     }
 }
 
+impl RequestAction for Outline {
+    type Response = Vec<OutlineItem>;
+
+    fn fallback_response() -> Result<Self::Response, ResponseError> {
+        Ok(vec![])
+    }
+
+    fn handle(
+        ctx: InitActionContext,
+        params: Self::Params,
+    ) -> Result<Self::Response, ResponseError> {
+        let file_path = parse_file_path!(&params.text_document.uri, "outline")?;
+        Ok(outline::outline(&ctx, &file_path))
+    }
+}
+
+impl RequestAction for NearestTest {
+    type Response = Option<TestFunction>;
+
+    fn fallback_response() -> Result<Self::Response, ResponseError> {
+        Ok(None)
+    }
+
+    fn handle(
+        ctx: InitActionContext,
+        params: Self::Params,
+    ) -> Result<Self::Response, ResponseError> {
+        let file_path = parse_file_path!(&params.text_document.uri, "nearest_test")?;
+        let position = ls_util::position_to_rls(params.position);
+        Ok(run::nearest_test(&ctx, &file_path, position))
+    }
+}
+
 impl RequestAction for Hover {
     type Response = lsp_data::Hover;
 
@@ -167,13 +255,38 @@ impl RequestAction for Implementation {
     }
 }
 
-impl RequestAction for Definition {
+impl RequestAction for ToggleImpl {
     type Response = Vec<Location>;
 
     fn fallback_response() -> Result<Self::Response, ResponseError> {
         Ok(vec![])
     }
 
+    fn handle(
+        ctx: InitActionContext,
+        params: Self::Params,
+    ) -> Result<Self::Response, ResponseError> {
+        let file_path = parse_file_path!(&params.text_document.uri, "toggle_impl")?;
+        let span = ctx.convert_pos_to_span(file_path, params.position);
+
+        // The name of a method implementing a trait method refers to the trait
+        // method (its `decl_id`), so `id` is the trait method for both.
+        let id = ctx.analysis.id(&span).map_err(|_| ResponseError::Empty)?;
+        let decl = ctx.analysis.get_def(id).map_err(|_| ResponseError::Empty)?.span;
+        let impls = ctx.analysis.method_implementations(id).unwrap_or_default();
+
+        let spans = if impls.is_empty() || impls.contains(&span) { vec![decl] } else { impls };
+        Ok(spans.iter().map(ls_util::rls_to_location).collect())
+    }
+}
+
+impl RequestAction for Definition {
+    type Response = GotoDefinitionResponse;
+
+    fn fallback_response() -> Result<Self::Response, ResponseError> {
+        Ok(GotoDefinitionResponse::Array(vec![]))
+    }
+
     fn handle(
         ctx: InitActionContext,
         params: Self::Params,
@@ -182,10 +295,22 @@ impl RequestAction for Definition {
         let file_path = parse_file_path!(&params.text_document.uri, "goto_def")?;
         let span = ctx.convert_pos_to_span(file_path.clone(), params.position);
 
-        if let Ok(out) = ctx.analysis.goto_def(&span) {
-            let result = vec![ls_util::rls_to_location(&out)];
+        // A reference can have multiple definitions, e.g., a field init shorthand.
+        let defs = if ctx.config.lock().unwrap().goto_def_all {
+            ctx.analysis.goto_def_all(&span).unwrap_or_default()
+        } else {
+            ctx.analysis.goto_def(&span).map(|def| vec![def]).unwrap_or_default()
+        };
+        if !defs.is_empty() {
+            if ctx.client_capabilities.definition_link_support {
+                let links: Vec<_> =
+                    defs.iter().map(|def| definition_link(&ctx, &span, def)).collect();
+                trace!("goto_def (compiler): {:?}", links);
+                return Ok(GotoDefinitionResponse::Link(links));
+            }
+            let result: Vec<_> = defs.iter().map(ls_util::rls_to_location).collect();
             trace!("goto_def (compiler): {:?}", result);
-            Ok(result)
+            Ok(GotoDefinitionResponse::Array(result))
         } else {
             let racer_enabled = {
                 let config = ctx.config.lock().unwrap();
@@ -202,7 +327,7 @@ impl RequestAction for Definition {
                     .unwrap_or_default();
 
                 trace!("goto_def (Racer): {:?}", r);
-                Ok(r)
+                Ok(GotoDefinitionResponse::Array(r))
             } else {
                 Self::fallback_response()
             }
@@ -210,6 +335,106 @@ impl RequestAction for Definition {
     }
 }
 
+/// Returns the link from the identifier at `origin` to its definition whose
+/// name is at `def_span`. The target range covers the whole item (e.g., a
+/// function with its body) if its end can be found, and the name otherwise.
+fn definition_link(ctx: &InitActionContext, origin: &Span, def_span: &Span) -> LocationLink {
+    let name_range = ls_util::rls_to_range(def_span.range);
+    let target_range = item_range(ctx, def_span).unwrap_or(name_range);
+    LocationLink {
+        origin_selection_range: Some(ls_util::rls_to_range(origin.range)),
+        target_uri: file_path_to_uri(&def_span.file).unwrap(),
+        target_range,
+        target_selection_range: name_range,
+    }
+}
+
+/// Returns the range of the item whose name is at `span`, from the start of
+/// the line of its name to the end of the item. Save-analysis only records
+/// the names of items, so the end is found in the text.
+fn item_range(ctx: &InitActionContext, span: &Span) -> Option<Range> {
+    let def = ctx.analysis.id(span).and_then(|id| ctx.analysis.get_def(id)).ok()?;
+    // Locals and fields end with the expression or item around them.
+    if def.kind == data::DefKind::Local || def.kind == data::DefKind::Field {
+        return None;
+    }
+    let text = match ctx.vfs.load_file(&span.file) {
+        Ok(FileContents::Text(text)) => text,
+        _ => return None,
+    };
+
+    let (row, col) = (span.range.row_start.0, span.range.col_start.0);
+    let (end_row, end_col) = outline::item_end(&text, row, col, def.kind)?;
+    let line = text.lines().nth(row as usize)?;
+    let indent = line.chars().take_while(|c| c.is_whitespace()).count() as u64;
+    Some(Range::new(
+        Position::new(row.into(), indent),
+        Position::new(end_row.into(), u64::from(end_col) + 1),
+    ))
+}
+
+impl RequestAction for BatchDefinition {
+    type Response = Vec<Option<Location>>;
+
+    fn fallback_response() -> Result<Self::Response, ResponseError> {
+        Err(ResponseError::Empty)
+    }
+
+    fn handle(
+        ctx: InitActionContext,
+        params: Self::Params,
+    ) -> Result<Self::Response, ResponseError> {
+        // Positions in documents we can't handle keep their place, but never
+        // have a definition.
+        let spans: Vec<Option<Span>> = params
+            .positions
+            .into_iter()
+            .map(|pos| {
+                let file_path = parse_file_path(&pos.text_document.uri).ok()?;
+                Some(ctx.convert_pos_to_span(file_path, pos.position))
+            })
+            .collect();
+        let valid_spans: Vec<Span> = spans.iter().flatten().cloned().collect();
+
+        let mut defs = match ctx.analysis.goto_defs(&valid_spans) {
+            Ok(defs) => defs.into_iter(),
+            Err(_) => vec![None; valid_spans.len()].into_iter(),
+        };
+        let result = spans
+            .iter()
+            .map(|span| span.as_ref().and_then(|_| defs.next().flatten()))
+            .map(|def| def.as_ref().map(ls_util::rls_to_location))
+            .collect();
+        trace!("batch_goto_def: {:?}", result);
+        Ok(result)
+    }
+}
+
+impl RequestAction for Declaration {
+    type Response = Vec<Location>;
+
+    fn fallback_response() -> Result<Self::Response, ResponseError> {
+        Ok(vec![])
+    }
+
+    fn handle(
+        ctx: InitActionContext,
+        params: Self::Params,
+    ) -> Result<Self::Response, ResponseError> {
+        let file_path = parse_file_path!(&params.text_document.uri, "goto_decl")?;
+        let span = ctx.convert_pos_to_span(file_path, params.position);
+
+        match ctx.analysis.goto_decl(&span) {
+            Ok(out) => {
+                let result = vec![ls_util::rls_to_location(&out)];
+                trace!("goto_decl: {:?}", result);
+                Ok(result)
+            }
+            Err(_) => Self::fallback_response(),
+        }
+    }
+}
+
 impl RequestAction for References {
     type Response = Vec<Location>;
 
@@ -225,16 +450,52 @@ impl RequestAction for References {
             parse_file_path!(&params.text_document_position.text_document.uri, "find_all_refs")?;
         let span = ctx.convert_pos_to_span(file_path, params.text_document_position.position);
 
-        let result =
-            match ctx.analysis.find_all_refs(&span, params.context.include_declaration, false) {
-                Ok(t) => t,
-                _ => vec![],
-            };
+        let options = FindRefsOptions {
+            include_decl: params.context.include_declaration,
+            sorted: true,
+            expansion_refs: ExpansionRefs::Remap,
+            ..FindRefsOptions::default()
+        };
+        let result = match ctx.analysis.find_all_refs(&span, options) {
+            Ok(t) => t,
+            _ => vec![],
+        };
 
         Ok(result.iter().map(|item| ls_util::rls_to_location(item)).collect())
     }
 }
 
+impl RequestAction for GroupedReferences {
+    type Response = Vec<FileReferences>;
+
+    fn fallback_response() -> Result<Self::Response, ResponseError> {
+        Ok(vec![])
+    }
+
+    fn handle(
+        ctx: InitActionContext,
+        params: Self::Params,
+    ) -> Result<Self::Response, ResponseError> {
+        let file_path = parse_file_path!(
+            &params.text_document_position.text_document.uri,
+            "grouped_references"
+        )?;
+        let span = ctx.convert_pos_to_span(file_path, params.text_document_position.position);
+
+        let grouped = ctx
+            .analysis
+            .refs_grouped(&span, params.context.include_declaration)
+            .unwrap_or_else(|_| vec![]);
+        Ok(grouped
+            .into_iter()
+            .map(|(file, refs)| FileReferences {
+                uri: file_path_to_uri(&file).unwrap(),
+                ranges: refs.into_iter().map(|r| ls_util::rls_to_range(r.range)).collect(),
+            })
+            .collect())
+    }
+}
+
 impl RequestAction for Completion {
     type Response = Vec<CompletionItem>;
 
@@ -252,6 +513,20 @@ impl RequestAction for Completion {
 
         let file_path =
             parse_file_path!(&params.text_document_position.text_document.uri, "complete")?;
+        // Only Rust files (including those of `rust_file_extensions`) are
+        // completed, not, e.g., the manifest or files of unknown extensions.
+        if ctx.vfs.language(&file_path) != Some(Language::Rust) {
+            return Self::fallback_response();
+        }
+
+        // In a struct literal, offer the fields which aren't given yet, and
+        // after the path of an enum, its variants.
+        let position = params.text_document_position.position;
+        let analysis_items = field_completion::complete(&ctx, &file_path, position)
+            .or_else(|| variant_completion::complete(&ctx, &file_path, position));
+        if let Some(items) = analysis_items.filter(|items| !items.is_empty()) {
+            return Ok(items);
+        }
 
         let cache = ctx.racer_cache();
         let session = ctx.racer_session(&cache);
@@ -260,16 +535,19 @@ impl RequestAction for Completion {
         let results = racer::complete_from_file(&file_path, location, &session);
         let is_use_stmt = racer::is_use_stmt(&file_path, location, &session);
 
-        let code_completion_has_snippet_support =
-            ctx.client_capabilities.code_completion_has_snippet_support;
+        let use_snippets = ctx.client_capabilities.code_completion_has_snippet_support
+            && ctx.config.lock().unwrap().use_completion_snippets;
 
         Ok(results
             .map(|comp| {
                 let mut item = completion_item_from_racer_match(&comp);
                 if is_use_stmt && comp.mtype.is_function() {
                     item.insert_text = Some(comp.matchstr);
-                } else if code_completion_has_snippet_support {
-                    let snippet = racer::snippet_for_match(&comp, &session);
+                } else if use_snippets {
+                    let snippet = Some(&comp)
+                        .filter(|comp| comp.mtype.is_function())
+                        .and_then(|comp| function_snippet(&comp.matchstr, &comp.contextstr))
+                        .unwrap_or_else(|| racer::snippet_for_match(&comp, &session));
                     if !snippet.is_empty() {
                         item.insert_text = Some(snippet);
                         item.insert_text_format = Some(InsertTextFormat::Snippet);
@@ -295,24 +573,56 @@ impl RequestAction for DocumentHighlight {
         let file_path = parse_file_path!(&params.text_document.uri, "highlight")?;
         let span = ctx.convert_pos_to_span(file_path.clone(), params.position);
 
-        let result = ctx.analysis.find_all_refs(&span, true, false).unwrap_or_else(|_| vec![]);
+        let result = ctx.analysis.find_refs_in_file(&span, &file_path).unwrap_or_else(|_| vec![]);
+        let def_span = ctx.analysis.id(&span).and_then(|id| ctx.analysis.get_def(id)).ok();
 
         Ok(result
             .iter()
-            .filter_map(|span| {
-                if span.file == file_path {
-                    Some(lsp_data::DocumentHighlight {
-                        range: ls_util::rls_to_range(span.range),
-                        kind: Some(DocumentHighlightKind::Text),
-                    })
+            .map(|span| {
+                let is_def = def_span.as_ref().map_or(false, |def| def.span == *span);
+                let kind = if is_def {
+                    DocumentHighlightKind::Write
                 } else {
-                    None
+                    match ctx.vfs.load_line(&span.file, span.range.row_start) {
+                        Ok(line) => highlight_kind(&line, span.range),
+                        Err(_) => DocumentHighlightKind::Text,
+                    }
+                };
+                lsp_data::DocumentHighlight {
+                    range: ls_util::rls_to_range(span.range),
+                    kind: Some(kind),
                 }
             })
             .collect())
     }
 }
 
+/// Classifies a reference on `line` as a write (the target of an assignment,
+/// or borrowed mutably) or a read.
+fn highlight_kind(line: &str, range: span::Range<span::ZeroIndexed>) -> DocumentHighlightKind {
+    const ASSIGN_OPS: &[&str] =
+        &["=", "+=", "-=", "*=", "/=", "%=", "^=", "&=", "|=", "<<=", ">>="];
+
+    let start = range.col_start.0 as usize;
+    let end = range.col_end.0 as usize;
+    let before: String = line.chars().take(start).collect();
+    let after: String = line.chars().skip(end).collect();
+    let after = after.trim_start();
+
+    let is_mut_borrow =
+        before.trim_end().strip_suffix("mut").map_or(false, |rest| rest.trim_end().ends_with('&'));
+    // `==` and `=>` aren't assignments.
+    let is_assigned = ASSIGN_OPS.iter().any(|op| {
+        after.starts_with(op) && !after[op.len()..].starts_with(|c: char| c == '=' || c == '>')
+    });
+
+    if is_mut_borrow || is_assigned {
+        DocumentHighlightKind::Write
+    } else {
+        DocumentHighlightKind::Read
+    }
+}
+
 impl RequestAction for Rename {
     type Response = ResponseWithMessage<WorkspaceEdit>;
 
@@ -362,8 +672,13 @@ impl RequestAction for Rename {
             )));
         }
 
+        let options = FindRefsOptions {
+            include_decl: true,
+            force_unique_spans: true,
+            ..FindRefsOptions::default()
+        };
         let result = unwrap_or_fallback!(
-            analysis.find_all_refs(&span, true, true),
+            analysis.find_all_refs(&span, options),
             "Rename failed: error finding references"
         );
 
@@ -401,6 +716,8 @@ impl RequestAction for Rename {
 pub enum ExecuteCommandResponse {
     /// Response/client request containing workspace edits.
     ApplyEdit(ApplyWorkspaceEditParams),
+    /// The command was carried out, there is nothing else to send.
+    Done,
 }
 
 impl server::Response for ExecuteCommandResponse {
@@ -414,6 +731,7 @@ impl server::Response for ExecuteCommandResponse {
                 let request = Request::<ApplyWorkspaceEdit>::new(id, params);
                 out.request(request);
             }
+            ExecuteCommandResponse::Done => {}
         }
 
         // The formal request response is a simple ACK, though the objective
@@ -429,15 +747,34 @@ impl RequestAction for ExecuteCommand {
         Err(ResponseError::Empty)
     }
 
-    /// Currently supports "rls.applySuggestion", "rls.deglobImports".
+    /// Currently supports "rls.applySuggestion", "rls.deglobImports",
+    /// "rls.extractFunction", "rls.rebuild" and "rls.reloadAnalysis".
     fn handle(
         ctx: InitActionContext,
         params: ExecuteCommandParams,
     ) -> Result<Self::Response, ResponseError> {
+        if ctx.is_command_disabled(&params.command) {
+            return Err(ResponseError::Message(
+                ErrorCode::MethodNotFound,
+                format!("`{}` is disabled by `disabled_capabilities`", params.command),
+            ));
+        }
         if params.command.starts_with("rls.applySuggestion") {
             apply_suggestion(&params.arguments).map(ExecuteCommandResponse::ApplyEdit)
         } else if params.command.starts_with("rls.deglobImports") {
             apply_deglobs(params.arguments, &ctx).map(ExecuteCommandResponse::ApplyEdit)
+        } else if params.command.starts_with("rls.extractFunction") {
+            extract_function(&params.arguments, &ctx).map(ExecuteCommandResponse::ApplyEdit)
+        } else if params.command.starts_with("rls.rebuild") {
+            ctx.rebuild();
+            Ok(ExecuteCommandResponse::Done)
+        } else if params.command.starts_with("rls.reloadAnalysis") {
+            ctx.reload_analysis().map(|()| ExecuteCommandResponse::Done).map_err(|e| {
+                ResponseError::Message(
+                    ErrorCode::InternalError,
+                    format!("Couldn't reload the analysis data: {:?}", e),
+                )
+            })
         } else {
             debug!("Unknown command: {}", params.command);
             Err(ResponseError::Message(ErrorCode::MethodNotFound, "Unknown command".to_owned()))
@@ -445,12 +782,139 @@ impl RequestAction for ExecuteCommand {
     }
 }
 
+/// Applies the edits given by the arguments, which are pairs of a location
+/// and the text replacing it, all in the same file.
 fn apply_suggestion(args: &[serde_json::Value]) -> Result<ApplyWorkspaceEditParams, ResponseError> {
-    let location = serde_json::from_value(args[0].clone()).expect("Bad argument");
-    let new_text = serde_json::from_value(args[1].clone()).expect("Bad argument");
+    let bad_arguments = || {
+        ResponseError::Message(
+            ErrorCode::InvalidParams,
+            "Expected pairs of a location and the text replacing it".to_owned(),
+        )
+    };
+    if args.is_empty() || args.len() % 2 != 0 {
+        return Err(bad_arguments());
+    }
+
+    let mut edit: Option<WorkspaceEdit> = None;
+    for pair in args.chunks(2) {
+        let location: Location =
+            serde_json::from_value(pair[0].clone()).map_err(|_| bad_arguments())?;
+        let new_text: String =
+            serde_json::from_value(pair[1].clone()).map_err(|_| bad_arguments())?;
+        trace!("apply_suggestion {:?} {}", location, new_text);
+
+        match edit {
+            Some(WorkspaceEdit { changes: Some(ref mut changes), .. }) => {
+                let text_edit = TextEdit { range: location.range, new_text };
+                changes.entry(location.uri).or_default().push(text_edit);
+            }
+            _ => edit = Some(make_workspace_edit(location, new_text)),
+        }
+    }
+    let edit = edit.ok_or_else(bad_arguments)?;
+    Ok(ApplyWorkspaceEditParams { edit })
+}
+
+/// Extracts the statements at the location given by the argument into a
+/// function. The statements are only analysed now, as they may not be
+/// extractable after all (e.g., if they return early), which the client is
+/// then told about.
+fn extract_function(
+    args: &[serde_json::Value],
+    ctx: &InitActionContext,
+) -> Result<ApplyWorkspaceEditParams, ResponseError> {
+    let bad_arguments = || {
+        ResponseError::Message(
+            ErrorCode::InvalidParams,
+            "Expected the location of the statements to extract".to_owned(),
+        )
+    };
+    let location = args.get(0).ok_or_else(bad_arguments)?;
+    let location: Location =
+        serde_json::from_value(location.clone()).map_err(|_| bad_arguments())?;
+    trace!("extract_function {:?}", location);
+    let file_path = parse_file_path!(&location.uri, "extract_function")?;
+
+    let text = match ctx.vfs.load_file(&file_path) {
+        Ok(FileContents::Text(text)) => text,
+        _ => return Err(ResponseError::Empty),
+    };
+    let block = refactor::statement_block(&text, location.range).ok_or(ResponseError::Empty)?;
+    let locals = block_locals(ctx, &file_path, &block);
+    let edits = refactor::extract_function_edits(&text, &block, &locals).map_err(|reason| {
+        ResponseError::Message(
+            ErrorCode::InvalidParams,
+            format!("Can't extract a function: {}", reason),
+        )
+    })?;
+
+    let mut changes = HashMap::new();
+    let edits = edits.into_iter().map(|(range, new_text)| TextEdit { range, new_text }).collect();
+    changes.insert(location.uri, edits);
+    Ok(ApplyWorkspaceEditParams {
+        edit: WorkspaceEdit { changes: Some(changes), document_changes: None },
+    })
+}
+
+/// Returns the local variables used by `block`, in the order of their first
+/// use in it.
+fn block_locals(
+    ctx: &InitActionContext,
+    file_path: &Path,
+    block: &refactor::StatementBlock,
+) -> Vec<refactor::BlockLocal> {
+    lazy_static! {
+        static ref IDENT_RE: Regex = Regex::new(r"\b[A-Za-z_]\w*\b").unwrap();
+    }
+
+    let start_row = block.range.start.line as u32;
+    let end_row = block.range.end.line as u32;
+    let mut seen = vec![];
+    let mut locals = vec![];
+    for (row, line) in (start_row..).zip(block.text.lines()) {
+        for ident in IDENT_RE.find_iter(line) {
+            let col_start = line[..ident.start()].chars().count() as u32;
+            let col_end = col_start + ident.as_str().chars().count() as u32;
+            let span = Span::new(
+                span::Row::new_zero_indexed(row),
+                span::Row::new_zero_indexed(row),
+                span::Column::new_zero_indexed(col_start),
+                span::Column::new_zero_indexed(col_end),
+                file_path.to_owned(),
+            );
+            let id = match ctx.analysis.id(&span) {
+                Ok(id) if !seen.contains(&id) => id,
+                _ => continue,
+            };
+            seen.push(id);
+            let def = match ctx.analysis.get_def(id) {
+                Ok(def) if def.kind == data::DefKind::Local => def,
+                _ => continue,
+            };
 
-    trace!("apply_suggestion {:?} {}", location, new_text);
-    Ok(ApplyWorkspaceEditParams { edit: make_workspace_edit(location, new_text) })
+            let def_row = def.span.range.row_start.0;
+            let declared_inside = def.span.file == file_path && def_row >= start_row;
+            let mutable = match ctx.vfs.load_line(&def.span.file, def.span.range.row_start) {
+                Ok(def_line) => {
+                    let before: String =
+                        def_line.chars().take(def.span.range.col_start.0 as usize).collect();
+                    before.trim_end().ends_with("mut")
+                }
+                Err(_) => false,
+            };
+            let used_after = ctx.analysis.find_all_refs_by_id(id).map_or(false, |refs| {
+                refs.iter().any(|r| r.file == file_path && r.range.row_start.0 >= end_row)
+            });
+            locals.push(refactor::BlockLocal {
+                name: def.name,
+                ty: def.value,
+                declared_inside,
+                mutable,
+                used_after,
+            });
+        }
+    }
+    locals
 }
 
 fn apply_deglobs(
@@ -487,7 +951,7 @@ fn make_suggestion_fix_actions(
     params: &<CodeAction as lsp_data::request::Request>::Params,
     file_path: &Path,
     ctx: &InitActionContext,
-    code_actions_result: &mut <CodeAction as RequestAction>::Response,
+    code_actions_result: &mut Vec<Command>,
 ) {
     // Search for compiler suggestions.
     if let Some(results) = ctx.previous_build_results.lock().unwrap().get(file_path) {
@@ -509,13 +973,59 @@ fn make_suggestion_fix_actions(
     }
 }
 
+// The kinds of code actions which clients can ask for, from which the kind of
+// each action follows. Commands don't have a kind themselves, so actions are
+// only given one if the client supports code action literals.
+const QUICKFIX: &str = "quickfix";
+const REFACTOR_EXTRACT: &str = "refactor.extract";
+const REFACTOR_INLINE: &str = "refactor.inline";
+const REFACTOR_REWRITE: &str = "refactor.rewrite";
+const SOURCE_ORGANIZE_IMPORTS: &str = "source.organizeImports";
+
+/// Returns `true` if the client asked for code actions of `kind`, i.e., didn't
+/// restrict the kinds or asked for it or a more general kind (e.g.,
+/// `refactor` for `refactor.extract`).
+fn wants_action_kind(
+    params: &<CodeAction as lsp_data::request::Request>::Params,
+    kind: &str,
+) -> bool {
+    match params.context.only {
+        Some(ref kinds) => kinds.iter().any(|wanted| {
+            let wanted = wanted.as_str();
+            kind == wanted || kind.starts_with(wanted) && kind[wanted.len()..].starts_with('.')
+        }),
+        None => true,
+    }
+}
+
+/// Returns a command applying `edits` to the file of `uri`.
+fn edits_command(
+    title: String,
+    uri: &Url,
+    edits: Vec<(Range, String)>,
+    ctx: &InitActionContext,
+) -> Command {
+    let arguments = edits
+        .into_iter()
+        .flat_map(|(range, new_text)| {
+            let location = Location { uri: uri.clone(), range };
+            vec![serde_json::to_value(&location).unwrap(), serde_json::to_value(&new_text).unwrap()]
+        })
+        .collect();
+    Command {
+        title,
+        command: format!("rls.applySuggestion-{}", ctx.pid),
+        arguments: Some(arguments),
+    }
+}
+
 /// Creates `CodeAction`s for performing deglobbing when a wildcard import is found.
 /// The results are appended to `code_actions_result`.
 fn make_deglob_actions(
     params: &<CodeAction as lsp_data::request::Request>::Params,
     file_path: &Path,
     ctx: &InitActionContext,
-    code_actions_result: &mut <CodeAction as RequestAction>::Response,
+    code_actions_result: &mut Vec<Command>,
 ) {
     // Search for a glob in the line.
     if let Ok(line) = ctx.vfs.load_line(file_path, ls_util::range_to_rls(params.range).row_start) {
@@ -564,70 +1074,1035 @@ fn make_deglob_actions(
     };
 }
 
-// Ideally we'd use Rustfmt for this, but reparsing is a bit of a pain.
-fn sort_deglob_str(s: &str) -> String {
-    let mut substrings = s.split(',').map(str::trim).collect::<Vec<_>>();
-    substrings.sort_by(|a, b| {
-        use std::cmp::Ordering;
-
-        // Algorithm taken from rustfmt (`rustfmt/src/imports.rs`).
-
-        let is_upper_snake_case =
-            |s: &str| s.chars().all(|c| c.is_uppercase() || c == '_' || c.is_numeric());
-
-        // snake_case < CamelCase < UPPER_SNAKE_CASE
-        if a.starts_with(char::is_uppercase) && b.starts_with(char::is_lowercase) {
-            return Ordering::Greater;
-        }
-        if a.starts_with(char::is_lowercase) && b.starts_with(char::is_uppercase) {
-            return Ordering::Less;
-        }
-        if is_upper_snake_case(a) && !is_upper_snake_case(b) {
-            return Ordering::Greater;
-        }
-        if !is_upper_snake_case(a) && is_upper_snake_case(b) {
-            return Ordering::Less;
-        }
-        a.cmp(b)
-    });
-    substrings.join(", ")
+/// Creates `CodeAction`s for adding common derives to the type defined at the
+/// cursor. The results are appended to `code_actions_result`.
+fn make_derive_actions(
+    params: &<CodeAction as lsp_data::request::Request>::Params,
+    file_path: &Path,
+    ctx: &InitActionContext,
+    code_actions_result: &mut Vec<Command>,
+) {
+    let text = match ctx.vfs.load_file(file_path) {
+        Ok(FileContents::Text(text)) => text,
+        _ => return,
+    };
+    for edit in derive::derive_edits(&text, params.range.start.line as usize) {
+        let location = Location { uri: params.text_document.uri.clone(), range: edit.range };
+        let cmd = Command {
+            title: format!("Derive {}", edit.derive),
+            command: format!("rls.applySuggestion-{}", ctx.pid),
+            arguments: Some(vec![
+                serde_json::to_value(&location).unwrap(),
+                serde_json::to_value(&edit.new_text).unwrap(),
+            ]),
+        };
+        code_actions_result.push(cmd);
+    }
 }
 
-impl RequestAction for CodeAction {
-    type Response = Vec<Command>;
-
-    fn fallback_response() -> Result<Self::Response, ResponseError> {
-        Ok(vec![])
+/// Creates `CodeAction`s adding a `use` for each name which the diagnostics
+/// in the range say couldn't be resolved, one for each def with that name.
+/// The actions are ordered by how close the def's crate is to the file's.
+/// The results are appended to `code_actions_result`.
+fn make_import_actions(
+    params: &<CodeAction as lsp_data::request::Request>::Params,
+    file_path: &Path,
+    ctx: &InitActionContext,
+    code_actions_result: &mut Vec<Command>,
+) {
+    let mut names: Vec<&str> = params
+        .context
+        .diagnostics
+        .iter()
+        .filter_map(|diagnostic| imports::unresolved_name(&diagnostic.message))
+        .collect();
+    names.sort();
+    names.dedup();
+    if names.is_empty() {
+        return;
     }
+    let text = match ctx.vfs.load_file(file_path) {
+        Ok(FileContents::Text(text)) => text,
+        _ => return,
+    };
+    for name in names {
+        let mut paths: Vec<String> = ctx
+            .analysis
+            .search_for_id(name)
+            .unwrap_or_default()
+            .into_iter()
+            .filter_map(|id| ctx.analysis.import_paths(id, file_path).ok()?.into_iter().next())
+            .collect();
+        paths.sort_by_key(|path| (imports::proximity(path), path.clone()));
+        paths.dedup();
 
-    fn handle(
-        ctx: InitActionContext,
-        params: Self::Params,
-    ) -> Result<Self::Response, ResponseError> {
-        trace!("code_action {:?}", params);
-
-        let file_path = parse_file_path!(&params.text_document.uri, "code_action")?;
-
-        let mut cmds = vec![];
-        if ctx.build_ready() {
-            make_suggestion_fix_actions(&params, &file_path, &ctx, &mut cmds);
-        }
-        if ctx.analysis_ready() {
-            make_deglob_actions(&params, &file_path, &ctx, &mut cmds);
+        for path in paths {
+            let (position, new_text) = match imports::import_edit(&text, &path) {
+                Some(edit) => edit,
+                None => continue,
+            };
+            let location = Location {
+                uri: params.text_document.uri.clone(),
+                range: Range::new(position, position),
+            };
+            let cmd = Command {
+                title: format!("Import `{}`", path),
+                command: format!("rls.applySuggestion-{}", ctx.pid),
+                arguments: Some(vec![
+                    serde_json::to_value(&location).unwrap(),
+                    serde_json::to_value(&new_text).unwrap(),
+                ]),
+            };
+            code_actions_result.push(cmd);
         }
-        Ok(cmds)
     }
 }
 
-impl RequestAction for Formatting {
-    type Response = Vec<TextEdit>;
-
-    fn fallback_response() -> Result<Self::Response, ResponseError> {
-        Err(ResponseError::Message(
-            ErrorCode::InternalError,
-            "Reformat failed to complete successfully".into(),
-        ))
-    }
+/// Creates a `CodeAction` extracting the selected expression into a variable.
+/// The results are appended to `code_actions_result`.
+fn make_extract_actions(
+    params: &<CodeAction as lsp_data::request::Request>::Params,
+    file_path: &Path,
+    ctx: &InitActionContext,
+    code_actions_result: &mut Vec<Command>,
+) {
+    let text = match ctx.vfs.load_file(file_path) {
+        Ok(FileContents::Text(text)) => text,
+        _ => return,
+    };
+    if let Some(edits) = refactor::extract_variable_edits(&text, params.range) {
+        let title = "Extract into variable".to_owned();
+        code_actions_result.push(edits_command(title, &params.text_document.uri, edits, ctx));
+    }
+}
+
+/// Creates a `CodeAction` extracting the selected statements into a function.
+/// Whether they can be extracted is only checked when the command is executed.
+/// The results are appended to `code_actions_result`.
+fn make_extract_function_actions(
+    params: &<CodeAction as lsp_data::request::Request>::Params,
+    file_path: &Path,
+    ctx: &InitActionContext,
+    code_actions_result: &mut Vec<Command>,
+) {
+    let text = match ctx.vfs.load_file(file_path) {
+        Ok(FileContents::Text(text)) => text,
+        _ => return,
+    };
+    if refactor::statement_block(&text, params.range).is_some() {
+        let location = Location { uri: params.text_document.uri.clone(), range: params.range };
+        code_actions_result.push(Command {
+            title: "Extract into function".to_owned(),
+            command: format!("rls.extractFunction-{}", ctx.pid),
+            arguments: Some(vec![serde_json::to_value(&location).unwrap()]),
+        });
+    }
+}
+
+/// Creates a `CodeAction` replacing the uses of the variable declared by the
+/// `let` on the line of the cursor with its value, and removing the `let`.
+/// Unless the value is a path or a literal, it's only offered for a variable
+/// used once, so as not to evaluate the value more than once. The results are
+/// appended to `code_actions_result`.
+fn make_inline_actions(
+    params: &<CodeAction as lsp_data::request::Request>::Params,
+    file_path: &Path,
+    ctx: &InitActionContext,
+    code_actions_result: &mut Vec<Command>,
+) {
+    let row = params.range.start.line;
+    let line = match ctx.vfs.load_line(file_path, span::Row::new_zero_indexed(row as u32)) {
+        Ok(line) => line,
+        Err(_) => return,
+    };
+    let binding = match refactor::let_binding(&line) {
+        Some(binding) => binding,
+        None => return,
+    };
+
+    let name_start = binding.name_col as u32;
+    let name_span = Span::new(
+        span::Row::new_zero_indexed(row as u32),
+        span::Row::new_zero_indexed(row as u32),
+        span::Column::new_zero_indexed(name_start),
+        span::Column::new_zero_indexed(name_start + binding.name.chars().count() as u32),
+        file_path,
+    );
+    let uses: Vec<Span> = match ctx.analysis.id(&name_span) {
+        Ok(id) => ctx.analysis.find_all_refs_by_id(id).unwrap_or_default(),
+        Err(_) => return,
+    };
+    let uses: Vec<Span> = uses.into_iter().filter(|span| *span != name_span).collect();
+    if uses.is_empty() || uses.iter().any(|span| span.file != file_path) {
+        return;
+    }
+    if uses.len() > 1 && !refactor::is_path_or_literal(binding.value) {
+        return;
+    }
+
+    let value = refactor::inlined_value(binding.value);
+    let mut edits: Vec<(Range, String)> =
+        uses.iter().map(|span| (ls_util::rls_to_range(span.range), value.clone())).collect();
+    let let_line = Range::new(Position::new(row, 0), Position::new(row + 1, 0));
+    edits.push((let_line, String::new()));
+
+    let title = format!("Inline variable `{}`", binding.name);
+    code_actions_result.push(edits_command(title, &params.text_document.uri, edits, ctx));
+}
+
+/// Returns a code action of `kind` running `command`, if the client supports
+/// code action literals, and `command` otherwise.
+fn with_kind(command: Command, kind: &str, ctx: &InitActionContext) -> CodeActionOrCommand {
+    if !ctx.client_capabilities.code_action_literal_support {
+        return CodeActionOrCommand::Command(command);
+    }
+    CodeActionOrCommand::CodeAction(lsp_types::CodeAction {
+        title: command.title.clone(),
+        kind: Some(kind.to_owned()),
+        diagnostics: None,
+        edit: None,
+        command: Some(command),
+    })
+}
+
+/// Creates a `CodeAction` converting the `if let` on the line of the cursor
+/// into a `match`, or the `match` into an `if let`. The arm replacing the
+/// `else` matches the other variant of an enum with two variants (e.g., `None`
+/// for `Some(x)`) and any value otherwise. The results are appended to
+/// `code_actions_result`.
+fn make_if_let_match_actions(
+    params: &<CodeAction as lsp_data::request::Request>::Params,
+    file_path: &Path,
+    ctx: &InitActionContext,
+    code_actions_result: &mut Vec<Command>,
+) {
+    let text = match ctx.vfs.load_file(file_path) {
+        Ok(FileContents::Text(text)) => text,
+        _ => return,
+    };
+    let row = params.range.start.line as usize;
+    let line = match text.split('\n').nth(row) {
+        Some(line) => line,
+        None => return,
+    };
+
+    let (title, edit) = if let Some((pattern, col)) = refactor::if_let_pattern(line) {
+        let other_pattern = if_let_other_pattern(ctx, file_path, row, pattern, col);
+        let other_pattern = other_pattern.as_ref().map_or("_", String::as_str);
+        ("Convert to `match`", refactor::if_let_to_match_edit(&text, row, other_pattern))
+    } else {
+        ("Convert to `if let`", refactor::match_to_if_let_edit(&text, row))
+    };
+    if let Some(edit) = edit {
+        let uri = &params.text_document.uri;
+        code_actions_result.push(edits_command(title.to_owned(), uri, vec![edit], ctx));
+    }
+}
+
+/// Returns the pattern matching what the `pattern` of an `if let`, at (char)
+/// column `col` of line `row`, doesn't, if that's the other variant of an
+/// enum with two variants.
+fn if_let_other_pattern(
+    ctx: &InitActionContext,
+    file_path: &Path,
+    row: usize,
+    pattern: &str,
+    col: usize,
+) -> Option<String> {
+    lazy_static! {
+        static ref VARIANT_RE: Regex = Regex::new(r"^(?:\w+::)*(?P<name>\w+)").unwrap();
+    }
+
+    let name = VARIANT_RE.captures(pattern)?.name("name")?;
+    let col_start = (col + pattern[..name.start()].chars().count()) as u32;
+    let span = Span::new(
+        span::Row::new_zero_indexed(row as u32),
+        span::Row::new_zero_indexed(row as u32),
+        span::Column::new_zero_indexed(col_start),
+        span::Column::new_zero_indexed(col_start + name.as_str().chars().count() as u32),
+        file_path,
+    );
+    let variant = ctx.analysis.id(&span).and_then(|id| ctx.analysis.get_def(id)).ok()?;
+    let variants = ctx.analysis.enum_variants(variant.parent?).ok()?;
+    refactor::other_variant_pattern(pattern, &variants)
+}
+
+/// Creates a `CodeAction` sorting the `use`s of the file, if they aren't. The
+/// results are appended to `code_actions_result`.
+fn make_organize_imports_actions(
+    params: &<CodeAction as lsp_data::request::Request>::Params,
+    file_path: &Path,
+    ctx: &InitActionContext,
+    code_actions_result: &mut Vec<Command>,
+) {
+    let text = match ctx.vfs.load_file(file_path) {
+        Ok(FileContents::Text(text)) => text,
+        _ => return,
+    };
+    let edits = imports::organize_edits(&text);
+    if !edits.is_empty() {
+        let title = "Organize imports".to_owned();
+        code_actions_result.push(edits_command(title, &params.text_document.uri, edits, ctx));
+    }
+}
+
+impl RequestAction for SemanticTokensFull {
+    type Response = SemanticTokens;
+
+    fn fallback_response() -> Result<Self::Response, ResponseError> {
+        Err(ResponseError::Empty)
+    }
+
+    fn handle(
+        ctx: InitActionContext,
+        params: Self::Params,
+    ) -> Result<Self::Response, ResponseError> {
+        let file_path = parse_file_path!(&params.text_document.uri, "semantic_tokens")?;
+        let data = semantic_tokens::semantic_tokens(&ctx, &file_path)?;
+        let result_id = ctx.semantic_tokens.store(&file_path, data.clone());
+        Ok(SemanticTokens { result_id: Some(result_id), data })
+    }
+}
+
+impl RequestAction for SemanticTokensFullDelta {
+    type Response = SemanticTokensFullDeltaResult;
+
+    fn fallback_response() -> Result<Self::Response, ResponseError> {
+        Err(ResponseError::Empty)
+    }
+
+    fn handle(
+        ctx: InitActionContext,
+        params: Self::Params,
+    ) -> Result<Self::Response, ResponseError> {
+        let file_path = parse_file_path!(&params.text_document.uri, "semantic_tokens_delta")?;
+        let data = semantic_tokens::semantic_tokens(&ctx, &file_path)?;
+        let previous = ctx.semantic_tokens.get(&file_path, &params.previous_result_id);
+        let result_id = ctx.semantic_tokens.store(&file_path, data.clone());
+
+        // We can only send edits against the tokens the client has.
+        Ok(match previous {
+            Some(previous) => SemanticTokensFullDeltaResult::Delta(SemanticTokensDelta {
+                result_id: Some(result_id),
+                edits: semantic_tokens::diff(&previous, &data),
+            }),
+            None => SemanticTokensFullDeltaResult::Tokens(SemanticTokens {
+                result_id: Some(result_id),
+                data,
+            }),
+        })
+    }
+}
+
+impl RequestAction for CallHierarchyPrepare {
+    type Response = Option<Vec<CallHierarchyItem>>;
+
+    fn fallback_response() -> Result<Self::Response, ResponseError> {
+        Ok(None)
+    }
+
+    fn handle(
+        ctx: InitActionContext,
+        params: Self::Params,
+    ) -> Result<Self::Response, ResponseError> {
+        let file_path = parse_file_path!(&params.text_document.uri, "prepare_call_hierarchy")?;
+        let span = ctx.convert_pos_to_span(file_path, params.position);
+
+        // Works on the name of a function as well as on a call to it.
+        let item = ctx.analysis.id(&span).ok().and_then(|id| call_hierarchy_item(&ctx, id));
+        Ok(item.map(|item| vec![item]))
+    }
+}
+
+impl RequestAction for CallHierarchyIncomingCalls {
+    type Response = Option<Vec<CallHierarchyIncomingCall>>;
+
+    fn fallback_response() -> Result<Self::Response, ResponseError> {
+        Ok(None)
+    }
+
+    fn handle(
+        ctx: InitActionContext,
+        params: Self::Params,
+    ) -> Result<Self::Response, ResponseError> {
+        let id = call_hierarchy_item_id(&ctx, &params.item)?;
+        let callers = ctx.analysis.callers_of(id).unwrap_or_else(|_| vec![]);
+
+        Ok(Some(
+            group_calls(&ctx, callers)
+                .into_iter()
+                .map(|(from, from_ranges)| CallHierarchyIncomingCall { from, from_ranges })
+                .collect(),
+        ))
+    }
+}
+
+impl RequestAction for CallHierarchyOutgoingCalls {
+    type Response = Option<Vec<CallHierarchyOutgoingCall>>;
+
+    fn fallback_response() -> Result<Self::Response, ResponseError> {
+        Ok(None)
+    }
+
+    fn handle(
+        ctx: InitActionContext,
+        params: Self::Params,
+    ) -> Result<Self::Response, ResponseError> {
+        let id = call_hierarchy_item_id(&ctx, &params.item)?;
+        let callees = ctx.analysis.callees_of(id).unwrap_or_else(|_| vec![]);
+
+        Ok(Some(
+            group_calls(&ctx, callees)
+                .into_iter()
+                .map(|(to, from_ranges)| CallHierarchyOutgoingCall { to, from_ranges })
+                .collect(),
+        ))
+    }
+}
+
+/// Returns the call hierarchy item for the function or method with `id`.
+///
+/// Save-analysis only records the span of a function's name, so that is used
+/// for both the range and the selection range.
+fn call_hierarchy_item(ctx: &InitActionContext, id: Id) -> Option<CallHierarchyItem> {
+    let def = ctx.analysis.get_def(id).ok()?;
+    if def.kind != data::DefKind::Function && def.kind != data::DefKind::Method {
+        return None;
+    }
+
+    let range = ls_util::rls_to_range(def.span.range);
+    Some(CallHierarchyItem {
+        name: def.name,
+        kind: source_kind_from_def_kind(def.kind),
+        detail: Some(def.value).filter(|value| !value.is_empty()),
+        uri: file_path_to_uri(&def.span.file).ok()?,
+        range,
+        selection_range: range,
+    })
+}
+
+/// Finds the id of the function or method of an item returned earlier.
+fn call_hierarchy_item_id(
+    ctx: &InitActionContext,
+    item: &CallHierarchyItem,
+) -> Result<Id, ResponseError> {
+    let file_path = parse_file_path!(&item.uri, "call_hierarchy")?;
+    let span = Span::from_range(ls_util::range_to_rls(item.selection_range), file_path);
+    ctx.analysis.id(&span).map_err(|_| ResponseError::Empty)
+}
+
+/// Groups calls by the function or method at the other end, in order of the
+/// first call to each.
+fn group_calls(
+    ctx: &InitActionContext,
+    calls: Vec<(Id, Span)>,
+) -> Vec<(CallHierarchyItem, Vec<Range>)> {
+    let mut grouped: Vec<(Id, Vec<Range>)> = vec![];
+    for (id, span) in calls {
+        let range = ls_util::rls_to_range(span.range);
+        match grouped.iter_mut().find(|(other, _)| *other == id) {
+            Some((_, ranges)) => ranges.push(range),
+            None => grouped.push((id, vec![range])),
+        }
+    }
+
+    grouped
+        .into_iter()
+        .filter_map(|(id, ranges)| Some((call_hierarchy_item(ctx, id)?, ranges)))
+        .collect()
+}
+
+impl RequestAction for TypeHierarchyPrepare {
+    type Response = Option<Vec<TypeHierarchyItem>>;
+
+    fn fallback_response() -> Result<Self::Response, ResponseError> {
+        Ok(None)
+    }
+
+    fn handle(
+        ctx: InitActionContext,
+        params: Self::Params,
+    ) -> Result<Self::Response, ResponseError> {
+        let file_path = parse_file_path!(&params.text_document.uri, "prepare_type_hierarchy")?;
+        let span = ctx.convert_pos_to_span(file_path, params.position);
+
+        let item = ctx.analysis.id(&span).ok().and_then(|id| type_hierarchy_item(&ctx, id));
+        Ok(item.map(|item| vec![item]))
+    }
+}
+
+impl RequestAction for TypeHierarchySupertypes {
+    type Response = Option<Vec<TypeHierarchyItem>>;
+
+    fn fallback_response() -> Result<Self::Response, ResponseError> {
+        Ok(None)
+    }
+
+    fn handle(
+        ctx: InitActionContext,
+        params: Self::Params,
+    ) -> Result<Self::Response, ResponseError> {
+        let id = type_hierarchy_item_id(&ctx, &params.item)?;
+        let mut ids: Vec<Id> =
+            ctx.analysis.supertypes(id).unwrap_or_default().into_iter().map(|(id, _)| id).collect();
+
+        // A type which derefs to another one gets its methods, so the target
+        // is a supertype too. Only the direct target is returned, so a cycle
+        // of `Deref` impls is only followed as far as the client expands it.
+        for (trait_id, impl_span) in ctx.analysis.implemented_traits(id).unwrap_or_default() {
+            let is_deref = ctx
+                .analysis
+                .get_def(trait_id)
+                .map(|def| def.qualname.ends_with("ops::deref::Deref"))
+                .unwrap_or(false);
+            if is_deref {
+                let target = ctx.analysis.deref_target(&impl_span).ok();
+                ids.extend(target.filter(|target| *target != id));
+            }
+        }
+
+        Ok(Some(ids.into_iter().filter_map(|id| type_hierarchy_item(&ctx, id)).collect()))
+    }
+}
+
+impl RequestAction for TypeHierarchySubtypes {
+    type Response = Option<Vec<TypeHierarchyItem>>;
+
+    fn fallback_response() -> Result<Self::Response, ResponseError> {
+        Ok(None)
+    }
+
+    fn handle(
+        ctx: InitActionContext,
+        params: Self::Params,
+    ) -> Result<Self::Response, ResponseError> {
+        let id = type_hierarchy_item_id(&ctx, &params.item)?;
+        let subtypes = ctx.analysis.subtypes(id).unwrap_or_default();
+        Ok(Some(subtypes.into_iter().filter_map(|(id, _)| type_hierarchy_item(&ctx, id)).collect()))
+    }
+}
+
+/// Returns the type hierarchy item for the struct, enum, union or trait with
+/// `id`. As for call hierarchy items, the span of the name is used for both
+/// ranges.
+fn type_hierarchy_item(ctx: &InitActionContext, id: Id) -> Option<TypeHierarchyItem> {
+    let def = ctx.analysis.get_def(id).ok()?;
+    match def.kind {
+        data::DefKind::Struct
+        | data::DefKind::Enum
+        | data::DefKind::Union
+        | data::DefKind::Trait => {}
+        _ => return None,
+    }
+
+    let range = ls_util::rls_to_range(def.span.range);
+    Some(TypeHierarchyItem {
+        name: def.name,
+        kind: source_kind_from_def_kind(def.kind),
+        detail: Some(def.value).filter(|value| !value.is_empty()),
+        uri: file_path_to_uri(&def.span.file).ok()?,
+        range,
+        selection_range: range,
+    })
+}
+
+/// Finds the id of the type or trait of an item returned earlier.
+fn type_hierarchy_item_id(
+    ctx: &InitActionContext,
+    item: &TypeHierarchyItem,
+) -> Result<Id, ResponseError> {
+    let file_path = parse_file_path!(&item.uri, "type_hierarchy")?;
+    let span = Span::from_range(ls_util::range_to_rls(item.selection_range), file_path);
+    ctx.analysis.id(&span).map_err(|_| ResponseError::Empty)
+}
+
+impl RequestAction for QualifiedPath {
+    type Response = Vec<String>;
+
+    fn fallback_response() -> Result<Self::Response, ResponseError> {
+        Ok(vec![])
+    }
+
+    fn handle(
+        ctx: InitActionContext,
+        params: Self::Params,
+    ) -> Result<Self::Response, ResponseError> {
+        let file_path = parse_file_path!(&params.text_document.uri, "qualified_path")?;
+        let span = ctx.convert_pos_to_span(file_path, params.position);
+
+        Ok(ctx.analysis.qualified_path(&span).unwrap_or_else(|_| vec![]))
+    }
+}
+
+impl RequestAction for ImplementMissingItems {
+    type Response = Option<WorkspaceEdit>;
+
+    fn fallback_response() -> Result<Self::Response, ResponseError> {
+        Ok(None)
+    }
+
+    fn handle(
+        ctx: InitActionContext,
+        params: Self::Params,
+    ) -> Result<Self::Response, ResponseError> {
+        let file_path = parse_file_path!(&params.text_document.uri, "implement_missing_items")?;
+        Ok(trait_stubs::missing_items_edit(&ctx, &file_path, params.position))
+    }
+}
+
+impl RequestAction for ActiveCfgs {
+    type Response = Vec<CrateCfgs>;
+
+    fn fallback_response() -> Result<Self::Response, ResponseError> {
+        Ok(vec![])
+    }
+
+    fn handle(
+        ctx: InitActionContext,
+        params: Self::Params,
+    ) -> Result<Self::Response, ResponseError> {
+        let file_path = parse_file_path!(&params.text_document.uri, "active_cfgs")?;
+        Ok(ctx.build_queue.active_cfgs(&file_path))
+    }
+}
+
+impl RequestAction for Status {
+    type Response = StatusParams;
+
+    fn fallback_response() -> Result<Self::Response, ResponseError> {
+        Err(ResponseError::Empty)
+    }
+
+    fn handle(
+        ctx: InitActionContext,
+        _params: Self::Params,
+    ) -> Result<Self::Response, ResponseError> {
+        Ok(ctx.status.current())
+    }
+}
+
+impl RequestAction for AnalysisFreshness {
+    type Response = Vec<FileFreshness>;
+
+    fn fallback_response() -> Result<Self::Response, ResponseError> {
+        Err(ResponseError::Empty)
+    }
+
+    fn handle(
+        ctx: InitActionContext,
+        _params: Self::Params,
+    ) -> Result<Self::Response, ResponseError> {
+        Ok(ctx
+            .idle
+            .open_files()
+            .into_iter()
+            .filter_map(|path| {
+                let fresh = ctx.freshness.is_fresh(&ctx.vfs, &path);
+                Some(FileFreshness { uri: file_path_to_uri(&path).ok()?, fresh })
+            })
+            .collect())
+    }
+}
+
+impl RequestAction for CrateGraph {
+    type Response = CrateGraphResult;
+
+    fn fallback_response() -> Result<Self::Response, ResponseError> {
+        Err(ResponseError::Empty)
+    }
+
+    fn handle(
+        ctx: InitActionContext,
+        params: Self::Params,
+    ) -> Result<Self::Response, ResponseError> {
+        let project = ctx.project_model().map_err(|e| {
+            ResponseError::Message(
+                ErrorCode::InternalError,
+                format!("Couldn't load the project model: {}", e),
+            )
+        })?;
+        let graph = crate_graph::crate_graph(&project);
+        match params.format.as_deref() {
+            None | Some("json") => Ok(CrateGraphResult::Graph(graph)),
+            Some("dot") => Ok(CrateGraphResult::Dot(crate_graph::to_dot(&graph))),
+            Some(format) => Err(ResponseError::Message(
+                ErrorCode::InvalidParams,
+                format!("Unknown crate graph format `{}`", format),
+            )),
+        }
+    }
+}
+
+impl RequestAction for DeadCode {
+    type Response = DeadCodeResult;
+
+    fn fallback_response() -> Result<Self::Response, ResponseError> {
+        Err(ResponseError::Empty)
+    }
+
+    fn handle(
+        ctx: InitActionContext,
+        _params: Self::Params,
+    ) -> Result<Self::Response, ResponseError> {
+        let (excludes, tests_analyzed) = {
+            let config = ctx.config.lock().unwrap();
+            (config.analysis_excludes(&ctx.current_project), config.cfg_test || config.all_targets)
+        };
+        let load_text = |path: &Path| match ctx.vfs.load_file(path) {
+            Ok(FileContents::Text(text)) => Some(text),
+            _ => None,
+        };
+
+        let defs =
+            ctx.analysis.unreferenced_defs(&ctx.current_project, &load_text).map_err(|e| {
+                ResponseError::Message(
+                    ErrorCode::InternalError,
+                    format!("Couldn't find the unreferenced items: {:?}", e),
+                )
+            })?;
+        let mut texts = HashMap::new();
+        let defs: Vec<_> = defs
+            .into_iter()
+            .filter(|def| !excludes.is_excluded(&def.span.file))
+            .filter(|def| {
+                let text =
+                    texts.entry(def.span.file.clone()).or_insert_with(|| load_text(&def.span.file));
+                text.as_ref().map_or(true, |text| {
+                    !dead_code::is_test_fn(text, def.span.range.row_start.0 as usize)
+                })
+            })
+            .collect();
+
+        // Without the test code in the analysis data, items only used by it
+        // look unused, so tell them apart by their names.
+        let test_texts: Vec<String> = if tests_analyzed {
+            vec![]
+        } else {
+            let files = ctx.file_to_crates.lock().unwrap();
+            files
+                .keys()
+                .filter(|path| path.starts_with(&ctx.current_project))
+                .filter_map(|path| load_text(path))
+                .collect()
+        };
+        let test_regions: Vec<&str> =
+            test_texts.iter().flat_map(|text| dead_code::cfg_test_regions(text)).collect();
+
+        let mut result = DeadCodeResult::default();
+        for def in defs {
+            let used_in_tests = dead_code::is_mentioned(&test_regions, &def.name);
+            let symbol = symbol_information(&ctx.analysis, def);
+            if used_in_tests {
+                result.used_in_tests.push(symbol);
+            } else {
+                result.unused.push(symbol);
+            }
+        }
+        Ok(result)
+    }
+}
+
+impl RequestAction for ExplainError {
+    type Response = String;
+
+    fn fallback_response() -> Result<Self::Response, ResponseError> {
+        Err(ResponseError::Empty)
+    }
+
+    fn handle(
+        ctx: InitActionContext,
+        params: Self::Params,
+    ) -> Result<Self::Response, ResponseError> {
+        Ok(ctx.explanations.explain(&params.code, explain::rustc_explain))
+    }
+}
+
+/// Only the types of definitions are known to the analysis, so this handles a
+/// selected name or the whole initializer of a `let` binding. For any other
+/// selection an error is returned, rather than an empty result.
+impl RequestAction for TypeOfSelection {
+    type Response = String;
+
+    fn fallback_response() -> Result<Self::Response, ResponseError> {
+        Err(ResponseError::Message(
+            ErrorCode::InternalError,
+            "Could not infer the type of the selection".into(),
+        ))
+    }
+
+    fn handle(
+        ctx: InitActionContext,
+        params: Self::Params,
+    ) -> Result<Self::Response, ResponseError> {
+        let file_path = parse_file_path!(&params.text_document.uri, "type_of_selection")?;
+        let span = Span::from_range(ls_util::range_to_rls(params.range), file_path);
+        let text = ctx.vfs.load_span(span.clone()).map_err(|e| {
+            ResponseError::Message(ErrorCode::InvalidParams, format!("Invalid selection: {}", e))
+        })?;
+
+        let expr = text.trim();
+        if !is_complete_expr(expr) {
+            return Err(ResponseError::Message(
+                ErrorCode::InvalidParams,
+                format!("`{}` is not a complete expression", expr),
+            ));
+        }
+
+        // The analysis only knows about the types of definitions.
+        let ty = ctx
+            .analysis
+            .show_type(&span)
+            .ok()
+            .filter(|ty| !ty.is_empty())
+            .or_else(|| let_binding_type(&ctx, &span));
+        trace!("type_of_selection: {:?} {:?}", expr, ty);
+
+        ty.ok_or_else(|| {
+            ResponseError::Message(
+                ErrorCode::InternalError,
+                format!(
+                    "Could not infer the type of `{}`, only names and `let` initializers are \
+                     supported",
+                    expr
+                ),
+            )
+        })
+    }
+}
+
+impl RequestAction for InlayHints {
+    type Response = Vec<InlayHint>;
+
+    fn fallback_response() -> Result<Self::Response, ResponseError> {
+        Ok(vec![])
+    }
+
+    fn handle(
+        ctx: InitActionContext,
+        params: Self::Params,
+    ) -> Result<Self::Response, ResponseError> {
+        let file_path = parse_file_path!(&params.text_document.uri, "inlay_hints")?;
+        Ok(inlay_hints(&ctx, &file_path, ls_util::range_to_rls(params.range)))
+    }
+}
+
+/// Returns type hints for the `let` bindings on the lines of `range` which
+/// bind a single name and have no type annotation, e.g., `: i32` after `x` in
+/// `let x = 42;`. Other patterns are skipped.
+fn inlay_hints(
+    ctx: &InitActionContext,
+    file_path: &Path,
+    range: span::Range<span::ZeroIndexed>,
+) -> Vec<InlayHint> {
+    lazy_static! {
+        static ref LET_RE: Regex =
+            Regex::new(r"\blet\s+(mut\s+)?(?P<name>[A-Za-z_]\w*)\s*(?P<annotation>:)?").unwrap();
+    }
+
+    let mut hints = vec![];
+    for row in range.row_start.0..=range.row_end.0 {
+        let row = span::Row::new_zero_indexed(row);
+        let line = match ctx.vfs.load_line(file_path, row) {
+            Ok(line) => line,
+            Err(_) => break,
+        };
+
+        for captures in LET_RE.captures_iter(&line) {
+            if captures.name("annotation").is_some() {
+                continue;
+            }
+            let name = captures.name("name").unwrap();
+            let col_start = line[..name.start()].chars().count() as u32;
+            let col_end = col_start + name.as_str().chars().count() as u32;
+            let span = Span::new(
+                row,
+                row,
+                span::Column::new_zero_indexed(col_start),
+                span::Column::new_zero_indexed(col_end),
+                file_path.to_owned(),
+            );
+
+            // The name may not be a binding at all, e.g., `None` in
+            // `if let None = ..`, or the match may be in a comment.
+            let def = match ctx.analysis.id(&span).and_then(|id| ctx.analysis.get_def(id)) {
+                Ok(def) if def.kind == data::DefKind::Local => def,
+                _ => continue,
+            };
+            // Closure types can't be written down.
+            if def.value.is_empty() || def.value.contains("[closure") {
+                continue;
+            }
+
+            hints.push(InlayHint {
+                position: ls_util::rls_to_position(span.range.end()),
+                label: format!(": {}", def.value),
+            });
+        }
+    }
+    hints
+}
+
+/// Checks (heuristically) whether `expr` could be a complete expression, i.e.,
+/// it is not empty, its delimiters are balanced and it neither starts nor ends
+/// with a binary operator.
+fn is_complete_expr(expr: &str) -> bool {
+    let mut delims = Vec::new();
+    for c in expr.chars() {
+        match c {
+            '(' | '[' | '{' => delims.push(c),
+            ')' | ']' | '}' => {
+                let open = match c {
+                    ')' => '(',
+                    ']' => '[',
+                    _ => '{',
+                };
+                if delims.pop() != Some(open) {
+                    return false;
+                }
+            }
+            _ => {}
+        }
+    }
+
+    let (first, last) = match (expr.chars().next(), expr.chars().last()) {
+        (Some(first), Some(last)) => (first, last),
+        _ => return false,
+    };
+    delims.is_empty() && !"+/%|^=<>,.:;".contains(first) && !"+-*/%&|^=<>,.:".contains(last)
+}
+
+/// If `span` covers the initializer of a `let` binding with a simple pattern,
+/// e.g., `let foo = <span>;`, returns the type of the binding.
+fn let_binding_type(ctx: &InitActionContext, span: &Span) -> Option<String> {
+    lazy_static! {
+        static ref LET_RE: Regex =
+            Regex::new(r"let\s+(mut\s+)?(?P<name>\w+)\s*(:[^=]*)?=\s*$").unwrap();
+    }
+
+    let line = ctx.vfs.load_line(&span.file, span.range.row_start).ok()?;
+    let col_start = span.range.col_start.0 as usize;
+    let prefix: String = line.chars().take(col_start).collect();
+    let name = LET_RE.captures(&prefix)?.name("name")?;
+
+    // The initializer must be followed by the end of the statement.
+    let end_line = ctx.vfs.load_line(&span.file, span.range.row_end).ok()?;
+    let suffix: String = end_line.chars().skip(span.range.col_end.0 as usize).collect();
+    if !suffix.trim_start().starts_with(';') {
+        return None;
+    }
+
+    let name_start = prefix[..name.start()].chars().count() as u32;
+    let name_end = name_start + name.as_str().chars().count() as u32;
+    let name_span = Span::new(
+        span.range.row_start,
+        span.range.row_start,
+        span::Column::new_zero_indexed(name_start),
+        span::Column::new_zero_indexed(name_end),
+        span.file.clone(),
+    );
+    ctx.analysis.show_type(&name_span).ok().filter(|ty| !ty.is_empty())
+}
+
+// Ideally we'd use Rustfmt for this, but reparsing is a bit of a pain.
+fn sort_deglob_str(s: &str) -> String {
+    let mut substrings = s.split(',').map(str::trim).collect::<Vec<_>>();
+    substrings.sort_by(|a, b| {
+        use std::cmp::Ordering;
+
+        // Algorithm taken from rustfmt (`rustfmt/src/imports.rs`).
+
+        let is_upper_snake_case =
+            |s: &str| s.chars().all(|c| c.is_uppercase() || c == '_' || c.is_numeric());
+
+        // snake_case < CamelCase < UPPER_SNAKE_CASE
+        if a.starts_with(char::is_uppercase) && b.starts_with(char::is_lowercase) {
+            return Ordering::Greater;
+        }
+        if a.starts_with(char::is_lowercase) && b.starts_with(char::is_uppercase) {
+            return Ordering::Less;
+        }
+        if is_upper_snake_case(a) && !is_upper_snake_case(b) {
+            return Ordering::Greater;
+        }
+        if !is_upper_snake_case(a) && is_upper_snake_case(b) {
+            return Ordering::Less;
+        }
+        a.cmp(b)
+    });
+    substrings.join(", ")
+}
+
+impl RequestAction for CodeAction {
+    type Response = Vec<CodeActionOrCommand>;
+
+    fn fallback_response() -> Result<Self::Response, ResponseError> {
+        Ok(vec![])
+    }
+
+    fn handle(
+        ctx: InitActionContext,
+        params: Self::Params,
+    ) -> Result<Self::Response, ResponseError> {
+        trace!("code_action {:?}", params);
+
+        let file_path = parse_file_path!(&params.text_document.uri, "code_action")?;
+
+        let wants = |kind| wants_action_kind(&params, kind);
+        let mut cmds = vec![];
+        let mut inline_cmds = vec![];
+        if ctx.build_ready() && wants(QUICKFIX) {
+            make_suggestion_fix_actions(&params, &file_path, &ctx, &mut cmds);
+        }
+        if ctx.analysis_ready() {
+            if wants(REFACTOR_REWRITE) {
+                make_deglob_actions(&params, &file_path, &ctx, &mut cmds);
+            }
+            if wants(QUICKFIX) {
+                make_import_actions(&params, &file_path, &ctx, &mut cmds);
+            }
+            if wants(REFACTOR_EXTRACT) {
+                make_extract_function_actions(&params, &file_path, &ctx, &mut cmds);
+            }
+            if wants(REFACTOR_INLINE) {
+                make_inline_actions(&params, &file_path, &ctx, &mut inline_cmds);
+            }
+        }
+        if wants(REFACTOR_REWRITE) {
+            make_derive_actions(&params, &file_path, &ctx, &mut cmds);
+            make_if_let_match_actions(&params, &file_path, &ctx, &mut cmds);
+        }
+        if wants(REFACTOR_EXTRACT) {
+            make_extract_actions(&params, &file_path, &ctx, &mut cmds);
+        }
+        if wants(SOURCE_ORGANIZE_IMPORTS) {
+            make_organize_imports_actions(&params, &file_path, &ctx, &mut cmds);
+        }
+
+        let mut actions: Self::Response = cmds
+            .into_iter()
+            .map(CodeActionOrCommand::Command)
+            .chain(inline_cmds.into_iter().map(|cmd| with_kind(cmd, REFACTOR_INLINE, &ctx)))
+            .collect();
+        actions.retain(|action| {
+            let command = match action {
+                CodeActionOrCommand::Command(command) => Some(command),
+                CodeActionOrCommand::CodeAction(action) => action.command.as_ref(),
+            };
+            command.map_or(true, |command| !ctx.is_command_disabled(&command.command))
+        });
+        Ok(actions)
+    }
+}
+
+impl RequestAction for Formatting {
+    type Response = Vec<TextEdit>;
+
+    fn fallback_response() -> Result<Self::Response, ResponseError> {
+        Err(ResponseError::Message(
+            ErrorCode::InternalError,
+            "Reformat failed to complete successfully".into(),
+        ))
+    }
 
     fn handle(
         ctx: InitActionContext,
@@ -655,6 +2130,34 @@ impl RequestAction for RangeFormatting {
     }
 }
 
+impl RequestAction for OnTypeFormatting {
+    type Response = Vec<TextEdit>;
+
+    fn fallback_response() -> Result<Self::Response, ResponseError> {
+        Ok(vec![])
+    }
+
+    fn handle(
+        ctx: InitActionContext,
+        params: Self::Params,
+    ) -> Result<Self::Response, ResponseError> {
+        let path = parse_file_path!(&params.text_document.uri, "on_type_formatting")?;
+        let text = match ctx.vfs.load_file(&path) {
+            Ok(FileContents::Text(text)) => text,
+            _ => return Ok(vec![]),
+        };
+        let (start, end) = match format::on_type_lines(&text, params.position, &params.ch) {
+            Some(lines) => lines,
+            None => return Ok(vec![]),
+        };
+
+        let range = Range { start: Position::new(start, 0), end: Position::new(end + 1, 0) };
+        // Code is often incomplete while it's being typed, in which case
+        // Rustfmt fails and it's left alone.
+        Ok(reformat(&params.text_document, Some(range), &params.options, &ctx).unwrap_or_default())
+    }
+}
+
 fn reformat(
     doc: &TextDocumentIdentifier,
     selection: Option<Range>,
@@ -690,15 +2193,12 @@ fn reformat(
     if !config.was_set().tab_spaces() {
         config.set().tab_spaces(opts.tab_size as usize);
     }
-    if !config.was_set().edition() {
+    // An edition set in the RLS config has already been applied by `fmt_config`.
+    let edition_configured = ctx.config.lock().unwrap().rustfmt_edition.is_some();
+    if !config.was_set().edition() && !edition_configured {
         match ctx.file_edition(path.clone()) {
             Some(edition) => {
-                let edition = match edition {
-                    Edition::Edition2015 => RustfmtEdition::Edition2015,
-                    Edition::Edition2018 => RustfmtEdition::Edition2018,
-                    Edition::Edition2021 => RustfmtEdition::Edition2021,
-                    Edition::Edition2024 => RustfmtEdition::Edition2024,
-                };
+                let edition = RustfmtEdition::from(edition);
                 config.set().edition(edition);
                 trace!("Detected edition {:?} for file `{}`", edition, path.display());
             }
@@ -713,20 +2213,20 @@ fn reformat(
         }
     }
 
-    if let Some(r) = selection {
-        let range_of_rls = ls_util::range_to_rls(r).one_indexed();
-        let range =
-            RustfmtRange::new(range_of_rls.row_start.0 as usize, range_of_rls.row_end.0 as usize);
-        let mut ranges = HashMap::new();
-        ranges.insert(FileName::Stdin, vec![range]);
-        let file_lines = FileLines::from_ranges(ranges);
-        config.set().file_lines(file_lines);
-    };
-
-    let text_edits = ctx
-        .formatter()
-        .calc_text_edits(input, config)
-        .map_err(|msg| ResponseError::Message(ErrorCode::InternalError, msg.to_string()))?;
+    let formatter = ctx.formatter();
+    let text_edits = match selection {
+        Some(r) => {
+            // A selection ending at the start of a line doesn't include it.
+            let end = if r.end.character == 0 && r.end.line > r.start.line {
+                r.end.line - 1
+            } else {
+                r.end.line
+            };
+            formatter.calc_range_text_edits(input, config, r.start.line, end)
+        }
+        None => formatter.calc_text_edits(input, config),
+    }
+    .map_err(|msg| ResponseError::Message(ErrorCode::InternalError, msg.to_string()))?;
 
     // Note that we don't need to update the VFS, the client echos back the
     // change to us when it applies the returned `TextEdit`.
@@ -769,6 +2269,73 @@ pub(crate) fn from_racer_coord(
     (coord.row, coord.col)
 }
 
+/// Builds a snippet calling the function named `name` with the given
+/// `signature`, with a placeholder for each parameter other than `self`, e.g.,
+/// `foo(${1:x}, ${2:y})` for `pub fn foo(&self, x: u32, y: u32)`.
+fn function_snippet(name: &str, signature: &str) -> Option<String> {
+    let decl = format!("fn {}", name);
+    let after_name = &signature[signature.find(&decl)? + decl.len()..];
+
+    // Skip any generic parameters, which may contain parentheses themselves.
+    let mut depth = 0;
+    let mut params_start = None;
+    let mut prev = ' ';
+    for (i, c) in after_name.char_indices() {
+        match c {
+            '<' => depth += 1,
+            '>' if prev != '-' => depth -= 1,
+            '(' if depth == 0 => {
+                params_start = Some(i + 1);
+                break;
+            }
+            _ => {}
+        }
+        prev = c;
+    }
+    let params = &after_name[params_start?..];
+
+    let mut placeholders = vec![];
+    for param in split_params(params)? {
+        let pattern = param.split(':').next().unwrap_or("").trim();
+        let is_self = pattern.split_whitespace().last().map(|p| p.trim_start_matches('&'));
+        if pattern.is_empty() || is_self == Some("self") {
+            continue;
+        }
+        let pattern = pattern.trim_start_matches("mut ").trim();
+        let escaped = pattern.replace('\\', "\\\\").replace('$', "\\$").replace('}', "\\}");
+        placeholders.push(format!("${{{}:{}}}", placeholders.len() + 1, escaped));
+    }
+    Some(format!("{}({})", name, placeholders.join(", ")))
+}
+
+/// Splits the parameters of a function (everything after the opening
+/// parenthesis) at the commas which aren't nested in brackets or generics.
+/// Returns `None` if the closing parenthesis is missing.
+fn split_params(params: &str) -> Option<Vec<&str>> {
+    let mut result = vec![];
+    let mut depth = 0;
+    let mut start = 0;
+    let mut prev = ' ';
+    for (i, c) in params.char_indices() {
+        match c {
+            '(' | '[' | '{' | '<' => depth += 1,
+            ')' if depth == 0 => {
+                result.push(&params[start..i]);
+                return Some(result);
+            }
+            ')' | ']' | '}' => depth -= 1,
+            '>' if prev != '-' => depth -= 1,
+            ',' if depth == 0 => {
+                result.push(&params[start..i]);
+                start = i + 1;
+            }
+            _ => {}
+        }
+        prev = c;
+    }
+    None
+}
+
 fn pos_to_racer_location(pos: Position) -> racer::Location {
     let pos = ls_util::position_to_rls(pos);
     racer::Location::Coords(racer_coord(pos.row.one_indexed(), pos.col))
@@ -795,13 +2362,14 @@ impl RequestAction for CodeLensRequest {
         ctx: InitActionContext,
         params: Self::Params,
     ) -> Result<Self::Response, ResponseError> {
+        let file_path = parse_file_path!(&params.text_document.uri, "code_lens")?;
+
         let mut ret = Vec::new();
         if ctx.client_supports_cmd_run {
-            let file_path = parse_file_path!(&params.text_document.uri, "code_lens")?;
             for action in collect_run_actions(&ctx, &file_path) {
                 let command = Command {
                     title: action.label,
-                    command: "rls.run".to_string(),
+                    command: action.command.to_string(),
                     arguments: Some(vec![serde_json::to_value(&action.cmd).unwrap()]),
                 };
                 let range = ls_util::rls_to_range(action.target_element);
@@ -809,14 +2377,151 @@ impl RequestAction for CodeLensRequest {
                 ret.push(lens);
             }
         }
+
+        if ctx.config.lock().unwrap().show_reference_lens {
+            // Counting references is expensive, so the lenses are only given a
+            // command once the client resolves them (see `CodeLensResolve`).
+            let data = serde_json::to_value(&params.text_document.uri).unwrap();
+            let symbols = ctx.analysis.symbols(&file_path).unwrap_or_else(|_| vec![]);
+            let items = symbols.into_iter().filter(|s| {
+                s.kind != data::DefKind::Mod
+                    && !s.name.is_empty()
+                    && s.parent
+                        .and_then(|id| ctx.analysis.get_def(id).ok())
+                        .map_or(false, |parent| parent.kind == data::DefKind::Mod)
+            });
+            for s in items {
+                let range = ls_util::rls_to_range(s.span.range);
+                ret.push(CodeLens { range, command: None, data: Some(data.clone()) });
+            }
+        }
+
         Ok(ret)
     }
 }
 
+impl RequestAction for CodeLensResolve {
+    type Response = CodeLens;
+
+    fn fallback_response() -> Result<Self::Response, ResponseError> {
+        Err(ResponseError::Empty)
+    }
+
+    fn handle(
+        ctx: InitActionContext,
+        mut params: Self::Params,
+    ) -> Result<Self::Response, ResponseError> {
+        // Only reference count lenses need resolving, those carry the document URI.
+        let uri: Url = match params.data.take().map(serde_json::from_value) {
+            Some(Ok(uri)) => uri,
+            _ => return Ok(params),
+        };
+        let file_path = parse_file_path!(&uri, "code_lens_resolve")?;
+        let span = ctx.convert_pos_to_span(file_path, params.range.start);
+
+        let refs = ctx
+            .analysis
+            .find_all_refs(
+                &span,
+                FindRefsOptions {
+                    expansion_refs: ExpansionRefs::Remap,
+                    ..FindRefsOptions::default()
+                },
+            )
+            .unwrap_or_else(|_| vec![]);
+        let title = match refs.len() {
+            1 => "1 reference".to_owned(),
+            n => format!("{} references", n),
+        };
+        let locations: Vec<_> = refs.iter().map(ls_util::rls_to_location).collect();
+
+        params.command = Some(Command {
+            title,
+            // The client's own command for showing a list of locations, as
+            // named by VS Code.
+            command: "editor.action.showReferences".to_owned(),
+            arguments: Some(vec![
+                serde_json::to_value(&uri).unwrap(),
+                serde_json::to_value(&params.range.start).unwrap(),
+                serde_json::to_value(&locations).unwrap(),
+            ]),
+        });
+        Ok(params)
+    }
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
 
+    #[test]
+    fn test_is_complete_expr() {
+        assert!(is_complete_expr("foo"));
+        assert!(is_complete_expr("foo + 2"));
+        assert!(is_complete_expr("-foo"));
+        assert!(is_complete_expr("v.iter().map(|x| x * 2)"));
+        assert!(is_complete_expr("x?"));
+        assert!(!is_complete_expr(""));
+        assert!(!is_complete_expr("foo +"));
+        assert!(!is_complete_expr("+ 2"));
+        assert!(!is_complete_expr("v.iter().map(|x|"));
+        assert!(!is_complete_expr("a) + (b"));
+        assert!(!is_complete_expr("v."));
+    }
+
+    #[test]
+    fn test_highlight_kind() {
+        let kind = |line: &str, start: u32, end: u32| {
+            let range = span::Range::from_positions(
+                span::Position::new(
+                    span::Row::new_zero_indexed(0),
+                    span::Column::new_zero_indexed(start),
+                ),
+                span::Position::new(
+                    span::Row::new_zero_indexed(0),
+                    span::Column::new_zero_indexed(end),
+                ),
+            );
+            highlight_kind(line, range)
+        };
+
+        assert_eq!(kind("    let baz = &mut bar;", 19, 22), DocumentHighlightKind::Write);
+        assert_eq!(kind("    bar = f + 1;", 4, 7), DocumentHighlightKind::Write);
+        assert_eq!(kind("    bar <<= 1;", 4, 7), DocumentHighlightKind::Write);
+        assert_eq!(kind("    let f = bar;", 12, 15), DocumentHighlightKind::Read);
+        assert_eq!(kind("    if bar == 1 {}", 7, 10), DocumentHighlightKind::Read);
+        assert_eq!(kind("    let r = &bar;", 13, 16), DocumentHighlightKind::Read);
+    }
+
+    #[test]
+    fn test_function_snippet() {
+        assert_eq!(
+            function_snippet("foo", "pub fn foo(x: u32, y: u32) -> u32").unwrap(),
+            "foo(${1:x}, ${2:y})"
+        );
+        assert_eq!(function_snippet("new", "pub fn new() -> Vec<T>").unwrap(), "new()");
+        assert_eq!(function_snippet("len", "pub fn len(&self) -> usize").unwrap(), "len()");
+        assert_eq!(
+            function_snippet("reserve", "pub fn reserve(&'a mut self, mut additional: usize)")
+                .unwrap(),
+            "reserve(${1:additional})"
+        );
+        assert_eq!(
+            function_snippet("into_vec", "fn into_vec(self: Box<Self>)").unwrap(),
+            "into_vec()"
+        );
+        assert_eq!(
+            function_snippet(
+                "map",
+                "fn map<U, F: FnOnce(T) -> U>(self, f: F, (a, b): (u8, u8), m: HashMap<K, V>)"
+            )
+            .unwrap(),
+            "map(${1:f}, ${2:(a, b)}, ${3:m})"
+        );
+        assert_eq!(function_snippet("foo", "struct Foo"), None);
+        assert_eq!(function_snippet("foo", "fn foo(x: u32,"), None);
+    }
+
     #[test]
     fn test_sort_deglob_str() {
         assert_eq!(sort_deglob_str(""), "");
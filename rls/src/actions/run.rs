@@ -1,9 +1,10 @@
 use crate::actions::InitActionContext;
+use crate::lsp_data::{ls_util, TestFunction};
 use lazy_static::lazy_static;
 use log::error;
 use ordslice::Ext;
 use regex::Regex;
-use rls_span::{Column, Position, Range, Row, ZeroIndexed};
+use rls_span::{Column, Position, Range, Row, Span, ZeroIndexed};
 use rls_vfs::FileContents;
 use serde_derive::Serialize;
 
@@ -22,8 +23,81 @@ pub fn collect_run_actions(ctx: &InitActionContext, file: &Path) -> Vec<RunActio
         return Vec::new();
     }
 
+    let package = ctx.owning_package(file).map(|id| id.name().to_string());
+
+    let mut ret = Vec::new();
+    for test_fn in test_fns(&text).into_iter().filter(|test_fn| !test_fn.bench) {
+        let target_element = test_fn.name_range;
+        let test_path = test_path(ctx, file, target_element).unwrap_or(test_fn.name);
+
+        let mut args = vec!["test".to_string()];
+        if let Some(ref package) = package {
+            args.push("-p".to_string());
+            args.push(package.clone());
+        }
+        args.extend(vec!["--".to_string(), "--nocapture".to_string(), test_path]);
+        let cmd = Cmd {
+            binary: "cargo".to_string(),
+            args,
+            env: iter::once(("RUST_BACKTRACE".to_string(), "short".to_string())).collect(),
+        };
+
+        ret.push(RunAction {
+            label: "Run test".to_string(),
+            command: "rls.run",
+            target_element,
+            cmd: cmd.clone(),
+        });
+        if ctx.client_supports_cmd_debug {
+            ret.push(RunAction {
+                label: "Debug test".to_string(),
+                command: "rls.debug",
+                target_element,
+                cmd,
+            });
+        }
+    }
+    ret
+}
+
+/// Returns the test (or benchmark) function which encloses `position` in
+/// `file`, or else the nearest one above it, with the filter for running it.
+pub fn nearest_test(
+    ctx: &InitActionContext,
+    file: &Path,
+    position: Position<ZeroIndexed>,
+) -> Option<TestFunction> {
+    let text = match ctx.vfs.load_file(file) {
+        Ok(FileContents::Text(text)) => text,
+        _ => return None,
+    };
+    // The test whose attributes start last before the cursor, so a cursor on
+    // the `#[test]` of a function is in that function.
+    let test_fn =
+        test_fns(&text).into_iter().take_while(|test_fn| test_fn.start <= position).last()?;
+
+    let filter = test_path(ctx, file, test_fn.name_range).unwrap_or_else(|| test_fn.name.clone());
+    Some(TestFunction {
+        name: test_fn.name,
+        filter,
+        bench: test_fn.bench,
+        range: ls_util::rls_to_range(test_fn.name_range),
+    })
+}
+
+/// A function with a `#[test]` or `#[bench]` attribute.
+struct TestFn {
+    name: String,
+    bench: bool,
+    /// The start of the attribute.
+    start: Position<ZeroIndexed>,
+    name_range: Range<ZeroIndexed>,
+}
+
+/// Finds the test and benchmark functions in `text`, in order.
+fn test_fns(text: &str) -> Vec<TestFn> {
     lazy_static! {
-        /// __(a):__ `\#\[([\w]+::)*test\]` matches `#[test]`, `#[async_executor::test]` or `#[async_lib::module::test]`.
+        /// __(a):__ `\#\[([\w]+::)*(?P<kind>test|bench)\]` matches `#[test]`, `#[async_executor::test]`, `#[async_lib::module::test]` or `#[bench]`.
         ///
         /// __(b):__ `^[^\/]*?fn\s+(?P<name>\w+)` matches any line which contains `fn name` before any comment is started and captures the word after fn.
         /// The laziness of the quantifier is there to make the regex quicker (about 5 times less steps)
@@ -42,45 +116,47 @@ pub fn collect_run_actions(ctx: &InitActionContext, file: &Path) -> Vec<RunActio
         /// fn right_function() {}
         /// ```
         static ref TEST_FN_RE: Regex =
-            Regex::new(r"(?m)#\[([\w]+::)*test\](\n|.)*?^[^/]*?fn\s+(?P<name>\w+)").unwrap();
+            Regex::new(r"(?m)#\[([\w]+::)*(?P<kind>test|bench)\](\n|.)*?^[^/]*?fn\s+(?P<name>\w+)")
+                .unwrap();
     }
 
-    let line_index = LineIndex::new(&text);
+    let line_index = LineIndex::new(text);
+    TEST_FN_RE
+        .captures_iter(text)
+        .map(|caps| {
+            let name = caps.name("name").unwrap();
+            TestFn {
+                name: name.as_str().to_string(),
+                bench: &caps["kind"] == "bench",
+                start: line_index.offset_to_position(caps.get(0).unwrap().start()),
+                name_range: Range::from_positions(
+                    line_index.offset_to_position(name.start()),
+                    line_index.offset_to_position(name.end()),
+                ),
+            }
+        })
+        .collect()
+}
 
-    let mut ret = Vec::new();
-    for caps in TEST_FN_RE.captures_iter(&text) {
-        let group = caps.name("name").unwrap();
-        let target_element = Range::from_positions(
-            line_index.offset_to_position(group.start()),
-            line_index.offset_to_position(group.end()),
-        );
-        let test_name = group.as_str();
-        let run_action = RunAction {
-            label: "Run test".to_string(),
-            target_element,
-            cmd: Cmd {
-                binary: "cargo".to_string(),
-                args: vec![
-                    "test".to_string(),
-                    "--".to_string(),
-                    "--nocapture".to_string(),
-                    test_name.to_string(),
-                ],
-                env: iter::once(("RUST_BACKTRACE".to_string(), "short".to_string())).collect(),
-            },
-        };
-        ret.push(run_action);
-    }
-    ret
+/// Returns the path of the test function whose name is at `name` relative to
+/// its crate root (e.g., `tests::test_foo`), if it is known to the analysis.
+fn test_path(ctx: &InitActionContext, file: &Path, name: Range<ZeroIndexed>) -> Option<String> {
+    let span = Span::from_range(name, file);
+    let id = ctx.analysis.id(&span).ok()?;
+    let def = ctx.analysis.get_def(id).ok()?;
+    // Qualified names start with the crate name, e.g., `foo::tests::test_foo`.
+    def.qualname.splitn(2, "::").nth(1).map(str::to_string)
 }
 
 pub struct RunAction {
     pub label: String,
+    /// The client command used to execute `cmd`, e.g., `rls.run`.
+    pub command: &'static str,
     pub target_element: Range<ZeroIndexed>,
     pub cmd: Cmd,
 }
 
-#[derive(Serialize)]
+#[derive(Clone, Serialize)]
 pub struct Cmd {
     pub binary: String,
     pub args: Vec<String>,
@@ -0,0 +1,352 @@
+//! Semantic tokens (`textDocument/semanticTokens/full`), computed from the
+//! identifiers recorded by the analysis. Requires the `idents` feature.
+
+// Without `idents` only the encoding is used (by the tests).
+#![cfg_attr(not(feature = "idents"), allow(dead_code))]
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+
+use rls_analysis::DefKind;
+
+use crate::actions::InitActionContext;
+use crate::lsp_data::{
+    SemanticTokensEdit, SemanticTokensFullOptions, SemanticTokensLegend, SemanticTokensOptions,
+};
+use crate::server::ResponseError;
+
+/// Token types, in the order of their indices in the encoded tokens.
+pub const TOKEN_TYPES: &[&str] = &[
+    "namespace",
+    "type",
+    "struct",
+    "enum",
+    "interface",
+    "enumMember",
+    "function",
+    "method",
+    "macro",
+    "variable",
+    "property",
+];
+
+/// Token modifiers, in the order of their bits in the encoded tokens.
+pub const TOKEN_MODIFIERS: &[&str] = &["declaration", "mutable", "readonly"];
+
+const MOD_DECLARATION: u32 = 1 << 0;
+const MOD_MUTABLE: u32 = 1 << 1;
+const MOD_READONLY: u32 = 1 << 2;
+
+/// The semantic tokens capability of the server.
+pub fn options() -> SemanticTokensOptions {
+    let names = |names: &[&str]| names.iter().map(|name| (*name).to_owned()).collect();
+    SemanticTokensOptions {
+        legend: SemanticTokensLegend {
+            token_types: names(TOKEN_TYPES),
+            token_modifiers: names(TOKEN_MODIFIERS),
+        },
+        full: SemanticTokensFullOptions { delta: true },
+    }
+}
+
+/// A single semantic token, with an absolute position.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct SemanticToken {
+    pub line: u32,
+    pub start: u32,
+    pub length: u32,
+    pub token_type: u32,
+    pub modifiers: u32,
+}
+
+/// Returns the index of the token type for defs of `kind`.
+fn token_type(kind: DefKind) -> u32 {
+    let name = match kind {
+        DefKind::Mod => "namespace",
+        DefKind::Type | DefKind::ExternType => "type",
+        DefKind::Struct | DefKind::Union | DefKind::Tuple => "struct",
+        DefKind::Enum => "enum",
+        DefKind::Trait => "interface",
+        DefKind::TupleVariant | DefKind::StructVariant => "enumMember",
+        DefKind::Function | DefKind::ForeignFunction => "function",
+        DefKind::Method => "method",
+        DefKind::Macro => "macro",
+        DefKind::Local | DefKind::Static | DefKind::ForeignStatic | DefKind::Const => "variable",
+        DefKind::Field => "property",
+    };
+    TOKEN_TYPES.iter().position(|t| *t == name).unwrap() as u32
+}
+
+/// Encodes `tokens` as the LSP requires: sorted by position, five integers per
+/// token, with each position relative to the previous token's.
+pub fn encode(tokens: &mut [SemanticToken]) -> Vec<u32> {
+    tokens.sort();
+
+    let mut data = Vec::with_capacity(tokens.len() * 5);
+    let (mut prev_line, mut prev_start) = (0, 0);
+    for token in tokens.iter() {
+        let delta_line = token.line - prev_line;
+        let delta_start = if delta_line == 0 { token.start - prev_start } else { token.start };
+        data.extend_from_slice(&[
+            delta_line,
+            delta_start,
+            token.length,
+            token.token_type,
+            token.modifiers,
+        ]);
+        prev_line = token.line;
+        prev_start = token.start;
+    }
+    data
+}
+
+/// The tokens last returned for each document, so that later requests can
+/// be answered with the changes since then.
+#[derive(Default)]
+pub struct TokensCache {
+    entries: Mutex<HashMap<PathBuf, (String, Vec<u32>)>>,
+    next_result_id: AtomicUsize,
+}
+
+impl TokensCache {
+    /// Records `data` as the latest tokens of `file_path`, returning the
+    /// result ID to send with them.
+    pub fn store(&self, file_path: &Path, data: Vec<u32>) -> String {
+        let result_id = self.next_result_id.fetch_add(1, Ordering::SeqCst).to_string();
+        self.entries.lock().unwrap().insert(file_path.to_owned(), (result_id.clone(), data));
+        result_id
+    }
+
+    /// Forgets the tokens of `file_path`, e.g., when it's closed.
+    pub fn remove(&self, file_path: &Path) {
+        self.entries.lock().unwrap().remove(file_path);
+    }
+
+    /// Returns the tokens of `file_path` if they're the ones last sent with
+    /// `result_id`.
+    pub fn get(&self, file_path: &Path, result_id: &str) -> Option<Vec<u32>> {
+        match self.entries.lock().unwrap().get(file_path) {
+            Some((id, data)) if id == result_id => Some(data.clone()),
+            _ => None,
+        }
+    }
+}
+
+/// Returns the edits which turn the encoded tokens `old` into `new`. This
+/// is a single edit replacing everything between the common prefix and suffix
+/// (whole tokens only), or none if the tokens didn't change.
+pub fn diff(old: &[u32], new: &[u32]) -> Vec<SemanticTokensEdit> {
+    let tokens = |data: &[u32]| data.chunks(5).map(<[u32]>::to_vec).collect::<Vec<_>>();
+    let (old_tokens, new_tokens) = (tokens(old), tokens(new));
+
+    let prefix = old_tokens.iter().zip(&new_tokens).take_while(|(o, n)| o == n).count();
+    let suffix = old_tokens[prefix..]
+        .iter()
+        .rev()
+        .zip(new_tokens[prefix..].iter().rev())
+        .take_while(|(o, n)| o == n)
+        .count();
+
+    let deleted = old_tokens.len() - prefix - suffix;
+    let inserted = &new_tokens[prefix..new_tokens.len() - suffix];
+    if deleted == 0 && inserted.is_empty() {
+        return vec![];
+    }
+    vec![SemanticTokensEdit {
+        start: (prefix * 5) as u32,
+        delete_count: (deleted * 5) as u32,
+        data: inserted.concat(),
+    }]
+}
+
+/// Computes the encoded semantic tokens of the file at `file_path`.
+#[cfg(feature = "idents")]
+pub fn semantic_tokens(
+    ctx: &InitActionContext,
+    file_path: &Path,
+) -> Result<Vec<u32>, ResponseError> {
+    use rls_analysis::IdentKind;
+    use rls_span::{Column, Position, Row, Span};
+    use rls_vfs::FileContents;
+
+    let text = match ctx.vfs.load_file(file_path) {
+        Ok(FileContents::Text(text)) => text,
+        _ => return Err(ResponseError::Empty),
+    };
+    let line_count = text.lines().count().max(1) as u32;
+    let file_span = Span::from_positions(
+        Position::new(Row::new_zero_indexed(0), Column::new_zero_indexed(0)),
+        Position::new(Row::new_zero_indexed(line_count - 1), Column::new_zero_indexed(u32::MAX)),
+        file_path,
+    );
+
+    let idents = ctx.analysis.idents(&file_span).unwrap_or_default();
+    let lines: Vec<&str> = text.lines().collect();
+    let mut tokens: Vec<_> = idents
+        .into_iter()
+        .filter(|ident| ident.span.range.row_start == ident.span.range.row_end)
+        .filter_map(|ident| {
+            let def = ctx.analysis.get_def(ident.id).ok()?;
+            let mut modifiers = 0;
+            if let IdentKind::Def = ident.kind {
+                modifiers |= MOD_DECLARATION;
+            }
+            match def.kind {
+                DefKind::Const => modifiers |= MOD_READONLY,
+                DefKind::Local | DefKind::Static if is_mut_binding(ctx, &def.span) => {
+                    modifiers |= MOD_MUTABLE
+                }
+                _ => {}
+            }
+
+            let range = ident.span.range;
+            let line = lines.get(range.row_start.0 as usize)?;
+            let start = utf16_len(line, range.col_start.0);
+            Some(SemanticToken {
+                line: range.row_start.0,
+                start,
+                length: utf16_len(line, range.col_end.0) - start,
+                token_type: token_type(def.kind),
+                modifiers,
+            })
+        })
+        .collect();
+
+    Ok(encode(&mut tokens))
+}
+
+/// Returns `true` if the binding whose name is at `span` is declared `mut`.
+#[cfg(feature = "idents")]
+fn is_mut_binding(ctx: &InitActionContext, span: &crate::Span) -> bool {
+    ctx.vfs.load_line(&span.file, span.range.row_start).map_or(false, |line| {
+        let before: String = line.chars().take(span.range.col_start.0 as usize).collect();
+        before.trim_end().ends_with("mut")
+    })
+}
+
+/// Returns the length in UTF-16 code units, as used by the LSP, of the first
+/// `chars` characters of `line`.
+fn utf16_len(line: &str, chars: u32) -> u32 {
+    line.chars().take(chars as usize).map(char::len_utf16).sum::<usize>() as u32
+}
+
+#[cfg(not(feature = "idents"))]
+pub fn semantic_tokens(
+    _ctx: &InitActionContext,
+    _file_path: &Path,
+) -> Result<Vec<u32>, ResponseError> {
+    Err(ResponseError::Message(
+        jsonrpc_core::types::ErrorCode::MethodNotFound,
+        "Semantic tokens require the RLS to be built with the `idents` feature".to_owned(),
+    ))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn decode(data: &[u32]) -> Vec<(u32, u32, u32, u32)> {
+        let (mut line, mut start) = (0, 0);
+        data.chunks(5)
+            .map(|chunk| {
+                if chunk[0] > 0 {
+                    start = 0;
+                }
+                line += chunk[0];
+                start += chunk[1];
+                (line, start, chunk[2], chunk[3])
+            })
+            .collect()
+    }
+
+    fn apply(data: &[u32], edits: &[SemanticTokensEdit]) -> Vec<u32> {
+        let mut data = data.to_vec();
+        for edit in edits.iter().rev() {
+            let start = edit.start as usize;
+            data.splice(start..start + edit.delete_count as usize, edit.data.iter().cloned());
+        }
+        data
+    }
+
+    #[test]
+    fn diff_is_smaller_than_full_tokens() {
+        // A local on each of ten lines, before and after renaming the third
+        // one to a longer name.
+        let full = |len| {
+            let mut tokens: Vec<_> = (0..10)
+                .map(|line| SemanticToken {
+                    line,
+                    start: 8,
+                    length: if line == 2 { len } else { 1 },
+                    token_type: token_type(DefKind::Local),
+                    modifiers: MOD_DECLARATION,
+                })
+                .collect();
+            encode(&mut tokens)
+        };
+        let (old, new) = (full(1), full(2));
+
+        let edits = diff(&old, &new);
+        assert_eq!(apply(&old, &edits), new);
+        let edited: usize = edits.iter().map(|e| e.data.len()).sum();
+        assert!(edited < new.len());
+        assert_eq!(edits[0].start, 10);
+        assert_eq!(edits[0].delete_count, 5);
+
+        assert_eq!(diff(&new, &new), vec![]);
+        assert_eq!(apply(&old, &diff(&old, &[])), Vec::<u32>::new());
+    }
+
+    #[test]
+    fn tokens_cache_checks_result_id() {
+        let cache = TokensCache::default();
+        let path = Path::new("src/main.rs");
+
+        let first = cache.store(path, vec![0, 0, 1, 0, 0]);
+        assert_eq!(cache.get(path, &first), Some(vec![0, 0, 1, 0, 0]));
+
+        let second = cache.store(path, vec![]);
+        assert_ne!(first, second);
+        // Too old.
+        assert_eq!(cache.get(path, &first), None);
+        assert_eq!(cache.get(Path::new("src/lib.rs"), &second), None);
+
+        cache.remove(path);
+        assert_eq!(cache.get(path, &second), None);
+    }
+
+    #[test]
+    fn utf16_lengths() {
+        let line = "let 😢x = \"é\";";
+        assert_eq!(utf16_len(line, 4), 4);
+        // The emoji is two code units.
+        assert_eq!(utf16_len(line, 6), 7);
+        assert_eq!(utf16_len(line, 11), 12);
+        // Past the end of the line.
+        assert_eq!(utf16_len(line, 20), 14);
+    }
+
+    #[test]
+    fn encode_sorts_and_uses_relative_positions() {
+        let token = |line, start, length, token_type| SemanticToken {
+            line,
+            start,
+            length,
+            token_type,
+            modifiers: 0,
+        };
+        // Unsorted, as returned by `idents`.
+        let mut tokens = vec![
+            token(2, 4, 3, token_type(DefKind::Local)),
+            token(0, 3, 4, token_type(DefKind::Function)),
+            token(2, 12, 3, token_type(DefKind::Struct)),
+            token(1, 8, 1, token_type(DefKind::Local)),
+        ];
+
+        let data = encode(&mut tokens);
+        assert_eq!(data[..10], [0, 3, 4, 6, 0, 1, 8, 1, 9, 0]);
+        assert_eq!(decode(&data), vec![(0, 3, 4, 6), (1, 8, 1, 9), (2, 4, 3, 9), (2, 12, 3, 2)]);
+    }
+}
@@ -0,0 +1,214 @@
+//! Diagnostics for `TODO`-style comments (see the `show_todo_diagnostics`
+//! option), published along with the compiler's diagnostics after a build.
+
+use lsp_types::{Diagnostic, DiagnosticSeverity, NumberOrString, Position, Range};
+
+/// Returns an information diagnostic for each line comment in `text` which
+/// starts with one of `tags`, e.g., `// TODO: ...`. Comment markers in string
+/// and character literals and in block comments are ignored.
+pub fn todo_diagnostics(text: &str, tags: &[String]) -> Vec<Diagnostic> {
+    let mut diagnostics = vec![];
+    let mut i = 0;
+    while i < text.len() {
+        let rest = &text[i..];
+        if rest.starts_with("//") {
+            let end = rest.find('\n').map_or(text.len(), |n| i + n);
+            diagnostics.extend(todo_comment(text, i, end, tags));
+            i = end;
+        } else if rest.starts_with("/*") {
+            i += block_comment_len(rest);
+        } else if rest.starts_with('"') {
+            i += string_len(rest);
+        } else if let Some(len) = raw_string_len(text, i) {
+            i += len;
+        } else if rest.starts_with('\'') {
+            i += char_literal_len(rest);
+        } else {
+            i += rest.chars().next().map_or(1, char::len_utf8);
+        }
+    }
+    diagnostics
+}
+
+/// Returns a diagnostic for the line comment from byte `start` to `end` of
+/// `text` if it starts with one of `tags`.
+fn todo_comment(text: &str, start: usize, end: usize, tags: &[String]) -> Option<Diagnostic> {
+    let comment = &text[start..end];
+    // Doc comments (`///` and `//!`) can have tags too.
+    let body = comment.trim_start_matches(&['/', '!'][..]).trim_start();
+    let tag = tags.iter().find(|tag| {
+        !tag.is_empty()
+            && body.starts_with(tag.as_str())
+            && !body[tag.len()..].starts_with(is_ident_char)
+    })?;
+
+    let message = body.trim_end();
+    let tag_start = start + (comment.len() - body.len());
+    Some(Diagnostic {
+        range: Range {
+            start: position_of(text, tag_start),
+            end: position_of(text, tag_start + message.len()),
+        },
+        severity: Some(DiagnosticSeverity::Information),
+        code: Some(NumberOrString::String(tag.clone())),
+        source: Some("rls".to_owned()),
+        message: message.to_owned(),
+        ..Diagnostic::default()
+    })
+}
+
+/// Returns the length of the (possibly nested) block comment at the start of
+/// `s`.
+fn block_comment_len(s: &str) -> usize {
+    let mut depth = 0;
+    let mut i = 0;
+    while i < s.len() {
+        if s[i..].starts_with("/*") {
+            depth += 1;
+            i += 2;
+        } else if s[i..].starts_with("*/") {
+            depth -= 1;
+            i += 2;
+            if depth == 0 {
+                return i;
+            }
+        } else {
+            i += s[i..].chars().next().map_or(1, char::len_utf8);
+        }
+    }
+    s.len()
+}
+
+/// Returns the length of the string literal at the start of `s`, including
+/// the quotes.
+fn string_len(s: &str) -> usize {
+    let mut escaped = false;
+    for (i, c) in s.char_indices().skip(1) {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' => escaped = true,
+            '"' => return i + 1,
+            _ => {}
+        }
+    }
+    s.len()
+}
+
+/// Returns the length of the raw string literal (e.g., `r#"..."#` or
+/// `br"..."`) at byte `i` of `text`, if there is one.
+fn raw_string_len(text: &str, i: usize) -> Option<usize> {
+    let rest = &text[i..];
+    let prefix_len = if rest.starts_with("br") {
+        2
+    } else if rest.starts_with('r') {
+        1
+    } else {
+        return None;
+    };
+    // Otherwise the `r` is part of an identifier.
+    if text[..i].ends_with(is_ident_char) {
+        return None;
+    }
+
+    let after_prefix = &rest[prefix_len..];
+    let hashes = after_prefix.len() - after_prefix.trim_start_matches('#').len();
+    if !after_prefix[hashes..].starts_with('"') {
+        return None;
+    }
+    let terminator = format!("\"{}", "#".repeat(hashes));
+    let body_start = prefix_len + hashes + 1;
+    Some(
+        rest[body_start..]
+            .find(&terminator)
+            .map_or(rest.len(), |end| body_start + end + terminator.len()),
+    )
+}
+
+/// Returns the length of the character literal at the start of `s`, or 1 if
+/// the `'` starts a lifetime or label instead.
+fn char_literal_len(s: &str) -> usize {
+    match s[1..].chars().next() {
+        Some('\\') => {
+            // Skip the escaped character, which may be a quote itself.
+            let escaped_len = s[2..].chars().next().map_or(0, char::len_utf8);
+            s[2 + escaped_len..].find('\'').map_or(1, |end| 2 + escaped_len + end + 1)
+        }
+        Some(c) if s[1 + c.len_utf8()..].starts_with('\'') => 2 + c.len_utf8(),
+        _ => 1,
+    }
+}
+
+fn is_ident_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_'
+}
+
+/// Converts a byte offset in `text` to an LSP position, whose column counts
+/// UTF-16 code units.
+fn position_of(text: &str, offset: usize) -> Position {
+    let before = &text[..offset];
+    let line_start = before.rfind('\n').map_or(0, |i| i + 1);
+    Position::new(
+        before.matches('\n').count() as u64,
+        before[line_start..].encode_utf16().count() as u64,
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn default_tags() -> Vec<String> {
+        vec!["TODO".to_owned(), "FIXME".to_owned(), "XXX".to_owned()]
+    }
+
+    fn messages(text: &str) -> Vec<String> {
+        todo_diagnostics(text, &default_tags()).into_iter().map(|d| d.message).collect()
+    }
+
+    #[test]
+    fn finds_todo_comments() {
+        let text = "fn main() {\n    // TODO: handle errors\n    let é = 1; //FIXME\r\n}\n";
+        let diagnostics = todo_diagnostics(text, &default_tags());
+
+        assert_eq!(diagnostics.len(), 2);
+        assert_eq!(diagnostics[0].message, "TODO: handle errors");
+        assert_eq!(diagnostics[0].severity, Some(DiagnosticSeverity::Information));
+        assert_eq!(
+            diagnostics[0].range,
+            Range { start: Position::new(1, 7), end: Position::new(1, 26) }
+        );
+        assert_eq!(diagnostics[1].message, "FIXME");
+        assert_eq!(
+            diagnostics[1].range,
+            Range { start: Position::new(2, 17), end: Position::new(2, 22) }
+        );
+
+        // Columns are in UTF-16 code units, so the emoji counts twice.
+        let diagnostics = todo_diagnostics("let s = \"😀\"; // TODO", &default_tags());
+        assert_eq!(
+            diagnostics[0].range,
+            Range { start: Position::new(0, 17), end: Position::new(0, 21) }
+        );
+
+        assert_eq!(messages("/// XXX(nrc) doc comment"), vec!["XXX(nrc) doc comment"]);
+        assert!(messages("// TODOS are not tags\n// todo: tags are case sensitive").is_empty());
+    }
+
+    #[test]
+    fn ignores_literals_and_block_comments() {
+        assert!(messages(r#"let s = "// TODO: not a comment \" // FIXME";"#).is_empty());
+        assert!(messages(r###"let s = r#"" // TODO"#;"###).is_empty());
+        assert!(messages("/* outer /* // TODO */ // FIXME */").is_empty());
+        assert_eq!(messages("let c = '\"'; // TODO: after a quote"), vec!["TODO: after a quote"]);
+        assert_eq!(messages("fn f<'a>(x: &'a str) {} // XXX"), vec!["XXX"]);
+        assert_eq!(messages("let r#type = 1; // TODO"), vec!["TODO"]);
+    }
+
+    #[test]
+    fn tags_are_configurable() {
+        let text = "// TODO: default\n// HACK: custom\n";
+        let diagnostics = todo_diagnostics(text, &["HACK".to_owned()]);
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].message, "HACK: custom");
+    }
+}
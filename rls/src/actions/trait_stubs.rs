@@ -0,0 +1,434 @@
+//! Stubs for the items of a trait which are missing from an impl of it, for
+//! the `rls/implementMissingItems` request.
+
+use std::collections::HashMap;
+use std::path::Path;
+
+use lazy_static::lazy_static;
+use regex::Regex;
+use rls_analysis::{DefKind, Signature};
+use rls_span as span;
+use rls_vfs::FileContents;
+
+use crate::actions::field_completion::{offset_of, position_of};
+use crate::actions::InitActionContext;
+use crate::lsp_data::{
+    file_path_to_uri, make_workspace_edit, Location, Position, Range, WorkspaceEdit,
+};
+use crate::Span;
+
+/// The offsets of a trait impl's header and braces.
+#[derive(Debug, PartialEq)]
+struct ImplBlock {
+    header_start: usize,
+    open: usize,
+    close: usize,
+}
+
+/// Returns the edit adding a stub for each required item of the trait which
+/// is missing from the impl the cursor is in, or `None` if the cursor isn't
+/// in a trait impl or nothing is missing.
+pub fn missing_items_edit(
+    ctx: &InitActionContext,
+    file_path: &Path,
+    position: Position,
+) -> Option<WorkspaceEdit> {
+    let text = load_text(ctx, file_path)?;
+    let block = impl_block_at(&text, offset_of(&text, position)?)?;
+
+    let (header_row, _) = position_of(&text, block.header_start);
+    let (open_row, open_col) = position_of(&text, block.open);
+    let header = Span::new(
+        span::Row::new_zero_indexed(header_row),
+        span::Row::new_zero_indexed(open_row),
+        span::Column::new_zero_indexed(0),
+        span::Column::new_zero_indexed(open_col),
+        file_path,
+    );
+    let trait_id = ctx.analysis.impl_trait(&header).ok()?;
+    let items = ctx.analysis.trait_items(trait_id).ok()?;
+
+    let implemented = implemented_items(&text[block.open + 1..block.close]);
+    let mut trait_texts = HashMap::new();
+    let indent = format!("{}    ", line_indent(&text, block.header_start));
+    let stubs: Vec<String> = items
+        .iter()
+        .filter(|def| !implemented.contains(&def.name))
+        .filter_map(|def| {
+            let trait_text = trait_texts
+                .entry(def.span.file.clone())
+                .or_insert_with(|| load_text(ctx, &def.span.file))
+                .as_ref()?;
+            let row = def.span.range.row_start.0 as usize;
+            stub(trait_text, def.kind, &def.name, row, def.sig.as_ref(), &indent)
+        })
+        .collect();
+    if stubs.is_empty() {
+        return None;
+    }
+
+    // Replace the whitespace before the closing brace.
+    let body = &text[block.open + 1..block.close];
+    let start = block.open + 1 + body.trim_end().len();
+    let separator = if body.trim().is_empty() { "\n" } else { "\n\n" };
+    let new_text =
+        format!("{}{}\n{}", separator, stubs.join("\n\n"), line_indent(&text, block.header_start));
+
+    let (start_row, start_col) = position_of(&text, start);
+    let (end_row, end_col) = position_of(&text, block.close);
+    let range = Range {
+        start: Position::new(start_row.into(), start_col.into()),
+        end: Position::new(end_row.into(), end_col.into()),
+    };
+    let uri = file_path_to_uri(file_path).ok()?;
+    Some(make_workspace_edit(Location::new(uri, range), new_text))
+}
+
+fn load_text(ctx: &InitActionContext, path: &Path) -> Option<String> {
+    match ctx.vfs.load_file(path).ok()? {
+        FileContents::Text(text) => Some(text),
+        FileContents::Binary(_) => None,
+    }
+}
+
+/// Finds the innermost trait impl (i.e., `impl Trait for Type`) whose body
+/// contains byte `offset` of `text`.
+fn impl_block_at(text: &str, offset: usize) -> Option<ImplBlock> {
+    lazy_static! {
+        static ref IMPL_RE: Regex =
+            Regex::new(r"^(?:#!?\[[^\]]*\]\s*)*(?:unsafe\s+)?impl\b[\s\S]*\bfor\b").unwrap();
+    }
+
+    let mut depth = 0;
+    for (open, c) in text[..offset].char_indices().rev() {
+        match c {
+            '}' => depth += 1,
+            '{' if depth > 0 => depth -= 1,
+            '{' => {
+                let header_start = text[..open].rfind(&[';', '{', '}'][..]);
+                let header_start = header_start.map_or(0, |i| i + 1);
+                let header = &text[header_start..open];
+                if !IMPL_RE.is_match(header.trim_start()) {
+                    continue;
+                }
+
+                let header_start = header_start + (header.len() - header.trim_start().len());
+                let close = matching_brace(text, open)?;
+                if close < offset {
+                    return None;
+                }
+                return Some(ImplBlock { header_start, open, close });
+            }
+            _ => {}
+        }
+    }
+    None
+}
+
+/// Returns the offset of the `}` closing the `{` at `open`.
+fn matching_brace(text: &str, open: usize) -> Option<usize> {
+    let mut depth = 0;
+    for (i, c) in text[open..].char_indices() {
+        match c {
+            '{' => depth += 1,
+            '}' if depth == 1 => return Some(open + i),
+            '}' => depth -= 1,
+            _ => {}
+        }
+    }
+    None
+}
+
+/// Returns the names of the functions, types and consts defined directly in
+/// the body of an impl.
+fn implemented_items(body: &str) -> Vec<String> {
+    lazy_static! {
+        static ref ITEM_RE: Regex = Regex::new(r"\b(?:fn|type|const)\s+(\w+)").unwrap();
+    }
+
+    // Blank out anything nested, e.g., the bodies of the methods.
+    let mut depth = 0;
+    let top_level: String = body
+        .chars()
+        .map(|c| match c {
+            '{' | '(' | '[' => {
+                depth += 1;
+                ' '
+            }
+            '}' | ')' | ']' => {
+                depth -= 1;
+                ' '
+            }
+            _ if depth > 0 => ' ',
+            _ => c,
+        })
+        .collect();
+    ITEM_RE.captures_iter(&top_level).map(|c| c[1].to_owned()).collect()
+}
+
+/// Returns the stub implementing the trait item declared on (zero-indexed)
+/// line `row` of `trait_text`, indented with `indent`, or `None` if the item
+/// has a default (e.g., a provided method).
+///
+/// The declaration is generated from the item's signature if the analysis
+/// recorded one and copied from the trait's source otherwise. Associated types
+/// get a `_` placeholder, which the user has to replace with the actual type.
+fn stub(
+    trait_text: &str,
+    kind: DefKind,
+    name: &str,
+    row: usize,
+    sig: Option<&Signature>,
+    indent: &str,
+) -> Option<String> {
+    let line_start = trait_text.split('\n').take(row).map(|line| line.len() + 1).sum();
+    // Signatures don't tell whether an item has a default, so the source does.
+    let (decl, trait_indent) = declaration(trait_text, line_start)?;
+    if kind != DefKind::Method && has_default(decl) {
+        return None;
+    }
+    let decl_text = match sig.and_then(|sig| sig_declaration(kind, sig)) {
+        Some(decl) => format!("{}{}", indent, decl),
+        None => decl
+            .lines()
+            .map(|line| format!("{}{}", indent, line.strip_prefix(trait_indent).unwrap_or(line)))
+            .collect::<Vec<_>>()
+            .join("\n"),
+    };
+
+    match kind {
+        DefKind::Method => Some(format!("{} {{\n{}    todo!()\n{}}}", decl_text, indent, indent)),
+        DefKind::Const => Some(format!("{} = todo!();", decl_text)),
+        DefKind::Type => Some(format!("{}type {} = _;", indent, name)),
+        _ => None,
+    }
+}
+
+/// Returns the declaration of a method or const from its signature, e.g.,
+/// `fn other(&self, _: u32) -> u32` for `fn other(: & Self,: u32,) -> u32 {}`.
+/// The compiler doesn't record the names of parameters, so a `Self` typed
+/// first parameter becomes the receiver and the others are named `_`.
+fn sig_declaration(kind: DefKind, sig: &Signature) -> Option<String> {
+    let text = sig.text.trim_end();
+    match kind {
+        DefKind::Const => Some(text.strip_suffix(';')?.trim_end().to_owned()),
+        DefKind::Method => {
+            let text = text.strip_suffix("{}")?.trim_end();
+            let name_end = sig.defs.first()?.end;
+
+            // Split the parameters, skipping the generics (which can contain
+            // parentheses in `Fn` bounds) and anything nested in a parameter.
+            let mut depth = 0;
+            let mut prev = ' ';
+            let (mut open, mut close) = (None, None);
+            let mut params = vec![];
+            let mut param_start = 0;
+            for (i, c) in text.char_indices().skip_while(|&(i, _)| i < name_end) {
+                match c {
+                    '(' if depth == 0 && open.is_none() => {
+                        open = Some(i);
+                        param_start = i + 1;
+                    }
+                    ',' | ')' if depth == 1 && open.is_some() => {
+                        params.push(&text[param_start..i]);
+                        param_start = i + 1;
+                        if c == ')' {
+                            close = Some(i);
+                            break;
+                        }
+                    }
+                    _ => {}
+                }
+                match c {
+                    '(' | '[' | '<' => depth += 1,
+                    ')' | ']' => depth -= 1,
+                    '>' if prev != '-' => depth -= 1,
+                    _ => {}
+                }
+                prev = c;
+            }
+            let (open, close) = (open?, close?);
+
+            let params: Vec<String> = params
+                .into_iter()
+                .map(|param| param.trim().trim_start_matches(':').trim().replace("& ", "&"))
+                .filter(|ty| !ty.is_empty())
+                .enumerate()
+                .map(|(i, ty)| match ty.strip_suffix("Self") {
+                    Some(receiver) if i == 0 && is_receiver_prefix(receiver) => {
+                        format!("{}self", receiver)
+                    }
+                    _ => format!("_: {}", ty),
+                })
+                .collect();
+            Some(format!("{}({}){}", &text[..open], params.join(", "), &text[close + 1..]))
+        }
+        _ => None,
+    }
+}
+
+/// Returns true if `prefix` followed by `Self` is the type of a `self`
+/// receiver, i.e., `Self`, `&Self`, `&mut Self` or `&'a Self`.
+fn is_receiver_prefix(prefix: &str) -> bool {
+    let prefix = prefix.trim_end();
+    let prefix = prefix.strip_suffix("mut").map_or(prefix, str::trim_end);
+    match prefix.strip_prefix('&') {
+        Some(lifetime) => {
+            let lifetime = lifetime.trim();
+            lifetime.is_empty() || lifetime.starts_with('\'') && !lifetime.contains(' ')
+        }
+        None => prefix.is_empty(),
+    }
+}
+
+/// Returns the declaration of the trait item on the line starting at
+/// `line_start`, up to (but excluding) the `;` ending it, together with the
+/// line's indentation. Returns `None` if the item has a body instead.
+fn declaration(text: &str, line_start: usize) -> Option<(&str, &str)> {
+    let line = &text[line_start..];
+    let indent = &line[..line.len() - line.trim_start().len()];
+    let start = line_start + indent.len();
+
+    let mut depth = 0;
+    for (i, c) in text[start..].char_indices() {
+        match c {
+            '(' | '[' => depth += 1,
+            ')' | ']' => depth -= 1,
+            ';' if depth == 0 => return Some((text[start..start + i].trim_end(), indent)),
+            '{' if depth == 0 => return None,
+            _ => {}
+        }
+    }
+    None
+}
+
+/// Returns true if the declaration of an associated type or const gives it a
+/// default, i.e., has an `=` which isn't in generic arguments (such as
+/// `Iterator<Item = u32>`).
+fn has_default(decl: &str) -> bool {
+    let mut depth = 0;
+    let mut prev = ' ';
+    for c in decl.chars() {
+        match c {
+            '<' => depth += 1,
+            '>' if prev != '-' => depth -= 1,
+            '=' if depth == 0 => return true,
+            _ => {}
+        }
+        prev = c;
+    }
+    false
+}
+
+/// Returns the indentation of the line containing byte `offset` of `text`.
+fn line_indent(text: &str, offset: usize) -> &str {
+    let line_start = text[..offset].rfind('\n').map_or(0, |i| i + 1);
+    let line = &text[line_start..];
+    &line[..line.len() - line.trim_start().len()]
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use rls_analysis::SigElement;
+
+    const TEXT: &str = "trait TestTrait {
+    type Output;
+    const LIMIT: u32;
+
+    fn test_method(&self);
+    fn provided(&self) -> u32 {
+        0
+    }
+    fn other(&self, x: u32) -> Option<Self::Output>;
+}
+
+struct Foo;
+
+impl TestTrait for Foo {
+    type Output = u32;
+    const LIMIT: u32 = 1;
+
+    fn other(&self, x: u32) -> Option<u32> {
+        Some(x)
+    }
+}
+";
+
+    #[test]
+    fn finds_impl_blocks() {
+        let offset = TEXT.find("Some(x)").unwrap();
+        let block = impl_block_at(TEXT, offset).unwrap();
+        assert!(TEXT[block.header_start..].starts_with("impl TestTrait for Foo {"));
+        assert_eq!(&TEXT[block.open..=block.open], "{");
+        assert_eq!(block.close, TEXT.len() - 2);
+
+        // Trait definitions and inherent impls aren't trait impls.
+        assert_eq!(impl_block_at(TEXT, TEXT.find("type Output;").unwrap()), None);
+        assert_eq!(impl_block_at("impl Foo {\n    \n}", 15), None);
+        assert!(impl_block_at("#[derive(Debug)]\nunsafe impl Send for Foo {}", 43).is_some());
+    }
+
+    #[test]
+    fn finds_implemented_items() {
+        let block = impl_block_at(TEXT, TEXT.find("Some(x)").unwrap()).unwrap();
+        assert_eq!(
+            implemented_items(&TEXT[block.open + 1..block.close]),
+            ["Output", "LIMIT", "other"]
+        );
+    }
+
+    #[test]
+    fn stubs_required_items() {
+        let stub = |kind, name, row| stub(TEXT, kind, name, row, None, "    ");
+        assert_eq!(
+            stub(DefKind::Method, "test_method", 4).unwrap(),
+            "    fn test_method(&self) {\n        todo!()\n    }"
+        );
+        assert_eq!(stub(DefKind::Const, "LIMIT", 2).unwrap(), "    const LIMIT: u32 = todo!();");
+        assert_eq!(stub(DefKind::Type, "Output", 1).unwrap(), "    type Output = _;");
+        assert_eq!(stub(DefKind::Method, "provided", 5), None);
+    }
+
+    fn sig(text: &str, name: &str) -> Signature {
+        let start = text.find(&format!(" {}", name)).unwrap() + 1;
+        let def = SigElement { id: rls_analysis::NULL, start, end: start + name.len() };
+        Signature { text: text.to_owned(), defs: vec![def], refs: vec![] }
+    }
+
+    #[test]
+    fn stubs_from_signatures() {
+        let stub = |kind, name, row, text| {
+            stub(TEXT, kind, name, row, Some(&sig(text, name)), "    ").unwrap()
+        };
+        assert_eq!(
+            stub(
+                DefKind::Method,
+                "other",
+                8,
+                "fn other(: & Self,: u32,) -> Option<Self::Output> {}"
+            ),
+            "    fn other(&self, _: u32) -> Option<Self::Output> {\n        todo!()\n    }"
+        );
+        assert_eq!(
+            stub(DefKind::Const, "LIMIT", 2, "const LIMIT: u32;"),
+            "    const LIMIT: u32 = todo!();"
+        );
+
+        let decl = |text| sig_declaration(DefKind::Method, &sig(text, "f")).unwrap();
+        assert_eq!(decl("fn f(: u32,) -> Self {}"), "fn f(_: u32) -> Self");
+        assert_eq!(decl("fn f(: & 'a mut Self,: & Self,) {}"), "fn f(&'a mut self, _: &Self)");
+        assert_eq!(
+            decl("fn f<F: Fn(u32) -> u32>(: Self,: (F, u32),) where F: Clone, {}"),
+            "fn f<F: Fn(u32) -> u32>(self, _: (F, u32)) where F: Clone,"
+        );
+    }
+
+    #[test]
+    fn skips_items_with_defaults() {
+        assert!(has_default("type Output = u32"));
+        assert!(has_default("const LIMIT: u32 = 1"));
+        assert!(!has_default("type Iter: Iterator<Item = u32>"));
+        assert!(!has_default("type F: Fn() -> Option<u32>"));
+    }
+}
@@ -0,0 +1,155 @@
+//! Completion of enum variants after the enum's path (e.g., `Foo::|` in a
+//! match arm), using the variants of the enum from the analysis data.
+
+use std::path::Path;
+
+use lazy_static::lazy_static;
+use regex::Regex;
+use rls_analysis::{DefKind, EnumVariant, VariantKind};
+use rls_span as span;
+
+use crate::actions::InitActionContext;
+use crate::lsp_data::{CompletionItem, CompletionItemKind, InsertTextFormat, Position};
+use crate::Span;
+
+/// Returns the variants of the enum whose path is before the cursor, or `None`
+/// if the cursor isn't after `Name::` or `Name` isn't a known enum.
+pub fn complete(
+    ctx: &InitActionContext,
+    file_path: &Path,
+    position: Position,
+) -> Option<Vec<CompletionItem>> {
+    let row = span::Row::new_zero_indexed(position.line as u32);
+    let line = ctx.vfs.load_line(file_path, row).ok()?;
+    let prefix: String = line.chars().take(position.character as usize).collect();
+    let (col_start, col_end) = enum_name_before(&prefix)?;
+
+    let name_span = Span::new(
+        row,
+        row,
+        span::Column::new_zero_indexed(col_start),
+        span::Column::new_zero_indexed(col_end),
+        file_path,
+    );
+    let is_enum = |id| ctx.analysis.get_def(id).map_or(false, |def| def.kind == DefKind::Enum);
+    let id = match ctx.analysis.id(&name_span) {
+        Ok(id) if is_enum(id) => id,
+        Ok(_) => return None,
+        // The path may have been typed since the last build, so fall back to
+        // the enum with that name, if there is only one.
+        Err(_) => {
+            let name: String = prefix
+                .chars()
+                .skip(col_start as usize)
+                .take((col_end - col_start) as usize)
+                .collect();
+            let mut enums =
+                ctx.analysis.search_for_id(&name).ok()?.into_iter().filter(|&id| is_enum(id));
+            match (enums.next(), enums.next()) {
+                (Some(id), None) => id,
+                _ => return None,
+            }
+        }
+    };
+
+    let snippets = ctx.client_capabilities.code_completion_has_snippet_support;
+    let variants = ctx.analysis.enum_variants(id).ok()?;
+    Some(
+        variants
+            .into_iter()
+            .map(|variant| {
+                let mut item =
+                    CompletionItem::new_simple(variant.name.clone(), signature(&variant));
+                item.kind = Some(CompletionItemKind::EnumMember);
+                if let Some(snippet) = snippet(&variant).filter(|_| snippets) {
+                    item.insert_text = Some(snippet);
+                    item.insert_text_format = Some(InsertTextFormat::Snippet);
+                }
+                item
+            })
+            .collect(),
+    )
+}
+
+/// Returns the (character) columns of `Name` if `prefix` ends with `Name::`,
+/// followed by the start of an identifier.
+fn enum_name_before(prefix: &str) -> Option<(u32, u32)> {
+    lazy_static! {
+        static ref PATH_RE: Regex = Regex::new(r"(?P<name>\w+)::\w*$").unwrap();
+    }
+
+    let name = PATH_RE.captures(prefix)?.name("name")?;
+    let start = prefix[..name.start()].chars().count() as u32;
+    Some((start, start + name.as_str().chars().count() as u32))
+}
+
+/// Describes the shape of a variant, e.g., `Move { x, y }`.
+fn signature(variant: &EnumVariant) -> String {
+    match variant.kind {
+        VariantKind::Unit => variant.name.clone(),
+        VariantKind::Tuple => format!("{}(..)", variant.name),
+        VariantKind::Struct => format!("{} {{ {} }}", variant.name, variant.fields.join(", ")),
+    }
+}
+
+/// Returns a snippet with a placeholder for each field of the variant, or
+/// `None` for unit variants.
+fn snippet(variant: &EnumVariant) -> Option<String> {
+    let mut placeholders: Vec<String> = variant
+        .fields
+        .iter()
+        .enumerate()
+        .map(|(i, field)| match variant.kind {
+            VariantKind::Struct => format!("{}: ${{{}:{}}}", field, i + 1, field),
+            _ => format!("${{{}:_}}", i + 1),
+        })
+        .collect();
+    if placeholders.is_empty() {
+        placeholders.push("$1".to_owned());
+    }
+
+    match variant.kind {
+        VariantKind::Unit => None,
+        VariantKind::Tuple => Some(format!("{}({})", variant.name, placeholders.join(", "))),
+        VariantKind::Struct => Some(format!("{} {{ {} }}", variant.name, placeholders.join(", "))),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use rls_analysis::NULL;
+
+    fn variant(name: &str, kind: VariantKind, fields: &[&str]) -> EnumVariant {
+        EnumVariant {
+            id: NULL,
+            name: name.to_owned(),
+            kind,
+            fields: fields.iter().map(|&f| f.to_owned()).collect(),
+        }
+    }
+
+    #[test]
+    fn finds_enum_name() {
+        assert_eq!(enum_name_before("    match x { FooEnum::"), Some((14, 21)));
+        assert_eq!(enum_name_before("    types::FooEnum::Str"), Some((11, 18)));
+        assert_eq!(enum_name_before("let é = Foo::"), Some((8, 11)));
+        assert_eq!(enum_name_before("    FooEnum"), None);
+        assert_eq!(enum_name_before("    FooEnum::Variant "), None);
+    }
+
+    #[test]
+    fn variant_snippets() {
+        let unit = variant("Quit", VariantKind::Unit, &[]);
+        assert_eq!(signature(&unit), "Quit");
+        assert_eq!(snippet(&unit), None);
+
+        let tuple = variant("Write", VariantKind::Tuple, &["0", "1"]);
+        assert_eq!(signature(&tuple), "Write(..)");
+        assert_eq!(snippet(&tuple).unwrap(), "Write(${1:_}, ${2:_})");
+
+        let strukt = variant("Move", VariantKind::Struct, &["x", "y"]);
+        assert_eq!(signature(&strukt), "Move { x, y }");
+        assert_eq!(snippet(&strukt).unwrap(), "Move { x: ${1:x}, y: ${2:y} }");
+    }
+}
@@ -1,4 +1,4 @@
-use crate::server::DEFAULT_REQUEST_TIMEOUT;
+use crate::server::{logging, DEFAULT_REQUEST_TIMEOUT};
 use lazy_static::lazy_static;
 use log::{info, warn};
 use std::sync::{mpsc, Mutex};
@@ -74,7 +74,9 @@ where
         work.push(description);
     }
 
+    let correlation_id = logging::current();
     WORK_POOL.spawn(move || {
+        let _correlation = logging::enter(correlation_id);
         let start = Instant::now();
 
         // panic details will be on stderr, otherwise ignore the work panic as it
@@ -516,9 +516,9 @@ impl Executor for RlsExecutor {
 
             let (crate_blacklist, full_docs) = {
                 let config = self.config.lock().unwrap();
-                (config.crate_blacklist.clone(), *config.full_docs.clone().as_ref())
+                (config.crate_blacklist(), *config.full_docs.clone().as_ref())
             };
-            if crate_blacklist.as_ref().0.contains(&crate_name) {
+            if crate_blacklist.0.contains(&crate_name) {
                 // By running the original command (rather than using our shim), we
                 // avoid producing save-analysis data.
                 trace!("crate is blacklisted");
@@ -540,6 +540,14 @@ impl Executor for RlsExecutor {
 
         self.reached_primary.store(true, Ordering::SeqCst);
 
+        if self.config.lock().unwrap().crate_blacklist().0.contains(&crate_name) {
+            warn!(
+                "workspace member `{}` is in the crate blacklist, so its analysis data won't be \
+                 loaded and features such as goto-definition won't work in it",
+                crate_name
+            );
+        }
+
         // Cache executed command for the build plan.
         {
             let mut cx = self.compilation_cx.lock().unwrap();
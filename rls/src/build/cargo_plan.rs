@@ -28,6 +28,7 @@ use log::{error, trace};
 use crate::build::plan::{BuildGraph, BuildKey, JobQueue, WorkStatus};
 use crate::build::rustc::src_path;
 use crate::build::PackageArg;
+use crate::lsp_data::CrateCfgs;
 
 /// Main key type by which `Unit`s will be distinguished in the build plan.
 /// In `Target` we're mostly interested in `TargetKind` (Lib, Bin, ...) and name
@@ -385,6 +386,54 @@ impl CargoPlan {
             }
         }
     }
+
+    /// Returns the `cfg` state of each unit which `file` is an input of, as
+    /// given to the compiler when the unit was last built.
+    pub(crate) fn active_cfgs(&self, file: &Path) -> Vec<CrateCfgs> {
+        // The input files are canonicalized when they're cached.
+        let file = std::fs::canonicalize(file).unwrap_or_else(|_| file.to_owned());
+        let mut cfgs: Vec<_> = self
+            .file_key_mapping
+            .get(&file)
+            .into_iter()
+            .flatten()
+            .filter_map(|key| self.compiler_jobs.get(key))
+            .map(|job| {
+                let args: Vec<_> =
+                    job.get_args().map(|arg| arg.to_string_lossy().into_owned()).collect();
+                crate_cfgs(&args)
+            })
+            .collect();
+        cfgs.sort_by(|a, b| {
+            (&a.crate_name, &a.crate_type, a.test).cmp(&(&b.crate_name, &b.crate_type, b.test))
+        });
+        cfgs
+    }
+}
+
+/// Extracts the `cfg` state of a crate from the arguments of its compiler
+/// invocation.
+fn crate_cfgs(args: &[String]) -> CrateCfgs {
+    let mut cfgs = CrateCfgs::default();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--crate-name" => cfgs.crate_name = args.next().cloned().unwrap_or_default(),
+            "--crate-type" => cfgs.crate_type = args.next().cloned(),
+            "--target" => cfgs.target = args.next().cloned(),
+            "--test" => cfgs.test = true,
+            "--cfg" => {
+                if let Some(cfg) = args.next() {
+                    match cfg.strip_prefix("feature=") {
+                        Some(feature) => cfgs.features.push(feature.trim_matches('"').to_owned()),
+                        None => cfgs.cfgs.push(cfg.clone()),
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+    cfgs
 }
 
 /// Maps paths to packages.
@@ -577,3 +626,49 @@ impl BuildGraph for CargoPlan {
         CargoPlan::prepare_work(self, files)
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|&arg| arg.to_owned()).collect()
+    }
+
+    #[test]
+    fn extracts_crate_cfgs() {
+        let cfgs = crate_cfgs(&args(&[
+            "--crate-name",
+            "foo",
+            "--edition=2018",
+            "src/lib.rs",
+            "--crate-type",
+            "lib",
+            "--test",
+            "--cfg",
+            r#"feature="default""#,
+            "--cfg",
+            r#"feature="extra""#,
+            "--cfg",
+            "has_foo",
+            "--target",
+            "wasm32-unknown-unknown",
+        ]));
+        assert_eq!(
+            cfgs,
+            CrateCfgs {
+                crate_name: "foo".to_owned(),
+                crate_type: Some("lib".to_owned()),
+                test: true,
+                target: Some("wasm32-unknown-unknown".to_owned()),
+                features: vec!["default".to_owned(), "extra".to_owned()],
+                cfgs: vec!["has_foo".to_owned()],
+            }
+        );
+
+        let cfgs = crate_cfgs(&args(&["--crate-name", "bar", "--crate-type", "bin"]));
+        assert!(!cfgs.test);
+        assert_eq!(cfgs.target, None);
+        assert!(cfgs.features.is_empty());
+    }
+}
@@ -1,6 +1,8 @@
 //! Running builds as-needed for the server to answer questions.
 
 use std::collections::{HashMap, HashSet};
+use std::ffi::OsStr;
+use std::fs;
 use std::io::{self, Write};
 use std::mem;
 use std::path::{Path, PathBuf};
@@ -20,7 +22,8 @@ pub use self::plan::{Crate, Edition};
 use crate::actions::post_build::PostBuildHandler;
 use crate::actions::progress::{ProgressNotifier, ProgressUpdate};
 use crate::config::Config;
-use crate::lsp_data::Range;
+use crate::lsp_data::{BuildRecoveryParams, CrateCfgs, Range};
+use crate::server::logging;
 
 mod cargo;
 mod cargo_plan;
@@ -30,6 +33,7 @@ mod external;
 mod ipc;
 mod plan;
 mod rustc;
+mod standalone;
 
 /// Manages builds.
 ///
@@ -69,6 +73,12 @@ pub struct BuildQueue {
 /// Used when tracking modified files across different builds.
 type FileVersion = u64;
 
+/// Number of consecutive failed builds after which the build environment is reset.
+const RECOVERY_THRESHOLD: usize = 3;
+/// Minimum time between two resets of the build environment, so that builds
+/// which fail regardless don't keep clearing the target directory.
+const RECOVERY_INTERVAL: Duration = Duration::from_secs(10 * 60);
+
 // Information needed to run and configure builds.
 struct Internals {
     // Arguments and environment with which we call rustc.
@@ -86,6 +96,38 @@ struct Internals {
     /// resumed when there are no builds to run.
     blocked: Mutex<Vec<thread::Thread>>,
     last_build_duration: RwLock<Option<Duration>>,
+    watchdog: Mutex<BuildWatchdog>,
+}
+
+/// Tracks consecutive build errors to decide when the build environment (e.g.,
+/// a wedged target directory) should be reset.
+#[derive(Debug, Default)]
+struct BuildWatchdog {
+    consecutive_errors: usize,
+    last_recovery: Option<Instant>,
+}
+
+impl BuildWatchdog {
+    /// Records the result of a build. Returns the number of consecutive failed
+    /// builds if the build environment should be reset now.
+    fn record(&mut self, result: &BuildResult, now: Instant) -> Option<usize> {
+        match result {
+            BuildResult::Err(..) => self.consecutive_errors += 1,
+            BuildResult::Success(..) => self.consecutive_errors = 0,
+            // Squashed builds and Cargo errors (e.g., a broken manifest) say
+            // nothing about the state of the build environment.
+            BuildResult::Squashed | BuildResult::CargoError { .. } => return None,
+        }
+
+        if self.consecutive_errors < RECOVERY_THRESHOLD {
+            return None;
+        }
+        if self.last_recovery.map_or(false, |last| now.duration_since(last) < RECOVERY_INTERVAL) {
+            return None;
+        }
+        self.last_recovery = Some(now);
+        Some(mem::replace(&mut self.consecutive_errors, 0))
+    }
 }
 
 /// The result of a build request.
@@ -184,6 +226,8 @@ struct PendingBuild {
     built_files: HashMap<PathBuf, FileVersion>,
     notifier: Box<dyn ProgressNotifier>,
     pbh: PostBuildHandler,
+    // Correlation ID of the message which requested the build, for logging.
+    correlation_id: Option<String>,
 }
 
 impl Build {
@@ -269,6 +313,7 @@ impl BuildQueue {
             priority,
             notifier,
             pbh,
+            correlation_id: logging::current(),
         };
 
         let mut queued = self.queued.lock().unwrap();
@@ -309,6 +354,17 @@ impl BuildQueue {
         }
     }
 
+    /// Returns the `cfg` state of each crate which `file` was compiled as part
+    /// of in the last Cargo build. Returns nothing for external build plans,
+    /// which don't map files to compiler invocations.
+    pub fn active_cfgs(&self, file: &Path) -> Vec<CrateCfgs> {
+        let cx = self.internals.compilation_cx.lock().unwrap();
+        match cx.build_plan {
+            BuildPlan::Cargo(ref plan) => plan.active_cfgs(file),
+            BuildPlan::External(_) => vec![],
+        }
+    }
+
     /// Essentially this is the opposite of 'would block' (see `block_on_build`). If this is
     /// true, then it is safe to rely on data from the build.
     pub fn build_ready(&self) -> bool {
@@ -358,6 +414,8 @@ impl BuildQueue {
                 }
             };
 
+            let _correlation = logging::enter(build.correlation_id.clone());
+
             // Normal priority threads sleep before starting up.
             if build.priority == BuildPriority::Normal {
                 let build_wait = internals.build_wait();
@@ -415,6 +473,17 @@ impl BuildQueue {
 
             // wait for progress to complete before starting analysis
             progress_thread.join().expect("progress-notifier panicked!");
+
+            let recovery = internals.watchdog.lock().unwrap().record(&result, Instant::now());
+            if let Some(failed_builds) = recovery {
+                let target_dir = internals.recover();
+                info!(
+                    "{} builds failed, reset build environment ({:?})",
+                    failed_builds, target_dir
+                );
+                pbh.notifier
+                    .notify_build_recovery(BuildRecoveryParams { failed_builds, target_dir });
+            }
             pbh.handle(result);
 
             // Remove the in-progress marker from the build queue.
@@ -449,6 +518,28 @@ impl Internals {
             building: AtomicBool::new(false),
             blocked: Mutex::new(vec![]),
             last_build_duration: RwLock::default(),
+            watchdog: Mutex::default(),
+        }
+    }
+
+    // Resets the build environment after repeated build failures: the next
+    // build re-runs Cargo and the RLS target directory is cleared. Returns the
+    // cleared directory, if any.
+    fn recover(&self) -> Option<PathBuf> {
+        self.compilation_cx.lock().unwrap().needs_rebuild = true;
+
+        let target_dir = self.config.lock().unwrap().target_dir.as_ref().clone()?;
+        // Only ever remove the `rls` subdirectory we build into, never a target
+        // directory shared with regular Cargo builds.
+        if target_dir.file_name() != Some(OsStr::new("rls")) {
+            return None;
+        }
+        match fs::remove_dir_all(&target_dir) {
+            Ok(()) => Some(target_dir),
+            Err(e) => {
+                info!("could not clear target directory {:?}: {}", target_dir, e);
+                None
+            }
         }
     }
 
@@ -521,7 +612,8 @@ impl Internals {
             let needs_rebuild = cx.needs_rebuild;
 
             // Check if an external build command was provided and execute that, instead.
-            if let Some(cmd) = self.config.lock().unwrap().build_command.clone() {
+            let build_command = self.config.lock().unwrap().build_command.clone();
+            if let Some(cmd) = build_command {
                 match (needs_rebuild, &cx.build_plan) {
                     (false, BuildPlan::External(ref plan)) => plan.prepare_work(&modified),
                     // We need to rebuild; regenerate the build plan if possible.
@@ -537,6 +629,21 @@ impl Internals {
                         }
                     },
                 }
+            // Without a manifest, compile the Rust files on their own.
+            } else if standalone::is_standalone(&build_dir) {
+                let config = self.config.lock().unwrap();
+                match standalone::job_queue(&self.vfs, &build_dir, &config) {
+                    Some(job_queue) => WorkStatus::Execute(job_queue),
+                    None => {
+                        return BuildResult::Success(
+                            build_dir,
+                            vec![],
+                            vec![],
+                            HashMap::new(),
+                            true,
+                        )
+                    }
+                }
             // Fall back to Cargo.
             } else {
                 // Cargo plan is recreated and `needs_rebuild` reset if we run `cargo::cargo()`.
@@ -638,3 +745,38 @@ fn dont_auto_tune_build_wait_configured() {
     *i.last_build_duration.write().unwrap() = Some(Duration::from_millis(70));
     assert_eq!(i.build_wait(), Duration::from_millis(350));
 }
+
+#[test]
+fn build_watchdog_recovers_once() {
+    let err = || BuildResult::Err("error".to_owned(), None);
+    let mut watchdog = BuildWatchdog::default();
+    let now = Instant::now();
+
+    for _ in 1..RECOVERY_THRESHOLD {
+        assert_eq!(watchdog.record(&err(), now), None);
+    }
+    assert_eq!(watchdog.record(&err(), now), Some(RECOVERY_THRESHOLD));
+
+    // Failures keep coming, but recovery is rate-limited.
+    for _ in 0..2 * RECOVERY_THRESHOLD {
+        assert_eq!(watchdog.record(&err(), now + Duration::from_secs(1)), None);
+    }
+    // Squashed builds don't reset the count...
+    assert_eq!(watchdog.record(&BuildResult::Squashed, now), None);
+    // ... but once enough time has passed, the environment is reset again.
+    assert_eq!(watchdog.record(&err(), now + RECOVERY_INTERVAL), Some(2 * RECOVERY_THRESHOLD + 1));
+}
+
+#[test]
+fn build_watchdog_resets_on_success() {
+    let err = || BuildResult::Err("error".to_owned(), None);
+    let success = BuildResult::Success(PathBuf::new(), vec![], vec![], HashMap::new(), false);
+    let mut watchdog = BuildWatchdog::default();
+    let now = Instant::now();
+
+    for _ in 1..RECOVERY_THRESHOLD {
+        assert_eq!(watchdog.record(&err(), now), None);
+    }
+    assert_eq!(watchdog.record(&success, now), None);
+    assert_eq!(watchdog.record(&err(), now), None);
+}
@@ -0,0 +1,195 @@
+//! Builds of a directory which isn't in a Cargo project, e.g., of a lone `.rs`
+//! file opened on its own. Without a manifest there is nothing for Cargo to
+//! build, so each Rust file directly in the directory which isn't a module of
+//! another one is compiled as a crate of its own, against the standard library
+//! only: crates which a file uses are reported as unresolved, but the analysis
+//! data covers the rest of the file.
+
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashSet;
+use std::env;
+use std::fs;
+use std::hash::{Hash, Hasher};
+use std::path::{Path, PathBuf};
+
+use cargo::util::important_paths::find_root_manifest_for_wd;
+use cargo_util::ProcessBuilder;
+use lazy_static::lazy_static;
+use regex::Regex;
+use rls_vfs::{FileContents, Vfs};
+
+use crate::build::plan::{Edition, JobQueue};
+use crate::config::Config;
+
+/// Returns `true` if there is no Cargo manifest in `build_dir` or any of its
+/// parents.
+pub(super) fn is_standalone(build_dir: &Path) -> bool {
+    find_root_manifest_for_wd(build_dir).is_err()
+}
+
+/// Returns the compiler invocations for the crate roots directly in
+/// `build_dir`, or `None` if there are none.
+pub(super) fn job_queue(vfs: &Vfs, build_dir: &Path, config: &Config) -> Option<JobQueue> {
+    // There is no workspace to infer a target directory from, so each
+    // directory gets its own one, lest builds of different directories (e.g.,
+    // by several RLS instances) overwrite each other's output.
+    let out_dir = config.target_dir.as_ref().clone().unwrap_or_else(|| {
+        let mut hasher = DefaultHasher::new();
+        build_dir.hash(&mut hasher);
+        env::temp_dir().join("rls-standalone").join(format!("{:016x}", hasher.finish()))
+    });
+
+    let mut files: Vec<PathBuf> = fs::read_dir(build_dir)
+        .ok()?
+        .filter_map(|entry| Some(entry.ok()?.path()))
+        .filter(|path| path.is_file() && path.extension().map_or(false, |ext| ext == "rs"))
+        .collect();
+    files.sort();
+    let files: Vec<(PathBuf, String)> = files
+        .into_iter()
+        .map(|file| {
+            let text = match vfs.load_file(&file) {
+                Ok(FileContents::Text(text)) => text,
+                _ => String::new(),
+            };
+            (file, text)
+        })
+        .collect();
+
+    let jobs: Vec<_> = crate_roots(&files)
+        .map(|(file, text)| {
+            let mut cmd = ProcessBuilder::new("rustc");
+            cmd.args(&standalone_args(file, text, config.default_edition, &out_dir));
+            cmd.cwd(build_dir);
+            cmd
+        })
+        .collect();
+    if jobs.is_empty() {
+        None
+    } else {
+        Some(JobQueue::with_commands(jobs))
+    }
+}
+
+/// Returns those of `files` (paths in the same directory and their contents)
+/// which aren't modules of another one, i.e., aren't declared by a `mod foo;`
+/// item or named by a `#[path = "foo.rs"]` attribute in another file. These
+/// are compiled along with the crate root declaring them.
+fn crate_roots(files: &[(PathBuf, String)]) -> impl Iterator<Item = &(PathBuf, String)> {
+    lazy_static! {
+        static ref MOD_RE: Regex =
+            Regex::new(r"(?m)^\s*(?:pub(?:\([^)]*\))?\s+)?mod\s+(?:r#)?(\w+)\s*;").unwrap();
+        static ref PATH_RE: Regex =
+            Regex::new(r#"(?m)^\s*#\[\s*path\s*=\s*"([^"]+)"\s*\]"#).unwrap();
+    }
+
+    let mut modules = HashSet::new();
+    for (file, text) in files {
+        let declared = MOD_RE.captures_iter(text).map(|c| format!("{}.rs", &c[1]));
+        let named = PATH_RE.captures_iter(text).map(|c| c[1].to_owned());
+        modules.extend(
+            declared
+                .chain(named)
+                .map(|module| file.with_file_name(module))
+                .filter(|module| module != file),
+        );
+    }
+    files.iter().filter(move |(file, _)| !modules.contains(file))
+}
+
+/// Returns the arguments compiling `file`, whose contents are `text`, on its
+/// own. It is compiled as a binary if it defines `main` and as a library
+/// otherwise, with the compiler's default edition unless `edition` is given.
+fn standalone_args(
+    file: &Path,
+    text: &str,
+    edition: Option<Edition>,
+    out_dir: &Path,
+) -> Vec<String> {
+    lazy_static! {
+        static ref MAIN_RE: Regex = Regex::new(r"(?m)^\s*(?:pub\s+)?fn\s+main\s*\(").unwrap();
+    }
+
+    let crate_type = if MAIN_RE.is_match(text) { "bin" } else { "lib" };
+    let mut args = vec![
+        "--crate-name".to_owned(),
+        crate_name(file),
+        "--crate-type".to_owned(),
+        crate_type.to_owned(),
+        file.display().to_string(),
+        "--emit=metadata".to_owned(),
+        "--out-dir".to_owned(),
+        out_dir.display().to_string(),
+    ];
+    if let Some(edition) = edition {
+        let edition = match edition {
+            Edition::Edition2015 => "2015",
+            Edition::Edition2018 => "2018",
+            Edition::Edition2021 => "2021",
+            Edition::Edition2024 => "2024",
+        };
+        args.push(format!("--edition={}", edition));
+    }
+    args
+}
+
+/// Returns the name of the crate of `file`, made of its stem like Cargo does
+/// for targets, e.g., `hello_world` for `hello-world.rs`.
+fn crate_name(file: &Path) -> String {
+    let stem = file.file_stem().and_then(|stem| stem.to_str()).unwrap_or("main");
+    let name: String =
+        stem.chars().map(|c| if c.is_alphanumeric() || c == '_' { c } else { '_' }).collect();
+    if name.starts_with(|c: char| c.is_ascii_digit()) {
+        format!("_{}", name)
+    } else {
+        name
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn standalone_crate_names() {
+        assert_eq!(crate_name(Path::new("/some/dir/main.rs")), "main");
+        assert_eq!(crate_name(Path::new("/some/dir/hello-world.rs")), "hello_world");
+        assert_eq!(crate_name(Path::new("/some/dir/2048.rs")), "_2048");
+    }
+
+    #[test]
+    fn standalone_crate_roots() {
+        let files: Vec<_> = [
+            ("main.rs", "mod foo;\npub(crate) mod bar;\n// mod baz;\n\nfn main() {}\n"),
+            ("foo.rs", "#[path = \"generated.rs\"]\nmod generated;\n"),
+            ("bar.rs", ""),
+            ("baz.rs", "fn main() {}\n"),
+            ("generated.rs", ""),
+            ("lib.rs", "mod lib;\n"),
+        ]
+        .iter()
+        .map(|(name, text)| (Path::new("/some/dir").join(name), text.to_string()))
+        .collect();
+        let roots: Vec<_> = crate_roots(&files)
+            .map(|(file, _)| file.file_name().unwrap().to_str().unwrap())
+            .collect();
+        assert_eq!(roots, vec!["main.rs", "baz.rs", "lib.rs"]);
+    }
+
+    #[test]
+    fn standalone_crate_types() {
+        let out_dir = Path::new("/tmp/out");
+        let file = Path::new("/some/dir/foo.rs");
+        let crate_type = |text: &str| {
+            let args = standalone_args(file, text, None, out_dir);
+            args[args.iter().position(|arg| arg == "--crate-type").unwrap() + 1].clone()
+        };
+        assert_eq!(crate_type("fn main() {\n}\n"), "bin");
+        assert_eq!(crate_type("mod foo;\n\npub fn main () {}\n"), "bin");
+        assert_eq!(crate_type("pub fn main_loop() {}\n"), "lib");
+        assert_eq!(crate_type("// fn main() {}\n"), "lib");
+
+        let args = standalone_args(file, "", Some(Edition::Edition2021), out_dir);
+        assert_eq!(args.last().unwrap(), "--edition=2021");
+    }
+}
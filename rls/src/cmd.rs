@@ -4,6 +4,7 @@
 
 use crate::actions::requests;
 use crate::config::Config;
+use crate::lsp_data::InitializeRequestParams;
 use crate::server::{self, LsService, Notification, Request, RequestId};
 use rls_analysis::{AnalysisHost, Target};
 use rls_vfs::Vfs;
@@ -300,6 +301,7 @@ fn initialize(root_path: String) -> Request<server::InitializeRequest> {
         trace: Some(TraceOption::Off),
         workspace_folders: None,
     };
+    let params = InitializeRequestParams { params, work_done_progress: false };
     Request { id: next_id(), params, received: Instant::now(), _action: PhantomData }
 }
 
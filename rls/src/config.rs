@@ -2,6 +2,7 @@
 //! tweaking the RLS's behavior itself.
 
 use std::collections::HashMap;
+use std::convert::TryFrom;
 use std::env;
 use std::fmt;
 use std::fmt::Debug;
@@ -18,9 +19,12 @@ use cargo::CargoResult;
 use serde::de::{Deserialize, Deserializer, Visitor};
 use serde_derive::{Deserialize, Serialize};
 
-use log::trace;
+use log::{trace, warn};
+
+use crate::build::Edition;
 
 use rustfmt_nightly::Config as RustfmtConfig;
+use rustfmt_nightly::Edition as RustfmtEdition;
 use rustfmt_nightly::{load_config, CliOptions, EmitMode, Verbosity};
 
 /// Some values in the config can be inferred without an explicit value set by
@@ -93,6 +97,20 @@ impl<T: Clone + Debug> Inferrable<T> {
     }
 }
 
+impl<T: PartialEq> Inferrable<T> {
+    /// Returns `true` if the user changed this value to `new`. Inferred
+    /// values only follow from the rest of the configuration, so changes
+    /// between them don't count.
+    pub fn changed_to(&self, new: &Self) -> bool {
+        match (self, new) {
+            (Inferrable::Specified(old), Inferrable::Specified(new)) => old != new,
+            (Inferrable::Inferred(_), Inferrable::Inferred(_))
+            | (Inferrable::None, Inferrable::None) => false,
+            _ => true,
+        }
+    }
+}
+
 impl<T> AsRef<T> for Inferrable<T> {
     fn as_ref(&self) -> &T {
         match *self {
@@ -117,6 +135,38 @@ pub fn unstable_features_allowed() -> bool {
     option_env!("CFG_RELEASE_CHANNEL").map_or(true, |c| c == "nightly" || c == "dev")
 }
 
+/// Name of the optional per-workspace configuration file, read from the
+/// workspace root when the RLS is initialized.
+pub const WORKSPACE_CONFIG_FILE: &str = ".rls.toml";
+
+/// The capabilities which `disabled_capabilities` can name: those of the
+/// requests whose method ends with the name, e.g., `textDocument/formatting`.
+pub const DISABLEABLE_CAPABILITIES: &[&str] = &[
+    "hover",
+    "completion",
+    "definition",
+    "implementation",
+    "references",
+    "documentHighlight",
+    "documentSymbol",
+    "symbol",
+    "codeAction",
+    "codeLens",
+    "formatting",
+    "rangeFormatting",
+    "onTypeFormatting",
+    "rename",
+    "executeCommand",
+    "semanticTokens",
+    "callHierarchy",
+    "typeHierarchy",
+    "rls.applySuggestion",
+    "rls.deglobImports",
+    "rls.extractFunction",
+    "rls.rebuild",
+    "rls.reloadAnalysis",
+];
+
 /// RLS configuration options.
 #[derive(Clone, Debug, Deserialize)]
 #[allow(missing_docs)]
@@ -140,6 +190,38 @@ pub struct Config {
     /// Blacklist of crates for RLS to skip. By default omits `winapi`, Unicode
     /// table crates, `serde`, `libc`, `glium` and other.
     pub crate_blacklist: Inferrable<CrateBlacklist>,
+    /// More crates for RLS to skip, in addition to those of
+    /// `crate_blacklist`, e.g., crates of generated code. To replace the
+    /// default blacklist rather than extend it, set `crate_blacklist` instead.
+    /// Default: `[]`.
+    pub analysis_crate_blacklist: Vec<String>,
+    /// Globs of paths, relative to the project root, to leave out of the
+    /// analysis, e.g. `"generated/**"`. Crates whose source root matches are
+    /// not loaded and matching symbols are left out of workspace symbol
+    /// results. Default: `[]`.
+    pub analysis_exclude_globs: Vec<String>,
+    /// Extensions of files, besides `rs`, to treat as Rust, e.g., `"rs.in"`
+    /// for templates which a build script turns into code. Such files are
+    /// read as text and get completions, but are only compiled if a crate
+    /// includes them. Extensions of other languages, such as `toml`, are
+    /// ignored. Default: `[]`.
+    pub rust_file_extensions: Vec<String>,
+    /// The profile (`"debug"` or `"release"`) whose save-analysis data is
+    /// loaded from the target directory. Default: `"debug"`.
+    pub analysis_target: AnalysisTarget,
+    /// If set, drops the loaded analysis data to reclaim memory after this
+    /// many minutes without messages from the client and with no open files.
+    /// It is reloaded when next needed. Default: `None`.
+    pub idle_unload_minutes: Option<u64>,
+    /// Send an `rls/metrics` notification with the time taken to handle the
+    /// requests of each method after each request. Default: `false`.
+    pub request_metrics: bool,
+    /// Capabilities to leave out of those advertised to the client, named
+    /// after the methods of their requests, e.g., `"formatting"` for
+    /// `textDocument/formatting`, or single commands, e.g.,
+    /// `"rls.extractFunction"`. Requests for them are rejected. Only read on
+    /// initialization. Default: `[]`.
+    pub disabled_capabilities: Vec<String>,
     /// The Cargo target directory. If set, overrides the default one.
     pub target_dir: Inferrable<Option<PathBuf>>,
     pub features: Vec<String>,
@@ -152,6 +234,10 @@ pub struct Config {
     /// Enabled also enables racer fallbacks for hover and go-to-definition functionality
     /// if rustc analysis should fail.
     pub racer_completion: bool,
+    /// If the client supports snippets, function completions insert a call with
+    /// a placeholder for each parameter, e.g., `foo(${1:x}, ${2:y})`.
+    /// Default: `true`.
+    pub use_completion_snippets: bool,
     #[serde(deserialize_with = "deserialize_clippy_preference")]
     pub clippy_preference: ClippyPreference,
     /// Instructs cargo to enable full documentation extraction during save-analysis
@@ -165,6 +251,37 @@ pub struct Config {
     /// local variable declaration. When set to false, the content is only available when
     /// holding the `Ctrl` key in some editors.
     pub show_hover_context: bool,
+    /// Show the number of references to each item as a code lens above it.
+    /// Clicking it runs `editor.action.showReferences`, as in VS Code.
+    /// Default: `false`.
+    pub show_reference_lens: bool,
+    /// Return every definition a reference could refer to from go to
+    /// definition, e.g., both the field and the local variable of a field
+    /// init shorthand, rather than only the first one. Default: `false`.
+    pub goto_def_all: bool,
+    /// Use the diagnostics as rendered by rustc (without colors), including
+    /// the annotated source snippet, as the diagnostic messages. Otherwise the
+    /// rendered diagnostics aren't sent, since the `data` field of diagnostics
+    /// is newer (LSP 3.16) than `lsp_types`. Default: `false`.
+    pub use_rendered_diagnostics: bool,
+    /// Publish an information diagnostic for each comment starting with one
+    /// of the `todo_tags`. Default: `false`.
+    pub show_todo_diagnostics: bool,
+    /// The tags of the comments reported with `show_todo_diagnostics`.
+    /// Default: `["TODO", "FIXME", "XXX"]`.
+    pub todo_tags: Vec<String>,
+    /// The edition (e.g., `"2018"`) Racer uses to parse files which don't
+    /// belong to a known package, and with which files outside of any Cargo
+    /// project are compiled. Files in a package use its edition.
+    #[serde(deserialize_with = "deserialize_edition")]
+    pub default_edition: Option<Edition>,
+    /// The Rustfmt config file used for formatting, instead of the one found
+    /// in the project. Default: `None`.
+    pub rustfmt_config_path: Option<PathBuf>,
+    /// The edition used for formatting, instead of the one from the Rustfmt
+    /// config or the package. Default: `None`.
+    #[serde(deserialize_with = "deserialize_edition")]
+    pub rustfmt_edition: Option<Edition>,
     /// Use provided rustfmt binary instead of the statically linked one.
     /// (requires unstable features).
     pub rustfmt_path: Option<String>,
@@ -193,6 +310,13 @@ impl Default for Config {
             clear_env_rust_log: true,
             build_on_save: false,
             crate_blacklist: Inferrable::Inferred(CrateBlacklist::default()),
+            analysis_crate_blacklist: vec![],
+            analysis_exclude_globs: vec![],
+            rust_file_extensions: vec![],
+            analysis_target: AnalysisTarget::default(),
+            idle_unload_minutes: None,
+            request_metrics: false,
+            disabled_capabilities: vec![],
             target_dir: Inferrable::Inferred(None),
             features: vec![],
             all_features: false,
@@ -200,9 +324,18 @@ impl Default for Config {
             jobs: None,
             all_targets: true,
             racer_completion: true,
+            use_completion_snippets: true,
             clippy_preference: ClippyPreference::default(),
             full_docs: Inferrable::Inferred(false),
             show_hover_context: true,
+            show_reference_lens: false,
+            goto_def_all: false,
+            use_rendered_diagnostics: false,
+            show_todo_diagnostics: false,
+            todo_tags: vec!["TODO".to_owned(), "FIXME".to_owned(), "XXX".to_owned()],
+            default_edition: None,
+            rustfmt_config_path: None,
+            rustfmt_edition: None,
             rustfmt_path: None,
             build_command: None,
             use_crate_blacklist: None,
@@ -273,6 +406,53 @@ impl Config {
         Err(())
     }
 
+    /// Reads the options stored in the `.rls.toml` file in `project_dir`, as a
+    /// JSON object suitable for `try_deserialize`. Returns `None` if there is
+    /// no such file, or if it is not a valid configuration (in which case a
+    /// warning is logged).
+    pub fn load_workspace_file(
+        project_dir: &Path,
+    ) -> Option<serde_json::Map<String, serde_json::Value>> {
+        let path = project_dir.join(WORKSPACE_CONFIG_FILE);
+        let text = std::fs::read_to_string(&path).ok()?;
+
+        let settings = match toml::from_str::<serde_json::Value>(&text) {
+            Ok(serde_json::Value::Object(settings)) => settings,
+            Ok(_) => return None,
+            Err(e) => {
+                warn!("Ignoring invalid `{}`: {}", path.display(), e);
+                return None;
+            }
+        };
+
+        let value = serde_json::Value::Object(settings.clone());
+        if Config::try_deserialize(&value, &mut Default::default(), &mut vec![], &mut vec![])
+            .is_err()
+        {
+            warn!("Ignoring invalid `{}`: unexpected option value", path.display());
+            return None;
+        }
+
+        Some(settings)
+    }
+
+    /// Merges the options read by `load_workspace_file` into `settings` (the
+    /// `rust` object of the client-provided settings). Options already present
+    /// in `settings` take precedence over those from the file.
+    pub fn merge_workspace_settings(
+        settings: &mut serde_json::Map<String, serde_json::Value>,
+        file_settings: serde_json::Map<String, serde_json::Value>,
+    ) {
+        use heck::ToSnakeCase;
+
+        for (k, v) in file_settings {
+            let snake_case = k.to_snake_case();
+            if !settings.keys().any(|key| key.to_snake_case() == snake_case) {
+                settings.insert(k, v);
+            }
+        }
+    }
+
     /// Join this configuration with the new config.
     pub fn update(&mut self, mut new: Config) {
         macro_rules! combine_option_with_default {
@@ -290,6 +470,41 @@ impl Config {
         *self = new;
     }
 
+    /// Returns `true` if the project has to be rebuilt for the change from
+    /// this configuration to `new` to take effect. Options which only affect
+    /// how results are presented, such as `show_warnings`, don't need one.
+    pub fn needs_rebuild(&self, new: &Config) -> bool {
+        macro_rules! changed {
+            ($($field: ident),*) => { false $(|| self.$field != new.$field)* };
+        }
+        macro_rules! inferrable_changed {
+            ($($field: ident),*) => { false $(|| self.$field.changed_to(&new.$field))* };
+        }
+
+        changed!(
+            sysroot,
+            target,
+            rustflags,
+            cfg_test,
+            unstable_features,
+            clear_env_rust_log,
+            analysis_crate_blacklist,
+            analysis_exclude_globs,
+            analysis_target,
+            features,
+            all_features,
+            no_default_features,
+            jobs,
+            all_targets,
+            clippy_preference,
+            build_command,
+            use_rendered_diagnostics,
+            default_edition,
+            show_todo_diagnostics,
+            todo_tags
+        ) || inferrable_changed!(build_lib, build_bin, crate_blacklist, target_dir, full_docs)
+    }
+
     /// Ensures that unstable options are only allowed if `unstable_features` is
     /// true and that is not allowed on stable release channels.
     pub fn normalise(&mut self) {
@@ -319,17 +534,20 @@ impl Config {
     /// Specifically, this:
     /// - detects correct `target/` build directory used by Cargo, if not specified.
     pub fn infer_defaults(&mut self, project_dir: &Path) -> CargoResult<()> {
-        // Note that this may not be equal `build_dir` when inside a workspace member.
-        let manifest_path = important_paths::find_root_manifest_for_wd(project_dir)?;
-        trace!("root manifest_path: {:?}", &manifest_path);
-
-        let shell = Shell::from_write(Box::new(sink()));
-        let cwd = env::current_dir().expect("failed to get cwd");
-
-        let config = CargoConfig::new(shell, cwd, homedir(project_dir).unwrap());
+        with_workspace(project_dir, |ws| self.infer_defaults_for(project_dir, ws))
+    }
 
-        let ws = Workspace::new(&manifest_path, &config)?;
+    /// Infers the inferred values again, e.g., after a manifest changed, since
+    /// the project may have moved to a different workspace (and target
+    /// directory) since.
+    pub fn reinfer_defaults(&mut self, project_dir: &Path) -> CargoResult<()> {
+        if let Inferrable::Inferred(_) = self.target_dir {
+            self.target_dir = Inferrable::Inferred(None);
+        }
+        self.infer_defaults(project_dir)
+    }
 
+    fn infer_defaults_for(&mut self, project_dir: &Path, ws: &Workspace<'_>) -> CargoResult<()> {
         // Constructing a `Workspace` also probes the filesystem and detects where to place the
         // build artifacts. We need to rely on Cargo's behaviour directly not to possibly place our
         // own artifacts somewhere else (e.g., when analyzing only a single crate in a workspace).
@@ -353,6 +571,234 @@ impl Config {
 
         Ok(())
     }
+
+    /// Checks the configuration against the Cargo workspace at `project_dir`,
+    /// see `validate`.
+    pub fn validate_project(&self, project_dir: &Path) -> CargoResult<Vec<ConfigWarning>> {
+        with_workspace(project_dir, |ws| Ok(self.validate(ws)))
+    }
+
+    /// Looks for options which are accepted on their own but conflict with
+    /// each other or refer to targets which don't exist in the workspace.
+    pub fn validate(&self, ws: &Workspace<'_>) -> Vec<ConfigWarning> {
+        let mut warnings = vec![];
+
+        if let (Inferrable::Specified(true), Inferrable::Specified(Some(bin))) =
+            (&self.build_lib, &self.build_bin)
+        {
+            warnings.push(ConfigWarning::ConflictingTargets { bin: bin.clone() });
+        }
+
+        if let Some(bin) = self.build_bin.as_ref() {
+            let exists = ws
+                .members()
+                .flat_map(|pkg| pkg.targets())
+                .any(|target| target.is_bin() && target.name() == bin);
+            if !exists {
+                warnings.push(ConfigWarning::MissingBin(bin.clone()));
+            }
+        }
+
+        if let Some(jobs) = self.jobs {
+            if jobs <= 0 {
+                warnings.push(ConfigWarning::InvalidJobs(jobs));
+            }
+        }
+
+        warnings.extend(
+            self.analysis_exclude_globs.iter().filter_map(|glob| exclude_pattern(glob).err()),
+        );
+        warnings
+            .extend(self.rust_file_extensions.iter().filter_map(|ext| rust_extension(ext).err()));
+        warnings.extend(
+            self.disabled_capabilities
+                .iter()
+                .filter(|capability| !DISABLEABLE_CAPABILITIES.contains(&capability.as_str()))
+                .map(|capability| ConfigWarning::UnknownCapability(capability.clone())),
+        );
+
+        warnings
+    }
+
+    /// Returns the crates to skip: those of `crate_blacklist` (by default, the
+    /// built-in list) together with those of `analysis_crate_blacklist`.
+    pub fn crate_blacklist(&self) -> CrateBlacklist {
+        let blacklist = self.crate_blacklist.as_ref();
+        if self.analysis_crate_blacklist.is_empty() {
+            return blacklist.clone();
+        }
+
+        let mut crates = blacklist.0.to_vec();
+        for name in &self.analysis_crate_blacklist {
+            if !crates.contains(name) {
+                crates.push(name.clone());
+            }
+        }
+        CrateBlacklist(crates.into())
+    }
+
+    /// Returns the extensions of `rust_file_extensions` (without the leading
+    /// dot). Extensions rejected by `validate` are left out.
+    pub fn rust_file_extensions(&self) -> Vec<&str> {
+        self.rust_file_extensions.iter().filter_map(|ext| rust_extension(ext).ok()).collect()
+    }
+
+    /// Returns the paths excluded by `analysis_exclude_globs` in the project
+    /// at `project_dir`. Globs rejected by `validate` are ignored.
+    pub fn analysis_excludes(&self, project_dir: &Path) -> AnalysisExcludes {
+        AnalysisExcludes {
+            project_dir: project_dir.to_owned(),
+            patterns: self
+                .analysis_exclude_globs
+                .iter()
+                .filter_map(|glob| exclude_pattern(glob).ok())
+                .collect(),
+        }
+    }
+}
+
+/// Checks an extension of `rust_file_extensions`, rejecting empty ones and
+/// ones which would make files of another language (e.g., `toml` or `in.toml`)
+/// count as Rust.
+fn rust_extension(extension: &str) -> Result<&str, ConfigWarning> {
+    let trimmed = extension.trim_start_matches('.');
+    let last = trimmed.rsplit('.').next().unwrap_or(trimmed);
+    let other_language = rls_vfs::DEFAULT_LANGUAGES
+        .iter()
+        .any(|&(ext, language)| ext == last && language != rls_vfs::Language::Rust);
+    if trimmed.is_empty() || other_language {
+        return Err(ConfigWarning::InvalidRustExtension(extension.to_owned()));
+    }
+    Ok(trimmed)
+}
+
+/// Parses a glob of `analysis_exclude_globs`, rejecting ones which would
+/// exclude the whole project.
+fn exclude_pattern(glob: &str) -> Result<glob::Pattern, ConfigWarning> {
+    if glob.chars().all(|c| c == '*' || c == '/') {
+        return Err(ConfigWarning::ExcludesEverything(glob.to_owned()));
+    }
+    glob::Pattern::new(glob).map_err(|_| ConfigWarning::InvalidExcludeGlob(glob.to_owned()))
+}
+
+/// The paths excluded from the analysis, see `Config::analysis_excludes`.
+#[derive(Clone, Debug, Default)]
+pub struct AnalysisExcludes {
+    project_dir: PathBuf,
+    patterns: Vec<glob::Pattern>,
+}
+
+impl AnalysisExcludes {
+    /// Returns `true` if `path` matches one of the globs. Paths inside the
+    /// project are matched relative to its root.
+    pub fn is_excluded(&self, path: &Path) -> bool {
+        let path = path.strip_prefix(&self.project_dir).unwrap_or(path);
+        self.patterns.iter().any(|pattern| pattern.matches_path(path))
+    }
+}
+
+/// Runs `f` with the Cargo workspace the project at `project_dir` belongs to.
+fn with_workspace<T>(
+    project_dir: &Path,
+    f: impl FnOnce(&Workspace<'_>) -> CargoResult<T>,
+) -> CargoResult<T> {
+    // Note that this may not be equal `build_dir` when inside a workspace member.
+    let manifest_path = important_paths::find_root_manifest_for_wd(project_dir)?;
+    trace!("root manifest_path: {:?}", &manifest_path);
+
+    let shell = Shell::from_write(Box::new(sink()));
+    let cwd = env::current_dir().expect("failed to get cwd");
+
+    let config = CargoConfig::new(shell, cwd, homedir(project_dir).unwrap());
+
+    let ws = Workspace::new(&manifest_path, &config)?;
+    f(&ws)
+}
+
+/// A problem with the configuration found by `Config::validate`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ConfigWarning {
+    /// Both `build_lib` and `build_bin` were specified.
+    ConflictingTargets { bin: String },
+    /// `build_bin` names a binary which is not in the workspace.
+    MissingBin(String),
+    /// `jobs` is not a positive number.
+    InvalidJobs(i32),
+    /// A glob of `analysis_exclude_globs` can't be parsed.
+    InvalidExcludeGlob(String),
+    /// A glob of `analysis_exclude_globs` matches every path.
+    ExcludesEverything(String),
+    /// An extension of `rust_file_extensions` is empty or of another language.
+    InvalidRustExtension(String),
+    /// A capability of `disabled_capabilities` isn't one which can be disabled.
+    UnknownCapability(String),
+}
+
+impl fmt::Display for ConfigWarning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigWarning::ConflictingTargets { bin } => write!(
+                f,
+                "RLS configuration sets both `build_lib` and `build_bin` (`{}`), but only one \
+                 target can be built",
+                bin
+            ),
+            ConfigWarning::MissingBin(bin) => {
+                write!(f, "RLS configuration option `build_bin`: no binary named `{}`", bin)
+            }
+            ConfigWarning::InvalidJobs(jobs) => {
+                write!(f, "RLS configuration option `jobs` must be positive, got {}", jobs)
+            }
+            ConfigWarning::InvalidExcludeGlob(glob) => {
+                write!(
+                    f,
+                    "RLS configuration option `analysis_exclude_globs`: invalid glob `{}`",
+                    glob
+                )
+            }
+            ConfigWarning::ExcludesEverything(glob) => write!(
+                f,
+                "RLS configuration option `analysis_exclude_globs`: ignoring `{}`, which would \
+                 exclude the whole project",
+                glob
+            ),
+            ConfigWarning::InvalidRustExtension(ext) => write!(
+                f,
+                "RLS configuration option `rust_file_extensions`: ignoring `{}`, which isn't an \
+                 extension of Rust files",
+                ext
+            ),
+            ConfigWarning::UnknownCapability(capability) => write!(
+                f,
+                "RLS configuration option `disabled_capabilities`: `{}` isn't a capability \
+                 which can be disabled",
+                capability
+            ),
+        }
+    }
+}
+
+/// The profile whose save-analysis data is loaded, see `rls_analysis::Target`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AnalysisTarget {
+    Debug,
+    Release,
+}
+
+impl Default for AnalysisTarget {
+    fn default() -> Self {
+        AnalysisTarget::Debug
+    }
+}
+
+impl From<AnalysisTarget> for rls_analysis::Target {
+    fn from(target: AnalysisTarget) -> Self {
+        match target {
+            AnalysisTarget::Debug => rls_analysis::Target::Debug,
+            AnalysisTarget::Release => rls_analysis::Target::Release,
+        }
+    }
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
@@ -420,6 +866,19 @@ where
     deserializer.deserialize_any(ClippyPrefDeserializer(PhantomData))
 }
 
+/// Custom deserialization for an optional `Edition` given as, e.g., `"2018"`.
+fn deserialize_edition<'de, D>(deserializer: D) -> Result<Option<Edition>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    match Option::<String>::deserialize(deserializer)? {
+        Some(edition) => Edition::try_from(edition.as_str()).map(Some).map_err(|_| {
+            serde::de::Error::unknown_variant(&edition, &["2015", "2018", "2021", "2024"])
+        }),
+        None => Ok(None),
+    }
+}
+
 /// A Rustfmt config (typically specified via `rustfmt.toml`).
 /// The `FmtConfig` is not an exact translation of the config
 /// Rustfmt generates from the user's TOML file, since when
@@ -450,6 +909,52 @@ impl FmtConfig {
         FmtConfig::default()
     }
 
+    /// Loads the config file at `config_path` if one is given, otherwise looks
+    /// for one in `path` as `FmtConfig::from` does. A missing config file is
+    /// warned about and also falls back to the latter. The `edition`, if given,
+    /// overrides the one from the config file.
+    pub fn with_options(
+        path: &Path,
+        config_path: Option<&Path>,
+        edition: Option<Edition>,
+    ) -> FmtConfig {
+        struct ConfigPathOptions<'a>(&'a Path);
+
+        impl CliOptions for ConfigPathOptions<'_> {
+            fn apply_to(self, _: &mut RustfmtConfig) {}
+            fn config_path(&self) -> Option<&Path> {
+                Some(self.0)
+            }
+        }
+
+        let loaded = config_path.and_then(|config_path| {
+            if !config_path.is_file() {
+                warn!("Rustfmt config file `{}` does not exist", config_path.display());
+                return None;
+            }
+            match load_config(None, Some(ConfigPathOptions(config_path))) {
+                Ok((config, _)) => Some(config),
+                Err(e) => {
+                    warn!("Could not load Rustfmt config `{}`: {}", config_path.display(), e);
+                    None
+                }
+            }
+        });
+        let mut config = match loaded {
+            Some(config) => {
+                let mut config = FmtConfig(config);
+                config.set_rls_options();
+                config
+            }
+            None => FmtConfig::from(path),
+        };
+
+        if let Some(edition) = edition {
+            config.0.set().edition(edition.into());
+        }
+        config
+    }
+
     /// Returns an immutable borrow of the config; will always
     /// have any relevant RLS specific options set.
     pub fn get_rustfmt_config(&self) -> &RustfmtConfig {
@@ -473,6 +978,17 @@ impl Default for FmtConfig {
     }
 }
 
+impl From<Edition> for RustfmtEdition {
+    fn from(edition: Edition) -> RustfmtEdition {
+        match edition {
+            Edition::Edition2015 => RustfmtEdition::Edition2015,
+            Edition::Edition2018 => RustfmtEdition::Edition2018,
+            Edition::Edition2021 => RustfmtEdition::Edition2021,
+            Edition::Edition2024 => RustfmtEdition::Edition2024,
+        }
+    }
+}
+
 #[derive(Clone, Debug, PartialEq)]
 /// List of crates for which IDE analysis should not be generated
 pub struct CrateBlacklist(pub Arc<[String]>);
@@ -538,3 +1054,260 @@ fn blacklist_default() {
         Config::try_deserialize(&value, &mut Default::default(), &mut vec![], &mut vec![]).unwrap();
     assert_eq!(&*config.crate_blacklist.as_ref().0, &["serde".to_string()]);
 }
+
+#[test]
+fn blacklist_extended() {
+    let value = serde_json::json!({"analysis_crate_blacklist": ["generated", "serde"]});
+    let config =
+        Config::try_deserialize(&value, &mut Default::default(), &mut vec![], &mut vec![]).unwrap();
+    let blacklist = config.crate_blacklist();
+    assert!(blacklist.0.contains(&"generated".to_string()));
+    assert!(blacklist.0.contains(&"winapi".to_string()));
+    assert_eq!(blacklist.0.len(), CrateBlacklist::default().0.len() + 1);
+
+    // Setting `crate_blacklist` replaces the defaults.
+    let value = serde_json::json!({
+        "crate_blacklist": ["libc"],
+        "analysis_crate_blacklist": ["generated"],
+    });
+    let config =
+        Config::try_deserialize(&value, &mut Default::default(), &mut vec![], &mut vec![]).unwrap();
+    assert_eq!(&*config.crate_blacklist().0, &["libc".to_string(), "generated".to_string()]);
+}
+
+#[test]
+fn needs_rebuild() {
+    let config = Config::default();
+    let changed = |f: fn(&mut Config)| {
+        let mut new = config.clone();
+        f(&mut new);
+        config.needs_rebuild(&new)
+    };
+
+    assert!(!changed(|_| {}));
+    assert!(!changed(|c| c.show_warnings = !c.show_warnings));
+    assert!(changed(|c| c.features = vec!["foo".to_owned()]));
+    assert!(changed(|c| c.default_edition = Some(Edition::Edition2018)));
+    assert!(changed(|c| c.show_todo_diagnostics = !c.show_todo_diagnostics));
+    assert!(changed(|c| c.todo_tags.push("HACK".to_owned())));
+}
+
+#[test]
+fn default_edition_from_str() {
+    let value = serde_json::json!({});
+    let config =
+        Config::try_deserialize(&value, &mut Default::default(), &mut vec![], &mut vec![]).unwrap();
+    assert_eq!(config.default_edition, None);
+
+    let value = serde_json::json!({"default_edition": "2018"});
+    let config =
+        Config::try_deserialize(&value, &mut Default::default(), &mut vec![], &mut vec![]).unwrap();
+    assert_eq!(config.default_edition, Some(Edition::Edition2018));
+
+    let value = serde_json::json!({"default_edition": "2017"});
+    assert!(
+        Config::try_deserialize(&value, &mut Default::default(), &mut vec![], &mut vec![]).is_err()
+    );
+}
+
+#[test]
+fn fmt_config_with_options() {
+    let dir = tempfile::tempdir().unwrap();
+    let config_path = dir.path().join("custom.toml");
+    std::fs::write(&config_path, "max_width = 42\n").unwrap();
+
+    let config = FmtConfig::with_options(dir.path(), Some(&config_path), None);
+    assert_eq!(config.get_rustfmt_config().max_width(), 42);
+
+    let config = FmtConfig::with_options(dir.path(), None, Some(Edition::Edition2018));
+    assert_eq!(config.get_rustfmt_config().edition(), RustfmtEdition::Edition2018);
+
+    // A missing config file falls back to the one found in the project.
+    let missing = dir.path().join("missing.toml");
+    let config = FmtConfig::with_options(dir.path(), Some(&missing), None);
+    assert_eq!(config.get_rustfmt_config().max_width(), RustfmtConfig::default().max_width());
+}
+
+#[test]
+fn analysis_target_from_str() {
+    let value = serde_json::json!({});
+    let config =
+        Config::try_deserialize(&value, &mut Default::default(), &mut vec![], &mut vec![]).unwrap();
+    assert_eq!(config.analysis_target, AnalysisTarget::Debug);
+
+    let value = serde_json::json!({"analysis_target": "release"});
+    let config =
+        Config::try_deserialize(&value, &mut Default::default(), &mut vec![], &mut vec![]).unwrap();
+    assert_eq!(rls_analysis::Target::from(config.analysis_target), rls_analysis::Target::Release);
+    assert!(Config::default().needs_rebuild(&config));
+}
+
+#[test]
+fn workspace_file_is_merged_under_client_settings() -> Result<(), std::io::Error> {
+    let dir = tempfile::tempdir()?;
+    std::fs::write(
+        dir.path().join(WORKSPACE_CONFIG_FILE),
+        "all_features = true\nbuild_lib = true\n",
+    )?;
+
+    let mut settings = serde_json::json!({"buildLib": false});
+    let file_settings = Config::load_workspace_file(dir.path()).unwrap();
+    Config::merge_workspace_settings(settings.as_object_mut().unwrap(), file_settings);
+    let config =
+        Config::try_deserialize(&settings, &mut Default::default(), &mut vec![], &mut vec![])
+            .unwrap();
+    assert!(config.all_features);
+    assert!(!config.build_lib.as_ref());
+
+    Ok(())
+}
+
+#[test]
+fn invalid_workspace_file_is_ignored() -> Result<(), std::io::Error> {
+    let dir = tempfile::tempdir()?;
+    let path = dir.path().join(WORKSPACE_CONFIG_FILE);
+
+    std::fs::write(&path, "all_features = \"yes\"\n")?;
+    assert_eq!(Config::load_workspace_file(dir.path()), None);
+
+    std::fs::write(&path, "all_features = \n")?;
+    assert_eq!(Config::load_workspace_file(dir.path()), None);
+
+    Ok(())
+}
+
+#[cfg(test)]
+fn bin_project() -> Result<tempfile::TempDir, std::io::Error> {
+    let dir = tempfile::tempdir()?;
+    std::fs::write(
+        dir.path().join("Cargo.toml"),
+        "[package]\nname = \"foo\"\nversion = \"0.1.0\"\n",
+    )?;
+    std::fs::create_dir(dir.path().join("src"))?;
+    std::fs::write(dir.path().join("src").join("main.rs"), "fn main() {}\n")?;
+    Ok(dir)
+}
+
+#[test]
+fn validate_conflicting_lib_and_bin() -> Result<(), std::io::Error> {
+    let dir = bin_project()?;
+
+    let mut config = Config::default();
+    config.build_lib = Inferrable::Specified(true);
+    config.build_bin = Inferrable::Specified(Some("foo".to_owned()));
+
+    let warnings = config.validate_project(dir.path()).unwrap();
+    assert_eq!(warnings, vec![ConfigWarning::ConflictingTargets { bin: "foo".to_owned() }]);
+
+    Ok(())
+}
+
+#[test]
+fn validate_missing_bin() -> Result<(), std::io::Error> {
+    let dir = bin_project()?;
+
+    let mut config = Config::default();
+    assert_eq!(config.validate_project(dir.path()).unwrap(), vec![]);
+
+    config.build_bin = Inferrable::Specified(Some("bar".to_owned()));
+    let warnings = config.validate_project(dir.path()).unwrap();
+    assert_eq!(warnings, vec![ConfigWarning::MissingBin("bar".to_owned())]);
+
+    Ok(())
+}
+
+#[test]
+fn validate_exclude_globs() -> Result<(), std::io::Error> {
+    let dir = bin_project()?;
+
+    let mut config = Config::default();
+    config.analysis_exclude_globs = vec!["**".to_owned(), "generated/**".to_owned()];
+
+    let warnings = config.validate_project(dir.path()).unwrap();
+    assert_eq!(warnings, vec![ConfigWarning::ExcludesEverything("**".to_owned())]);
+
+    config.analysis_exclude_globs = vec!["generated/**".to_owned(), "vendor/*/src".to_owned()];
+    let excludes = config.analysis_excludes(dir.path());
+    let matches = ["generated/foo", "generated/foo/src", "vendor/bar/src"];
+    for path in &matches {
+        assert!(excludes.is_excluded(&dir.path().join(path)), "{} is not excluded", path);
+    }
+    let non_matches = ["src", "src/generated/foo", "generated_code/src", "vendor/bar/lib"];
+    for path in &non_matches {
+        assert!(!excludes.is_excluded(&dir.path().join(path)), "{} is excluded", path);
+    }
+    // Paths outside the project don't match.
+    assert!(!excludes.is_excluded(Path::new("/elsewhere/generated/foo")));
+
+    Ok(())
+}
+
+#[test]
+fn validate_rust_file_extensions() -> Result<(), std::io::Error> {
+    let dir = bin_project()?;
+
+    let mut config = Config::default();
+    config.rust_file_extensions =
+        vec![".rs.in".to_owned(), "toml".to_owned(), "in.toml".to_owned(), ".".to_owned()];
+
+    let warnings = config.validate_project(dir.path()).unwrap();
+    assert_eq!(
+        warnings,
+        vec![
+            ConfigWarning::InvalidRustExtension("toml".to_owned()),
+            ConfigWarning::InvalidRustExtension("in.toml".to_owned()),
+            ConfigWarning::InvalidRustExtension(".".to_owned()),
+        ]
+    );
+    assert_eq!(config.rust_file_extensions(), ["rs.in"]);
+
+    Ok(())
+}
+
+#[test]
+fn validate_disabled_capabilities() -> Result<(), std::io::Error> {
+    let dir = bin_project()?;
+
+    let mut config = Config::default();
+    config.disabled_capabilities = vec!["formatting".to_owned(), "textDocument/hover".to_owned()];
+
+    let warnings = config.validate_project(dir.path()).unwrap();
+    assert_eq!(warnings, vec![ConfigWarning::UnknownCapability("textDocument/hover".to_owned())]);
+
+    Ok(())
+}
+
+#[test]
+fn only_build_options_need_rebuild() {
+    let config = Config::default();
+
+    let mut new = config.clone();
+    new.show_warnings = false;
+    new.racer_completion = false;
+    new.target_dir = Inferrable::Inferred(Some(PathBuf::from("target/rls")));
+    assert!(!config.needs_rebuild(&new));
+
+    new.features = vec!["foo".to_owned()];
+    assert!(config.needs_rebuild(&new));
+
+    let mut new = config.clone();
+    new.target_dir = Inferrable::Specified(Some(PathBuf::from("target/other")));
+    assert!(config.needs_rebuild(&new));
+}
+
+#[test]
+fn reinfer_target_dir() -> Result<(), std::io::Error> {
+    let dir = bin_project()?;
+
+    let mut config = Config::default();
+    config.target_dir = Inferrable::Inferred(Some(dir.path().join("old")));
+    config.reinfer_defaults(dir.path()).unwrap();
+    assert_eq!(config.target_dir.as_ref(), &Some(dir.path().join("target").join("rls")));
+
+    let custom = Some(dir.path().join("custom"));
+    config.target_dir = Inferrable::Specified(custom.clone());
+    config.reinfer_defaults(dir.path()).unwrap();
+    assert_eq!(config.target_dir.as_ref(), &custom);
+
+    Ok(())
+}
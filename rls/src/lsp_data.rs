@@ -1,9 +1,12 @@
 //! Types, helpers, and conversions to and from LSP and `racer` types.
 
+use std::collections::HashMap;
 use std::error::Error;
 use std::fmt;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
 
+use lazy_static::lazy_static;
 pub use lsp_types::notification::Notification as LSPNotification;
 pub use lsp_types::request::Request as LSPRequest;
 pub use lsp_types::*;
@@ -18,7 +21,7 @@ use crate::config;
 /// An error that can occur when parsing a file URI.
 #[derive(Debug)]
 pub enum UrlFileParseError {
-    /// The URI scheme is not `file`.
+    /// The URI scheme is neither `file` nor `untitled`.
     InvalidScheme,
     /// Invalid file path in the URI.
     InvalidFilePath,
@@ -32,19 +35,53 @@ where
 {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         let description = match self {
-            UrlFileParseError::InvalidScheme => "URI scheme is not `file`",
+            UrlFileParseError::InvalidScheme => "URI scheme is not `file` or `untitled`",
             UrlFileParseError::InvalidFilePath => "Invalid file path in URI",
         };
         write!(f, "{}", description)
     }
 }
 
+/// The URI scheme editors use for new documents which haven't been saved yet.
+const UNTITLED_SCHEME: &str = "untitled";
+
+lazy_static! {
+    /// The `untitled:` documents seen so far, by the path they're stored under
+    /// (see `untitled_path`).
+    static ref UNTITLED_URIS: Mutex<HashMap<PathBuf, Url>> = Mutex::default();
+}
+
 /// Parses the given URI into a `PathBuf`.
+///
+/// `untitled:` documents are mapped to a synthetic path (see `untitled_path`).
 pub fn parse_file_path(uri: &Url) -> Result<PathBuf, UrlFileParseError> {
-    if uri.scheme() == "file" {
-        uri.to_file_path().map_err(|_err| UrlFileParseError::InvalidFilePath)
-    } else {
-        Err(UrlFileParseError::InvalidScheme)
+    match uri.scheme() {
+        "file" => uri.to_file_path().map_err(|_err| UrlFileParseError::InvalidFilePath),
+        UNTITLED_SCHEME => Ok(untitled_path(uri)),
+        _ => Err(UrlFileParseError::InvalidScheme),
+    }
+}
+
+/// Returns the path under which the in-memory contents of an `untitled:`
+/// document are stored in the VFS. Nothing is ever written to this path.
+pub fn untitled_path(uri: &Url) -> PathBuf {
+    let name: String = uri
+        .path()
+        .chars()
+        .map(|c| if std::path::is_separator(c) || c == ':' { '_' } else { c })
+        .collect();
+    let path = std::env::temp_dir().join("rls-untitled").join(name);
+    UNTITLED_URIS.lock().unwrap().insert(path.clone(), uri.clone());
+    path
+}
+
+/// Returns the URI of the document at `path`, i.e., the reverse of
+/// `parse_file_path`, so that `untitled:` documents keep their URI in
+/// responses.
+pub fn file_path_to_uri(path: &Path) -> Result<Url, ()> {
+    match UNTITLED_URIS.lock().unwrap().get(path) {
+        Some(uri) => Ok(uri.clone()),
+        None => Url::from_file_path(path),
     }
 }
 
@@ -87,13 +124,13 @@ pub mod ls_util {
     /// Converts an RLS span into a language server protocol location.
     pub fn rls_to_location(span: &Span) -> Location {
         // An RLS span has the same info as an LSP `Location`.
-        Location { uri: Url::from_file_path(&span.file).unwrap(), range: rls_to_range(span.range) }
+        Location { uri: file_path_to_uri(&span.file).unwrap(), range: rls_to_range(span.range) }
     }
 
     /// Converts an RLS location into a language server protocol location.
     pub fn rls_location_to_location(l: &span::Location<span::ZeroIndexed>) -> Location {
         Location {
-            uri: Url::from_file_path(&l.file).unwrap(),
+            uri: file_path_to_uri(&l.file).unwrap(),
             range: rls_to_range(span::Range::from_positions(l.position, l.position)),
         }
     }
@@ -263,9 +300,21 @@ impl ChangeConfigSettings {
 pub struct InitializationOptions {
     /// `true` if build should not be triggered immediately after receiving `initialize`.
     pub omit_init_build: bool,
+    /// `true` if the client implements the `rls.run` command, which runs the
+    /// `Cmd` (binary, arguments and environment) given as its argument. Test
+    /// functions then get a "Run test" lens.
     pub cmd_run: bool,
+    /// `true` if the client implements the `rls.debug` command, which runs
+    /// the `Cmd` given as its argument under a debugger. Test functions then
+    /// also get a "Debug test" lens, if `cmd_run` is set.
+    pub cmd_debug: bool,
     /// `DidChangeConfigurationParams.settings` payload for upfront configuration.
     pub settings: Option<ChangeConfigSettings>,
+    /// Files, absolute or relative to the project root, which the user is
+    /// likely to work on first. They are read, and the analysis data of a
+    /// previous session is loaded, in the background, so that the first
+    /// requests about them don't wait for a build.
+    pub prewarm_files: Vec<PathBuf>,
 }
 
 impl InitializationOptions {
@@ -285,11 +334,78 @@ impl InitializationOptions {
 
         Ok(InitializationOptions { settings, ..serde_json::from_value(val).map_err(|_| ())? })
     }
+
+    /// The capabilities which the `settings` disable, see
+    /// `Config::disabled_capabilities`.
+    pub fn disabled_capabilities(&self) -> &[String] {
+        self.settings.as_ref().map_or(&[], |settings| &settings.rust.disabled_capabilities)
+    }
 }
 
 impl Default for InitializationOptions {
     fn default() -> Self {
-        InitializationOptions { omit_init_build: false, cmd_run: false, settings: None }
+        InitializationOptions {
+            omit_init_build: false,
+            cmd_run: false,
+            cmd_debug: false,
+            settings: None,
+            prewarm_files: vec![],
+        }
+    }
+}
+
+/// The `initialize` request. Unlike `lsp_types::request::Initialize`, its
+/// params keep the client capabilities which are newer than `lsp_types`.
+pub enum Initialize {}
+
+impl LSPRequest for Initialize {
+    type Params = InitializeRequestParams;
+    type Result = InitializeRequestResult;
+    const METHOD: &'static str = "initialize";
+}
+
+/// The result of the `initialize` request. Unlike `lsp_types::InitializeResult`,
+/// its capabilities include those which are newer than `lsp_types`.
+#[derive(Debug, Deserialize, Serialize)]
+pub struct InitializeRequestResult {
+    pub capabilities: ExtendedServerCapabilities,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ExtendedServerCapabilities {
+    #[serde(flatten)]
+    pub base: ServerCapabilities,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub semantic_tokens_provider: Option<SemanticTokensOptions>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub call_hierarchy_provider: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub type_hierarchy_provider: Option<bool>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct InitializeRequestParams {
+    #[serde(flatten)]
+    pub params: InitializeParams,
+    /// Whether the client supports work done progress, i.e.,
+    /// `window/workDoneProgress/create` requests and `$/progress`
+    /// notifications (`capabilities.window.workDoneProgress`).
+    #[serde(skip)]
+    pub work_done_progress: bool,
+}
+
+impl<'de> serde::Deserialize<'de> for InitializeRequestParams {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        use serde::de::Error as _;
+
+        let value = <serde_json::Value as serde::Deserialize>::deserialize(deserializer)?;
+        let work_done_progress = value
+            .pointer("/capabilities/window/workDoneProgress")
+            .and_then(serde_json::Value::as_bool)
+            .unwrap_or(false);
+        let params = serde_json::from_value(value).map_err(D::Error::custom)?;
+        Ok(InitializeRequestParams { params, work_done_progress })
     }
 }
 
@@ -300,10 +416,14 @@ impl Default for InitializationOptions {
 pub struct ClientCapabilities {
     pub code_completion_has_snippet_support: bool,
     pub related_information_support: bool,
+    pub definition_link_support: bool,
+    pub code_action_literal_support: bool,
+    pub work_done_progress: bool,
 }
 
 impl ClientCapabilities {
-    pub fn new(params: &lsp_types::InitializeParams) -> ClientCapabilities {
+    pub fn new(request_params: &InitializeRequestParams) -> ClientCapabilities {
+        let params = &request_params.params;
         // `lsp_types::ClientCapabilities` is a rather awkward object to use internally
         // (for instance, it doesn't `Clone`). Instead we pick out the bits of it that we
         // are going to handle into `ClientCapabilities`. The upside of
@@ -328,6 +448,714 @@ impl ClientCapabilities {
             .copied()
             .unwrap_or(false);
 
-        ClientCapabilities { code_completion_has_snippet_support, related_information_support }
+        let definition_link_support = params
+            .capabilities
+            .text_document
+            .as_ref()
+            .and_then(|doc| doc.definition.as_ref())
+            .and_then(|def| def.link_support.as_ref())
+            .copied()
+            .unwrap_or(false);
+
+        let code_action_literal_support = params
+            .capabilities
+            .text_document
+            .as_ref()
+            .and_then(|doc| doc.code_action.as_ref())
+            .map_or(false, |action| action.code_action_literal_support.is_some());
+
+        ClientCapabilities {
+            code_completion_has_snippet_support,
+            related_information_support,
+            definition_link_support,
+            code_action_literal_support,
+            work_done_progress: request_params.work_done_progress,
+        }
+    }
+}
+
+/// Custom request for the type of the expression covered by a range. The range
+/// must cover a name or the whole initializer of a `let` binding, otherwise an
+/// error is returned.
+pub enum TypeOfSelection {}
+
+impl LSPRequest for TypeOfSelection {
+    type Params = TypeOfSelectionParams;
+    type Result = String;
+    const METHOD: &'static str = "rls/typeOfSelection";
+}
+
+#[derive(Debug, PartialEq, Deserialize, Serialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct TypeOfSelectionParams {
+    pub text_document: TextDocumentIdentifier,
+    pub range: Range,
+}
+
+/// Custom request for type hints for the `let` bindings in a range.
+pub enum InlayHints {}
+
+impl LSPRequest for InlayHints {
+    type Params = InlayHintsParams;
+    type Result = Vec<InlayHint>;
+    const METHOD: &'static str = "rls/inlayHints";
+}
+
+#[derive(Debug, PartialEq, Deserialize, Serialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct InlayHintsParams {
+    pub text_document: TextDocumentIdentifier,
+    pub range: Range,
+}
+
+/// A hint to be shown inline at `position`, e.g., `: i32` after the name of a
+/// `let` binding.
+#[derive(Debug, PartialEq, Deserialize, Serialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct InlayHint {
+    pub position: Position,
+    pub label: String,
+}
+
+/// The `textDocument/semanticTokens/full` request (LSP 3.16), which isn't
+/// available in the version of `lsp_types` used here.
+pub enum SemanticTokensFull {}
+
+impl LSPRequest for SemanticTokensFull {
+    type Params = SemanticTokensParams;
+    type Result = SemanticTokens;
+    const METHOD: &'static str = "textDocument/semanticTokens/full";
+}
+
+#[derive(Debug, PartialEq, Deserialize, Serialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct SemanticTokensParams {
+    pub text_document: TextDocumentIdentifier,
+}
+
+#[derive(Debug, PartialEq, Deserialize, Serialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct SemanticTokens {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub result_id: Option<String>,
+    /// The tokens, encoded as described by the LSP (see
+    /// `actions::semantic_tokens::encode`).
+    pub data: Vec<u32>,
+}
+
+#[derive(Debug, PartialEq, Deserialize, Serialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct SemanticTokensOptions {
+    pub legend: SemanticTokensLegend,
+    pub full: SemanticTokensFullOptions,
+}
+
+/// The names of the token types and modifiers, indexed by the encoded tokens.
+#[derive(Debug, PartialEq, Deserialize, Serialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct SemanticTokensLegend {
+    pub token_types: Vec<String>,
+    pub token_modifiers: Vec<String>,
+}
+
+#[derive(Debug, PartialEq, Deserialize, Serialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct SemanticTokensFullOptions {
+    /// Whether `textDocument/semanticTokens/full/delta` is supported.
+    pub delta: bool,
+}
+
+/// The `textDocument/semanticTokens/full/delta` request (LSP 3.16).
+pub enum SemanticTokensFullDelta {}
+
+impl LSPRequest for SemanticTokensFullDelta {
+    type Params = SemanticTokensDeltaParams;
+    type Result = SemanticTokensFullDeltaResult;
+    const METHOD: &'static str = "textDocument/semanticTokens/full/delta";
+}
+
+#[derive(Debug, PartialEq, Deserialize, Serialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct SemanticTokensDeltaParams {
+    pub text_document: TextDocumentIdentifier,
+    /// The `result_id` of the tokens previously returned for the document.
+    pub previous_result_id: String,
+}
+
+/// Either all the tokens, or the edits to the previous tokens.
+#[derive(Debug, PartialEq, Deserialize, Serialize, Clone)]
+#[serde(untagged)]
+pub enum SemanticTokensFullDeltaResult {
+    Tokens(SemanticTokens),
+    Delta(SemanticTokensDelta),
+}
+
+#[derive(Debug, PartialEq, Deserialize, Serialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct SemanticTokensDelta {
+    pub result_id: Option<String>,
+    pub edits: Vec<SemanticTokensEdit>,
+}
+
+/// Replaces `delete_count` integers of the encoded tokens at `start` with
+/// `data`.
+#[derive(Debug, PartialEq, Deserialize, Serialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct SemanticTokensEdit {
+    pub start: u32,
+    pub delete_count: u32,
+    pub data: Vec<u32>,
+}
+
+/// The `textDocument/prepareCallHierarchy` request (LSP 3.16), which isn't
+/// available in the version of `lsp_types` used here either.
+pub enum CallHierarchyPrepare {}
+
+impl LSPRequest for CallHierarchyPrepare {
+    type Params = TextDocumentPositionParams;
+    type Result = Option<Vec<CallHierarchyItem>>;
+    const METHOD: &'static str = "textDocument/prepareCallHierarchy";
+}
+
+/// A function or method in a call hierarchy. Items are found again from
+/// `uri` and `selection_range` in the follow-up requests.
+#[derive(Debug, PartialEq, Deserialize, Serialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct CallHierarchyItem {
+    pub name: String,
+    pub kind: SymbolKind,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub detail: Option<String>,
+    pub uri: Url,
+    pub range: Range,
+    pub selection_range: Range,
+}
+
+/// The `callHierarchy/incomingCalls` request (LSP 3.16).
+pub enum CallHierarchyIncomingCalls {}
+
+impl LSPRequest for CallHierarchyIncomingCalls {
+    type Params = CallHierarchyIncomingCallsParams;
+    type Result = Option<Vec<CallHierarchyIncomingCall>>;
+    const METHOD: &'static str = "callHierarchy/incomingCalls";
+}
+
+#[derive(Debug, PartialEq, Deserialize, Serialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct CallHierarchyIncomingCallsParams {
+    pub item: CallHierarchyItem,
+}
+
+/// A caller, with the ranges of its calls.
+#[derive(Debug, PartialEq, Deserialize, Serialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct CallHierarchyIncomingCall {
+    pub from: CallHierarchyItem,
+    pub from_ranges: Vec<Range>,
+}
+
+/// The `callHierarchy/outgoingCalls` request (LSP 3.16).
+pub enum CallHierarchyOutgoingCalls {}
+
+impl LSPRequest for CallHierarchyOutgoingCalls {
+    type Params = CallHierarchyOutgoingCallsParams;
+    type Result = Option<Vec<CallHierarchyOutgoingCall>>;
+    const METHOD: &'static str = "callHierarchy/outgoingCalls";
+}
+
+#[derive(Debug, PartialEq, Deserialize, Serialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct CallHierarchyOutgoingCallsParams {
+    pub item: CallHierarchyItem,
+}
+
+/// A callee, with the ranges of the calls to it (in the caller's file).
+#[derive(Debug, PartialEq, Deserialize, Serialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct CallHierarchyOutgoingCall {
+    pub to: CallHierarchyItem,
+    pub from_ranges: Vec<Range>,
+}
+
+/// The `textDocument/prepareTypeHierarchy` request (LSP 3.17).
+pub enum TypeHierarchyPrepare {}
+
+impl LSPRequest for TypeHierarchyPrepare {
+    type Params = TextDocumentPositionParams;
+    type Result = Option<Vec<TypeHierarchyItem>>;
+    const METHOD: &'static str = "textDocument/prepareTypeHierarchy";
+}
+
+/// A type or trait in a type hierarchy. Like call hierarchy items, items are
+/// found again from `uri` and `selection_range` in the follow-up requests.
+#[derive(Debug, PartialEq, Deserialize, Serialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct TypeHierarchyItem {
+    pub name: String,
+    pub kind: SymbolKind,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub detail: Option<String>,
+    pub uri: Url,
+    pub range: Range,
+    pub selection_range: Range,
+}
+
+/// The `typeHierarchy/supertypes` request (LSP 3.17).
+pub enum TypeHierarchySupertypes {}
+
+impl LSPRequest for TypeHierarchySupertypes {
+    type Params = TypeHierarchyParams;
+    type Result = Option<Vec<TypeHierarchyItem>>;
+    const METHOD: &'static str = "typeHierarchy/supertypes";
+}
+
+/// The `typeHierarchy/subtypes` request (LSP 3.17).
+pub enum TypeHierarchySubtypes {}
+
+impl LSPRequest for TypeHierarchySubtypes {
+    type Params = TypeHierarchyParams;
+    type Result = Option<Vec<TypeHierarchyItem>>;
+    const METHOD: &'static str = "typeHierarchy/subtypes";
+}
+
+/// The parameters of both the supertypes and the subtypes requests.
+#[derive(Debug, PartialEq, Deserialize, Serialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct TypeHierarchyParams {
+    pub item: TypeHierarchyItem,
+}
+
+/// Custom request for the definitions of several positions at once. The
+/// result has an entry for each position, which is `null` if there is no
+/// definition for it.
+pub enum BatchDefinition {}
+
+impl LSPRequest for BatchDefinition {
+    type Params = BatchDefinitionParams;
+    type Result = Vec<Option<Location>>;
+    const METHOD: &'static str = "rls/batchDefinition";
+}
+
+#[derive(Debug, PartialEq, Deserialize, Serialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct BatchDefinitionParams {
+    pub positions: Vec<TextDocumentPositionParams>,
+}
+
+/// Custom request for the path of the item at a position (e.g.,
+/// `["crate", "foo", "Bar"]`), for breadcrumbs.
+pub enum QualifiedPath {}
+
+impl LSPRequest for QualifiedPath {
+    type Params = TextDocumentPositionParams;
+    type Result = Vec<String>;
+    const METHOD: &'static str = "rls/qualifiedPath";
+}
+
+/// Custom request for the edit adding stubs for the items of a trait which are
+/// missing from the impl at a position.
+pub enum ImplementMissingItems {}
+
+impl LSPRequest for ImplementMissingItems {
+    type Params = TextDocumentPositionParams;
+    type Result = Option<WorkspaceEdit>;
+    const METHOD: &'static str = "rls/implementMissingItems";
+}
+
+/// Custom request for the references to the item at a position, like
+/// `textDocument/references`, but grouped by file.
+pub enum GroupedReferences {}
+
+impl LSPRequest for GroupedReferences {
+    type Params = ReferenceParams;
+    type Result = Vec<FileReferences>;
+    const METHOD: &'static str = "rls/groupedReferences";
+}
+
+/// The references in a file, ordered by position. The declaration comes
+/// first if it was asked for.
+#[derive(Debug, PartialEq, Deserialize, Serialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct FileReferences {
+    pub uri: Url,
+    pub ranges: Vec<Range>,
+}
+
+/// Custom request for the long-form explanation of an error code, as given by
+/// `rustc --explain`.
+pub enum ExplainError {}
+
+impl LSPRequest for ExplainError {
+    type Params = ExplainErrorParams;
+    type Result = String;
+    const METHOD: &'static str = "rls/explainError";
+}
+
+#[derive(Debug, PartialEq, Deserialize, Serialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct ExplainErrorParams {
+    /// The error code, e.g., `E0499`.
+    pub code: String,
+}
+
+/// Custom request for the dependency graph of the packages of the workspace,
+/// e.g., to visualize it when debugging dependency issues.
+pub enum CrateGraph {}
+
+impl LSPRequest for CrateGraph {
+    type Params = CrateGraphParams;
+    type Result = CrateGraphResult;
+    const METHOD: &'static str = "rls/crateGraph";
+}
+
+#[derive(Debug, PartialEq, Deserialize, Serialize, Clone, Default)]
+pub struct CrateGraphParams {
+    /// `"json"` (the default) for the nodes and edges, or `"dot"` for a
+    /// Graphviz graph.
+    pub format: Option<String>,
+}
+
+/// Either the nodes and edges, or a Graphviz graph.
+#[derive(Debug, PartialEq, Deserialize, Serialize, Clone)]
+#[serde(untagged)]
+pub enum CrateGraphResult {
+    Graph(CrateGraphData),
+    Dot(String),
+}
+
+#[derive(Debug, PartialEq, Deserialize, Serialize, Clone, Default)]
+pub struct CrateGraphData {
+    pub nodes: Vec<CrateGraphNode>,
+    pub edges: Vec<CrateGraphEdge>,
+}
+
+#[derive(Debug, PartialEq, Deserialize, Serialize, Clone)]
+pub struct CrateGraphNode {
+    pub id: usize,
+    /// The name of the package.
+    pub name: String,
+}
+
+/// A dependency of the package `from` on the package `to`.
+#[derive(Debug, PartialEq, Deserialize, Serialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct CrateGraphEdge {
+    pub from: usize,
+    pub to: usize,
+    /// The name of the dependency's library crate in the dependent package.
+    pub crate_name: String,
+    /// Whether the edge is part of a cycle, which only dev-dependencies can
+    /// make.
+    pub cyclic: bool,
+}
+
+/// Custom request for the symbols of the workspace whose names match a regular
+/// expression, e.g., for a more precise search than `workspace/symbol`.
+pub enum RegexSymbol {}
+
+impl LSPRequest for RegexSymbol {
+    type Params = RegexSymbolParams;
+    type Result = Vec<SymbolInformation>;
+    const METHOD: &'static str = "rls/regexSymbol";
+}
+
+#[derive(Debug, PartialEq, Deserialize, Serialize, Clone)]
+pub struct RegexSymbolParams {
+    /// Matched case-insensitively against the names, which only need to
+    /// contain a match (e.g., `^new_` for the names starting with `new_`).
+    pub pattern: String,
+}
+
+/// Custom request for the private and `pub(crate)` items of the workspace
+/// which are never referenced.
+pub enum DeadCode {}
+
+impl LSPRequest for DeadCode {
+    type Params = ();
+    type Result = DeadCodeResult;
+    const METHOD: &'static str = "rls/deadCode";
+}
+
+#[derive(Debug, PartialEq, Deserialize, Serialize, Clone, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct DeadCodeResult {
+    pub unused: Vec<SymbolInformation>,
+    /// Items only referenced in `#[cfg(test)]` code, when it isn't analyzed
+    /// (see the `cfg_test` option).
+    pub used_in_tests: Vec<SymbolInformation>,
+}
+
+/// Custom request for the current status of the RLS, e.g., to be shown in a
+/// status bar.
+pub enum Status {}
+
+impl LSPRequest for Status {
+    type Params = ();
+    type Result = StatusParams;
+    const METHOD: &'static str = "rls/status";
+}
+
+/// Custom request for whether the analysis data of each open document
+/// reflects its current contents, e.g., to show that results may be stale.
+pub enum AnalysisFreshness {}
+
+impl LSPRequest for AnalysisFreshness {
+    type Params = ();
+    type Result = Vec<FileFreshness>;
+    const METHOD: &'static str = "rls/analysisFreshness";
+}
+
+#[derive(Debug, PartialEq, Deserialize, Serialize, Clone)]
+pub struct FileFreshness {
+    pub uri: Url,
+    /// False if the document was changed since the build whose analysis data
+    /// is loaded was requested, or if no analysis data was loaded yet.
+    pub fresh: bool,
+}
+
+/// Custom notification sent whenever the status returned by `rls/status`
+/// changes.
+pub enum StatusChanged {}
+
+impl LSPNotification for StatusChanged {
+    type Params = StatusParams;
+    const METHOD: &'static str = "rls/statusChanged";
+}
+
+/// Custom notification of the time taken to handle the requests of each
+/// method, sent after each request if the `request_metrics` option is set.
+pub enum Metrics {}
+
+impl LSPNotification for Metrics {
+    type Params = MetricsParams;
+    const METHOD: &'static str = "rls/metrics";
+}
+
+#[derive(Debug, PartialEq, Deserialize, Serialize, Clone, Default)]
+pub struct MetricsParams {
+    pub methods: Vec<MethodMetrics>,
+}
+
+/// The number of requests of a method handled so far, and percentiles of the
+/// time, in milliseconds, taken by the latest ones.
+#[derive(Debug, PartialEq, Deserialize, Serialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct MethodMetrics {
+    pub method: String,
+    pub count: u64,
+    pub p50_ms: f64,
+    pub p90_ms: f64,
+    pub p99_ms: f64,
+    pub max_ms: f64,
+}
+
+/// Request from the server for the client to create a work done progress
+/// with the given token, before any `$/progress` notification for it.
+pub enum WorkDoneProgressCreate {}
+
+impl LSPRequest for WorkDoneProgressCreate {
+    type Params = WorkDoneProgressCreateParams;
+    type Result = ();
+    const METHOD: &'static str = "window/workDoneProgress/create";
+}
+
+#[derive(Debug, PartialEq, Deserialize, Serialize, Clone)]
+pub struct WorkDoneProgressCreateParams {
+    pub token: String,
+}
+
+/// Notification of the progress of the work done for a token (begin, any
+/// number of reports, then end), for clients supporting work done progress.
+pub enum WorkDoneProgress {}
+
+impl LSPNotification for WorkDoneProgress {
+    type Params = WorkDoneProgressParams;
+    const METHOD: &'static str = "$/progress";
+}
+
+#[derive(Debug, PartialEq, Deserialize, Serialize, Clone)]
+pub struct WorkDoneProgressParams {
+    pub token: String,
+    pub value: WorkDoneProgressValue,
+}
+
+#[derive(Debug, PartialEq, Deserialize, Serialize, Clone)]
+#[serde(tag = "kind", rename_all = "camelCase")]
+pub enum WorkDoneProgressValue {
+    Begin {
+        title: String,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        message: Option<String>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        percentage: Option<u32>,
+    },
+    Report {
+        #[serde(skip_serializing_if = "Option::is_none")]
+        message: Option<String>,
+        /// From 0 to 100.
+        #[serde(skip_serializing_if = "Option::is_none")]
+        percentage: Option<u32>,
+    },
+    End {
+        #[serde(skip_serializing_if = "Option::is_none")]
+        message: Option<String>,
+    },
+}
+
+/// What the RLS is currently doing.
+#[derive(Debug, PartialEq, Eq, Deserialize, Serialize, Clone, Copy)]
+#[serde(rename_all = "camelCase")]
+pub enum ServerState {
+    Idle,
+    Building,
+    Indexing,
+    /// Idle, but the last build failed.
+    Error,
+}
+
+impl Default for ServerState {
+    fn default() -> Self {
+        ServerState::Idle
+    }
+}
+
+#[derive(Debug, PartialEq, Deserialize, Serialize, Clone, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct StatusParams {
+    pub state: ServerState,
+    /// The current build or reload phase, e.g., the crate being compiled.
+    pub phase: Option<String>,
+    /// The message of the last build error, if any.
+    pub last_error: Option<String>,
+}
+
+/// Custom notification sent when the build environment was reset after
+/// repeated build failures.
+pub enum BuildRecovery {}
+
+impl LSPNotification for BuildRecovery {
+    type Params = BuildRecoveryParams;
+    const METHOD: &'static str = "rls/buildRecovery";
+}
+
+#[derive(Debug, PartialEq, Deserialize, Serialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct BuildRecoveryParams {
+    /// Number of consecutive builds which failed.
+    pub failed_builds: usize,
+    /// The target directory which was cleared, if any.
+    pub target_dir: Option<PathBuf>,
+}
+
+/// Custom request for the `cfg` state (enabled features, target, etc.) with
+/// which each crate containing a file was last built.
+pub enum ActiveCfgs {}
+
+impl LSPRequest for ActiveCfgs {
+    type Params = ActiveCfgsParams;
+    type Result = Vec<CrateCfgs>;
+    const METHOD: &'static str = "rls/activeCfgs";
+}
+
+#[derive(Debug, PartialEq, Deserialize, Serialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct ActiveCfgsParams {
+    pub text_document: TextDocumentIdentifier,
+}
+
+/// The `cfg` state of a crate, as passed to the compiler.
+#[derive(Debug, PartialEq, Deserialize, Serialize, Clone, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct CrateCfgs {
+    pub crate_name: String,
+    /// E.g., `lib` or `bin`.
+    pub crate_type: Option<String>,
+    /// Whether the crate was built as a test harness, i.e., with `cfg(test)`.
+    pub test: bool,
+    /// The target triple, if cross-compiling; `None` means the host.
+    pub target: Option<String>,
+    /// The enabled features, i.e., the values of `cfg(feature = "...")`.
+    pub features: Vec<String>,
+    /// Any other `cfg`s set on the command line, e.g., by build scripts.
+    pub cfgs: Vec<String>,
+}
+
+/// Custom request toggling between a trait method and its implementations:
+/// on a trait method, the result is the methods implementing it, and on one of
+/// those, the trait method. Other methods (e.g., in inherent impls) toggle to
+/// themselves.
+pub enum ToggleImpl {}
+
+impl LSPRequest for ToggleImpl {
+    type Params = TextDocumentPositionParams;
+    type Result = Vec<Location>;
+    const METHOD: &'static str = "rls/toggleImpl";
+}
+
+/// Custom request for the test (or benchmark) function which encloses the
+/// cursor, or else the nearest one above it, e.g., to run the test under the
+/// cursor.
+pub enum NearestTest {}
+
+impl LSPRequest for NearestTest {
+    type Params = TextDocumentPositionParams;
+    type Result = Option<TestFunction>;
+    const METHOD: &'static str = "rls/nearestTest";
+}
+
+#[derive(Debug, PartialEq, Deserialize, Serialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct TestFunction {
+    pub name: String,
+    /// The path of the function from its crate root (e.g.,
+    /// `tests::test_foo`), to filter the tests run by `cargo test` (or
+    /// `cargo bench`) with.
+    pub filter: String,
+    pub bench: bool,
+    /// The range of the function's name.
+    pub range: Range,
+}
+
+/// Custom request for a compact outline of a file (e.g., for minimaps): its
+/// items in order of position, without the ranges and nesting of document
+/// symbols.
+pub enum Outline {}
+
+impl LSPRequest for Outline {
+    type Params = OutlineParams;
+    type Result = Vec<OutlineItem>;
+    const METHOD: &'static str = "rls/outline";
+}
+
+#[derive(Debug, PartialEq, Deserialize, Serialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct OutlineParams {
+    pub text_document: TextDocumentIdentifier,
+}
+
+/// An item of an outline, with the (zero-indexed) lines it spans and the
+/// number of items it is nested in.
+#[derive(Debug, PartialEq, Deserialize, Serialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct OutlineItem {
+    pub kind: SymbolKind,
+    pub name: String,
+    pub start_line: u64,
+    pub end_line: u64,
+    pub depth: u32,
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn untitled_uris_round_trip() {
+        let uri = Url::parse("untitled:Untitled-1").unwrap();
+        let path = parse_file_path(&uri).unwrap();
+        assert_eq!(file_path_to_uri(&path), Ok(uri));
+
+        let path = std::env::temp_dir().join("main.rs");
+        assert_eq!(file_path_to_uri(&path), Url::from_file_path(&path));
     }
 }
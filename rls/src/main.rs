@@ -20,7 +20,7 @@ pub fn main() {
 }
 
 fn main_inner() -> i32 {
-    env_logger::init();
+    rls::server::logging::init();
 
     // [workaround]
     // Currently sccache breaks RLS with obscure error messages.
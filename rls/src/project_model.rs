@@ -15,8 +15,10 @@ use cargo::{
 };
 use log::warn;
 use rls_vfs::{FileContents, Vfs};
+
+use crate::build::{Crate, Edition};
 use std::{
-    collections::HashMap,
+    collections::{HashMap, HashSet},
     path::{Path, PathBuf},
     sync::Arc,
 };
@@ -27,12 +29,16 @@ pub struct ProjectModel {
     packages: Vec<PackageData>,
 }
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct Package(usize);
 
 #[derive(Debug)]
 struct PackageData {
+    id: PackageId,
+    name: String,
     lib: Option<(PathBuf, String)>,
+    /// The root source files of the package's targets.
+    target_roots: Vec<PathBuf>,
     deps: Vec<Dep>,
     edition: racer::Edition,
 }
@@ -79,6 +85,8 @@ impl ProjectModel {
             let cargo_pkg = cargo_packages.get_one(pkg_id)?;
             let manifest = cargo_pkg.manifest_path().to_owned();
             packages.push(PackageData {
+                id: pkg_id,
+                name: cargo_pkg.name().to_string(),
                 lib: cargo_pkg
                     .targets()
                     .iter()
@@ -90,6 +98,12 @@ impl ProjectModel {
                             t.name().replace('-', "_"),
                         )
                     }),
+                target_roots: cargo_pkg
+                    .targets()
+                    .iter()
+                    .filter_map(|t| t.src_path().path())
+                    .map(ToOwned::to_owned)
+                    .collect(),
                 deps: Vec::new(),
                 edition: match cargo_pkg.manifest().edition() {
                     cargo::core::Edition::Edition2015 => racer::Edition::Ed2015,
@@ -117,10 +131,54 @@ impl ProjectModel {
         Ok(ProjectModel { manifest_to_id, packages })
     }
 
+    /// Returns all the packages, the workspace members and their dependencies.
+    pub fn packages(&self) -> impl Iterator<Item = Package> {
+        (0..self.packages.len()).map(Package)
+    }
+
     pub fn package_for_manifest(&self, manifest_path: &Path) -> Option<Package> {
         self.manifest_to_id.get(manifest_path).cloned()
     }
 
+    /// Returns the package whose manifest directory most closely contains `file`.
+    pub fn package_for_file(&self, file: &Path) -> Option<Package> {
+        self.manifest_to_id
+            .iter()
+            .filter_map(|(manifest, pkg)| Some((manifest.parent()?, pkg)))
+            .filter(|(dir, _)| file.starts_with(dir))
+            .max_by_key(|(dir, _)| dir.components().count())
+            .map(|(_, pkg)| *pkg)
+    }
+
+    /// Returns the id of the package which owns `file`, given the `crates` it
+    /// was compiled into by the last build (see
+    /// `InitActionContext::owning_package`).
+    ///
+    /// A file can be part of crates of several packages, e.g., when it's
+    /// `include!`d by them, in which case the package whose directory contains
+    /// the file owns it, if it's one of them. Falls back to `package_for_file`
+    /// if the file wasn't part of the build.
+    pub fn owning_package(&self, file: &Path, crates: &HashSet<Crate>) -> Option<PackageId> {
+        let mut candidates: Vec<Package> = crates
+            .iter()
+            .filter_map(|krate| self.package_for_target_root(krate.src_path.as_ref()?))
+            .collect();
+        candidates.sort_by_key(|pkg| pkg.0);
+        candidates.dedup();
+
+        let by_dir = self.package_for_file(file);
+        let owner = match candidates[..] {
+            [] => by_dir,
+            [pkg] => Some(pkg),
+            _ => by_dir.filter(|pkg| candidates.contains(pkg)),
+        };
+        owner.map(|pkg| pkg.id(self))
+    }
+
+    fn package_for_target_root(&self, root: &Path) -> Option<Package> {
+        self.packages.iter().position(|pkg| pkg.target_roots.iter().any(|r| r == root)).map(Package)
+    }
+
     fn get(&self, pkg: Package) -> &PackageData {
         &self.packages[pkg.0]
     }
@@ -131,6 +189,16 @@ impl ProjectModel {
 }
 
 impl Package {
+    /// Returns a number identifying the package in `project`.
+    pub fn index(self) -> usize {
+        self.0
+    }
+    pub fn id(self, project: &ProjectModel) -> PackageId {
+        project.get(self).id
+    }
+    pub fn name(self, project: &ProjectModel) -> &str {
+        &project.get(self).name
+    }
     pub fn deps(self, project: &ProjectModel) -> &[Dep] {
         &project.get(self).deps
     }
@@ -139,14 +207,25 @@ impl Package {
     }
 }
 
+pub fn racer_edition(edition: Edition) -> racer::Edition {
+    match edition {
+        Edition::Edition2015 => racer::Edition::Ed2015,
+        Edition::Edition2018 => racer::Edition::Ed2018,
+        // FIXME: Use Racer's Ed2021 once
+        // https://github.com/racer-rust/racer/pull/1152 is published.
+        Edition::Edition2021 | Edition::Edition2024 => racer::Edition::Ed2018,
+    }
+}
+
 // We use the following wrappers to teach Racer about the structure
-// of the project.
+// of the project. The `Option<racer::Edition>` is the edition to use for
+// files which don't belong to a known package.
 
-pub struct RacerProjectModel(pub Arc<ProjectModel>);
+pub struct RacerProjectModel(pub Arc<ProjectModel>, pub Option<racer::Edition>);
 
 impl racer::ProjectModelProvider for RacerProjectModel {
     fn edition(&self, manifest: &Path) -> Option<racer::Edition> {
-        self.0.package_for_manifest(manifest).map(|pkg| self.0.get(pkg).edition)
+        self.0.package_for_manifest(manifest).map(|pkg| self.0.get(pkg).edition).or(self.1)
     }
 
     fn search_dependencies(
@@ -192,11 +271,11 @@ impl racer::ProjectModelProvider for RacerProjectModel {
     }
 }
 
-pub struct RacerFallbackModel;
+pub struct RacerFallbackModel(pub Option<racer::Edition>);
 
 impl racer::ProjectModelProvider for RacerFallbackModel {
     fn edition(&self, _manifest: &Path) -> Option<racer::Edition> {
-        None
+        self.0
     }
 
     fn search_dependencies(
@@ -232,3 +311,58 @@ fn resolve_with_prev<'cfg>(
         true,
     )
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use cargo::core::SourceId;
+
+    fn package(name: &str, root: &str) -> PackageData {
+        let source = SourceId::for_path(Path::new(root)).unwrap();
+        PackageData {
+            id: PackageId::new(name, "0.1.0", source).unwrap(),
+            name: name.to_owned(),
+            lib: None,
+            target_roots: vec![Path::new(root).join("src/lib.rs")],
+            deps: vec![],
+            edition: racer::Edition::Ed2018,
+        }
+    }
+
+    fn krate(name: &str, root: &str) -> Crate {
+        Crate {
+            name: name.to_owned(),
+            src_path: Some(Path::new(root).join("src/lib.rs")),
+            edition: Edition::Edition2018,
+            disambiguator: (0, 0),
+        }
+    }
+
+    #[test]
+    fn owning_package() {
+        let project = ProjectModel {
+            manifest_to_id: vec![
+                (PathBuf::from("/ws/foo/Cargo.toml"), Package(0)),
+                (PathBuf::from("/ws/bar/Cargo.toml"), Package(1)),
+            ]
+            .into_iter()
+            .collect(),
+            packages: vec![package("foo", "/ws/foo"), package("bar", "/ws/bar")],
+        };
+        let owner = |file: &str, crates: &[&Crate]| {
+            let crates = crates.iter().map(|&krate| krate.clone()).collect();
+            project.owning_package(Path::new(file), &crates)
+        };
+        let (foo, bar) = (krate("foo", "/ws/foo"), krate("bar", "/ws/bar"));
+        let (foo_id, bar_id) = (Package(0).id(&project), Package(1).id(&project));
+
+        assert_eq!(owner("/ws/foo/src/a.rs", &[&foo]), Some(foo_id));
+        assert_eq!(owner("/ws/bar/src/lib.rs", &[&bar]), Some(bar_id));
+        // Files which weren't part of the build.
+        assert_eq!(owner("/ws/bar/src/new.rs", &[]), Some(bar_id));
+        assert_eq!(owner("/elsewhere/a.rs", &[]), None);
+        // Files which are `include!`d by both packages.
+        assert_eq!(owner("/ws/bar/src/shared.rs", &[&foo, &bar]), Some(bar_id));
+        assert_eq!(owner("/ws/shared.rs", &[&foo, &bar]), None);
+    }
+}
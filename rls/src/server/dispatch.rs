@@ -1,6 +1,6 @@
 use std::sync::mpsc;
 use std::thread;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
 use jsonrpc_core::types::ErrorCode;
 use log::debug;
@@ -9,11 +9,12 @@ use crate::actions::work_pool;
 use crate::actions::work_pool::WorkDescription;
 use crate::actions::InitActionContext;
 use crate::concurrency::{ConcurrentJob, JobToken};
-use crate::lsp_data::LSPRequest;
+use crate::lsp_data::{LSPRequest, Metrics};
 use crate::server;
 use crate::server::io::Output;
+use crate::server::logging;
 use crate::server::message::ResponseError;
-use crate::server::{Request, Response};
+use crate::server::{Notification, Request, Response};
 
 use super::requests::*;
 
@@ -52,6 +53,10 @@ macro_rules! define_dispatch_request_enum {
                     DispatchRequest::$request_type(req) => {
                         let Request { id, params, received, .. } = req;
                         let timeout = $request_type::timeout();
+                        let _correlation =
+                            logging::enter(Some(logging::correlation_id(&(&id).into())));
+                        let metrics = ctx.request_metrics();
+                        let handler_metrics = metrics.clone();
 
                         let receiver = work_pool::receive_from_thread(move || {
                             // Checking timeout here can prevent starting expensive work that has
@@ -62,7 +67,13 @@ macro_rules! define_dispatch_request_enum {
                                 $request_type::fallback_response()
                             }
                             else {
-                                $request_type::handle(ctx, params)
+                                let start = Instant::now();
+                                let response = $request_type::handle(ctx, params);
+                                // Recorded here, so that requests which time out count too.
+                                if let Some(metrics) = handler_metrics {
+                                    metrics.record($request_type::METHOD, start.elapsed());
+                                }
+                                response
                             }
                         }, WorkDescription($request_type::METHOD));
 
@@ -76,6 +87,9 @@ macro_rules! define_dispatch_request_enum {
                                 out.failure_message(id, code, msg)
                             }
                         }
+                        if let Some(metrics) = metrics {
+                            out.notify(Notification::<Metrics>::new(metrics.report()));
+                        }
                     }
                 )*
                 }
@@ -87,7 +101,10 @@ macro_rules! define_dispatch_request_enum {
 define_dispatch_request_enum!(
     Completion,
     Definition,
+    BatchDefinition,
+    Declaration,
     References,
+    GroupedReferences,
     WorkspaceSymbol,
     Symbols,
     Hover,
@@ -98,8 +115,32 @@ define_dispatch_request_enum!(
     ResolveCompletion,
     Formatting,
     RangeFormatting,
+    OnTypeFormatting,
     ExecuteCommand,
     CodeLensRequest,
+    CodeLensResolve,
+    TypeOfSelection,
+    InlayHints,
+    Status,
+    AnalysisFreshness,
+    CrateGraph,
+    DeadCode,
+    RegexSymbol,
+    QualifiedPath,
+    ExplainError,
+    SemanticTokensFull,
+    SemanticTokensFullDelta,
+    CallHierarchyPrepare,
+    CallHierarchyIncomingCalls,
+    CallHierarchyOutgoingCalls,
+    TypeHierarchyPrepare,
+    TypeHierarchySupertypes,
+    TypeHierarchySubtypes,
+    ImplementMissingItems,
+    ActiveCfgs,
+    ToggleImpl,
+    Outline,
+    NearestTest,
 );
 
 /// Provides ability to dispatch requests to a worker thread that will
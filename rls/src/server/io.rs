@@ -3,10 +3,12 @@ use log::{debug, trace};
 use super::{Notification, Request, RequestId};
 use crate::lsp_data::{LSPNotification, LSPRequest};
 
+use std::collections::HashMap;
 use std::fmt;
 use std::io::{self, BufRead, Write};
 use std::sync::atomic::{AtomicU64, Ordering};
-use std::sync::Arc;
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::{Arc, Mutex};
 
 use jsonrpc_core::{self as jsonrpc, response, version, Id};
 
@@ -164,18 +166,39 @@ pub trait Output: Sync + Send + Clone + 'static {
     {
         self.response(format!("{}", request));
     }
+
+    /// Sends a request along the output and returns a receiver for the
+    /// client's response to it. Outputs which don't receive responses (by
+    /// default) disconnect the receiver straight away.
+    fn request_with_response<A>(&self, request: Request<A>) -> Receiver<ClientResponse>
+    where
+        A: LSPRequest,
+        <A as LSPRequest>::Params: serde::Serialize,
+    {
+        self.request(request);
+        channel().1
+    }
+
+    /// Passes a response from the client to the receiver returned by
+    /// `request_with_response` for its request, if any.
+    fn receive_response(&self, _response: response::Output) {}
 }
 
+/// The result of a request sent to the client, as returned in its response.
+pub type ClientResponse = Result<serde_json::Value, jsonrpc::Error>;
+
 /// An output that sends notifications and responses on `stdout`.
 #[derive(Clone)]
 pub(super) struct StdioOutput {
     next_id: Arc<AtomicU64>,
+    /// Senders for the responses awaited from the client, by request ID.
+    pending: Arc<Mutex<HashMap<RequestId, Sender<ClientResponse>>>>,
 }
 
 impl StdioOutput {
     /// Constructs a new `stdout` output.
     pub(crate) fn new() -> StdioOutput {
-        StdioOutput { next_id: Arc::new(AtomicU64::new(1)) }
+        StdioOutput { next_id: Arc::new(AtomicU64::new(1)), pending: Default::default() }
     }
 }
 
@@ -194,6 +217,34 @@ impl Output for StdioOutput {
     fn provide_id(&self) -> RequestId {
         RequestId::Num(self.next_id.fetch_add(1, Ordering::SeqCst))
     }
+
+    fn request_with_response<A>(&self, request: Request<A>) -> Receiver<ClientResponse>
+    where
+        A: LSPRequest,
+        <A as LSPRequest>::Params: serde::Serialize,
+    {
+        let (sender, receiver) = channel();
+        // Register the request before sending it, so its response can't be
+        // received first.
+        self.pending.lock().unwrap().insert(request.id.clone(), sender);
+        self.request(request);
+        receiver
+    }
+
+    fn receive_response(&self, response: response::Output) {
+        let id = match response.id() {
+            Id::Num(n) => RequestId::Num(*n),
+            Id::Str(s) => RequestId::Str(s.clone()),
+            Id::Null => return,
+        };
+        match self.pending.lock().unwrap().remove(&id) {
+            // The receiver may have given up waiting already.
+            Some(sender) => {
+                let _ = sender.send(response.into());
+            }
+            None => debug!("Ignoring a response to unknown request {}", id),
+        }
+    }
 }
 
 #[cfg(test)]
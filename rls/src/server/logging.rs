@@ -0,0 +1,156 @@
+//! Correlation of log output with the LSP message it was produced for.
+//!
+//! While a message is handled, its correlation ID (based on the request ID,
+//! or synthetic for notifications) is set for the current thread and carried
+//! over to the work pool and build threads doing work for it.
+//! `CorrelatedLogger` prefixes every log record with it.
+
+use std::cell::RefCell;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use jsonrpc_core::Id;
+use log::{Log, Metadata, Record};
+
+thread_local! {
+    static CORRELATION_ID: RefCell<Option<String>> = RefCell::new(None);
+}
+
+/// Returns the correlation ID for a message with the JSON-RPC `id`. Messages
+/// without one (i.e., notifications) get a new synthetic ID.
+pub fn correlation_id(id: &Id) -> String {
+    static NEXT_NOTIFICATION: AtomicU64 = AtomicU64::new(0);
+
+    match id {
+        Id::Num(n) => format!("req:{}", n),
+        Id::Str(s) => format!("req:{}", s),
+        Id::Null => format!("notif:{}", NEXT_NOTIFICATION.fetch_add(1, Ordering::SeqCst)),
+    }
+}
+
+/// Returns the correlation ID set for the current thread, if any.
+pub fn current() -> Option<String> {
+    CORRELATION_ID.with(|id| id.borrow().clone())
+}
+
+/// Sets the correlation ID of the current thread until the returned guard is
+/// dropped, after which the previous one is restored.
+pub fn enter(id: Option<String>) -> CorrelationScope {
+    let previous = CORRELATION_ID.with(|current| current.replace(id));
+    CorrelationScope { previous }
+}
+
+/// Guard returned by `enter`.
+pub struct CorrelationScope {
+    previous: Option<String>,
+}
+
+impl Drop for CorrelationScope {
+    fn drop(&mut self) {
+        let previous = self.previous.take();
+        CORRELATION_ID.with(|current| *current.borrow_mut() = previous);
+    }
+}
+
+/// Logger prefixing the records logged by the inner logger with the
+/// correlation ID of the current thread.
+pub struct CorrelatedLogger<L> {
+    inner: L,
+}
+
+impl<L: Log> CorrelatedLogger<L> {
+    pub fn new(inner: L) -> CorrelatedLogger<L> {
+        CorrelatedLogger { inner }
+    }
+}
+
+impl<L: Log> Log for CorrelatedLogger<L> {
+    fn enabled(&self, metadata: &Metadata<'_>) -> bool {
+        self.inner.enabled(metadata)
+    }
+
+    fn log(&self, record: &Record<'_>) {
+        match current() {
+            Some(id) => self.inner.log(
+                &Record::builder()
+                    .args(format_args!("[{}] {}", id, record.args()))
+                    .metadata(record.metadata().clone())
+                    .module_path(record.module_path())
+                    .file(record.file())
+                    .line(record.line())
+                    .build(),
+            ),
+            None => self.inner.log(record),
+        }
+    }
+
+    fn flush(&self) {
+        self.inner.flush()
+    }
+}
+
+/// Initializes logging with `env_logger`, configured by `RUST_LOG` as usual,
+/// with correlation IDs.
+pub fn init() {
+    let logger = env_logger::Builder::from_default_env().build();
+    let max_level = logger.filter();
+    if log::set_boxed_logger(Box::new(CorrelatedLogger::new(logger))).is_ok() {
+        log::set_max_level(max_level);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::actions::work_pool::{self, WorkDescription};
+    use log::Level;
+    use std::sync::Mutex;
+
+    #[derive(Default)]
+    struct Capture(Mutex<Vec<String>>);
+
+    impl Log for Capture {
+        fn enabled(&self, _: &Metadata<'_>) -> bool {
+            true
+        }
+
+        fn log(&self, record: &Record<'_>) {
+            self.0.lock().unwrap().push(record.args().to_string());
+        }
+
+        fn flush(&self) {}
+    }
+
+    fn log_info(logger: &impl Log, message: &str) {
+        logger.log(&Record::builder().args(format_args!("{}", message)).level(Level::Info).build());
+    }
+
+    #[test]
+    fn logs_carry_request_id() {
+        let logger = CorrelatedLogger::new(Capture::default());
+
+        log_info(&logger, "idle");
+        {
+            let _scope = enter(Some(correlation_id(&Id::Num(42))));
+            log_info(&logger, "handling hover");
+
+            // Work done for the request on the work pool has the same ID.
+            let receiver =
+                work_pool::receive_from_thread(current, WorkDescription("logs_carry_request_id"));
+            assert_eq!(receiver.recv().unwrap(), Some("req:42".to_owned()));
+        }
+        log_info(&logger, "idle again");
+
+        assert_eq!(
+            *logger.inner.0.lock().unwrap(),
+            vec!["idle", "[req:42] handling hover", "idle again"]
+        );
+    }
+
+    #[test]
+    fn notifications_get_synthetic_ids() {
+        let (first, second) = (correlation_id(&Id::Null), correlation_id(&Id::Null));
+        assert!(first.starts_with("notif:"));
+        assert!(second.starts_with("notif:"));
+        assert_ne!(first, second);
+    }
+}
@@ -326,7 +326,6 @@ impl RawMessage {
         let method = match ls_command.get("method") {
             Some(method) => method,
             // No method means this is a response to one of our requests.
-            // FIXME: we should confirm these, but currently just ignore them.
             None => return Ok(None),
         };
 
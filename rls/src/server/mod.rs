@@ -2,15 +2,17 @@
 //! interactions (for example, to add support for handling new types of
 //! requests).
 
-use crate::actions::{notifications, requests, ActionContext};
+use crate::actions::{notifications, requests, semantic_tokens, ActionContext};
 use crate::config::{Config, DEPRECATED_OPTIONS};
 use crate::lsp_data;
+pub use crate::lsp_data::Initialize as InitializeRequest;
 use crate::lsp_data::{
-    InitializationOptions, LSPNotification, LSPRequest, MessageType, ShowMessageParams,
+    ExtendedServerCapabilities, InitializationOptions, InitializeRequestResult, LSPNotification,
+    LSPRequest, MessageType, ShowMessageParams,
 };
 use crate::server::dispatch::Dispatcher;
 pub use crate::server::dispatch::{RequestAction, DEFAULT_REQUEST_TIMEOUT};
-pub use crate::server::io::{MessageReader, Output};
+pub use crate::server::io::{ClientResponse, MessageReader, Output};
 use crate::server::io::{StdioMsgReader, StdioOutput};
 use crate::server::message::RawMessage;
 pub use crate::server::message::{
@@ -21,21 +23,21 @@ use crate::version;
 use jsonrpc_core::{self as jsonrpc, types::error::ErrorCode, Id};
 use log::{debug, error, trace, warn};
 pub use lsp_types::notification::{Exit as ExitNotification, ShowMessage};
-pub use lsp_types::request::Initialize as InitializeRequest;
 pub use lsp_types::request::Shutdown as ShutdownRequest;
 use lsp_types::{
     CodeActionProviderCapability, CodeLensOptions, CompletionOptions, ExecuteCommandOptions,
-    ImplementationProviderCapability, InitializeParams, InitializeResult, RenameProviderCapability,
+    ImplementationProviderCapability, InitializeParams, RenameProviderCapability,
     ServerCapabilities, TextDocumentSyncCapability, TextDocumentSyncKind,
 };
 use rls_analysis::AnalysisHost;
 use rls_vfs::Vfs;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::sync::atomic::Ordering;
 use std::sync::{Arc, Mutex};
 
 mod dispatch;
 mod io;
+pub mod logging;
 mod message;
 
 const NOT_INITIALIZED_CODE: ErrorCode = ErrorCode::ServerError(-32002);
@@ -67,6 +69,7 @@ impl BlockingRequestAction for ShutdownRequest {
         if let Ok(ctx) = ctx.inited() {
             // Currently we don't perform an explicit clean-up, other than storing state.
             ctx.shut_down.store(true, Ordering::SeqCst);
+            crate::actions::idle::remove_index(ctx);
             Ok(Ack)
         } else {
             Err(ResponseError::Message(
@@ -152,16 +155,17 @@ impl BlockingRequestAction for InitializeRequest {
 
     fn handle<O: Output>(
         id: RequestId,
-        mut params: Self::Params,
+        params: Self::Params,
         ctx: &mut ActionContext,
         out: O,
     ) -> Result<NoResponse, ResponseError> {
+        let client_capabilities = lsp_data::ClientCapabilities::new(&params);
+        let mut params = params.params;
         let mut dups = std::collections::HashMap::new();
         let mut unknowns = Vec::new();
         let mut deprecated = Vec::new();
-        let init_options = params
-            .initialization_options
-            .take()
+        let root_path = get_root_path(&params);
+        let init_options = with_workspace_config(params.initialization_options.take(), &root_path)
             .and_then(|opt| {
                 InitializationOptions::try_deserialize(
                     opt,
@@ -191,14 +195,15 @@ impl BlockingRequestAction for InitializeRequest {
         maybe_notify_deprecated_configs(&out, &deprecated);
         maybe_notify_duplicated_configs(&out, &dups);
 
-        let result = InitializeResult { capabilities: server_caps(ctx) };
+        let mut capabilities = server_caps(ctx);
+        disable_capabilities(&mut capabilities, init_options.disabled_capabilities());
+        let result = InitializeRequestResult { capabilities };
 
         // Send response early before `ctx.init` to enforce
         // initialize-response-before-all-other-messages constraint.
         result.send(id, &out);
 
-        let capabilities = lsp_data::ClientCapabilities::new(&params);
-        ctx.init(get_root_path(&params), init_options, capabilities, &out).unwrap();
+        ctx.init(root_path, init_options, client_capabilities, &out).unwrap();
 
         Ok(NoResponse)
     }
@@ -299,7 +304,16 @@ impl<O: Output> LsService<O> {
                     <$request as LSPRequest>::METHOD => {
                         let request: Request<$request> = msg.parse_as_request()?;
                         if let Ok(ctx) = self.ctx.inited() {
-                            self.dispatcher.dispatch(request, ctx);
+                            if ctx.is_method_disabled(&$method) {
+                                debug!("Capability disabled, rejecting {}", $method);
+                                self.output.failure_message(
+                                    request.id,
+                                    ErrorCode::MethodNotFound,
+                                    format!("`{}` is disabled by `disabled_capabilities`", $method),
+                                );
+                            } else {
+                                self.dispatcher.dispatch(request, ctx);
+                            }
                         }
                         else {
                             warn!(
@@ -333,6 +347,7 @@ impl<O: Output> LsService<O> {
             notifications:
                 notifications::Initialized,
                 notifications::DidOpenTextDocument,
+                notifications::DidCloseTextDocument,
                 notifications::DidChangeTextDocument,
                 notifications::DidSaveTextDocument,
                 notifications::DidChangeConfiguration,
@@ -345,6 +360,7 @@ impl<O: Output> LsService<O> {
                 requests::ExecuteCommand,
                 requests::Formatting,
                 requests::RangeFormatting,
+                requests::OnTypeFormatting,
                 requests::ResolveCompletion,
                 requests::Rename,
                 requests::CodeAction,
@@ -354,9 +370,35 @@ impl<O: Output> LsService<O> {
                 requests::Hover,
                 requests::WorkspaceSymbol,
                 requests::Definition,
+                requests::Declaration,
                 requests::References,
+                requests::GroupedReferences,
                 requests::Completion,
-                requests::CodeLensRequest;
+                requests::CodeLensRequest,
+                requests::CodeLensResolve,
+                requests::TypeOfSelection,
+                requests::InlayHints,
+                requests::Status,
+                requests::AnalysisFreshness,
+                requests::CrateGraph,
+                requests::DeadCode,
+                requests::RegexSymbol,
+                requests::QualifiedPath,
+                requests::BatchDefinition,
+                requests::ExplainError,
+                requests::SemanticTokensFull,
+                requests::SemanticTokensFullDelta,
+                requests::CallHierarchyPrepare,
+                requests::CallHierarchyIncomingCalls,
+                requests::CallHierarchyOutgoingCalls,
+                requests::TypeHierarchyPrepare,
+                requests::TypeHierarchySupertypes,
+                requests::TypeHierarchySubtypes,
+                requests::ImplementMissingItems,
+                requests::ActiveCfgs,
+                requests::ToggleImpl,
+                requests::Outline,
+                requests::NearestTest;
         );
         Ok(())
     }
@@ -378,7 +420,14 @@ impl<O: Output> LsService<O> {
 
         let raw_message = match RawMessage::try_parse(&msg_string) {
             Ok(Some(rm)) => rm,
-            Ok(None) => return ServerStateChange::Continue,
+            Ok(None) => {
+                // A response to one of our requests.
+                match serde_json::from_str(&msg_string) {
+                    Ok(response) => self.output.receive_response(response),
+                    Err(e) => debug!("Couldn't parse response `{}`: {:?}", msg_string, e),
+                }
+                return ServerStateChange::Continue;
+            }
             Err(e) => {
                 error!("parsing error, {:?}", e);
                 self.output.failure(Id::Null, jsonrpc::Error::parse_error());
@@ -387,6 +436,7 @@ impl<O: Output> LsService<O> {
         };
 
         trace!("Parsed message `{:?}`", raw_message);
+        let _correlation = logging::enter(Some(logging::correlation_id(&raw_message.id)));
 
         // If we're in shutdown mode, ignore any messages other than 'exit'.
         // This is not actually in the spec; I'm not sure we should do this,
@@ -406,6 +456,10 @@ impl<O: Output> LsService<O> {
             }
         }
 
+        if let ActionContext::Init(ref ctx) = self.ctx {
+            ctx.record_activity();
+        }
+
         if let Err(e) = self.dispatch_message(&raw_message) {
             error!("dispatch error: {:?}, message: `{}`", e, msg_string);
             self.output.failure(raw_message.id, e);
@@ -432,8 +486,8 @@ pub enum ServerStateChange {
     Break { exit_code: i32 },
 }
 
-fn server_caps(ctx: &ActionContext) -> ServerCapabilities {
-    ServerCapabilities {
+fn server_caps(ctx: &ActionContext) -> ExtendedServerCapabilities {
+    let base = ServerCapabilities {
         text_document_sync: Some(TextDocumentSyncCapability::Kind(
             TextDocumentSyncKind::Incremental,
         )),
@@ -458,6 +512,9 @@ fn server_caps(ctx: &ActionContext) -> ServerCapabilities {
             commands: vec![
                 format!("rls.applySuggestion-{}", ctx.pid()),
                 format!("rls.deglobImports-{}", ctx.pid()),
+                format!("rls.extractFunction-{}", ctx.pid()),
+                format!("rls.rebuild-{}", ctx.pid()),
+                format!("rls.reloadAnalysis-{}", ctx.pid()),
             ],
         }),
         rename_provider: Some(RenameProviderCapability::Simple(true)),
@@ -467,15 +524,86 @@ fn server_caps(ctx: &ActionContext) -> ServerCapabilities {
         // We'll update these capabilities dynamically when we get config
         // info from the client.
         document_range_formatting_provider: Some(false),
-
-        code_lens_provider: Some(CodeLensOptions { resolve_provider: Some(false) }),
         document_on_type_formatting_provider: None,
+
+        code_lens_provider: Some(CodeLensOptions { resolve_provider: Some(true) }),
         signature_help_provider: None,
 
         folding_range_provider: None,
         workspace: None,
         selection_range_provider: None,
+    };
+
+    ExtendedServerCapabilities {
+        base,
+        // Semantic tokens are computed from the idents recorded by the analysis.
+        semantic_tokens_provider: if cfg!(feature = "idents") {
+            Some(semantic_tokens::options())
+        } else {
+            None
+        },
+        call_hierarchy_provider: Some(true),
+        type_hierarchy_provider: Some(true),
+    }
+}
+
+/// Leaves the `disabled` capabilities (see `Config::disabled_capabilities`) out
+/// of `caps`.
+fn disable_capabilities(caps: &mut ExtendedServerCapabilities, disabled: &[String]) {
+    for capability in disabled {
+        match capability.as_str() {
+            "hover" => caps.base.hover_provider = None,
+            "completion" => caps.base.completion_provider = None,
+            "definition" => caps.base.definition_provider = None,
+            "implementation" => caps.base.implementation_provider = None,
+            "references" => caps.base.references_provider = None,
+            "documentHighlight" => caps.base.document_highlight_provider = None,
+            "documentSymbol" => caps.base.document_symbol_provider = None,
+            "symbol" => caps.base.workspace_symbol_provider = None,
+            "codeAction" => caps.base.code_action_provider = None,
+            "codeLens" => caps.base.code_lens_provider = None,
+            "formatting" => caps.base.document_formatting_provider = None,
+            "rangeFormatting" => caps.base.document_range_formatting_provider = None,
+            "onTypeFormatting" => caps.base.document_on_type_formatting_provider = None,
+            "rename" => caps.base.rename_provider = None,
+            "executeCommand" => caps.base.execute_command_provider = None,
+            "semanticTokens" => caps.semantic_tokens_provider = None,
+            "callHierarchy" => caps.call_hierarchy_provider = None,
+            "typeHierarchy" => caps.type_hierarchy_provider = None,
+            command if command.starts_with("rls.") => {
+                if let Some(options) = caps.base.execute_command_provider.as_mut() {
+                    let prefix = format!("{}-", command);
+                    options.commands.retain(|name| !name.starts_with(&prefix));
+                }
+            }
+            _ => warn!("Unknown capability to disable: {}", capability),
+        }
+    }
+}
+
+/// Fills in the `settings.rust` initialization options with the options from
+/// the workspace's `.rls.toml` file, without overriding any option sent by the
+/// client.
+fn with_workspace_config(
+    init_options: Option<serde_json::Value>,
+    root_path: &Path,
+) -> Option<serde_json::Value> {
+    let file_settings = match Config::load_workspace_file(root_path) {
+        Some(file_settings) => file_settings,
+        None => return init_options,
+    };
+
+    let mut init_options = init_options.unwrap_or_else(|| serde_json::json!({}));
+    if let Some(rust) = init_options
+        .as_object_mut()
+        .map(|opts| opts.entry("settings").or_insert_with(|| serde_json::json!({})))
+        .and_then(|settings| settings.as_object_mut())
+        .map(|settings| settings.entry("rust").or_insert_with(|| serde_json::json!({})))
+        .and_then(|rust| rust.as_object_mut())
+    {
+        Config::merge_workspace_settings(rust, file_settings);
     }
+    Some(init_options)
 }
 
 fn get_root_path(params: &InitializeParams) -> PathBuf {
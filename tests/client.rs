@@ -4,6 +4,12 @@ use std::time::Duration;
 
 use futures::future;
 use lsp_types::{notification::*, request::*, *};
+use rls::lsp_data::{
+    ActiveCfgs, ActiveCfgsParams, AnalysisFreshness, CrateGraph, CrateGraphParams,
+    CrateGraphResult, DeadCode, FileFreshness, ImplementMissingItems, InlayHint, InlayHints,
+    InlayHintsParams, NearestTest, ServerState, Status, StatusChanged, StatusParams, ToggleImpl,
+    TypeOfSelection, TypeOfSelectionParams,
+};
 use serde::de::Deserialize;
 use serde_json::json;
 
@@ -176,6 +182,67 @@ fn client_test_simple_workspace() {
     assert_eq!(count, 4);
 }
 
+#[test]
+fn client_work_done_progress() {
+    let p = ProjectBuilder::try_from_fixture(fixtures_dir().join("infer_bin")).unwrap().build();
+    let root_path = p.root();
+    let mut rls = p.spawn_rls_async();
+
+    let is_create = |msg: &serde_json::Value| msg["method"] == "window/workDoneProgress/create";
+    let created = rls.future_msg(is_create);
+
+    // `lsp_types` doesn't know about the `workDoneProgress` capability yet.
+    let mut params = serde_json::to_value(initialize_params(root_path)).unwrap();
+    params["capabilities"]["window"]["workDoneProgress"] = json!(true);
+    rls.send(json!({ "jsonrpc": "2.0", "id": 0, "method": Initialize::METHOD, "params": params }));
+
+    // The progress of the build is only reported once the client created it.
+    let create = rls.block_on(created).unwrap().unwrap();
+    let building = create["params"]["token"].clone();
+    let token = building.clone();
+    assert!(!rls.messages().iter().any(|msg| msg["params"]["token"] == token));
+    let ended = rls.future_msg(move |msg| {
+        msg["method"] == "$/progress"
+            && msg["params"]["token"] == token
+            && msg["params"]["value"]["kind"] == "end"
+    });
+    let created = rls.future_msg(is_create);
+    rls.send(json!({ "jsonrpc": "2.0", "id": create["id"], "result": null }));
+    rls.block_on(ended).unwrap().unwrap();
+
+    // The progress of the indexing isn't reported if the client fails to
+    // create it.
+    let create = rls.block_on(created).unwrap().unwrap();
+    let indexing = create["params"]["token"].clone();
+    let idle = rls
+        .future_msg(|msg| msg["method"] == "rls/statusChanged" && msg["params"]["state"] == "idle");
+    rls.send(json!({
+        "jsonrpc": "2.0",
+        "id": create["id"],
+        "error": { "code": -32603, "message": "Can't create progress" },
+    }));
+    rls.block_on(idle).unwrap().unwrap();
+
+    let messages = rls.messages();
+    assert!(messages.iter().all(|msg| msg["method"] != "window/progress"));
+    let progress = |token: &serde_json::Value| -> Vec<serde_json::Value> {
+        messages
+            .iter()
+            .filter(|msg| msg["method"] == "$/progress" && msg["params"]["token"] == *token)
+            .map(|msg| msg["params"]["value"].clone())
+            .collect()
+    };
+
+    let values = progress(&building);
+    assert_eq!(values.first().unwrap()["kind"], "begin");
+    assert_eq!(values.first().unwrap()["title"], "Building");
+    assert_eq!(values.last().unwrap()["kind"], "end");
+    assert!(values
+        .iter()
+        .any(|value| value["kind"] == "report" && value["message"] == "infer_bin"));
+    assert!(progress(&indexing).is_empty());
+}
+
 #[test]
 fn client_changing_workspace_lib_retains_diagnostics() {
     let p = project("simple_workspace")
@@ -465,6 +532,249 @@ fn client_test_complete_self_crate_name() {
     assert_eq!(item.detail.unwrap(), "pub fn function() -> usize");
 }
 
+#[test]
+fn client_completion_uses_package_edition() {
+    // `async` is a valid identifier in the 2015 edition.
+    let p = project("completion_edition_2015")
+        .file(
+            "Cargo.toml",
+            r#"
+                [package]
+                name = "edition_2015"
+                version = "0.1.0"
+                authors = ["Example <rls@example.com>"]
+                edition = "2015"
+            "#,
+        )
+        .file(
+            "src/lib.rs",
+            r#"
+                pub fn async() -> usize { 5 }
+                fn foo() {
+                    let a = asy~
+                }
+            "#,
+        )
+        .build();
+
+    let root_path = p.root();
+    let mut rls = p.spawn_rls_async();
+
+    rls.request::<Initialize>(
+        0,
+        initialize_params_with_opts(
+            root_path,
+            json!({ "settings": { "rust": { "default_edition": "2018" } } }),
+        ),
+    );
+
+    rls.wait_for_diagnostics();
+
+    let response = rls.request::<Completion>(
+        100,
+        CompletionParams {
+            context: None,
+            text_document_position: TextDocumentPositionParams {
+                position: Position::new(3, 31),
+                text_document: TextDocumentIdentifier {
+                    uri: Url::from_file_path(p.root().join("src/lib.rs")).unwrap(),
+                },
+            },
+        },
+    );
+
+    let items = match response {
+        Some(CompletionResponse::Array(items)) => items,
+        Some(CompletionResponse::List(CompletionList { items, .. })) => items,
+        _ => Vec::new(),
+    };
+
+    let item = items.into_iter().find(|item| item.label == "async").expect("no `async` completion");
+    assert_eq!(item.detail.unwrap(), "pub fn async() -> usize");
+}
+
+#[test]
+fn client_completion_in_rust_file_extensions() {
+    let p = project("completion_rs_in")
+        .file("Cargo.toml", &basic_bin_manifest("foo"))
+        .file("src/main.rs", "fn main() {}\n")
+        .file(
+            "src/template.rs.in",
+            r#"
+                pub fn templated_value() -> usize { 5 }
+                fn templated() {
+                    let a = templated_v~
+                }
+            "#,
+        )
+        .file(
+            "src/template.txt",
+            r#"
+                pub fn templated_value() -> usize { 5 }
+                fn templated() {
+                    let a = templated_v~
+                }
+            "#,
+        )
+        .build();
+
+    let root_path = p.root();
+    let mut rls = p.spawn_rls_async();
+
+    rls.request::<Initialize>(
+        0,
+        initialize_params_with_opts(
+            root_path,
+            json!({ "settings": { "rust": { "rust_file_extensions": [".rs.in"] } } }),
+        ),
+    );
+
+    rls.wait_for_indexing();
+
+    let params = |file: &str| CompletionParams {
+        context: None,
+        text_document_position: TextDocumentPositionParams {
+            position: Position::new(3, 39),
+            text_document: TextDocumentIdentifier {
+                uri: Url::from_file_path(p.root().join(file)).unwrap(),
+            },
+        },
+    };
+    let items = |response: Option<CompletionResponse>| match response {
+        Some(CompletionResponse::Array(items)) => items,
+        Some(CompletionResponse::List(CompletionList { items, .. })) => items,
+        _ => Vec::new(),
+    };
+
+    let response = rls.request::<Completion>(100, params("src/template.rs.in"));
+    assert!(items(response).iter().any(|item| item.label == "templated_value"));
+    // Files of other languages or unknown extensions aren't completed.
+    let response = rls.request::<Completion>(101, params("src/template.txt"));
+    assert!(items(response).is_empty());
+
+    // Nor are those of extensions dropped from the configuration.
+    let settings = json!({ "rust": { "rust_file_extensions": [] } });
+    rls.notify::<DidChangeConfiguration>(DidChangeConfigurationParams { settings });
+    let response = rls.request::<Completion>(102, params("src/template.rs.in"));
+    assert!(items(response).is_empty());
+}
+
+#[test]
+fn client_completion_of_struct_literal_fields() {
+    let p = project("struct_literal_fields")
+        .file("Cargo.toml", &basic_bin_manifest("foo"))
+        .file(
+            "src/main.rs",
+            r#"
+                #[derive(Default)]
+                struct Foo {
+                    x: u32,
+                    name: String,
+                    tags: Vec<String>,
+                }
+
+                fn main() {
+                    let _ = Foo { x: 3, ..Foo::default() };
+                }
+            "#,
+        )
+        .build();
+
+    let root_path = p.root();
+    let mut rls = p.spawn_rls_async();
+
+    rls.request::<Initialize>(0, initialize_params(root_path));
+
+    rls.wait_for_indexing();
+
+    // Right before `..Foo::default()`.
+    let response = rls.request::<Completion>(
+        100,
+        CompletionParams {
+            context: None,
+            text_document_position: TextDocumentPositionParams {
+                position: Position::new(9, 40),
+                text_document: TextDocumentIdentifier {
+                    uri: Url::from_file_path(p.root().join("src/main.rs")).unwrap(),
+                },
+            },
+        },
+    );
+
+    let items = match response {
+        Some(CompletionResponse::Array(items)) => items,
+        Some(CompletionResponse::List(CompletionList { items, .. })) => items,
+        _ => Vec::new(),
+    };
+
+    let fields: Vec<_> = items.into_iter().map(|item| (item.label, item.detail.unwrap())).collect();
+    assert_eq!(
+        fields,
+        vec![
+            ("name".to_owned(), "String".to_owned()),
+            ("tags".to_owned(), "Vec<String>".to_owned())
+        ]
+    );
+}
+
+#[test]
+fn client_completion_in_untitled_document() {
+    let p = project("untitled_document")
+        .file("Cargo.toml", &basic_bin_manifest("foo"))
+        .file("src/main.rs", "fn main() {}\n")
+        .build();
+    let root_path = p.root();
+    let mut rls = p.spawn_rls_async();
+
+    rls.request::<Initialize>(0, initialize_params(root_path));
+
+    let uri = Url::parse("untitled:Untitled-1").unwrap();
+    rls.notify::<DidOpenTextDocument>(DidOpenTextDocumentParams {
+        text_document: TextDocumentItem {
+            uri: uri.clone(),
+            language_id: "rust".to_owned(),
+            version: 0,
+            text: "fn untitled_fn() {}\nfn main() {\n    untitled_\n}\n".to_owned(),
+        },
+    });
+
+    let response = rls.request::<Completion>(
+        100,
+        CompletionParams {
+            context: None,
+            text_document_position: TextDocumentPositionParams {
+                position: Position::new(2, 13),
+                text_document: TextDocumentIdentifier { uri },
+            },
+        },
+    );
+
+    let items = match response {
+        Some(CompletionResponse::Array(items)) => items,
+        Some(CompletionResponse::List(CompletionList { items, .. })) => items,
+        _ => Vec::new(),
+    };
+    assert!(items.iter().any(|item| item.label == "untitled_fn"));
+
+    // Other schemes aren't supported and get an error response.
+    rls.send(json!({
+        "jsonrpc": "2.0",
+        "id": 101,
+        "method": Completion::METHOD,
+        "params": CompletionParams {
+            context: None,
+            text_document_position: TextDocumentPositionParams {
+                position: Position::new(0, 0),
+                text_document: TextDocumentIdentifier {
+                    uri: Url::parse("git:/src/main.rs").unwrap(),
+                },
+            },
+        },
+    }));
+    let response = rls.wait_for_message(|val| val["id"] == 101);
+    assert_eq!(response["error"]["code"], -32602);
+}
+
 // Spurious in Rust CI, e.g.
 // https://github.com/rust-lang/rust/pull/60730
 // https://github.com/rust-lang/rust/pull/61771
@@ -719,6 +1029,39 @@ fn client_dependency_typo_and_fix() {
     );
 }
 
+/// A burst of changes to the watched files, e.g., from `cargo update`, only
+/// triggers a single Cargo build.
+#[test]
+fn client_watched_files_changes_debounced() {
+    let p = ProjectBuilder::try_from_fixture(fixtures_dir().join("infer_bin")).unwrap().build();
+    let root_path = p.root();
+    let mut rls = p.spawn_rls_async();
+
+    rls.request::<Initialize>(0, initialize_params(root_path));
+    rls.wait_for_indexing();
+
+    let change = |file: &str| FileEvent {
+        uri: Url::from_file_path(root_path.join(file)).unwrap(),
+        typ: FileChangeType::Changed,
+    };
+    for _ in 0..3 {
+        rls.notify::<DidChangeWatchedFiles>(DidChangeWatchedFilesParams {
+            changes: vec![change("Cargo.toml"), change("Cargo.lock")],
+        });
+    }
+    rls.wait_for_indexing();
+
+    let mut builds: Vec<_> = rls
+        .messages()
+        .iter()
+        .filter(|msg| msg["params"]["title"] == "Building")
+        .map(|msg| msg["params"]["id"].as_str().unwrap().to_owned())
+        .collect();
+    builds.dedup();
+    // The initial build and the one for the changes.
+    assert_eq!(builds.len(), 2);
+}
+
 /// Tests correct positioning of a toml parse error, use of `==` instead of `=`.
 #[test]
 fn client_invalid_toml_manifest() {
@@ -970,7 +1313,7 @@ fn client_lens_run() {
             process_id: None,
             root_uri: None,
             root_path: Some(root_path.display().to_string()),
-            initialization_options: Some(json!({ "cmdRun": true})),
+            initialization_options: Some(json!({ "cmdRun": true, "cmdDebug": true })),
             capabilities: Default::default(),
             trace: None,
             workspace_folders: None,
@@ -989,93 +1332,373 @@ fn client_lens_run() {
         },
     );
 
-    let expected = vec![
-        CodeLens {
-            command: Some(Command {
-                command: "rls.run".to_string(),
-                title: "Run test".to_string(),
-                arguments: Some(vec![json!({
-                    "args": [ "test", "--", "--nocapture", "test_foo" ],
-                    "binary": "cargo",
-                    "env": { "RUST_BACKTRACE": "short" }
-                })]),
-            }),
-            data: None,
-            range: Range {
-                start: Position { line: 4, character: 3 },
-                end: Position { line: 4, character: 11 },
+    let lenses = |line, start, end, test_path| {
+        let cmd = json!({
+            "args": [ "test", "-p", "run", "--", "--nocapture", test_path ],
+            "binary": "cargo",
+            "env": { "RUST_BACKTRACE": "short" }
+        });
+        let range = Range {
+            start: Position { line, character: start },
+            end: Position { line, character: end },
+        };
+        vec![
+            CodeLens {
+                command: Some(Command {
+                    command: "rls.run".to_string(),
+                    title: "Run test".to_string(),
+                    arguments: Some(vec![cmd.clone()]),
+                }),
+                data: None,
+                range,
             },
-        },
-        CodeLens {
-            command: Some(Command {
-                command: "rls.run".to_string(),
-                title: "Run test".to_string(),
-                arguments: Some(vec![json!({
-                    "args": [ "test", "--", "--nocapture", "test_bar"],
-                    "binary": "cargo",
-                    "env": { "RUST_BACKTRACE": "short" }
-                })]),
-            }),
-            data: None,
-            range: Range {
-                start: Position { line: 9, character: 3 },
-                end: Position { line: 9, character: 11 },
+            CodeLens {
+                command: Some(Command {
+                    command: "rls.debug".to_string(),
+                    title: "Debug test".to_string(),
+                    arguments: Some(vec![cmd]),
+                }),
+                data: None,
+                range,
             },
-        },
-    ];
+        ]
+    };
+
+    let mut expected = lenses(4, 3, 11, "test_foo");
+    expected.extend(lenses(9, 3, 11, "test_bar"));
+    // Tests in nested modules are filtered by their path from the crate root.
+    expected.extend(lenses(21, 7, 15, "tests::test_qux"));
 
     assert_eq!(lens, Some(expected));
 }
 
 #[test]
-#[ignore] // Spurious in Rust CI, https://github.com/rust-lang/rust/issues/62225
-fn client_find_definitions() {
-    const SRC: &str = r#"
-        struct Foo {
-        }
+fn client_nearest_test() {
+    let p = ProjectBuilder::try_from_fixture(fixtures_dir().join("lens_run")).unwrap().build();
+    let root_path = p.root();
+    let mut rls = p.spawn_rls_async();
 
-        impl Foo {
-            fn new() {
-            }
-        }
+    rls.request::<Initialize>(0, initialize_params(root_path));
 
-        fn main() {
-            Foo::new();
-        }
-    "#;
+    rls.wait_for_indexing();
 
-    let p = project("simple_workspace")
+    let uri = Url::from_file_path(p.root().join("src/main.rs")).unwrap();
+    let mut nearest_test = |id, line, character| {
+        rls.request::<NearestTest>(
+            id,
+            TextDocumentPositionParams {
+                text_document: TextDocumentIdentifier { uri: uri.clone() },
+                position: Position { line, character },
+            },
+        )
+        .map(|test| (test.name, test.filter, test.range.start))
+    };
+
+    // In the body of a test in a nested module.
+    assert_eq!(
+        nearest_test(100, 22, 4),
+        Some(("test_qux".to_owned(), "tests::test_qux".to_owned(), Position::new(21, 7)))
+    );
+    // On the attribute of a test.
+    assert_eq!(
+        nearest_test(101, 3, 0),
+        Some(("test_foo".to_owned(), "test_foo".to_owned(), Position::new(4, 3)))
+    );
+    // Outside any test, the nearest one above is returned, if there is one.
+    assert_eq!(
+        nearest_test(102, 15, 4),
+        Some(("test_bar".to_owned(), "test_bar".to_owned(), Position::new(9, 3)))
+    );
+    assert_eq!(nearest_test(103, 0, 4), None);
+}
+
+#[test]
+fn client_lens_references() {
+    const SRC: &str = "fn main() {\n    foo();\n    foo();\n}\n\nfn foo() {}\n";
+
+    let p = project("lens_references")
         .file("Cargo.toml", &basic_bin_manifest("bar"))
         .file("src/main.rs", SRC)
         .build();
-
     let root_path = p.root();
     let mut rls = p.spawn_rls_async();
 
-    // FIXME: Without `all_targets=false`, this test will randomly fail.
-    let opts = json!({"settings": {"rust": {"racer_completion": false, "all_targets": false } } });
+    let opts = json!({"settings": {"rust": { "show_reference_lens": true } } });
     rls.request::<Initialize>(0, initialize_params_with_opts(root_path, opts));
 
     rls.wait_for_indexing();
 
-    let mut results = vec![];
-    for (line_index, line) in SRC.lines().enumerate() {
-        for i in 0..line.len() {
-            let id = (line_index * 100 + i) as u64;
-            let result = rls.request::<GotoDefinition>(
-                id,
-                TextDocumentPositionParams {
-                    position: Position { line: line_index as u64, character: i as u64 },
-                    text_document: TextDocumentIdentifier {
-                        uri: Url::from_file_path(p.root().join("src/main.rs")).unwrap(),
-                    },
-                },
-            );
+    let uri = Url::from_file_path(p.root().join("src/main.rs")).unwrap();
+    let lenses = rls
+        .request::<CodeLensRequest>(
+            1,
+            CodeLensParams { text_document: TextDocumentIdentifier { uri: uri.clone() } },
+        )
+        .unwrap();
+    // Reference counts are only computed when resolving.
+    assert!(lenses.iter().all(|lens| lens.command.is_none()));
 
-            let ranges: Vec<_> = result
-                .into_iter()
-                .flat_map(|x| match x {
-                    GotoDefinitionResponse::Scalar(loc) => vec![loc].into_iter(),
+    let mut resolve = |id, line, character| {
+        let lens = lenses.iter().find(|lens| lens.range.start == Position { line, character });
+        rls.request::<CodeLensResolve>(id, lens.unwrap().clone()).command.unwrap()
+    };
+
+    let main = resolve(2, 0, 3);
+    assert_eq!(main.title, "0 references");
+
+    let foo = resolve(3, 5, 3);
+    assert_eq!(foo.title, "2 references");
+    assert_eq!(foo.command, "editor.action.showReferences");
+    let locations: Vec<Location> =
+        serde_json::from_value(foo.arguments.unwrap()[2].clone()).unwrap();
+    let mut lines: Vec<_> = locations.iter().map(|l| (&l.uri, l.range.start.line)).collect();
+    lines.sort();
+    assert_eq!(lines, vec![(&uri, 1), (&uri, 2)]);
+}
+
+#[test]
+fn client_goto_declaration() {
+    const SRC: &str = "trait Greet {
+    fn greet(&self);
+}
+
+struct Foo;
+
+impl Greet for Foo {
+    fn greet(&self) {}
+}
+
+fn main() {
+    Foo.greet();
+}
+";
+
+    let p = project("goto_declaration")
+        .file("Cargo.toml", &basic_bin_manifest("bar"))
+        .file("src/main.rs", SRC)
+        .build();
+    let root_path = p.root();
+    let mut rls = p.spawn_rls_async();
+
+    rls.request::<Initialize>(0, initialize_params(root_path));
+
+    rls.wait_for_indexing();
+
+    // Ask for the declaration of `greet` in the impl.
+    let result = rls.request::<GotoDeclaration>(
+        1,
+        TextDocumentPositionParams {
+            position: Position { line: 7, character: 8 },
+            text_document: TextDocumentIdentifier {
+                uri: Url::from_file_path(p.root().join("src/main.rs")).unwrap(),
+            },
+        },
+    );
+
+    let expected = Range {
+        start: Position { line: 1, character: 7 },
+        end: Position { line: 1, character: 12 },
+    };
+    match result {
+        Some(GotoDefinitionResponse::Array(locs)) => {
+            assert_eq!(locs.iter().map(|l| l.range).collect::<Vec<_>>(), vec![expected]);
+        }
+        other => panic!("unexpected declaration response: {:?}", other),
+    }
+}
+
+#[test]
+fn client_type_of_selection() {
+    const SRC: &str = "fn main() {
+    let foo = 1;
+    let bar = foo + 2;
+    let baz = (foo + 2) * bar;
+    println!(\"{} {}\", bar, baz);
+}
+";
+
+    let p = project("type_of_selection")
+        .file("Cargo.toml", &basic_bin_manifest("bar"))
+        .file("src/main.rs", SRC)
+        .build();
+    let root_path = p.root();
+    let mut rls = p.spawn_rls_async();
+
+    rls.request::<Initialize>(0, initialize_params(root_path));
+
+    rls.wait_for_indexing();
+
+    let uri = Url::from_file_path(p.root().join("src/main.rs")).unwrap();
+    let mut type_of = |id: u64, line: u64, start: u64, end: u64| {
+        let params = TypeOfSelectionParams {
+            text_document: TextDocumentIdentifier { uri: uri.clone() },
+            range: Range {
+                start: Position { line, character: start },
+                end: Position { line, character: end },
+            },
+        };
+        rls.send(json!({
+            "jsonrpc": "2.0",
+            "id": id,
+            "method": TypeOfSelection::METHOD,
+            "params": params,
+        }));
+        rls.wait_for_message(move |val| val["id"] == id)
+    };
+
+    // `foo + 2`
+    assert_eq!(type_of(1, 2, 14, 21)["result"], json!("i32"));
+    // `foo +` is not a complete expression.
+    let err = type_of(2, 2, 14, 19);
+    assert!(err["error"]["message"].as_str().unwrap().contains("not a complete expression"));
+    // `foo + 2` is neither a name nor a `let` initializer here, so the type is
+    // not known.
+    let err = type_of(3, 3, 15, 22);
+    assert!(err["error"]["message"].as_str().unwrap().contains("Could not infer the type"));
+    // `bar` is a name.
+    assert_eq!(type_of(4, 3, 26, 29)["result"], json!("i32"));
+}
+
+#[test]
+fn client_inlay_hints() {
+    const SRC: &str = "fn main() {
+    let mut bar = 42;
+    let baz: u64 = 1;
+    let (a, b) = (1, 2);
+    bar += 1;
+    let add = |x: i32| x + bar;
+    let flag = true;
+    let ratio = 1.5;
+    println!(\"{} {} {} {} {} {} {}\", bar, baz, a, b, add(1), flag, ratio);
+}
+";
+
+    let p = project("inlay_hints")
+        .file("Cargo.toml", &basic_bin_manifest("bar"))
+        .file("src/main.rs", SRC)
+        .build();
+    let root_path = p.root();
+    let mut rls = p.spawn_rls_async();
+
+    rls.request::<Initialize>(0, initialize_params(root_path));
+
+    rls.wait_for_indexing();
+
+    let uri = Url::from_file_path(p.root().join("src/main.rs")).unwrap();
+    // The lines of the requested range and the expected hints as
+    // `(line, character, label)`.
+    let tests: &[((u64, u64), &[(u64, u64, &str)])] = &[
+        // `baz` has a type annotation, tuple patterns and closures are skipped.
+        ((0, 9), &[(1, 15, ": i32"), (6, 12, ": bool"), (7, 13, ": f64")]),
+        ((1, 1), &[(1, 15, ": i32")]),
+        ((2, 5), &[]),
+        ((7, 9), &[(7, 13, ": f64")]),
+    ];
+
+    for (i, ((start, end), expected)) in tests.iter().enumerate() {
+        let id = i as u64 + 1;
+        let params = InlayHintsParams {
+            text_document: TextDocumentIdentifier { uri: uri.clone() },
+            range: Range { start: Position::new(*start, 0), end: Position::new(*end, 0) },
+        };
+        rls.send(json!({
+            "jsonrpc": "2.0",
+            "id": id,
+            "method": InlayHints::METHOD,
+            "params": params,
+        }));
+        let result = rls.wait_for_message(move |val| val["id"] == id)["result"].clone();
+        let hints: Vec<InlayHint> = serde_json::from_value(result).unwrap();
+
+        let expected: Vec<_> = expected
+            .iter()
+            .map(|&(line, character, label)| InlayHint {
+                position: Position::new(line, character),
+                label: label.to_owned(),
+            })
+            .collect();
+        assert_eq!(hints, expected, "range {}..{}", start, end);
+    }
+}
+
+#[test]
+fn client_status_building_then_idle() {
+    let p = project("status")
+        .file("Cargo.toml", &basic_bin_manifest("foo"))
+        .file("src/main.rs", "fn main() {}\n")
+        .build();
+    let root_path = p.root();
+    let mut rls = p.spawn_rls_async();
+
+    rls.request::<Initialize>(0, initialize_params(root_path));
+
+    rls.wait_for_message(|msg| {
+        msg["method"] == StatusChanged::METHOD && msg["params"]["state"] == "idle"
+    });
+
+    let states: Vec<_> = rls
+        .messages()
+        .iter()
+        .filter(|msg| msg["method"] == StatusChanged::METHOD)
+        .map(|msg| StatusParams::deserialize(&msg["params"]).unwrap().state)
+        .collect();
+    assert_eq!(states.first(), Some(&ServerState::Building));
+    assert_eq!(states.last(), Some(&ServerState::Idle));
+
+    let status = rls.request::<Status>(1, ());
+    assert_eq!(status.state, ServerState::Idle);
+    assert_eq!(status.last_error, None);
+}
+
+#[test]
+#[ignore] // Spurious in Rust CI, https://github.com/rust-lang/rust/issues/62225
+fn client_find_definitions() {
+    const SRC: &str = r#"
+        struct Foo {
+        }
+
+        impl Foo {
+            fn new() {
+            }
+        }
+
+        fn main() {
+            Foo::new();
+        }
+    "#;
+
+    let p = project("simple_workspace")
+        .file("Cargo.toml", &basic_bin_manifest("bar"))
+        .file("src/main.rs", SRC)
+        .build();
+
+    let root_path = p.root();
+    let mut rls = p.spawn_rls_async();
+
+    // FIXME: Without `all_targets=false`, this test will randomly fail.
+    let opts = json!({"settings": {"rust": {"racer_completion": false, "all_targets": false } } });
+    rls.request::<Initialize>(0, initialize_params_with_opts(root_path, opts));
+
+    rls.wait_for_indexing();
+
+    let mut results = vec![];
+    for (line_index, line) in SRC.lines().enumerate() {
+        for i in 0..line.len() {
+            let id = (line_index * 100 + i) as u64;
+            let result = rls.request::<GotoDefinition>(
+                id,
+                TextDocumentPositionParams {
+                    position: Position { line: line_index as u64, character: i as u64 },
+                    text_document: TextDocumentIdentifier {
+                        uri: Url::from_file_path(p.root().join("src/main.rs")).unwrap(),
+                    },
+                },
+            );
+
+            let ranges: Vec<_> = result
+                .into_iter()
+                .flat_map(|x| match x {
+                    GotoDefinitionResponse::Scalar(loc) => vec![loc].into_iter(),
                     GotoDefinitionResponse::Array(locs) => locs.into_iter(),
                     _ => unreachable!(),
                 })
@@ -1294,6 +1917,322 @@ fn client_deglob() {
     );
 }
 
+#[test]
+fn client_code_actions_without_diagnostics() {
+    let p = project("code_actions_without_diagnostics")
+        .file("Cargo.toml", &basic_bin_manifest("foo"))
+        .file(
+            "src/main.rs",
+            r#"fn main() {
+    let x = 1;
+    println!("{}", x * (2 + 3));
+}
+"#,
+        )
+        .build();
+    let root_path = p.root();
+    let mut rls = p.spawn_rls_async();
+
+    rls.request::<Initialize>(0, initialize_params(root_path));
+    rls.wait_for_indexing();
+
+    let params = |only| CodeActionParams {
+        text_document: TextDocumentIdentifier {
+            uri: Url::from_file_path(p.root().join("src/main.rs")).unwrap(),
+        },
+        range: Range { start: Position::new(2, 23), end: Position::new(2, 30) },
+        context: CodeActionContext { diagnostics: vec![], only },
+    };
+    let titles = |commands: Vec<CodeActionOrCommand>| -> Vec<String> {
+        commands
+            .into_iter()
+            .map(|command| match command {
+                CodeActionOrCommand::Command(command) => command.title,
+                CodeActionOrCommand::CodeAction(action) => action.title,
+            })
+            .collect()
+    };
+
+    let commands = rls.request::<CodeActionRequest>(1, params(None)).unwrap();
+    assert!(titles(commands).contains(&"Extract into variable".to_owned()));
+
+    let only = Some(vec!["refactor".to_owned()]);
+    let commands = rls.request::<CodeActionRequest>(2, params(only)).unwrap();
+    assert!(titles(commands).contains(&"Extract into variable".to_owned()));
+
+    let only = Some(vec!["quickfix".to_owned()]);
+    let commands = rls.request::<CodeActionRequest>(3, params(only)).unwrap_or_default();
+    assert!(!titles(commands).contains(&"Extract into variable".to_owned()));
+}
+
+#[test]
+fn client_convert_if_let_to_match() {
+    let p = project("convert_if_let")
+        .file("Cargo.toml", &basic_bin_manifest("foo"))
+        .file(
+            "src/main.rs",
+            r#"enum Shape {
+    Circle(u32),
+    Square,
+}
+
+fn main() {
+    let shape = Shape::Circle(1);
+    if let Shape::Circle(r) = shape {
+        println!("{}", r);
+    } else {
+        println!("square");
+    }
+}
+"#,
+        )
+        .build();
+    let root_path = p.root();
+    let mut rls = p.spawn_rls_async();
+
+    rls.request::<Initialize>(0, initialize_params(root_path));
+    rls.wait_for_indexing();
+
+    let uri = Url::from_file_path(p.root().join("src/main.rs")).unwrap();
+    let commands = rls
+        .request::<CodeActionRequest>(
+            1,
+            CodeActionParams {
+                text_document: TextDocumentIdentifier { uri: uri.clone() },
+                range: Range { start: Position::new(7, 6), end: Position::new(7, 6) },
+                context: CodeActionContext { diagnostics: vec![], only: None },
+            },
+        )
+        .expect("No code actions returned");
+    let Command { arguments, .. } = commands
+        .into_iter()
+        .filter_map(|command| match command {
+            CodeActionOrCommand::Command(command) => Some(command),
+            CodeActionOrCommand::CodeAction(_) => None,
+        })
+        .find(|command| command.title == "Convert to `match`")
+        .expect("No conversion to `match`");
+    let arguments = arguments.expect("Missing command arguments");
+
+    assert_eq!(
+        serde_json::from_value::<Location>(arguments[0].clone()).unwrap(),
+        Location { uri, range: Range { start: Position::new(7, 4), end: Position::new(11, 5) } }
+    );
+    assert_eq!(
+        arguments[1],
+        "match shape {
+        Shape::Circle(r) => {
+            println!(\"{}\", r);
+        }
+        Shape::Square => {
+            println!(\"square\");
+        }
+    }"
+    );
+}
+
+#[test]
+fn client_import_local_item() {
+    let p = project("import_local_item")
+        .file("Cargo.toml", &basic_bin_manifest("foo"))
+        .file(
+            "src/main.rs",
+            r#"mod shapes {
+    pub struct Circle;
+}
+
+fn main() {
+    let _ = Circle;
+}
+"#,
+        )
+        .build();
+    let root_path = p.root();
+    let mut rls = p.spawn_rls_async();
+
+    rls.request::<Initialize>(0, initialize_params(root_path));
+    rls.wait_for_indexing();
+
+    let uri = Url::from_file_path(p.root().join("src/main.rs")).unwrap();
+    let diagnostic = Diagnostic {
+        range: Range { start: Position::new(5, 12), end: Position::new(5, 18) },
+        message: "cannot find value `Circle` in this scope".to_owned(),
+        ..Diagnostic::default()
+    };
+    let commands = rls
+        .request::<CodeActionRequest>(
+            1,
+            CodeActionParams {
+                text_document: TextDocumentIdentifier { uri: uri.clone() },
+                range: diagnostic.range,
+                context: CodeActionContext { diagnostics: vec![diagnostic], only: None },
+            },
+        )
+        .expect("No code actions returned");
+    let Command { arguments, .. } = commands
+        .into_iter()
+        .filter_map(|command| match command {
+            CodeActionOrCommand::Command(command) => Some(command),
+            CodeActionOrCommand::CodeAction(_) => None,
+        })
+        .find(|command| command.title == "Import `crate::shapes::Circle`")
+        .expect("No import of the crate's own item");
+    let arguments = arguments.expect("Missing command arguments");
+
+    assert_eq!(
+        serde_json::from_value::<Location>(arguments[0].clone()).unwrap(),
+        Location { uri, range: Range { start: Position::new(0, 0), end: Position::new(0, 0) } }
+    );
+    assert_eq!(arguments[1], "use crate::shapes::Circle;\n\n");
+}
+
+#[test]
+fn client_extract_function() {
+    let p = project("extract_function")
+        .file("Cargo.toml", &basic_bin_manifest("foo"))
+        .file(
+            "src/main.rs",
+            r#"fn main() {
+    let x = 1;
+    let y = x + 1;
+    println!("{}", y);
+}
+"#,
+        )
+        .build();
+    let root_path = p.root();
+    let mut rls = p.spawn_rls_async();
+
+    rls.request::<Initialize>(0, initialize_params(root_path));
+    rls.wait_for_indexing();
+
+    let uri = Url::from_file_path(p.root().join("src/main.rs")).unwrap();
+    let commands = rls
+        .request::<CodeActionRequest>(
+            1,
+            CodeActionParams {
+                text_document: TextDocumentIdentifier { uri: uri.clone() },
+                range: Range { start: Position::new(2, 4), end: Position::new(3, 22) },
+                context: CodeActionContext { diagnostics: vec![], only: None },
+            },
+        )
+        .expect("No code actions returned");
+    let Command { command, arguments, .. } = commands
+        .into_iter()
+        .filter_map(|command| match command {
+            CodeActionOrCommand::Command(command) => Some(command),
+            CodeActionOrCommand::CodeAction(_) => None,
+        })
+        .find(|command| command.title == "Extract into function")
+        .expect("No extract function action");
+    assert!(command.starts_with("rls.extractFunction-"));
+    let arguments = arguments.expect("Missing command arguments");
+
+    // Missing arguments are rejected.
+    rls.send(json!({
+        "jsonrpc": "2.0",
+        "id": 3,
+        "method": ExecuteCommand::METHOD,
+        "params": ExecuteCommandParams { command: command.clone(), arguments: vec![] },
+    }));
+    let response = rls.wait_for_message(|val| val["id"] == 3);
+    assert_eq!(response["error"]["code"], -32602);
+
+    rls.request::<ExecuteCommand>(2, ExecuteCommandParams { command, arguments });
+    let result = rls
+        .messages()
+        .iter()
+        .rfind(|msg| msg["method"] == ApplyWorkspaceEdit::METHOD)
+        .unwrap()
+        .clone();
+    let params = <ApplyWorkspaceEdit as Request>::Params::deserialize(&result["params"])
+        .expect("Couldn't deserialize params");
+
+    let (url, edits) = params.edit.changes.unwrap().drain().nth(0).unwrap();
+    assert_eq!(url, uri);
+    assert_eq!(
+        edits,
+        vec![
+            TextEdit {
+                range: Range { start: Position::new(2, 0), end: Position::new(4, 0) },
+                new_text: "    extracted(x);\n".to_owned(),
+            },
+            TextEdit {
+                range: Range { start: Position::new(5, 0), end: Position::new(5, 0) },
+                new_text:
+                    "\nfn extracted(x: i32) {\n    let y = x + 1;\n    println!(\"{}\", y);\n}\n"
+                        .to_owned(),
+            },
+        ]
+    );
+}
+
+#[test]
+fn client_inline_variable() {
+    let p = project("inline_variable")
+        .file("Cargo.toml", &basic_bin_manifest("foo"))
+        .file(
+            "src/main.rs",
+            r#"fn main() {
+    let once = 1 + 2;
+    let twice = 3 + 4;
+    let limit = 10;
+    println!("{} {} {} {} {}", once, twice, twice, limit, limit);
+}
+"#,
+        )
+        .build();
+    let root_path = p.root();
+    let mut rls = p.spawn_rls_async();
+
+    let literal_support = CodeActionLiteralSupport {
+        code_action_kind: CodeActionKindLiteralSupport {
+            value_set: vec!["refactor.inline".to_owned()],
+        },
+    };
+    let text_document = TextDocumentClientCapabilities {
+        code_action: Some(CodeActionCapability {
+            dynamic_registration: None,
+            code_action_literal_support: Some(literal_support),
+        }),
+        ..TextDocumentClientCapabilities::default()
+    };
+    let mut init_params = initialize_params(root_path);
+    init_params.capabilities.text_document = Some(text_document);
+    rls.request::<Initialize>(0, init_params);
+    rls.wait_for_indexing();
+
+    let uri = Url::from_file_path(p.root().join("src/main.rs")).unwrap();
+    let mut inline_actions = |id, row| {
+        let only = Some(vec!["refactor.inline".to_owned()]);
+        rls.request::<CodeActionRequest>(
+            id,
+            CodeActionParams {
+                text_document: TextDocumentIdentifier { uri: uri.clone() },
+                range: Range { start: Position::new(row, 8), end: Position::new(row, 8) },
+                context: CodeActionContext { diagnostics: vec![], only },
+            },
+        )
+        .unwrap_or_default()
+    };
+
+    // A variable used once can always be inlined.
+    let actions = inline_actions(1, 1);
+    assert_eq!(actions.len(), 1);
+    match &actions[0] {
+        CodeActionOrCommand::CodeAction(action) => {
+            assert_eq!(action.title, "Inline variable `once`");
+            assert_eq!(action.kind.as_ref().map(String::as_str), Some("refactor.inline"));
+            assert!(action.command.is_some());
+        }
+        CodeActionOrCommand::Command(_) => panic!("Expected a code action literal"),
+    }
+    // Inlining a computed value used twice would evaluate it twice.
+    assert!(inline_actions(2, 2).is_empty());
+    // A literal can be inlined wherever it's used.
+    assert_eq!(inline_actions(3, 3).len(), 1);
+}
+
 fn is_notification_for_unknown_config(msg: &serde_json::Value) -> bool {
     msg["method"] == ShowMessage::METHOD
         && msg["params"]["message"].as_str().unwrap().contains("Unknown")
@@ -1396,6 +2335,54 @@ fn client_did_change_configuration_duplicated_and_unknown_settings() {
     }
 }
 
+#[test]
+fn client_change_show_warnings_without_rebuild() {
+    let p = project("show_warnings")
+        .file("Cargo.toml", &basic_bin_manifest("foo"))
+        .file("src/main.rs", "fn main() {\n    let unused = 1;\n}\n")
+        .build();
+    let root_path = p.root();
+    let mut rls = p.spawn_rls_async();
+
+    rls.request::<Initialize>(0, initialize_params(root_path));
+
+    let diag = rls.wait_for_diagnostics();
+    assert_eq!(diag.diagnostics.len(), 1);
+    assert!(diag.diagnostics[0].message.contains("unused variable: `unused`"));
+    rls.wait_for_indexing();
+
+    let settings = json!({ "rust": { "show_warnings": false } });
+    rls.notify::<DidChangeConfiguration>(DidChangeConfigurationParams { settings });
+    let diag = rls.wait_for_diagnostics();
+    assert!(diag.uri.as_str().ends_with("src/main.rs"));
+    assert_eq!(diag.diagnostics, vec![]);
+
+    let settings = json!({ "rust": { "show_warnings": true } });
+    rls.notify::<DidChangeConfiguration>(DidChangeConfigurationParams { settings });
+    let diag = rls.wait_for_diagnostics();
+    assert_eq!(diag.diagnostics.len(), 1);
+
+    // An invalid configuration is rejected and the current one is kept.
+    let settings = json!({ "rust": { "show_warnings": "no" } });
+    rls.notify::<DidChangeConfiguration>(DidChangeConfigurationParams { settings });
+    rls.wait_for_message(|msg| {
+        msg["method"] == ShowMessage::METHOD
+            && msg["params"]["message"].as_str().unwrap().contains("invalid RLS configuration")
+    });
+
+    // Only the initial build was started.
+    let builds = rls
+        .messages()
+        .iter()
+        .filter(|msg| {
+            msg["method"] == StatusChanged::METHOD
+                && msg["params"]["state"] == "building"
+                && msg["params"]["phase"].is_null()
+        })
+        .count();
+    assert_eq!(builds, 1);
+}
+
 #[test]
 fn client_shutdown() {
     let p = ProjectBuilder::try_from_fixture(fixtures_dir().join("common")).unwrap().build();
@@ -1471,6 +2458,61 @@ fn client_hover() {
     assert_eq!(result.contents, HoverContents::Array(contents));
 }
 
+/// A file outside of any Cargo project is compiled on its own.
+#[test]
+fn client_hover_standalone_file() {
+    // Test projects are generated in the target directory, which is part of
+    // the RLS's own workspace.
+    let dir = tempfile::tempdir().unwrap();
+    let p = ProjectBuilder::new(dir.path().join("standalone"))
+        .file(
+            "hello.rs",
+            r#"use some_crate::Foo;
+
+fn main() {
+    let world = "world";
+    println!("Hello, {}!", world);
+}
+
+mod greeting;
+const WORLD: &str = "world";
+"#,
+        )
+        // A module is only compiled as part of its crate, where it has a parent.
+        .file("greeting.rs", "pub fn world() -> &'static str {\n    super::WORLD\n}\n")
+        .build();
+    let root_path = p.root();
+    let mut rls = p.spawn_rls_async();
+
+    rls.request::<Initialize>(0, initialize_params(root_path));
+    rls.wait_for_indexing();
+
+    assert!(!rls.messages().iter().any(|msg| {
+        msg["method"] == PublishDiagnostics::METHOD
+            && msg["params"]["uri"].as_str().unwrap().ends_with("greeting.rs")
+            && msg["params"]["diagnostics"].as_array().map_or(false, |d| !d.is_empty())
+    }));
+
+    let result = rls
+        .request::<HoverRequest>(
+            1,
+            TextDocumentPositionParams {
+                position: Position { line: 3, character: 9 },
+                text_document: TextDocumentIdentifier {
+                    uri: Url::from_file_path(p.root().join("hello.rs")).unwrap(),
+                },
+            },
+        )
+        .unwrap();
+
+    let contents: Vec<_> = ["&str", "let world = \"world\";"]
+        .iter()
+        .map(|value| LanguageString { language: "rust".to_string(), value: (*value).to_string() })
+        .map(MarkedString::LanguageString)
+        .collect();
+    assert_eq!(result.contents, HoverContents::Array(contents));
+}
+
 /// Test hover continues to work after the source has moved line
 #[ignore] // FIXME(#1265): Spurious failure - sometimes we lose the semantic information from Rust - why?
 #[test]
@@ -1580,6 +2622,26 @@ fn client_workspace_symbol() {
     }
 }
 
+#[test]
+fn client_workspace_symbol_excluded() {
+    let p =
+        ProjectBuilder::try_from_fixture(fixtures_dir().join("workspace_symbol")).unwrap().build();
+    let root_path = p.root();
+    let mut rls = p.spawn_rls_async();
+
+    let opts = json!({"settings": {"rust": { "analysis_exclude_globs": ["src/foo.rs"] } } });
+    rls.request::<Initialize>(0, initialize_params_with_opts(root_path, opts));
+
+    rls.wait_for_indexing();
+
+    let symbols = rls
+        .request::<WorkspaceSymbol>(42, WorkspaceSymbolParams { query: "nemo".to_owned() })
+        .unwrap();
+
+    let files: Vec<_> = symbols.iter().map(|s| s.location.uri.clone()).collect();
+    assert_eq!(files, vec![Url::from_file_path(p.root().join("src/main.rs")).unwrap()]);
+}
+
 #[test]
 fn client_workspace_symbol_duplicates() {
     let p = ProjectBuilder::try_from_fixture(fixtures_dir().join("workspace_symbol_duplicates"))
@@ -1743,6 +2805,63 @@ fn client_highlight() {
     }
 }
 
+#[test]
+fn client_highlight_read_write() {
+    let p = project("highlight_read_write")
+        .file("Cargo.toml", &basic_bin_manifest("foo"))
+        .file(
+            "src/main.rs",
+            "fn main() {
+    let mut bar = 1;
+    let baz = &mut bar;
+    *baz += 1;
+    let f = bar;
+    bar = f + 1;
+    println!(\"{}\", bar);
+}
+",
+        )
+        .build();
+    let root_path = p.root();
+    let mut rls = p.spawn_rls_async();
+
+    rls.request::<Initialize>(0, initialize_params(root_path));
+
+    rls.wait_for_indexing();
+
+    let result = rls
+        .request::<DocumentHighlightRequest>(
+            1,
+            TextDocumentPositionParams {
+                position: Position::new(1, 12),
+                text_document: TextDocumentIdentifier {
+                    uri: Url::from_file_path(p.root().join("src/main.rs")).unwrap(),
+                },
+            },
+        )
+        .unwrap();
+
+    let expected = [
+        (1, 12, DocumentHighlightKind::Write),
+        (2, 19, DocumentHighlightKind::Write),
+        (4, 12, DocumentHighlightKind::Read),
+        (5, 4, DocumentHighlightKind::Write),
+    ];
+    for (line, character, kind) in &expected {
+        let range = Range {
+            start: Position::new(*line, *character),
+            end: Position::new(*line, character + 3),
+        };
+        assert!(
+            result.iter().any(|x| x.range == range && x.kind.as_ref() == Some(kind)),
+            "{:?} not highlighted as {:?}: {:?}",
+            range,
+            kind,
+            result
+        );
+    }
+}
+
 #[test]
 fn client_rename() {
     let p = ProjectBuilder::try_from_fixture(fixtures_dir().join("common")).unwrap().build();
@@ -1851,17 +2970,19 @@ fn client_reformat_with_range() {
     );
 
     let newline = if cfg!(windows) { "\r\n" } else { "\n" };
-    let formatted = r#"pub fn main() {
-    let world1 = "world";
-    println!("Hello, {}!", world1);
-"#
-    .replace("\r", "")
-    .replace("\n", newline);
 
+    // The selected `{` line is snapped to the `fn` item it opens; the following
+    // statements and the missing newline at the end of the file are left alone.
     let edits = result.unwrap();
-    assert_eq!(edits.len(), 2);
-    assert_eq!(edits[0].new_text, formatted);
-    assert_eq!(edits[1].new_text, newline);
+    assert_eq!(edits.len(), 1);
+    assert_eq!(
+        edits[0].range,
+        Range {
+            start: Position { line: 0, character: 0 },
+            end: Position { line: 2, character: 0 }
+        }
+    );
+    assert_eq!(edits[0].new_text, format!("pub fn main() {{{}", newline));
 }
 
 #[test]
@@ -1955,31 +3076,98 @@ fn client_bin_lib_project() {
     let root_path = p.root();
     let mut rls = p.spawn_rls_async();
 
-    let opts = json!({"settings": {"rust": { "cfg_test": true, "build_bin": "bin_lib" } } });
-    rls.request::<Initialize>(0, initialize_params_with_opts(root_path, opts));
+    let opts = json!({"settings": {"rust": { "cfg_test": true, "build_bin": "bin_lib" } } });
+    rls.request::<Initialize>(0, initialize_params_with_opts(root_path, opts));
+
+    let diag: PublishDiagnosticsParams = rls.wait_for_diagnostics();
+
+    assert!(diag.uri.as_str().ends_with("bin_lib/tests/tests.rs"));
+    assert_eq!(diag.diagnostics.len(), 1);
+    assert_eq!(diag.diagnostics[0].severity, Some(DiagnosticSeverity::Warning));
+    assert!(diag.diagnostics[0].message.contains("unused variable: `unused_var`"));
+}
+
+#[test]
+fn client_infer_lib() {
+    let p = ProjectBuilder::try_from_fixture(fixtures_dir().join("infer_lib")).unwrap().build();
+    let root_path = p.root();
+    let mut rls = p.spawn_rls_async();
+
+    rls.request::<Initialize>(0, initialize_params(root_path));
+
+    let diag = rls.wait_for_diagnostics();
+
+    assert!(diag.uri.as_str().ends_with("src/lib.rs"));
+    assert_eq!(diag.diagnostics.len(), 1);
+    assert_eq!(diag.diagnostics[0].severity, Some(DiagnosticSeverity::Warning));
+    assert!(diag.diagnostics[0].message.contains("struct `UnusedLib` is never constructed"));
+}
+
+#[test]
+fn client_todo_comment_diagnostics() {
+    let p = ProjectBuilder::try_from_fixture(fixtures_dir().join("todo_comments")).unwrap().build();
+    let root_path = p.root();
+    let mut rls = p.spawn_rls_async();
+
+    let opts = json!({"settings": {"rust": { "show_todo_diagnostics": true } } });
+    rls.request::<Initialize>(0, initialize_params_with_opts(root_path, opts));
+
+    let diag = rls.wait_for_diagnostics();
+
+    assert!(diag.uri.as_str().ends_with("src/main.rs"));
+    assert_eq!(diag.diagnostics.len(), 1);
+    assert_eq!(diag.diagnostics[0].severity, Some(DiagnosticSeverity::Information));
+    assert_eq!(diag.diagnostics[0].message, "TODO: say something more interesting");
+    assert_eq!(diag.diagnostics[0].range.start, Position::new(1, 7));
+}
+
+#[test]
+fn client_implement_missing_items() {
+    let p = ProjectBuilder::try_from_fixture(fixtures_dir().join("trait_stubs")).unwrap().build();
+    let root_path = p.root();
+    let mut rls = p.spawn_rls_async();
+
+    rls.request::<Initialize>(0, initialize_params(root_path));
+
+    rls.wait_for_indexing();
 
-    let diag: PublishDiagnosticsParams = rls.wait_for_diagnostics();
+    let uri = Url::from_file_path(p.root().join("src/main.rs")).unwrap();
+    let result = rls.request::<ImplementMissingItems>(
+        1,
+        TextDocumentPositionParams {
+            text_document: TextDocumentIdentifier::new(uri.clone()),
+            position: Position { line: 15, character: 0 }, // In `impl TestTrait for Foo`
+        },
+    );
 
-    assert!(diag.uri.as_str().ends_with("bin_lib/tests/tests.rs"));
-    assert_eq!(diag.diagnostics.len(), 1);
-    assert_eq!(diag.diagnostics[0].severity, Some(DiagnosticSeverity::Warning));
-    assert!(diag.diagnostics[0].message.contains("unused variable: `unused_var`"));
+    let mut changes = result.unwrap().changes.unwrap();
+    let edits = changes.remove(&uri).unwrap();
+    assert_eq!(edits.len(), 1);
+    assert_eq!(edits[0].range, Range { start: Position::new(19, 5), end: Position::new(20, 0) });
+    assert_eq!(edits[0].new_text, "\n\n    fn test_method(&self) {\n        todo!()\n    }\n");
 }
 
 #[test]
-fn client_infer_lib() {
-    let p = ProjectBuilder::try_from_fixture(fixtures_dir().join("infer_lib")).unwrap().build();
+fn client_active_cfgs() {
+    let p = ProjectBuilder::try_from_fixture(fixtures_dir().join("cfgs")).unwrap().build();
     let root_path = p.root();
     let mut rls = p.spawn_rls_async();
 
     rls.request::<Initialize>(0, initialize_params(root_path));
 
-    let diag = rls.wait_for_diagnostics();
+    rls.wait_for_indexing();
 
-    assert!(diag.uri.as_str().ends_with("src/lib.rs"));
-    assert_eq!(diag.diagnostics.len(), 1);
-    assert_eq!(diag.diagnostics[0].severity, Some(DiagnosticSeverity::Warning));
-    assert!(diag.diagnostics[0].message.contains("struct `UnusedLib` is never constructed"));
+    let uri = Url::from_file_path(p.root().join("src/lib.rs")).unwrap();
+    let cfgs = rls.request::<ActiveCfgs>(
+        1,
+        ActiveCfgsParams { text_document: TextDocumentIdentifier::new(uri) },
+    );
+
+    assert_eq!(cfgs.len(), 1);
+    assert_eq!(cfgs[0].crate_name, "cfgs");
+    assert!(!cfgs[0].test);
+    assert!(cfgs[0].features.contains(&"extra".to_owned()));
+    assert!(!cfgs[0].features.contains(&"unused".to_owned()));
 }
 
 #[test]
@@ -2002,6 +3190,84 @@ fn client_omit_init_build() {
     assert_eq!(rls.messages().iter().count(), 2);
 }
 
+#[test]
+fn client_prewarm_files() {
+    let p = ProjectBuilder::try_from_fixture(fixtures_dir().join("common")).unwrap().build();
+    let root_path = p.root();
+    let opts = json!({"settings": {"rust": { "all_targets": false, "racer_completion": false } } });
+
+    // Leave the analysis data of a build on disk.
+    let mut rls = p.spawn_rls_async();
+    rls.request::<Initialize>(0, initialize_params_with_opts(root_path, opts.clone()));
+    rls.wait_for_indexing();
+    drop(rls);
+
+    let mut rls = p.spawn_rls_async();
+    let prewarmed = rls
+        .future_msg(|msg| msg["params"]["title"] == "Prewarming" && msg["params"]["done"] == true);
+    let mut opts = opts;
+    opts["omitInitBuild"] = json!(true);
+    opts["prewarmFiles"] = json!(["src/main.rs"]);
+    rls.request::<Initialize>(0, initialize_params_with_opts(root_path, opts));
+    rls.block_on(prewarmed).unwrap().unwrap();
+
+    // Without a build or Racer, only the prewarmed analysis data can answer.
+    let result = rls
+        .request::<HoverRequest>(
+            1,
+            TextDocumentPositionParams {
+                position: Position { line: 12, character: 27 },
+                text_document: TextDocumentIdentifier {
+                    uri: Url::from_file_path(p.root().join("src/main.rs")).unwrap(),
+                },
+            },
+        )
+        .unwrap();
+    let contents: Vec<_> = ["&str", "let world = \"world\";"]
+        .iter()
+        .map(|value| LanguageString { language: "rust".to_string(), value: (*value).to_string() })
+        .map(MarkedString::LanguageString)
+        .collect();
+    assert_eq!(result.contents, HoverContents::Array(contents));
+}
+
+#[test]
+fn client_request_metrics() {
+    let p = ProjectBuilder::try_from_fixture(fixtures_dir().join("common")).unwrap().build();
+    let root_path = p.root();
+    let mut rls = p.spawn_rls_async();
+
+    let opts = json!({"settings": {"rust": { "request_metrics": true } } });
+    rls.request::<Initialize>(0, initialize_params_with_opts(root_path, opts));
+    rls.wait_for_indexing();
+
+    let hover_params = || TextDocumentPositionParams {
+        position: Position { line: 12, character: 27 },
+        text_document: TextDocumentIdentifier {
+            uri: Url::from_file_path(p.root().join("src/main.rs")).unwrap(),
+        },
+    };
+    rls.request::<HoverRequest>(1, hover_params());
+    rls.request::<HoverRequest>(2, hover_params());
+    let metrics = rls.future_msg(|msg| {
+        msg["method"] == "rls/metrics"
+            && msg["params"]["methods"].as_array().map_or(false, |methods| methods.len() == 2)
+    });
+    rls.request::<WorkspaceSymbol>(3, WorkspaceSymbolParams { query: "world".to_owned() });
+
+    let metrics = rls.block_on(metrics).unwrap().unwrap();
+    let methods = metrics["params"]["methods"].as_array().unwrap();
+    let counts: Vec<_> = methods.iter().map(|m| (&m["method"], &m["count"])).collect();
+    assert_eq!(
+        counts,
+        vec![(&json!("textDocument/hover"), &json!(2)), (&json!("workspace/symbol"), &json!(1))]
+    );
+    for method in methods {
+        assert!(method["maxMs"].as_f64().unwrap() > 0.0);
+        assert!(method["p50Ms"].as_f64().unwrap() <= method["maxMs"].as_f64().unwrap());
+    }
+}
+
 #[test]
 fn client_find_impls() {
     let p = ProjectBuilder::try_from_fixture(fixtures_dir().join("find_impls")).unwrap().build();
@@ -2063,6 +3329,111 @@ fn client_find_impls() {
     }
 }
 
+#[test]
+fn client_disabled_capabilities() {
+    let p = project("disabled_capabilities")
+        .file("Cargo.toml", &basic_bin_manifest("foo"))
+        .file("src/main.rs", "fn main() {}\n")
+        .build();
+    let root_path = p.root();
+    let mut rls = p.spawn_rls_async();
+
+    let disabled = ["formatting", "callHierarchy", "rls.rebuild"];
+    let opts = json!({ "settings": { "rust": { "disabled_capabilities": disabled } } });
+    let result = rls.request::<Initialize>(0, initialize_params_with_opts(root_path, opts));
+    assert_eq!(result.capabilities.document_formatting_provider, None);
+    assert_eq!(result.capabilities.hover_provider, Some(true));
+    let commands = result.capabilities.execute_command_provider.unwrap().commands;
+    assert!(!commands.iter().any(|command| command.starts_with("rls.rebuild-")));
+    let reload =
+        commands.iter().find(|command| command.starts_with("rls.reloadAnalysis-")).unwrap();
+    let rebuild = reload.replace("rls.reloadAnalysis-", "rls.rebuild-");
+    let response = rls.messages().iter().find(|msg| msg["id"] == 0).unwrap().clone();
+    assert!(response["result"]["capabilities"].get("callHierarchyProvider").is_none());
+    assert_eq!(response["result"]["capabilities"]["typeHierarchyProvider"], true);
+
+    rls.send(json!({
+        "jsonrpc": "2.0",
+        "id": 1,
+        "method": Formatting::METHOD,
+        "params": DocumentFormattingParams {
+            text_document: TextDocumentIdentifier {
+                uri: Url::from_file_path(p.root().join("src/main.rs")).unwrap(),
+            },
+            options: FormattingOptions {
+                tab_size: 4,
+                insert_spaces: true,
+                properties: Default::default(),
+            },
+        },
+    }));
+    let response = rls.wait_for_message(|val| val["id"] == 1);
+    assert_eq!(response["error"]["code"], -32601);
+
+    rls.send(json!({
+        "jsonrpc": "2.0",
+        "id": 2,
+        "method": ExecuteCommand::METHOD,
+        "params": ExecuteCommandParams { command: rebuild, arguments: vec![] },
+    }));
+    let response = rls.wait_for_message(|val| val["id"] == 2);
+    assert_eq!(response["error"]["code"], -32601);
+}
+
+#[test]
+fn client_goto_def_location_links() {
+    let p = project("goto_def_links")
+        .file("Cargo.toml", &basic_bin_manifest("foo"))
+        .file(
+            "src/main.rs",
+            r#"fn foo() -> u32 {
+    1
+}
+
+fn main() {
+    foo();
+}
+"#,
+        )
+        .build();
+    let root_path = p.root();
+    let mut rls = p.spawn_rls_async();
+
+    let mut params = initialize_params(root_path);
+    params.capabilities.text_document = Some(TextDocumentClientCapabilities {
+        definition: Some(GotoCapability { dynamic_registration: None, link_support: Some(true) }),
+        ..TextDocumentClientCapabilities::default()
+    });
+    rls.request::<Initialize>(0, params);
+    rls.wait_for_indexing();
+
+    let uri = Url::from_file_path(p.root().join("src/main.rs")).unwrap();
+    let result = rls.request::<GotoDefinition>(
+        1,
+        TextDocumentPositionParams {
+            position: Position::new(5, 5),
+            text_document: TextDocumentIdentifier { uri: uri.clone() },
+        },
+    );
+
+    let links = match result {
+        Some(GotoDefinitionResponse::Link(links)) => links,
+        result => panic!("Expected location links, got {:?}", result),
+    };
+    assert_eq!(
+        links,
+        vec![LocationLink {
+            origin_selection_range: Some(Range {
+                start: Position::new(5, 4),
+                end: Position::new(5, 7),
+            }),
+            target_uri: uri,
+            target_range: Range { start: Position::new(0, 0), end: Position::new(2, 1) },
+            target_selection_range: Range { start: Position::new(0, 3), end: Position::new(0, 6) },
+        }]
+    );
+}
+
 #[test]
 fn client_features() {
     let p = ProjectBuilder::try_from_fixture(fixtures_dir().join("features")).unwrap().build();
@@ -2097,6 +3468,48 @@ fn client_all_features() {
     );
 }
 
+#[test]
+fn client_rebuild_command() {
+    let p = project("rebuild_command")
+        .file("Cargo.toml", &basic_bin_manifest("foo"))
+        .file("src/main.rs", "fn main() {}\n\n#[cfg(feature = \"extra\")]\nfn extra() {}\n")
+        .build();
+    let root_path = p.root();
+    let mut rls = p.spawn_rls_async();
+
+    let opts = json!({"settings": {"rust": { "build_on_save": false, "wait_to_build": 3000 } } });
+    let result = rls.request::<Initialize>(0, initialize_params_with_opts(root_path, opts));
+    rls.wait_for_indexing();
+    let commands = result.capabilities.execute_command_provider.unwrap().commands;
+    let command = commands.into_iter().find(|c| c.starts_with("rls.rebuild-")).unwrap();
+
+    // Only re-running Cargo picks up the new default feature.
+    let manifest =
+        format!("{}[features]\ndefault = [\"extra\"]\nextra = []\n", basic_bin_manifest("foo"));
+    fs::write(root_path.join("Cargo.toml"), manifest).unwrap();
+    // A change queues a build which doesn't re-run Cargo, which the one of
+    // the command takes priority over.
+    rls.notify::<DidChangeTextDocument>(DidChangeTextDocumentParams {
+        content_changes: vec![TextDocumentContentChangeEvent {
+            range: Some(Range { start: Position::new(0, 12), end: Position::new(0, 12) }),
+            range_length: Some(0),
+            text: "\n".to_string(),
+        }],
+        text_document: VersionedTextDocumentIdentifier {
+            uri: Url::from_file_path(root_path.join("src/main.rs")).unwrap(),
+            version: Some(1),
+        },
+    });
+    let diagnostics = rls.future_msg(|msg| {
+        msg["method"] == PublishDiagnostics::METHOD
+            && msg["params"]["diagnostics"].as_array().map_or(false, |diags| {
+                diags.iter().any(|diag| diag["message"].as_str().unwrap().contains("`extra`"))
+            })
+    });
+    rls.request::<ExecuteCommand>(1, ExecuteCommandParams { command, arguments: vec![] });
+    rls.block_on(diagnostics).unwrap().unwrap();
+}
+
 #[test]
 fn client_no_default_features() {
     let p = ProjectBuilder::try_from_fixture(fixtures_dir().join("features")).unwrap().build();
@@ -2280,3 +3693,219 @@ fn client_cargo_target_directory_is_excluded_from_backups() {
         .unwrap()
         .starts_with("Signature: 8a477f597d28d172789f06886806bc55"));
 }
+
+#[test]
+fn client_analysis_freshness() {
+    let p = project("analysis_freshness")
+        .file("Cargo.toml", &basic_bin_manifest("foo"))
+        .file("src/main.rs", "fn main() {}\n")
+        .build();
+    let root_path = p.root();
+    let mut rls = p.spawn_rls_async();
+
+    rls.request::<Initialize>(0, initialize_params(root_path));
+    rls.wait_for_indexing();
+
+    let uri = Url::from_file_path(p.root().join("src/main.rs")).unwrap();
+    rls.notify::<DidOpenTextDocument>(DidOpenTextDocumentParams {
+        text_document: TextDocumentItem {
+            uri: uri.clone(),
+            language_id: "rust".to_owned(),
+            version: 0,
+            text: "fn main() {}\n".to_owned(),
+        },
+    });
+    // Unchanged since the build, which used the file on disk.
+    let freshness = rls.request::<AnalysisFreshness>(1, ());
+    assert_eq!(freshness, vec![FileFreshness { uri: uri.clone(), fresh: true }]);
+
+    let indexed =
+        rls.future_msg(|msg| msg["params"]["title"] == "Indexing" && msg["params"]["done"] == true);
+    rls.notify::<DidChangeTextDocument>(DidChangeTextDocumentParams {
+        content_changes: vec![TextDocumentContentChangeEvent {
+            range: Some(Range {
+                start: Position { line: 0, character: 11 },
+                end: Position { line: 0, character: 11 },
+            }),
+            range_length: Some(0),
+            text: " let x = 1; ".to_owned(),
+        }],
+        text_document: VersionedTextDocumentIdentifier { uri: uri.clone(), version: Some(1) },
+    });
+    let freshness = rls.request::<AnalysisFreshness>(2, ());
+    assert_eq!(freshness, vec![FileFreshness { uri: uri.clone(), fresh: false }]);
+
+    rls.block_on(indexed).unwrap().unwrap();
+    let freshness = rls.request::<AnalysisFreshness>(3, ());
+    assert_eq!(freshness, vec![FileFreshness { uri, fresh: true }]);
+}
+
+#[test]
+fn client_crate_graph() {
+    let p = project("crate_graph")
+        .file(
+            "Cargo.toml",
+            r#"
+                [workspace]
+                members = ["app", "util"]
+            "#,
+        )
+        .file(
+            "app/Cargo.toml",
+            r#"
+                [package]
+                name = "app"
+                version = "0.1.0"
+                authors = ["Example <rls@example.com>"]
+
+                [dependencies]
+                util = { path = "../util" }
+            "#,
+        )
+        .file("app/src/main.rs", "fn main() {\n    util::greet();\n}\n")
+        .file(
+            "util/Cargo.toml",
+            r#"
+                [package]
+                name = "util"
+                version = "0.1.0"
+                authors = ["Example <rls@example.com>"]
+            "#,
+        )
+        .file("util/src/lib.rs", "pub fn greet() {}\n")
+        .build();
+    let root_path = p.root();
+    let mut rls = p.spawn_rls_async();
+
+    rls.request::<Initialize>(0, initialize_params(root_path));
+
+    let graph = match rls.request::<CrateGraph>(1, CrateGraphParams::default()) {
+        CrateGraphResult::Graph(graph) => graph,
+        CrateGraphResult::Dot(dot) => panic!("expected nodes and edges, got {}", dot),
+    };
+    let id = |name: &str| graph.nodes.iter().find(|node| node.name == name).unwrap().id;
+    let (app, util) = (id("app"), id("util"));
+    let edge = graph.edges.iter().find(|edge| edge.from == app && edge.to == util).unwrap();
+    assert_eq!(edge.crate_name, "util");
+    assert!(!edge.cyclic);
+    assert!(!graph.edges.iter().any(|edge| edge.from == util));
+
+    let params = CrateGraphParams { format: Some("dot".to_owned()) };
+    match rls.request::<CrateGraph>(2, params) {
+        CrateGraphResult::Dot(dot) => assert!(dot.contains(&format!("    {} -> {};", app, util))),
+        CrateGraphResult::Graph(graph) => panic!("expected a DOT graph, got {:?}", graph),
+    }
+}
+
+#[test]
+fn client_analysis_crate_blacklist() {
+    let p = project("analysis_crate_blacklist")
+        .file(
+            "Cargo.toml",
+            r#"
+                [workspace]
+                members = ["app", "generated"]
+            "#,
+        )
+        .file(
+            "app/Cargo.toml",
+            r#"
+                [package]
+                name = "app"
+                version = "0.1.0"
+                authors = ["Example <rls@example.com>"]
+
+                [dependencies]
+                generated = { path = "../generated" }
+            "#,
+        )
+        .file("app/src/main.rs", "fn main() {\n    generated::frobnicate();\n}\n")
+        .file(
+            "generated/Cargo.toml",
+            r#"
+                [package]
+                name = "generated"
+                version = "0.1.0"
+                authors = ["Example <rls@example.com>"]
+            "#,
+        )
+        .file("generated/src/lib.rs", "pub fn frobnicate() {}\n")
+        .build();
+    let root_path = p.root();
+    let mut rls = p.spawn_rls_async();
+
+    let opts = json!({"settings": {"rust": { "analysis_crate_blacklist": ["generated"] } } });
+    rls.request::<Initialize>(0, initialize_params_with_opts(root_path, opts));
+    rls.wait_for_indexing();
+
+    // The analysis of the blacklisted crate isn't loaded, unlike that of the
+    // other workspace member.
+    let symbols =
+        rls.request::<WorkspaceSymbol>(1, WorkspaceSymbolParams { query: "main".to_owned() });
+    assert_eq!(symbols.unwrap_or_default().len(), 1);
+    let symbols =
+        rls.request::<WorkspaceSymbol>(2, WorkspaceSymbolParams { query: "frobnicate".to_owned() });
+    assert!(symbols.unwrap_or_default().is_empty());
+}
+
+#[test]
+fn client_dead_code() {
+    const SRC: &str = "fn main() {\n    used();\n}\n\nfn used() {}\n\nfn unused() {}\n\n\
+                       pub(crate) fn only_tested() {}\n\npub fn exported() {}\n\n\
+                       #[cfg(test)]\nmod tests {\n    #[test]\n    fn it_works() {\n        \
+                       super::only_tested();\n    }\n}\n";
+
+    let p = project("dead_code")
+        .file("Cargo.toml", &basic_bin_manifest("foo"))
+        .file("src/main.rs", SRC)
+        .build();
+    let root_path = p.root();
+    let mut rls = p.spawn_rls_async();
+
+    // Leave the test code out of the analysis.
+    let opts = json!({"settings": {"rust": { "all_targets": false } } });
+    rls.request::<Initialize>(0, initialize_params_with_opts(root_path, opts));
+    rls.wait_for_indexing();
+
+    let result = rls.request::<DeadCode>(1, ());
+    let names = |symbols: &[SymbolInformation]| -> Vec<String> {
+        symbols.iter().map(|s| s.name.clone()).collect()
+    };
+    assert_eq!(names(&result.unused), vec!["unused"]);
+    assert_eq!(result.unused[0].kind, SymbolKind::Function);
+    assert_eq!(result.unused[0].location.range.start, Position { line: 6, character: 3 });
+    assert_eq!(names(&result.used_in_tests), vec!["only_tested"]);
+}
+
+#[test]
+fn client_toggle_impl() {
+    const SRC: &str = "trait Shape {\n    fn area(&self) -> u32;\n}\n\nstruct Square;\n\n\
+                       impl Shape for Square {\n    fn area(&self) -> u32 {\n        1\n    }\n}\n\n\
+                       fn main() {\n    Square.area();\n}\n";
+
+    let p = project("toggle_impl")
+        .file("Cargo.toml", &basic_bin_manifest("foo"))
+        .file("src/main.rs", SRC)
+        .build();
+    let root_path = p.root();
+    let mut rls = p.spawn_rls_async();
+
+    rls.request::<Initialize>(0, initialize_params(root_path));
+    rls.wait_for_indexing();
+
+    let uri = Url::from_file_path(p.root().join("src/main.rs")).unwrap();
+    let params = |line, character| TextDocumentPositionParams {
+        text_document: TextDocumentIdentifier::new(uri.clone()),
+        position: Position { line, character },
+    };
+    let range = |line, start, end| Range {
+        start: Position { line, character: start },
+        end: Position { line, character: end },
+    };
+
+    // From the trait method to its implementation, and back.
+    let result = rls.request::<ToggleImpl>(1, params(1, 8));
+    assert_eq!(result, vec![Location { uri: uri.clone(), range: range(7, 7, 11) }]);
+    let result = rls.request::<ToggleImpl>(2, params(7, 8));
+    assert_eq!(result, vec![Location { uri: uri.clone(), range: range(1, 7, 11) }]);
+}
@@ -0,0 +1,9 @@
+#[cfg(feature = "extra")]
+pub fn extra() -> u32 {
+    1
+}
+
+#[cfg(feature = "unused")]
+pub fn unused() -> u32 {
+    2
+}
@@ -15,3 +15,10 @@ fn test_bar() {
 fn test_baz() {
 
 }
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn test_qux() {
+    }
+}
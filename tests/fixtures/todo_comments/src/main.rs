@@ -0,0 +1,4 @@
+fn main() {
+    // TODO: say something more interesting
+    println!("// FIXME: not a comment");
+}
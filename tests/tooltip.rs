@@ -182,6 +182,8 @@ impl TooltipTestHarness {
         let client_caps = ClientCapabilities {
             code_completion_has_snippet_support: true,
             related_information_support: true,
+            definition_link_support: false,
+            work_done_progress: false,
         };
 
         let _working_dir = tempfile::tempdir().expect("Couldn't create tempdir");